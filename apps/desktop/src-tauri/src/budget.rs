@@ -0,0 +1,725 @@
+//! Token budgets
+//!
+//! Every completed call is charged its prompt and completion tokens against
+//! three budgets: the current day, the current app session and the client
+//! that sent it. Each budget has an optional soft and hard limit. Crossing a
+//! soft limit publishes [`BUDGET_WARNING_EVENT`] and changes the tray
+//! tooltip; once a hard limit is reached the proxy rejects new requests of
+//! that scope until the day rolls over or the limit is raised.
+//!
+//! Instead of failing, requests can be downgraded: once a budget reaches
+//! the configured level the `model` field is rewritten to a cheaper model,
+//! e.g. gpt-4o to gpt-4o-mini or anything to a local Ollama model. The
+//! captured call keeps the requested model next to the one that answered.
+//! Past a hard limit only downgrades to a local or free model go through.
+//! Gemini names the model in the URL, so its requests are never downgraded.
+//!
+//! Clients name themselves with the `x-blackbox-client` header, otherwise
+//! the product of their `User-Agent` is used; clients holding a virtual key
+//! are named by it, and the key's limit wins over the settings. Client usage
+//! counts per day.
+//! Daily usage is saved to disk so a restart does not reset it. Calls the
+//! provider reported no usage for are charged the local count, see
+//! [`crate::usage`]; answers served from the cache are free.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use axum::http::{header, HeaderMap};
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::cache;
+use crate::capture::{CapturedCall, Usage};
+use crate::events::EventSink;
+use crate::sync::lock;
+
+/// File under the app data dir holding today's usage
+pub const BUDGET_USAGE_FILE: &str = "budget-usage.json";
+/// Request header naming the client, stripped before forwarding
+pub const CLIENT_HEADER: &str = "x-blackbox-client";
+/// Client of requests that neither name themselves nor send a `User-Agent`
+pub const UNKNOWN_CLIENT: &str = "unknown";
+/// Event published when a call pushes a budget past its soft limit
+pub const BUDGET_WARNING_EVENT: &str = "budget-warning";
+/// Event published when a call pushes a budget to its hard limit
+pub const BUDGET_EXCEEDED_EVENT: &str = "budget-exceeded";
+/// Key under which the budgets a call crossed are recorded on it
+pub const METADATA_KEY: &str = "budget";
+/// Key under which a model downgrade is recorded on the call
+pub const DOWNGRADE_METADATA_KEY: &str = "downgrade";
+/// Local Ollama model from `.env.example`, a natural last-resort downgrade
+pub const DEFAULT_LOCAL_MODEL: &str = "llama3.2:3b";
+
+/// Token limits of one budget, unset limits never trigger
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetLimit {
+    /// Tokens after which a warning is raised
+    pub soft_tokens: Option<u64>,
+    /// Tokens after which requests are rejected
+    pub hard_tokens: Option<u64>,
+}
+
+impl BudgetLimit {
+    /// Returns how close `used` tokens are to this limit
+    pub fn level(&self, used: u64) -> BudgetLevel {
+        if self.hard_tokens.is_some_and(|hard| used >= hard) {
+            BudgetLevel::Exceeded
+        } else if self.soft_tokens.is_some_and(|soft| used >= soft) {
+            BudgetLevel::Warning
+        } else {
+            BudgetLevel::Ok
+        }
+    }
+}
+
+/// Budget limits, persisted in the settings store next to `AppSettings`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BudgetSettings {
+    pub daily: BudgetLimit,
+    pub session: BudgetLimit,
+    /// Daily limit of clients without an entry in `clients`
+    pub per_client: BudgetLimit,
+    /// Daily limits by client name
+    pub clients: HashMap<String, BudgetLimit>,
+    pub downgrade: DowngradeSettings,
+}
+
+/// Cheaper models to switch to once a budget runs low
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DowngradeSettings {
+    pub enabled: bool,
+    /// Budget level from which requests are downgraded
+    pub at: BudgetLevel,
+    /// Replacements by requested model
+    pub models: HashMap<String, String>,
+    /// Replacement for models without an entry in `models`
+    pub fallback: Option<String>,
+}
+
+impl Default for DowngradeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            at: BudgetLevel::Warning,
+            models: HashMap::from([("gpt-4o".to_string(), "gpt-4o-mini".to_string())]),
+            fallback: None,
+        }
+    }
+}
+
+impl DowngradeSettings {
+    /// Returns the model `model` is downgraded to, if any
+    pub fn target(&self, model: &str) -> Option<&str> {
+        self.models
+            .get(model)
+            .or(self.fallback.as_ref())
+            .map(String::as_str)
+            .filter(|target| *target != model)
+    }
+}
+
+/// Model rewrite applied to a request, recorded on the captured call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Downgrade {
+    pub from: String,
+    pub to: String,
+    /// Budget that triggered the downgrade
+    pub scope: BudgetScope,
+    pub level: BudgetLevel,
+}
+
+impl BudgetSettings {
+    /// Returns the limit that applies to `client`
+    pub fn client_limit(&self, client: &str) -> BudgetLimit {
+        self.clients.get(client).copied().unwrap_or(self.per_client)
+    }
+
+    /// Checks that every soft limit lies below its hard limit and downgrades need a low budget
+    pub fn validate(&self) -> Result<(), String> {
+        if self.downgrade.at == BudgetLevel::Ok {
+            return Err("downgrade: level must be warning or exceeded".to_string());
+        }
+        let limits = [("daily", &self.daily), ("session", &self.session)]
+            .into_iter()
+            .chain([("per_client", &self.per_client)])
+            .chain(
+                self.clients
+                    .iter()
+                    .map(|(name, limit)| (name.as_str(), limit)),
+            );
+        for (name, limit) in limits {
+            if let (Some(soft), Some(hard)) = (limit.soft_tokens, limit.hard_tokens) {
+                if soft > hard {
+                    return Err(format!(
+                        "{}: soft limit {} is above the hard limit {}",
+                        name, soft, hard
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a budget is counted over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetScope {
+    Daily,
+    Session,
+    Client,
+}
+
+impl BudgetScope {
+    fn describe(self, client: Option<&str>) -> String {
+        match (self, client) {
+            (BudgetScope::Daily, _) => "Daily token budget".to_string(),
+            (BudgetScope::Session, _) => "Session token budget".to_string(),
+            (BudgetScope::Client, client) => format!(
+                "Daily token budget of client \"{}\"",
+                client.unwrap_or(UNKNOWN_CLIENT)
+            ),
+        }
+    }
+}
+
+/// How much of a budget is used up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetLevel {
+    Ok,
+    Warning,
+    Exceeded,
+}
+
+/// Tokens charged to one budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TokenCount {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenCount {
+    /// Returns prompt and completion tokens together
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens.saturating_add(self.completion_tokens)
+    }
+
+    fn add(&mut self, usage: &Usage) {
+        self.prompt_tokens = self.prompt_tokens.saturating_add(usage.prompt_tokens);
+        self.completion_tokens = self
+            .completion_tokens
+            .saturating_add(usage.completion_tokens);
+    }
+}
+
+/// State of one budget, reported to the frontend and in events
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BudgetStatus {
+    pub scope: BudgetScope,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    pub used: TokenCount,
+    pub limit: BudgetLimit,
+    pub level: BudgetLevel,
+}
+
+impl BudgetStatus {
+    fn new(scope: BudgetScope, client: Option<&str>, used: TokenCount, limit: BudgetLimit) -> Self {
+        Self {
+            scope,
+            client: client.map(str::to_string),
+            used,
+            limit,
+            level: limit.level(used.total()),
+        }
+    }
+
+    /// Human readable summary, e.g. for the tray tooltip
+    pub fn describe(&self) -> String {
+        let limit = match self.level {
+            BudgetLevel::Exceeded => self.limit.hard_tokens,
+            _ => self.limit.soft_tokens.or(self.limit.hard_tokens),
+        };
+        match limit {
+            Some(limit) => format!(
+                "{}: {} of {} tokens used",
+                self.scope.describe(self.client.as_deref()),
+                self.used.total(),
+                limit
+            ),
+            None => format!(
+                "{}: {} tokens used",
+                self.scope.describe(self.client.as_deref()),
+                self.used.total()
+            ),
+        }
+    }
+}
+
+/// Limits together with the current usage, returned by `get_budgets`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BudgetOverview {
+    pub limits: BudgetSettings,
+    /// Day the daily budgets count, `YYYY-MM-DD` in local time
+    pub day: String,
+    pub daily: BudgetStatus,
+    pub session: BudgetStatus,
+    /// Clients seen today
+    pub clients: Vec<BudgetStatus>,
+}
+
+/// Rejection of a request whose budget has no tokens left
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{}. Raise the limit in Settings or wait for the budget to reset.", .0.describe())]
+pub struct BudgetExceeded(pub BudgetStatus);
+
+/// Usage persisted across restarts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct DailyUsage {
+    day: Option<NaiveDate>,
+    total: TokenCount,
+    clients: BTreeMap<String, TokenCount>,
+}
+
+struct State {
+    settings: BudgetSettings,
+    /// Limits of clients holding a virtual key, ahead of `settings.clients`
+    key_limits: HashMap<String, BudgetLimit>,
+    daily: DailyUsage,
+    session: TokenCount,
+}
+
+/// Counts token usage and enforces the configured limits
+pub struct BudgetTracker {
+    /// `None` keeps usage in memory only
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+    events: Arc<dyn EventSink>,
+}
+
+impl BudgetTracker {
+    /// Creates a tracker that forgets daily usage on restart
+    pub fn in_memory(settings: BudgetSettings, events: Arc<dyn EventSink>) -> Self {
+        Self {
+            path: None,
+            state: Mutex::new(State {
+                settings,
+                key_limits: HashMap::new(),
+                daily: DailyUsage::default(),
+                session: TokenCount::default(),
+            }),
+            events,
+        }
+    }
+
+    /// Creates a tracker that keeps daily usage in `path`
+    pub fn open(path: PathBuf, settings: BudgetSettings, events: Arc<dyn EventSink>) -> Self {
+        let daily = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        let tracker = Self {
+            path: Some(path),
+            ..Self::in_memory(settings, events)
+        };
+        tracker.lock().daily = daily;
+        tracker
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        let mut state = lock(&self.state);
+        let today = Local::now().date_naive();
+        if state.daily.day != Some(today) {
+            state.daily = DailyUsage {
+                day: Some(today),
+                ..DailyUsage::default()
+            };
+        }
+        state
+    }
+
+    /// Returns the configured limits
+    pub fn settings(&self) -> BudgetSettings {
+        self.lock().settings.clone()
+    }
+
+    /// Replaces the limits, keeping the usage counted so far
+    pub fn set_settings(&self, settings: BudgetSettings) {
+        self.lock().settings = settings;
+    }
+
+    /// Replaces the limits that come with clients' virtual keys
+    pub fn set_key_limits(&self, limits: HashMap<String, BudgetLimit>) {
+        self.lock().key_limits = limits;
+    }
+
+    /// Fails when a budget `client` draws from has no tokens left
+    pub fn admit(&self, client: &str) -> Result<(), BudgetExceeded> {
+        let state = self.lock();
+        match Self::statuses(&state, client)
+            .into_iter()
+            .find(|status| status.level == BudgetLevel::Exceeded)
+        {
+            Some(status) => Err(BudgetExceeded(status)),
+            None => Ok(()),
+        }
+    }
+
+    /// Picks a cheaper model for `model` when a budget of `client` has reached the downgrade level
+    pub fn downgrade(&self, client: &str, model: &str) -> Option<Downgrade> {
+        let state = self.lock();
+        let rules = &state.settings.downgrade;
+        if !rules.enabled {
+            return None;
+        }
+        let trigger = Self::statuses(&state, client)
+            .into_iter()
+            .filter(|status| status.level >= rules.at)
+            .max_by_key(|status| status.level)?;
+        Some(Downgrade {
+            from: model.to_string(),
+            to: rules.target(model)?.to_string(),
+            scope: trigger.scope,
+            level: trigger.level,
+        })
+    }
+
+    fn statuses(state: &State, client: &str) -> [BudgetStatus; 3] {
+        let client_used = state.daily.clients.get(client).copied().unwrap_or_default();
+        [
+            BudgetStatus::new(
+                BudgetScope::Daily,
+                None,
+                state.daily.total,
+                state.settings.daily,
+            ),
+            BudgetStatus::new(
+                BudgetScope::Session,
+                None,
+                state.session,
+                state.settings.session,
+            ),
+            BudgetStatus::new(
+                BudgetScope::Client,
+                Some(client),
+                client_used,
+                state
+                    .key_limits
+                    .get(client)
+                    .copied()
+                    .unwrap_or_else(|| state.settings.client_limit(client)),
+            ),
+        ]
+    }
+
+    /// Charges a finished call and publishes the budgets it pushed to a new level
+    pub fn charge(&self, call: &mut CapturedCall) {
+        let Some(usage) = call.usage else {
+            return;
+        };
+        let cached = call
+            .metadata
+            .get(cache::METADATA_KEY)
+            .and_then(Value::as_str);
+        if matches!(cached, Some("hit" | "semantic-hit")) {
+            return;
+        }
+        let client = call.client.as_deref().unwrap_or(UNKNOWN_CLIENT);
+        let mut state = self.lock();
+        let before = Self::statuses(&state, client);
+        state.daily.total.add(&usage);
+        state
+            .daily
+            .clients
+            .entry(client.to_string())
+            .or_default()
+            .add(&usage);
+        state.session.add(&usage);
+        let after = Self::statuses(&state, client);
+        let daily = state.daily.clone();
+        drop(state);
+
+        let crossed: Vec<BudgetStatus> = before
+            .iter()
+            .zip(after)
+            .filter(|(before, after)| after.level > before.level)
+            .map(|(_, after)| after)
+            .collect();
+        for status in &crossed {
+            let event = match status.level {
+                BudgetLevel::Exceeded => BUDGET_EXCEEDED_EVENT,
+                _ => BUDGET_WARNING_EVENT,
+            };
+            let mut payload = json!(status);
+            payload["message"] = json!(status.describe());
+            self.events.emit(event, payload);
+        }
+        if !crossed.is_empty() {
+            call.metadata
+                .insert(METADATA_KEY.to_string(), json!(crossed));
+        }
+        if let Err(e) = self.save(&daily) {
+            log::warn!("Failed to save token usage: {}", e);
+        }
+    }
+
+    fn save(&self, daily: &DailyUsage) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let staging = path.with_extension("tmp");
+        fs::write(&staging, serde_json::to_vec(daily)?)?;
+        fs::rename(&staging, path)
+    }
+
+    /// Returns the limits and what has been used of them
+    pub fn overview(&self) -> BudgetOverview {
+        let state = self.lock();
+        let [daily, session, _] = Self::statuses(&state, UNKNOWN_CLIENT);
+        let clients = state
+            .daily
+            .clients
+            .keys()
+            .map(|client| {
+                let [_, _, status] = Self::statuses(&state, client);
+                status
+            })
+            .collect();
+        BudgetOverview {
+            limits: state.settings.clone(),
+            day: state.daily.day.map(|d| d.to_string()).unwrap_or_default(),
+            daily,
+            session,
+            clients,
+        }
+    }
+
+    /// Tray tooltip naming the most used up budget, `None` while all are fine
+    pub fn tooltip(&self) -> Option<String> {
+        let overview = self.overview();
+        [overview.daily, overview.session]
+            .into_iter()
+            .chain(overview.clients)
+            .filter(|status| status.level != BudgetLevel::Ok)
+            .max_by_key(|status| status.level)
+            .map(|status| status.describe())
+    }
+}
+
+/// Names the client that sent a request
+pub fn client_id(headers: &HeaderMap) -> String {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    };
+    if let Some(client) = header(CLIENT_HEADER) {
+        return client.to_string();
+    }
+    // `User-Agent: cursor/0.42 (darwin)` names the client `cursor`
+    header(header::USER_AGENT.as_str())
+        .and_then(|agent| agent.split(['/', ' ']).next())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_else(|| UNKNOWN_CLIENT.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::RecordingSink;
+    use axum::http::HeaderValue;
+
+    fn limit(soft: u64, hard: u64) -> BudgetLimit {
+        BudgetLimit {
+            soft_tokens: Some(soft),
+            hard_tokens: Some(hard),
+        }
+    }
+
+    fn call(client: &str, prompt_tokens: u64, completion_tokens: u64) -> CapturedCall {
+        let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+        call.client = Some(client.to_string());
+        call.usage = Some(Usage::new(prompt_tokens, completion_tokens));
+        call
+    }
+
+    mod limit_tests {
+        use super::*;
+
+        #[test]
+        fn test_levels() {
+            let limit = limit(50, 100);
+            assert_eq!(limit.level(49), BudgetLevel::Ok);
+            assert_eq!(limit.level(50), BudgetLevel::Warning);
+            assert_eq!(limit.level(100), BudgetLevel::Exceeded);
+            assert_eq!(BudgetLimit::default().level(u64::MAX), BudgetLevel::Ok);
+        }
+
+        #[test]
+        fn test_validate_rejects_soft_above_hard() {
+            let mut settings = BudgetSettings::default();
+            settings
+                .clients
+                .insert("cursor".to_string(), limit(100, 50));
+            assert!(settings.validate().unwrap_err().starts_with("cursor"));
+            settings.clients.clear();
+            settings.daily = limit(10, 10);
+            assert!(settings.validate().is_ok());
+        }
+
+        #[test]
+        fn test_client_id() {
+            let mut headers = HeaderMap::new();
+            assert_eq!(client_id(&headers), UNKNOWN_CLIENT);
+            headers.insert(
+                header::USER_AGENT,
+                HeaderValue::from_static("Cursor/0.42 (darwin)"),
+            );
+            assert_eq!(client_id(&headers), "cursor");
+            headers.insert(CLIENT_HEADER, HeaderValue::from_static("nightly-script"));
+            assert_eq!(client_id(&headers), "nightly-script");
+        }
+    }
+
+    mod tracker_tests {
+        use super::*;
+
+        #[test]
+        fn test_soft_limit_warns_once() {
+            let sink = Arc::new(RecordingSink::default());
+            let settings = BudgetSettings {
+                daily: limit(100, 1000),
+                ..BudgetSettings::default()
+            };
+            let tracker = BudgetTracker::in_memory(settings, sink.clone());
+
+            tracker.charge(&mut call("cli", 40, 20));
+            assert!(sink.names().is_empty());
+            let mut crossing = call("cli", 30, 20);
+            tracker.charge(&mut crossing);
+            tracker.charge(&mut call("cli", 10, 0));
+
+            assert_eq!(sink.names(), vec![BUDGET_WARNING_EVENT]);
+            let (_, payload) = &sink.events()[0];
+            assert_eq!(payload["scope"], "daily");
+            assert_eq!(payload["used"]["prompt_tokens"], 70);
+            assert_eq!(crossing.metadata[METADATA_KEY][0]["level"], "warning");
+            assert!(tracker.admit("cli").is_ok());
+            assert!(tracker.tooltip().unwrap().contains("120 of 100 tokens"));
+        }
+
+        #[test]
+        fn test_hard_limit_rejects_only_its_client() {
+            let sink = Arc::new(RecordingSink::default());
+            let mut settings = BudgetSettings::default();
+            settings.clients.insert("script".to_string(), limit(5, 10));
+            let tracker = BudgetTracker::in_memory(settings, sink.clone());
+
+            tracker.charge(&mut call("script", 8, 4));
+            assert_eq!(sink.names(), vec![BUDGET_EXCEEDED_EVENT]);
+            let rejected = tracker.admit("script").unwrap_err();
+            assert_eq!(rejected.0.scope, BudgetScope::Client);
+            assert!(rejected.to_string().contains("client \"script\""));
+            assert!(tracker.admit("cursor").is_ok());
+
+            let mut raised = tracker.settings();
+            raised.clients.insert("script".to_string(), limit(5, 100));
+            tracker.set_settings(raised);
+            assert!(tracker.admit("script").is_ok());
+        }
+
+        #[test]
+        fn test_key_limits_win_over_settings() {
+            let mut settings = BudgetSettings::default();
+            settings.clients.insert("cursor".to_string(), limit(5, 10));
+            let tracker = BudgetTracker::in_memory(settings, Arc::new(RecordingSink::default()));
+            tracker.set_key_limits(HashMap::from([("cursor".to_string(), limit(50, 100))]));
+
+            tracker.charge(&mut call("cursor", 20, 0));
+            assert!(tracker.admit("cursor").is_ok());
+            tracker.set_key_limits(HashMap::new());
+            assert!(tracker.admit("cursor").is_err());
+        }
+
+        #[test]
+        fn test_session_limit_and_free_calls() {
+            let settings = BudgetSettings {
+                session: limit(1, 2),
+                ..BudgetSettings::default()
+            };
+            let tracker = BudgetTracker::in_memory(settings, Arc::new(RecordingSink::default()));
+            let mut unreported = call("cli", 0, 0);
+            unreported.usage = None;
+            tracker.charge(&mut unreported);
+            assert!(tracker.admit("cli").is_ok());
+
+            tracker.charge(&mut call("cli", 1, 1));
+            assert_eq!(
+                tracker.admit("other").unwrap_err().0.scope,
+                BudgetScope::Session
+            );
+        }
+
+        #[test]
+        fn test_downgrade_once_budget_runs_low() {
+            let settings = BudgetSettings {
+                daily: limit(10, 20),
+                downgrade: DowngradeSettings {
+                    enabled: true,
+                    fallback: Some(DEFAULT_LOCAL_MODEL.to_string()),
+                    ..DowngradeSettings::default()
+                },
+                ..BudgetSettings::default()
+            };
+            let tracker = BudgetTracker::in_memory(settings, Arc::new(RecordingSink::default()));
+            assert!(tracker.downgrade("cli", "gpt-4o").is_none());
+
+            tracker.charge(&mut call("cli", 8, 4));
+            let downgrade = tracker.downgrade("cli", "gpt-4o").unwrap();
+            assert_eq!(downgrade.to, "gpt-4o-mini");
+            assert_eq!(
+                (downgrade.scope, downgrade.level),
+                (BudgetScope::Daily, BudgetLevel::Warning)
+            );
+            let downgrade = tracker.downgrade("cli", "claude-3-opus").unwrap();
+            assert_eq!(downgrade.to, DEFAULT_LOCAL_MODEL);
+            assert!(tracker.downgrade("cli", DEFAULT_LOCAL_MODEL).is_none());
+
+            let mut strict = tracker.settings();
+            strict.downgrade.at = BudgetLevel::Exceeded;
+            tracker.set_settings(strict);
+            assert!(tracker.downgrade("cli", "gpt-4o").is_none());
+        }
+
+        #[test]
+        fn test_daily_usage_survives_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(BUDGET_USAGE_FILE);
+            let sink: Arc<dyn EventSink> = Arc::new(RecordingSink::default());
+            let tracker =
+                BudgetTracker::open(path.clone(), BudgetSettings::default(), sink.clone());
+            tracker.charge(&mut call("cli", 3, 4));
+            drop(tracker);
+
+            let reopened = BudgetTracker::open(path, BudgetSettings::default(), sink);
+            let overview = reopened.overview();
+            assert_eq!(overview.daily.used.total(), 7);
+            assert_eq!(overview.session.used.total(), 0);
+            assert_eq!(overview.clients.len(), 1);
+            assert_eq!(overview.clients[0].client.as_deref(), Some("cli"));
+        }
+    }
+}
@@ -0,0 +1,817 @@
+//! Exact-match response cache
+//!
+//! Agents often send the same deterministic prompt again and again. A
+//! successful upstream answer is stored under a SHA-256 of everything that
+//! determines it: the provider, the route, headers that change API
+//! behaviour and the forwarded body. The key also covers who asked, that is
+//! the client key or client name, the upstream and any credential headers,
+//! so one client is never served another's answer. Streamed answers are stored as their
+//! raw event stream and replayed through the normal stream path, so plugins
+//! and capture see a cached answer exactly like a live one.
+//!
+//! Every entry is one JSON file under the app data dir. Entries expire after
+//! their TTL and the least recently used ones are evicted once the cache
+//! grows past its size limit. Recency is kept in the file modification time
+//! so the order survives restarts.
+//!
+//! The cache is off by default. Only requests sent with temperature 0 are
+//! cached unless `sampled` is set, since sampled answers differ per call.
+//!
+//! Clients control caching per request with the `x-blackbox-cache` header:
+//! `bypass` skips the cache, `refresh` skips the lookup but stores the new
+//! answer and `no-store` reads without storing. `cache-control: no-cache`
+//! and `no-store` map to `refresh` and `bypass`. `x-blackbox-cache-ttl`
+//! overrides the TTL, in seconds, of the answer being stored.
+//!
+//! When the [`semantic`] layer is enabled, an exact miss falls back to the
+//! stored answer of the most similar earlier prompt.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::capture::ModelParameters;
+use crate::proxy::Provider;
+use crate::sync::lock;
+
+pub mod semantic;
+
+use semantic::{SemanticCache, SemanticConfig, SemanticMatch, SemanticQuery};
+
+/// Directory under the app data dir holding cache entries
+pub const CACHE_DIR: &str = "cache";
+/// Request header selecting the cache mode, reported back on responses
+pub const CACHE_HEADER: &str = "x-blackbox-cache";
+/// Request header overriding the TTL in seconds
+pub const CACHE_TTL_HEADER: &str = "x-blackbox-cache-ttl";
+/// Response header naming the entry a cached answer came from
+pub const CACHE_ENTRY_HEADER: &str = "x-blackbox-cache-entry";
+/// Response header with the similarity of a semantic hit
+pub const CACHE_SIMILARITY_HEADER: &str = "x-blackbox-cache-similarity";
+/// Key under which the cache outcome is recorded on captured calls
+pub const METADATA_KEY: &str = "cache";
+/// Key under which the entry a cached answer came from is recorded
+pub const METADATA_ENTRY_KEY: &str = "cache_entry";
+/// Key under which the similarity of a semantic hit is recorded
+pub const METADATA_SIMILARITY_KEY: &str = "cache_similarity";
+
+/// Request headers that change what the upstream answers, or for whom
+const KEYED_HEADERS: &[&str] = &[
+    "anthropic-version",
+    "anthropic-beta",
+    "openai-beta",
+    "authorization",
+    "x-api-key",
+    "x-goog-api-key",
+];
+const ENTRY_EXTENSION: &str = "json";
+
+/// Cache configuration, persisted in the settings store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Default lifetime of an entry
+    pub ttl_secs: u64,
+    /// Total size of all entries before the least recently used are evicted
+    pub max_bytes: u64,
+    /// Also caches requests sampled above temperature 0
+    pub sampled: bool,
+    pub semantic: SemanticConfig,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl_secs: 60 * 60,
+            max_bytes: 256 * 1024 * 1024,
+            sampled: false,
+            semantic: SemanticConfig::default(),
+        }
+    }
+}
+
+/// How a single request uses the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Serve from the cache and store misses
+    Default,
+    /// Neither read nor store
+    Bypass,
+    /// Store a fresh answer without reading
+    Refresh,
+    /// Read without storing
+    NoStore,
+}
+
+impl CacheMode {
+    /// Reads the mode from `x-blackbox-cache`, falling back to `cache-control`
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_ascii_lowercase)
+        };
+        match value(CACHE_HEADER).as_deref() {
+            Some("bypass") | Some("off") => return CacheMode::Bypass,
+            Some("refresh") => return CacheMode::Refresh,
+            Some("no-store") => return CacheMode::NoStore,
+            _ => {}
+        }
+        let control = value(header::CACHE_CONTROL.as_str()).unwrap_or_default();
+        let directives: Vec<&str> = control.split(',').map(str::trim).collect();
+        if directives.contains(&"no-store") {
+            CacheMode::Bypass
+        } else if directives.contains(&"no-cache") {
+            CacheMode::Refresh
+        } else {
+            CacheMode::Default
+        }
+    }
+
+    /// Whether a cached answer may be served
+    pub fn reads(self) -> bool {
+        matches!(self, CacheMode::Default | CacheMode::NoStore)
+    }
+
+    /// Whether the upstream answer may be stored
+    pub fn writes(self) -> bool {
+        matches!(self, CacheMode::Default | CacheMode::Refresh)
+    }
+}
+
+/// Computes the cache key of a request about to be forwarded, `scope`
+/// naming who asked
+pub fn request_key(
+    provider: Provider,
+    scope: &str,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    body: &Value,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(provider.as_str());
+    hasher.update([0]);
+    hasher.update(scope);
+    hasher.update([0]);
+    hasher.update(path_and_query);
+    for name in KEYED_HEADERS {
+        hasher.update([0]);
+        if let Some(value) = headers.get(*name) {
+            hasher.update(value.as_bytes());
+        }
+    }
+    hasher.update([0]);
+    // Object keys serialize sorted, so the same content always hashes the same
+    hasher.update(body.to_string());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Reads the TTL override from `x-blackbox-cache-ttl`
+pub fn ttl_override(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CACHE_TTL_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// A stored upstream answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Whether `body` is a raw event stream
+    pub stream: bool,
+    pub body: String,
+    /// Unix seconds
+    pub created_at: u64,
+    /// Unix seconds
+    pub expires_at: u64,
+}
+
+impl CachedResponse {
+    /// Response headers to replay the answer with
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(value) = self
+            .content_type
+            .as_deref()
+            .and_then(|v| HeaderValue::from_str(v).ok())
+        {
+            headers.insert(header::CONTENT_TYPE, value);
+        }
+        headers
+    }
+}
+
+/// Cache counters reported to the frontend; hits and misses count since launch
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub entries: usize,
+    pub size_bytes: u64,
+    pub max_bytes: u64,
+    pub hits: u64,
+    /// Exact misses, including those answered by the semantic layer
+    pub misses: u64,
+    pub evictions: u64,
+    pub semantic_hits: u64,
+    /// Prompts indexed by the semantic layer
+    pub semantic_entries: usize,
+}
+
+struct Meta {
+    size: u64,
+    /// Larger is more recent
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Meta>,
+    size: u64,
+    clock: u64,
+}
+
+impl Index {
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        if let Some(meta) = self.entries.get_mut(key) {
+            meta.last_used = self.clock;
+        }
+    }
+
+    fn insert(&mut self, key: String, size: u64) {
+        self.remove(&key);
+        self.clock += 1;
+        self.size += size;
+        self.entries.insert(
+            key,
+            Meta {
+                size,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(meta) = self.entries.remove(key) {
+            self.size -= meta.size;
+        }
+    }
+
+    fn least_recent(&self) -> Option<String> {
+        self.entries
+            .iter()
+            .min_by_key(|(_, meta)| meta.last_used)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+/// On-disk response cache shared by every proxy route
+pub struct ResponseCache {
+    /// `None` when the cache could not be opened; every lookup misses
+    dir: Option<PathBuf>,
+    config: CacheConfig,
+    index: Mutex<Index>,
+    /// `None` unless semantic lookups are enabled
+    semantic: Option<SemanticCache>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    semantic_hits: AtomicU64,
+}
+
+impl ResponseCache {
+    /// Creates a cache that never stores anything
+    pub fn disabled() -> Self {
+        Self {
+            dir: None,
+            config: CacheConfig {
+                enabled: false,
+                ..CacheConfig::default()
+            },
+            index: Mutex::new(Index::default()),
+            semantic: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            semantic_hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Opens the cache in `dir`, indexing the entries left by earlier runs
+    pub fn open(dir: PathBuf, config: CacheConfig) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut found = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                continue;
+            }
+            if path.extension().and_then(|e| e.to_str()) != Some(ENTRY_EXTENSION) {
+                // Leftovers of interrupted writes
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            let Some(key) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let metadata = fs::metadata(&path)?;
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+            found.push((modified, key.to_string(), metadata.len()));
+        }
+        found.sort();
+
+        let mut index = Index::default();
+        for (_, key, size) in found {
+            index.insert(key, size);
+        }
+        let semantic = config
+            .semantic
+            .enabled
+            .then(|| SemanticCache::open(dir.join(semantic::SEMANTIC_DIR), config.semantic.clone()))
+            .and_then(|opened| {
+                opened
+                    .map_err(|e| log::warn!("Semantic cache unavailable: {}", e))
+                    .ok()
+            });
+        let cache = Self {
+            dir: Some(dir),
+            config,
+            index: Mutex::new(index),
+            semantic,
+            ..Self::disabled()
+        };
+        cache.evict(&mut lock(&cache.index));
+        Ok(cache)
+    }
+
+    /// Returns the configuration the cache was opened with
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Returns the semantic layer when it is enabled
+    pub fn semantic(&self) -> Option<&SemanticCache> {
+        self.semantic.as_ref().filter(|_| self.config.enabled)
+    }
+
+    /// Whether a request sampled with `parameters` may use the cache
+    pub fn admits(&self, parameters: Option<&ModelParameters>) -> bool {
+        self.config.sampled || parameters.and_then(|p| p.temperature) == Some(0.0)
+    }
+
+    fn active_dir(&self) -> Option<&Path> {
+        self.dir.as_deref().filter(|_| self.config.enabled)
+    }
+
+    fn entry_path(dir: &Path, key: &str) -> PathBuf {
+        dir.join(format!("{}.{}", key, ENTRY_EXTENSION))
+    }
+
+    /// Returns the live entry for `key`, counting a hit or a miss
+    pub fn lookup(&self, key: &str) -> Option<CachedResponse> {
+        let dir = self.active_dir()?;
+        let found = self.read(dir, key);
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Returns the live entry whose prompt is most similar to `query`, counting a semantic hit
+    pub fn lookup_similar(&self, query: &SemanticQuery) -> Option<(SemanticMatch, CachedResponse)> {
+        let dir = self.active_dir()?;
+        let semantic = self.semantic()?;
+        let found = semantic.search(query)?;
+        let Some(entry) = self.read(dir, &found.key) else {
+            // The answer expired or was evicted since the prompt was indexed
+            if let Err(e) = semantic.remove(&found.key) {
+                log::warn!("Failed to update the semantic index: {}", e);
+            }
+            return None;
+        };
+        self.semantic_hits.fetch_add(1, Ordering::Relaxed);
+        Some((found, entry))
+    }
+
+    fn read(&self, dir: &Path, key: &str) -> Option<CachedResponse> {
+        let mut index = lock(&self.index);
+        index.entries.get(key)?;
+        let path = Self::entry_path(dir, key);
+        let entry = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<CachedResponse>(&bytes).ok())
+            .filter(|entry| entry.expires_at > unix_now());
+        match entry {
+            Some(entry) => {
+                index.touch(key);
+                // Persist recency for the next launch
+                let _ = fs::File::options()
+                    .append(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::now()));
+                Some(entry)
+            }
+            None => {
+                index.remove(key);
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    /// Returns where to store the answer for `key`, or `None` when it must not be stored.
+    /// `ttl_secs` overrides the configured TTL.
+    pub fn slot(
+        self: &Arc<Self>,
+        key: &str,
+        mode: CacheMode,
+        ttl_secs: Option<u64>,
+    ) -> Option<CacheSlot> {
+        self.active_dir()?;
+        if !mode.writes() {
+            return None;
+        }
+        let ttl_secs = ttl_secs.unwrap_or(self.config.ttl_secs);
+        (ttl_secs > 0).then(|| CacheSlot {
+            cache: self.clone(),
+            key: key.to_string(),
+            ttl_secs,
+            query: None,
+        })
+    }
+
+    fn store(&self, key: &str, entry: &CachedResponse) -> io::Result<()> {
+        let Some(dir) = self.active_dir() else {
+            return Ok(());
+        };
+        let bytes = serde_json::to_vec(entry)?;
+        let size = bytes.len() as u64;
+        if size > self.config.max_bytes {
+            return Ok(());
+        }
+        let path = Self::entry_path(dir, key);
+        // Unique so concurrent writes of the same key never share a file
+        let staging = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4().simple()));
+        fs::write(&staging, &bytes)?;
+        fs::rename(&staging, &path)?;
+
+        let mut index = lock(&self.index);
+        index.insert(key.to_string(), size);
+        self.evict(&mut index);
+        Ok(())
+    }
+
+    /// Drops least recently used entries until the cache fits its size limit
+    fn evict(&self, index: &mut Index) {
+        while index.size > self.config.max_bytes {
+            let Some(key) = index.least_recent() else {
+                break;
+            };
+            index.remove(&key);
+            if let Some(dir) = &self.dir {
+                let _ = fs::remove_file(Self::entry_path(dir, &key));
+            }
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Removes every entry
+    pub fn clear(&self) -> io::Result<()> {
+        let mut index = lock(&self.index);
+        if let Some(dir) = &self.dir {
+            for key in index.entries.keys() {
+                match fs::remove_file(Self::entry_path(dir, key)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+        }
+        *index = Index::default();
+        match &self.semantic {
+            Some(semantic) => semantic.clear(),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the current counters
+    pub fn stats(&self) -> CacheStats {
+        let index = lock(&self.index);
+        CacheStats {
+            enabled: self.active_dir().is_some(),
+            entries: index.entries.len(),
+            size_bytes: index.size,
+            max_bytes: self.config.max_bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            semantic_hits: self.semantic_hits.load(Ordering::Relaxed),
+            semantic_entries: self.semantic().map_or(0, SemanticCache::len),
+        }
+    }
+}
+
+/// Place reserved for the answer to one request, filled once it completed
+#[derive(Clone)]
+pub struct CacheSlot {
+    cache: Arc<ResponseCache>,
+    key: String,
+    ttl_secs: u64,
+    /// Prompt to index once the answer is stored
+    query: Option<SemanticQuery>,
+}
+
+impl CacheSlot {
+    /// Indexes the prompt for semantic lookups once the answer is stored
+    pub fn with_query(mut self, query: Option<SemanticQuery>) -> Self {
+        self.query = query;
+        self
+    }
+
+    /// Stores a successful upstream answer; failures only cost a future hit
+    pub fn fill(&self, status: StatusCode, headers: &HeaderMap, stream: bool, body: &[u8]) {
+        if !status.is_success() {
+            return;
+        }
+        let Ok(body) = std::str::from_utf8(body) else {
+            return;
+        };
+        let created_at = unix_now();
+        let entry = CachedResponse {
+            status: status.as_u16(),
+            content_type: headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            stream,
+            body: body.to_string(),
+            created_at,
+            expires_at: created_at.saturating_add(self.ttl_secs),
+        };
+        if let Err(e) = self.cache.store(&self.key, &entry) {
+            log::warn!("Failed to store cached response: {}", e);
+            return;
+        }
+        let (Some(semantic), Some(query)) = (self.cache.semantic(), &self.query) else {
+            return;
+        };
+        if let Err(e) = semantic.insert(&self.key, query) {
+            log::warn!("Failed to update the semantic index: {}", e);
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn open(dir: &Path, max_bytes: u64) -> Arc<ResponseCache> {
+        Arc::new(
+            ResponseCache::open(
+                dir.to_path_buf(),
+                CacheConfig {
+                    enabled: true,
+                    max_bytes,
+                    ..CacheConfig::default()
+                },
+            )
+            .unwrap(),
+        )
+    }
+
+    fn fill(cache: &Arc<ResponseCache>, key: &str, body: &str) {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        cache.slot(key, CacheMode::Default, None).unwrap().fill(
+            StatusCode::OK,
+            &headers,
+            false,
+            body.as_bytes(),
+        );
+    }
+
+    mod key_tests {
+        use super::*;
+
+        #[test]
+        fn test_key_ignores_json_formatting() {
+            let a: Value = serde_json::from_str(r#"{"model":"gpt-4o","temperature":0}"#).unwrap();
+            let b: Value =
+                serde_json::from_str(r#"{ "temperature": 0, "model": "gpt-4o" }"#).unwrap();
+            let headers = HeaderMap::new();
+            assert_eq!(
+                request_key(Provider::OpenAi, "c", "/v1/chat/completions", &headers, &a),
+                request_key(Provider::OpenAi, "c", "/v1/chat/completions", &headers, &b)
+            );
+        }
+
+        #[test]
+        fn test_key_covers_route_and_versions() {
+            let body = json!({"model": "claude"});
+            let mut headers = HeaderMap::new();
+            let base = request_key(Provider::Anthropic, "c", "/v1/messages", &headers, &body);
+            assert_ne!(
+                base,
+                request_key(Provider::OpenAi, "c", "/v1/messages", &headers, &body)
+            );
+            assert_ne!(
+                base,
+                request_key(Provider::Anthropic, "c", "/v1/complete", &headers, &body)
+            );
+            assert_ne!(
+                base,
+                request_key(Provider::Anthropic, "d", "/v1/messages", &headers, &body)
+            );
+            headers.insert("anthropic-beta", HeaderValue::from_static("tools"));
+            let beta = request_key(Provider::Anthropic, "c", "/v1/messages", &headers, &body);
+            assert_ne!(base, beta);
+            headers.insert("x-api-key", HeaderValue::from_static("sk-other"));
+            assert_ne!(
+                beta,
+                request_key(Provider::Anthropic, "c", "/v1/messages", &headers, &body)
+            );
+        }
+
+        #[test]
+        fn test_only_temperature_zero_is_admitted() {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = open(dir.path(), u64::MAX);
+            let sampled = |temperature| ModelParameters {
+                temperature,
+                ..ModelParameters::default()
+            };
+            assert!(cache.admits(Some(&sampled(Some(0.0)))));
+            assert!(!cache.admits(Some(&sampled(Some(0.7)))));
+            assert!(!cache.admits(Some(&sampled(None))));
+            assert!(!cache.admits(None));
+
+            let config = CacheConfig {
+                sampled: true,
+                ..CacheConfig::default()
+            };
+            let cache = ResponseCache::open(dir.path().to_path_buf(), config).unwrap();
+            assert!(cache.admits(None));
+        }
+
+        #[test]
+        fn test_modes_from_headers() {
+            let mode = |name: &str, value: &'static str| {
+                let mut headers = HeaderMap::new();
+                headers.insert(
+                    axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                    HeaderValue::from_static(value),
+                );
+                CacheMode::from_headers(&headers)
+            };
+            assert_eq!(
+                CacheMode::from_headers(&HeaderMap::new()),
+                CacheMode::Default
+            );
+            assert_eq!(mode(CACHE_HEADER, "BYPASS"), CacheMode::Bypass);
+            assert_eq!(mode(CACHE_HEADER, "no-store"), CacheMode::NoStore);
+            assert_eq!(mode("cache-control", "no-cache"), CacheMode::Refresh);
+            assert_eq!(
+                mode("cache-control", "max-age=0, no-store"),
+                CacheMode::Bypass
+            );
+            assert!(!CacheMode::Refresh.reads());
+            assert!(!CacheMode::NoStore.writes());
+        }
+    }
+
+    mod store_tests {
+        use super::*;
+
+        #[test]
+        fn test_hit_and_miss_are_counted() {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = open(dir.path(), 1 << 20);
+            assert!(cache.lookup("a").is_none());
+            fill(&cache, "a", "{\"ok\":true}");
+
+            let hit = cache.lookup("a").unwrap();
+            assert_eq!(hit.body, "{\"ok\":true}");
+            assert_eq!(hit.content_type.as_deref(), Some("application/json"));
+            let stats = cache.stats();
+            assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+        }
+
+        #[test]
+        fn test_entries_survive_reopening() {
+            let dir = tempfile::tempdir().unwrap();
+            fill(&open(dir.path(), 1 << 20), "a", "{}");
+
+            let reopened = open(dir.path(), 1 << 20);
+            assert_eq!(reopened.stats().entries, 1);
+            assert!(reopened.lookup("a").is_some());
+        }
+
+        #[test]
+        fn test_expired_entries_miss() {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = open(dir.path(), 1 << 20);
+            let mut headers = HeaderMap::new();
+            headers.insert(CACHE_TTL_HEADER, HeaderValue::from_static("0"));
+            let ttl = ttl_override(&headers);
+            assert_eq!(ttl, Some(0));
+            assert!(cache.slot("a", CacheMode::Default, ttl).is_none());
+
+            let slot = cache.slot("a", CacheMode::Default, None).unwrap();
+            let entry = CachedResponse {
+                status: 200,
+                content_type: None,
+                stream: false,
+                body: "{}".to_string(),
+                created_at: 0,
+                expires_at: unix_now() - 1,
+            };
+            slot.cache.store("a", &entry).unwrap();
+            assert!(cache.lookup("a").is_none());
+            assert_eq!(cache.stats().entries, 0);
+        }
+
+        #[test]
+        fn test_evicts_least_recently_used() {
+            let dir = tempfile::tempdir().unwrap();
+            let body = "x".repeat(100);
+            let probe = open(dir.path(), 1 << 20);
+            fill(&probe, "probe", &body);
+            let entry_size = probe.stats().size_bytes;
+            probe.clear().unwrap();
+
+            let cache = open(dir.path(), entry_size * 2);
+            fill(&cache, "a", &body);
+            fill(&cache, "b", &body);
+            assert!(cache.lookup("a").is_some());
+            fill(&cache, "c", &body);
+
+            assert!(cache.lookup("b").is_none());
+            assert!(cache.lookup("a").is_some());
+            assert!(cache.lookup("c").is_some());
+            assert_eq!(cache.stats().evictions, 1);
+        }
+
+        #[test]
+        fn test_failures_and_bypass_are_not_stored() {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = open(dir.path(), 1 << 20);
+            assert!(cache.slot("a", CacheMode::Bypass, None).is_none());
+            cache.slot("a", CacheMode::Default, None).unwrap().fill(
+                StatusCode::TOO_MANY_REQUESTS,
+                &HeaderMap::new(),
+                false,
+                b"{}",
+            );
+            assert_eq!(cache.stats().entries, 0);
+        }
+
+        #[test]
+        fn test_clear_removes_files() {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = open(dir.path(), 1 << 20);
+            fill(&cache, "a", "{}");
+            cache.clear().unwrap();
+
+            assert_eq!(cache.stats().entries, 0);
+            assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        }
+
+        #[test]
+        fn test_disabled_cache_never_stores() {
+            let cache = Arc::new(ResponseCache::disabled());
+            assert!(cache.slot("a", CacheMode::Default, None).is_none());
+            assert!(cache.lookup("a").is_none());
+            let stats = cache.stats();
+            assert!(!stats.enabled);
+            assert_eq!((stats.hits, stats.misses), (0, 0));
+        }
+    }
+}
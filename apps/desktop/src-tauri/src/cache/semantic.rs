@@ -0,0 +1,556 @@
+//! Semantic layer of the response cache
+//!
+//! Prompts that differ only in wording miss the exact cache. This layer
+//! embeds the conversation through an OpenAI-compatible `/embeddings`
+//! endpoint (a local Ollama by default) and keeps one vector per cached
+//! answer in an append-only JSON lines index. A request is answered from the
+//! entry whose vector is most similar to its own, provided the cosine
+//! similarity reaches the threshold configured for the model.
+//!
+//! Vectors only point at entries of the exact cache, which still owns the
+//! stored answers, their TTL and eviction. Only requests with the same
+//! scope are compared: same provider, route, model and parameters, with
+//! just the prompt allowed to differ.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::request_key;
+use crate::capture::{Message, MessageRole};
+use crate::proxy::Provider;
+use crate::sync::lock;
+
+/// Subdirectory of the cache dir holding the vector index
+pub const SEMANTIC_DIR: &str = "semantic";
+const INDEX_FILE: &str = "index.jsonl";
+
+/// Request fields holding the prompt; everything else must match exactly
+const PROMPT_FIELDS: &[&str] = &[
+    "messages",
+    "system",
+    "prompt",
+    "contents",
+    "systemInstruction",
+];
+
+/// Semantic cache configuration, part of [`super::CacheConfig`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SemanticConfig {
+    pub enabled: bool,
+    /// Base URL of an OpenAI-compatible API, without `/embeddings`
+    pub embeddings_url: String,
+    pub embeddings_model: String,
+    /// Sent as a bearer token when set
+    pub api_key: Option<String>,
+    /// Minimum cosine similarity for models without their own threshold
+    pub threshold: f32,
+    /// Per-model thresholds, keyed by the requested model name
+    pub thresholds: HashMap<String, f32>,
+    /// Vectors kept before the oldest are dropped
+    pub max_entries: usize,
+    pub timeout_ms: u64,
+}
+
+impl Default for SemanticConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            embeddings_url: "http://localhost:11434/v1".to_string(),
+            embeddings_model: "nomic-embed-text".to_string(),
+            api_key: None,
+            threshold: 0.95,
+            thresholds: HashMap::new(),
+            max_entries: 10_000,
+            timeout_ms: 2_000,
+        }
+    }
+}
+
+impl SemanticConfig {
+    /// Returns the similarity a cached answer needs to be served for `model`
+    pub fn threshold_for(&self, model: &str) -> f32 {
+        self.thresholds
+            .get(model)
+            .copied()
+            .unwrap_or(self.threshold)
+    }
+}
+
+/// Embedded prompt of one request
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticQuery {
+    /// Hash of everything but the prompt
+    pub scope: String,
+    pub model: String,
+    /// Unit-length embedding
+    pub vector: Vec<f32>,
+}
+
+/// Best match for a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticMatch {
+    /// Key of the exact cache entry holding the answer
+    pub key: String,
+    pub similarity: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct VectorEntry {
+    key: String,
+    scope: String,
+    model: String,
+    vector: Vec<f32>,
+}
+
+/// Errors raised while embedding a prompt
+#[derive(Debug, thiserror::Error)]
+pub enum EmbeddingError {
+    #[error("embeddings request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("embeddings endpoint returned no vector")]
+    MissingVector,
+}
+
+/// Embedding client and on-disk vector index
+pub struct SemanticCache {
+    config: SemanticConfig,
+    client: reqwest::Client,
+    path: PathBuf,
+    entries: Mutex<Vec<VectorEntry>>,
+}
+
+impl SemanticCache {
+    /// Opens the index in `dir`, loading the vectors stored by earlier runs
+    pub fn open(dir: PathBuf, config: SemanticConfig) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(INDEX_FILE);
+        let mut entries = Vec::new();
+        if let Ok(file) = fs::File::open(&path) {
+            for line in BufReader::new(file).lines() {
+                // A torn last line from a crash is simply dropped
+                if let Ok(entry) = serde_json::from_str::<VectorEntry>(&line?) {
+                    entries.push(entry);
+                }
+            }
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .map_err(io::Error::other)?;
+        let cache = Self {
+            config,
+            client,
+            path,
+            entries: Mutex::new(entries),
+        };
+        cache.rewrite(&mut lock(&cache.entries))?;
+        Ok(cache)
+    }
+
+    /// Returns the configuration the index was opened with
+    pub fn config(&self) -> &SemanticConfig {
+        &self.config
+    }
+
+    /// Embeds the prompt of a request, `None` when it has no text or the
+    /// embeddings endpoint failed
+    #[allow(clippy::too_many_arguments)]
+    pub async fn query(
+        &self,
+        provider: Provider,
+        scope: &str,
+        path_and_query: &str,
+        headers: &HeaderMap,
+        request: &Value,
+        model: &str,
+        messages: &[Message],
+    ) -> Option<SemanticQuery> {
+        let text = prompt_text(messages);
+        if text.is_empty() {
+            return None;
+        }
+        let vector = match self.embed(&text).await {
+            Ok(vector) => vector,
+            Err(e) => {
+                log::warn!("Skipping the semantic cache: {}", e);
+                return None;
+            }
+        };
+        Some(SemanticQuery {
+            scope: scope_key(provider, scope, path_and_query, headers, request),
+            model: model.to_string(),
+            vector: normalize(vector)?,
+        })
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError> {
+        let url = format!(
+            "{}/embeddings",
+            self.config.embeddings_url.trim_end_matches('/')
+        );
+        let mut request = self.client.post(url).json(&json!({
+            "model": self.config.embeddings_model,
+            "input": text,
+        }));
+        if let Some(key) = &self.config.api_key {
+            request = request.bearer_auth(key);
+        }
+        let body: Value = request.send().await?.error_for_status()?.json().await?;
+        body.pointer("/data/0/embedding")
+            .and_then(Value::as_array)
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(Value::as_f64)
+                    .map(|v| v as f32)
+                    .collect()
+            })
+            .ok_or(EmbeddingError::MissingVector)
+    }
+
+    /// Finds the most similar entry of the same scope that passes the model's threshold
+    pub fn search(&self, query: &SemanticQuery) -> Option<SemanticMatch> {
+        let threshold = self.config.threshold_for(&query.model);
+        lock(&self.entries)
+            .iter()
+            .filter(|e| e.scope == query.scope && e.vector.len() == query.vector.len())
+            .map(|e| SemanticMatch {
+                key: e.key.clone(),
+                similarity: dot(&e.vector, &query.vector),
+            })
+            .filter(|m| m.similarity >= threshold)
+            .max_by(|a, b| a.similarity.total_cmp(&b.similarity))
+    }
+
+    /// Indexes the answer stored under `key`
+    pub fn insert(&self, key: &str, query: &SemanticQuery) -> io::Result<()> {
+        let entry = VectorEntry {
+            key: key.to_string(),
+            scope: query.scope.clone(),
+            model: query.model.clone(),
+            vector: query.vector.clone(),
+        };
+        let mut entries = lock(&self.entries);
+        let replaced = entries.iter().any(|e| e.key == key);
+        entries.retain(|e| e.key != key);
+        entries.push(entry.clone());
+        if replaced || entries.len() > self.config.max_entries {
+            return self.rewrite(&mut entries);
+        }
+        let mut file = fs::File::options()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+    }
+
+    /// Drops the vector of an entry the exact cache no longer holds
+    pub fn remove(&self, key: &str) -> io::Result<()> {
+        let mut entries = lock(&self.entries);
+        let before = entries.len();
+        entries.retain(|e| e.key != key);
+        if entries.len() == before {
+            return Ok(());
+        }
+        self.rewrite(&mut entries)
+    }
+
+    /// Removes every vector
+    pub fn clear(&self) -> io::Result<()> {
+        let mut entries = lock(&self.entries);
+        entries.clear();
+        self.rewrite(&mut entries)
+    }
+
+    /// Returns the number of indexed vectors
+    pub fn len(&self) -> usize {
+        lock(&self.entries).len()
+    }
+
+    /// Returns true when no vector is indexed
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Trims the index to its size limit and writes it out in full
+    fn rewrite(&self, entries: &mut Vec<VectorEntry>) -> io::Result<()> {
+        let excess = entries.len().saturating_sub(self.config.max_entries);
+        entries.drain(..excess);
+        let mut out = String::new();
+        for entry in entries.iter() {
+            out.push_str(&serde_json::to_string(entry)?);
+            out.push('\n');
+        }
+        let staging = self.path.with_extension("tmp");
+        fs::write(&staging, out)?;
+        fs::rename(&staging, &self.path)
+    }
+}
+
+/// Hashes a request with its prompt fields removed
+pub fn scope_key(
+    provider: Provider,
+    scope: &str,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    request: &Value,
+) -> String {
+    let mut scoped = request.clone();
+    if let Some(object) = scoped.as_object_mut() {
+        for field in PROMPT_FIELDS {
+            object.remove(*field);
+        }
+    }
+    request_key(provider, scope, path_and_query, headers, &scoped)
+}
+
+/// Flattens a conversation into the text that gets embedded
+fn prompt_text(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .filter_map(|m| {
+            let role = match m.role {
+                MessageRole::System => "system",
+                MessageRole::User => "user",
+                MessageRole::Assistant => "assistant",
+                MessageRole::Tool => "tool",
+            };
+            let content = m.content.as_deref().filter(|c| !c.trim().is_empty())?;
+            Some(format!("{}: {}", role, content))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Scales a vector to unit length so cosine similarity is a dot product
+fn normalize(mut vector: Vec<f32>) -> Option<Vec<f32>> {
+    let norm = dot(&vector, &vector).sqrt();
+    if vector.is_empty() || !norm.is_normal() {
+        return None;
+    }
+    vector.iter_mut().for_each(|v| *v /= norm);
+    Some(vector)
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Stand-in embeddings endpoint for tests
+#[cfg(test)]
+pub(crate) mod testing {
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    /// Words the stub embeds on; anything else is ignored
+    const VOCABULARY: &[&str] = &["capital", "france", "paris", "weather", "rust", "borrow"];
+
+    /// Embeds text as counts of [`VOCABULARY`] words, so rewordings of the
+    /// same question land on the same vector
+    pub fn embeddings_upstream() -> Router {
+        Router::new().route(
+            "/v1/embeddings",
+            post(|Json(body): Json<Value>| async move {
+                let text = body["input"].as_str().unwrap_or_default().to_lowercase();
+                let vector: Vec<f32> = VOCABULARY
+                    .iter()
+                    .map(|word| text.matches(word).count() as f32)
+                    .collect();
+                Json(json!({"data": [{"index": 0, "embedding": vector}]}))
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(scope: &str, model: &str, vector: &[f32]) -> SemanticQuery {
+        SemanticQuery {
+            scope: scope.to_string(),
+            model: model.to_string(),
+            vector: normalize(vector.to_vec()).unwrap(),
+        }
+    }
+
+    fn open(dir: &std::path::Path, config: SemanticConfig) -> SemanticCache {
+        SemanticCache::open(dir.to_path_buf(), config).unwrap()
+    }
+
+    mod index_tests {
+        use super::*;
+
+        #[test]
+        fn test_search_respects_threshold_and_scope() {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = open(dir.path(), SemanticConfig::default());
+            cache
+                .insert("a", &query("s", "gpt-4o", &[1.0, 0.0]))
+                .unwrap();
+            cache
+                .insert("b", &query("s", "gpt-4o", &[1.0, 1.0]))
+                .unwrap();
+
+            let found = cache.search(&query("s", "gpt-4o", &[1.0, 0.05])).unwrap();
+            assert_eq!(found.key, "a");
+            assert!(found.similarity > 0.99);
+            assert!(cache.search(&query("s", "gpt-4o", &[1.0, 0.5])).is_none());
+            assert!(cache
+                .search(&query("other", "gpt-4o", &[1.0, 0.0]))
+                .is_none());
+        }
+
+        #[test]
+        fn test_per_model_threshold() {
+            let dir = tempfile::tempdir().unwrap();
+            let config = SemanticConfig {
+                thresholds: HashMap::from([("llama3.2:3b".to_string(), 0.8)]),
+                ..SemanticConfig::default()
+            };
+            let cache = open(dir.path(), config);
+            cache
+                .insert("a", &query("s", "llama3.2:3b", &[1.0, 0.0]))
+                .unwrap();
+            cache
+                .insert("b", &query("t", "gpt-4o", &[1.0, 0.0]))
+                .unwrap();
+
+            assert!(cache
+                .search(&query("s", "llama3.2:3b", &[1.0, 0.5]))
+                .is_some());
+            assert!(cache.search(&query("t", "gpt-4o", &[1.0, 0.5])).is_none());
+        }
+
+        #[test]
+        fn test_index_survives_reopening() {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = open(dir.path(), SemanticConfig::default());
+            cache.insert("a", &query("s", "m", &[1.0, 0.0])).unwrap();
+            cache.insert("b", &query("s", "m", &[0.0, 1.0])).unwrap();
+            cache.remove("a").unwrap();
+            drop(cache);
+
+            let reopened = open(dir.path(), SemanticConfig::default());
+            assert_eq!(reopened.len(), 1);
+            assert_eq!(
+                reopened.search(&query("s", "m", &[0.0, 1.0])).unwrap().key,
+                "b"
+            );
+        }
+
+        #[test]
+        fn test_oldest_vectors_are_dropped() {
+            let dir = tempfile::tempdir().unwrap();
+            let config = SemanticConfig {
+                max_entries: 2,
+                ..SemanticConfig::default()
+            };
+            let cache = open(dir.path(), config);
+            for (key, vector) in [("a", [1.0, 0.0]), ("b", [0.0, 1.0]), ("c", [1.0, 1.0])] {
+                cache.insert(key, &query("s", "m", &vector)).unwrap();
+            }
+            assert_eq!(cache.len(), 2);
+            assert!(cache.search(&query("s", "m", &[1.0, 0.0])).is_none());
+        }
+    }
+
+    mod query_tests {
+        use super::*;
+        use crate::proxy::testing::spawn_upstream;
+
+        #[test]
+        fn test_scope_ignores_prompt_only() {
+            let headers = HeaderMap::new();
+            let scope = |body: Value| {
+                scope_key(
+                    Provider::OpenAi,
+                    "c",
+                    "/v1/chat/completions",
+                    &headers,
+                    &body,
+                )
+            };
+            let a =
+                scope(json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "a"}]}));
+            let b =
+                scope(json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "b"}]}));
+            let c = scope(json!({"model": "gpt-4o", "temperature": 1, "messages": []}));
+            assert_eq!(a, b);
+            assert_ne!(a, c);
+        }
+
+        #[tokio::test]
+        async fn test_query_embeds_the_conversation() {
+            let upstream = spawn_upstream(testing::embeddings_upstream()).await;
+            let config = SemanticConfig {
+                enabled: true,
+                embeddings_url: format!("{}/v1", upstream),
+                ..SemanticConfig::default()
+            };
+            let dir = tempfile::tempdir().unwrap();
+            let cache = open(dir.path(), config);
+            let messages = vec![Message::text(MessageRole::User, "Capital of France?")];
+
+            let query = cache
+                .query(
+                    Provider::OpenAi,
+                    "c",
+                    "/v1/chat/completions",
+                    &HeaderMap::new(),
+                    &json!({}),
+                    "gpt-4o",
+                    &messages,
+                )
+                .await
+                .unwrap();
+            let expected = normalize(vec![1.0, 1.0, 0.0, 0.0, 0.0, 0.0]).unwrap();
+            assert_eq!(query.vector, expected);
+            assert!(cache
+                .query(
+                    Provider::OpenAi,
+                    "c",
+                    "/v1/chat/completions",
+                    &HeaderMap::new(),
+                    &json!({}),
+                    "gpt-4o",
+                    &[]
+                )
+                .await
+                .is_none());
+        }
+
+        #[tokio::test]
+        async fn test_unreachable_endpoint_skips_the_cache() {
+            let config = SemanticConfig {
+                enabled: true,
+                embeddings_url: "http://127.0.0.1:9/v1".to_string(),
+                ..SemanticConfig::default()
+            };
+            let dir = tempfile::tempdir().unwrap();
+            let cache = open(dir.path(), config);
+            let messages = vec![Message::text(MessageRole::User, "hi")];
+            assert!(cache
+                .query(
+                    Provider::OpenAi,
+                    "c",
+                    "/v1/chat/completions",
+                    &HeaderMap::new(),
+                    &json!({}),
+                    "gpt-4o",
+                    &messages
+                )
+                .await
+                .is_none());
+        }
+    }
+}
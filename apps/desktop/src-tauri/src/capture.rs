@@ -0,0 +1,353 @@
+//! Captured LLM call model shared by every proxy route
+//!
+//! Mirrors the `LLMCall` / `CapturedCall` types in `packages/shared` and
+//! `packages/capture` so the frontend and the TypeScript analysis packages
+//! can consume calls recorded by the desktop proxy without translation.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::events::EventSink;
+use crate::sync::lock;
+
+/// Event emitted to the frontend whenever a call is recorded
+pub const CALL_CAPTURED_EVENT: &str = "call-captured";
+
+/// Default number of calls kept in memory
+pub const DEFAULT_CAPTURE_CAPACITY: usize = 500;
+
+/// Role of a chat message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageRole {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl MessageRole {
+    /// Parses a provider role string, defaulting unknown roles to `User`
+    pub fn parse(role: &str) -> Self {
+        match role {
+            "system" | "developer" => MessageRole::System,
+            "assistant" | "model" => MessageRole::Assistant,
+            "tool" | "function" => MessageRole::Tool,
+            _ => MessageRole::User,
+        }
+    }
+}
+
+/// Function invocation inside a tool call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+/// Tool call requested by the model
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+impl ToolCall {
+    /// Creates a function tool call
+    pub fn function(
+        id: impl Into<String>,
+        name: impl Into<String>,
+        arguments: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            kind: "function".to_string(),
+            function: FunctionCall {
+                name: name.into(),
+                arguments: arguments.into(),
+            },
+        }
+    }
+}
+
+/// A single normalised chat message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: MessageRole,
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Message {
+    /// Creates a plain text message
+    pub fn text(role: MessageRole, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: Some(content.into()),
+            name: None,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+}
+
+/// Sampling parameters sent with the request
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelParameters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+}
+
+impl ModelParameters {
+    /// Returns true when no parameter was set
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Token usage reported for a call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl Usage {
+    /// Creates usage from prompt and completion counts
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+/// The model's answer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapturedResponse {
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+}
+
+/// A single request/response pair that went through the proxy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapturedCall {
+    pub id: String,
+    pub timestamp: String,
+    pub model: String,
+    /// Model the client asked for when the proxy answered with another one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_model: Option<String>,
+    pub provider: String,
+    /// Proxy route the call came in on, e.g. `/v1/chat/completions`
+    pub endpoint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<ModelParameters>,
+    pub messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Value>,
+    pub response: CapturedResponse,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Price in US dollars from the pricing table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    /// End-to-end latency in milliseconds
+    pub latency: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// HTTP status returned to the client
+    pub status: u16,
+    pub stream: bool,
+    /// Client that sent the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    /// Notes attached by pipeline plugins
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub metadata: Map<String, Value>,
+}
+
+impl CapturedCall {
+    /// Creates an empty call for the given provider and route
+    pub fn new(provider: &str, endpoint: &str) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            model: String::new(),
+            requested_model: None,
+            provider: provider.to_string(),
+            endpoint: endpoint.to_string(),
+            parameters: None,
+            messages: Vec::new(),
+            tools: Vec::new(),
+            response: CapturedResponse::default(),
+            usage: None,
+            cost: None,
+            latency: 0,
+            error: None,
+            status: 0,
+            stream: false,
+            client: None,
+            metadata: Map::new(),
+        }
+    }
+}
+
+/// Bounded in-memory log of recent calls
+pub struct CaptureLog {
+    calls: Mutex<VecDeque<CapturedCall>>,
+    capacity: usize,
+    events: Arc<dyn EventSink>,
+}
+
+impl CaptureLog {
+    /// Creates a log holding at most `capacity` calls
+    pub fn new(capacity: usize, events: Arc<dyn EventSink>) -> Self {
+        Self {
+            calls: Mutex::new(VecDeque::with_capacity(
+                capacity.min(DEFAULT_CAPTURE_CAPACITY),
+            )),
+            capacity: capacity.max(1),
+            events,
+        }
+    }
+
+    /// Stores a call, evicting the oldest one when full, and notifies the frontend
+    pub fn record(&self, call: CapturedCall) {
+        if let Ok(payload) = serde_json::to_value(&call) {
+            self.events.emit(CALL_CAPTURED_EVENT, payload);
+        }
+        let mut calls = lock(&self.calls);
+        if calls.len() == self.capacity {
+            calls.pop_front();
+        }
+        calls.push_back(call);
+    }
+
+    /// Returns up to `limit` calls, newest first
+    pub fn recent(&self, limit: usize) -> Vec<CapturedCall> {
+        let calls = lock(&self.calls);
+        calls.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Returns the number of stored calls
+    pub fn len(&self) -> usize {
+        lock(&self.calls).len()
+    }
+
+    /// Returns true when no call has been stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::RecordingSink;
+
+    mod message_role_tests {
+        use super::*;
+
+        #[test]
+        fn test_parse_known_roles() {
+            assert_eq!(MessageRole::parse("system"), MessageRole::System);
+            assert_eq!(MessageRole::parse("developer"), MessageRole::System);
+            assert_eq!(MessageRole::parse("assistant"), MessageRole::Assistant);
+            assert_eq!(MessageRole::parse("model"), MessageRole::Assistant);
+            assert_eq!(MessageRole::parse("tool"), MessageRole::Tool);
+        }
+
+        #[test]
+        fn test_parse_unknown_defaults_to_user() {
+            assert_eq!(MessageRole::parse("user"), MessageRole::User);
+            assert_eq!(MessageRole::parse("narrator"), MessageRole::User);
+        }
+    }
+
+    mod captured_call_tests {
+        use super::*;
+
+        #[test]
+        fn test_serializes_with_shared_field_names() {
+            let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+            call.response.finish_reason = Some("stop".to_string());
+            call.usage = Some(Usage::new(3, 4));
+
+            let value = serde_json::to_value(&call).unwrap();
+            assert_eq!(value["response"]["finishReason"], "stop");
+            assert_eq!(value["usage"]["promptTokens"], 3);
+            assert_eq!(value["usage"]["totalTokens"], 7);
+            assert!(value.get("error").is_none());
+        }
+
+        #[test]
+        fn test_new_generates_unique_ids() {
+            let a = CapturedCall::new("openai", "/v1/models");
+            let b = CapturedCall::new("openai", "/v1/models");
+            assert_ne!(a.id, b.id);
+        }
+    }
+
+    mod capture_log_tests {
+        use super::*;
+
+        #[test]
+        fn test_record_emits_event() {
+            let sink = Arc::new(RecordingSink::default());
+            let log = CaptureLog::new(10, sink.clone());
+            log.record(CapturedCall::new("openai", "/v1/completions"));
+
+            assert_eq!(sink.names(), vec![CALL_CAPTURED_EVENT.to_string()]);
+        }
+
+        #[test]
+        fn test_evicts_oldest_when_full() {
+            let log = CaptureLog::new(2, Arc::new(RecordingSink::default()));
+            for model in ["a", "b", "c"] {
+                let mut call = CapturedCall::new("openai", "/v1/completions");
+                call.model = model.to_string();
+                log.record(call);
+            }
+
+            let models: Vec<_> = log.recent(10).into_iter().map(|c| c.model).collect();
+            assert_eq!(models, vec!["c", "b"]);
+        }
+
+        #[test]
+        fn test_recent_respects_limit() {
+            let log = CaptureLog::new(10, Arc::new(RecordingSink::default()));
+            for _ in 0..5 {
+                log.record(CapturedCall::new("openai", "/v1/completions"));
+            }
+            assert_eq!(log.recent(3).len(), 3);
+            assert_eq!(log.len(), 5);
+        }
+    }
+}
@@ -0,0 +1,559 @@
+//! Virtual API keys for clients of the proxy
+//!
+//! Anything on the machine can reach the localhost proxy. Virtual keys name
+//! the clients that are meant to: one per IDE, one per script. A client
+//! sends its key in place of a provider key, in whichever auth header its
+//! API format uses or, like Gemini clients, as the `key` query parameter.
+//! The proxy attributes the call to the key's name and strips the key
+//! before forwarding. Each key can carry its own daily token budget, the
+//! models it may request and the plugins that run on its calls.
+//!
+//! Only a SHA-256 hash of each key is kept, in [`CLIENT_KEYS_FILE`] under
+//! the app data dir; the key itself is shown once, when it is created. With
+//! `required` set, requests without a valid key are turned away.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use axum::http::{uri::PathAndQuery, HeaderMap, Uri};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::budget::BudgetLimit;
+use crate::routing;
+use crate::sync::lock;
+
+/// File under the app data dir holding the key hashes
+pub const CLIENT_KEYS_FILE: &str = "client-keys.json";
+/// Prefix telling virtual keys apart from provider keys
+pub const KEY_PREFIX: &str = "bbx-";
+/// Key under which the id of the key a call was made with is recorded on it
+pub const METADATA_KEY: &str = "client_key";
+
+/// Headers a client may send its virtual key in
+const KEY_HEADERS: &[&str] = &["authorization", "x-api-key", "x-goog-api-key"];
+/// Query parameter Gemini clients may send their key in
+const KEY_PARAM: &str = "key";
+
+/// Permissions of a new key, sent by the frontend
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientKeyRequest {
+    /// Client name the key's calls are attributed to
+    pub name: String,
+    /// Daily token limits of the client
+    pub budget: BudgetLimit,
+    /// Glob patterns of the models the client may request, any model when empty
+    pub models: Vec<String>,
+    /// Ids of the plugins that run on the client's calls, every enabled one when unset
+    pub plugins: Option<Vec<String>>,
+}
+
+/// A virtual key as listed to the frontend, without the key itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientKey {
+    pub id: String,
+    pub name: String,
+    /// Start of the key, enough to recognise it
+    pub hint: String,
+    /// RFC 3339 creation time
+    pub created_at: String,
+    #[serde(default)]
+    pub budget: BudgetLimit,
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub plugins: Option<Vec<String>>,
+}
+
+impl ClientKey {
+    /// Whether the client may request `model`
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.models.is_empty()
+            || self
+                .models
+                .iter()
+                .any(|pattern| routing::glob_match(pattern, model))
+    }
+}
+
+/// A freshly created key, the only time its plaintext is available
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreatedClientKey {
+    pub key: String,
+    pub client: ClientKey,
+}
+
+/// Keys and whether they are required, returned by `list_client_keys`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientKeyList {
+    pub required: bool,
+    pub keys: Vec<ClientKey>,
+}
+
+/// Client keys as stored on disk
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientKeys {
+    /// Whether requests without a valid key are rejected
+    pub required: bool,
+    pub keys: Vec<StoredKey>,
+}
+
+/// A key's hash next to its settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredKey {
+    /// Hex SHA-256 of the key
+    pub hash: String,
+    #[serde(flatten)]
+    pub client: ClientKey,
+}
+
+/// Result of looking for a virtual key on a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyCheck {
+    /// No virtual key was sent
+    Anonymous,
+    Valid(ClientKey),
+    /// A virtual key was sent but is unknown or revoked
+    Invalid,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientKeyError {
+    #[error("client name must not be empty")]
+    EmptyName,
+    #[error("a key named {0} already exists")]
+    DuplicateName(String),
+    #[error("no client key with id {0}")]
+    UnknownKey(String),
+    #[error("soft limit {soft} is above the hard limit {hard}")]
+    InvalidBudget { soft: u64, hard: u64 },
+    #[error("failed to save {CLIENT_KEYS_FILE}: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to encode {CLIENT_KEYS_FILE}: {0}")]
+    Encode(#[from] serde_json::Error),
+}
+
+/// Issues, checks and revokes virtual keys
+pub struct ClientKeyStore {
+    /// `None` keeps keys in memory only
+    path: Option<PathBuf>,
+    keys: Mutex<ClientKeys>,
+}
+
+impl ClientKeyStore {
+    /// Creates a store that forgets its keys on restart
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            keys: Mutex::new(ClientKeys::default()),
+        }
+    }
+
+    /// Loads the keys saved in `path`, starting empty when there are none
+    pub fn open(path: PathBuf) -> Self {
+        let keys = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable {}: {}", CLIENT_KEYS_FILE, e);
+                ClientKeys::default()
+            }),
+            Err(_) => ClientKeys::default(),
+        };
+        Self {
+            path: Some(path),
+            keys: Mutex::new(keys),
+        }
+    }
+
+    fn save(&self, keys: &ClientKeys) -> Result<(), ClientKeyError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let staging = path.with_extension("tmp");
+        fs::write(&staging, serde_json::to_vec_pretty(keys)?)?;
+        fs::rename(&staging, path)?;
+        Ok(())
+    }
+
+    /// Lists every key, oldest first
+    pub fn list(&self) -> ClientKeyList {
+        let keys = lock(&self.keys);
+        ClientKeyList {
+            required: keys.required,
+            keys: keys
+                .keys
+                .iter()
+                .map(|stored| stored.client.clone())
+                .collect(),
+        }
+    }
+
+    /// Issues a new key, returning its plaintext this one time
+    pub fn create(&self, request: ClientKeyRequest) -> Result<CreatedClientKey, ClientKeyError> {
+        let name = request.name.trim().to_string();
+        if name.is_empty() {
+            return Err(ClientKeyError::EmptyName);
+        }
+        if let (Some(soft), Some(hard)) = (request.budget.soft_tokens, request.budget.hard_tokens) {
+            if soft > hard {
+                return Err(ClientKeyError::InvalidBudget { soft, hard });
+            }
+        }
+        let mut keys = lock(&self.keys);
+        if keys.keys.iter().any(|stored| stored.client.name == name) {
+            return Err(ClientKeyError::DuplicateName(name));
+        }
+
+        let key = format!("{}{}", KEY_PREFIX, uuid::Uuid::new_v4().simple());
+        let client = ClientKey {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            hint: format!("{}…", &key[..KEY_PREFIX.len() + 6]),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            budget: request.budget,
+            models: request.models,
+            plugins: request.plugins,
+        };
+        keys.keys.push(StoredKey {
+            hash: hash_key(&key),
+            client: client.clone(),
+        });
+        if let Err(e) = self.save(&keys) {
+            keys.keys.pop();
+            return Err(e);
+        }
+        Ok(CreatedClientKey { key, client })
+    }
+
+    /// Revokes the key with `id`, its client's requests are rejected from now on
+    pub fn revoke(&self, id: &str) -> Result<ClientKey, ClientKeyError> {
+        let mut keys = lock(&self.keys);
+        let index = keys
+            .keys
+            .iter()
+            .position(|stored| stored.client.id == id)
+            .ok_or_else(|| ClientKeyError::UnknownKey(id.to_string()))?;
+        let revoked = keys.keys.remove(index);
+        if let Err(e) = self.save(&keys) {
+            keys.keys.insert(index, revoked);
+            return Err(e);
+        }
+        Ok(revoked.client)
+    }
+
+    /// Whether requests need a valid key
+    pub fn required(&self) -> bool {
+        lock(&self.keys).required
+    }
+
+    /// Turns the key requirement on or off
+    pub fn set_required(&self, required: bool) -> Result<(), ClientKeyError> {
+        let mut keys = lock(&self.keys);
+        let previous = std::mem::replace(&mut keys.required, required);
+        if let Err(e) = self.save(&keys) {
+            keys.required = previous;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Daily token limits of the clients holding a key, by client name
+    pub fn budgets(&self) -> HashMap<String, BudgetLimit> {
+        lock(&self.keys)
+            .keys
+            .iter()
+            .filter(|stored| stored.client.budget != BudgetLimit::default())
+            .map(|stored| (stored.client.name.clone(), stored.client.budget))
+            .collect()
+    }
+
+    /// Takes the virtual key off a request, rejecting unknown keys and, when keys are
+    /// required, requests without one
+    pub fn admit(
+        &self,
+        headers: &mut HeaderMap,
+        uri: &mut Uri,
+    ) -> Result<Option<ClientKey>, &'static str> {
+        match self.authenticate(headers, uri) {
+            KeyCheck::Valid(key) => Ok(Some(key)),
+            KeyCheck::Anonymous if !self.required() => Ok(None),
+            KeyCheck::Anonymous => Err("This proxy requires a Blackbox client key"),
+            KeyCheck::Invalid => Err("Unknown or revoked Blackbox client key"),
+        }
+    }
+
+    /// Looks for a virtual key on a request and removes it so it never reaches an upstream
+    pub fn authenticate(&self, headers: &mut HeaderMap, uri: &mut Uri) -> KeyCheck {
+        let mut presented = take_query_key(uri);
+        for name in KEY_HEADERS {
+            let key = headers
+                .get(*name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim())
+                .map(|v| v.strip_prefix("Bearer ").unwrap_or(v).trim())
+                .filter(|v| v.starts_with(KEY_PREFIX))
+                .map(str::to_string);
+            if let Some(key) = key {
+                headers.remove(*name);
+                presented.get_or_insert(key);
+            }
+        }
+        let Some(key) = presented else {
+            return KeyCheck::Anonymous;
+        };
+        let hash = hash_key(&key);
+        lock(&self.keys)
+            .keys
+            .iter()
+            .find(|stored| stored.hash == hash)
+            .map_or(KeyCheck::Invalid, |stored| {
+                KeyCheck::Valid(stored.client.clone())
+            })
+    }
+}
+
+/// Removes a virtual key sent as `?key=` from `uri`, leaving provider keys alone
+fn take_query_key(uri: &mut Uri) -> Option<String> {
+    let mut key = None;
+    let kept: Vec<&str> = uri
+        .query()?
+        .split('&')
+        .filter(|pair| match pair.split_once('=') {
+            Some((KEY_PARAM, value)) if value.starts_with(KEY_PREFIX) => {
+                key = Some(value.to_string());
+                false
+            }
+            _ => true,
+        })
+        .collect();
+    let key = key?;
+    let mut path_and_query = uri.path().to_string();
+    if !kept.is_empty() {
+        path_and_query = format!("{}?{}", path_and_query, kept.join("&"));
+    }
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = PathAndQuery::try_from(path_and_query).ok();
+    if let Ok(stripped) = Uri::from_parts(parts) {
+        *uri = stripped;
+    }
+    Some(key)
+}
+
+/// Hex SHA-256 of a key as stored on disk
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn request(name: &str) -> ClientKeyRequest {
+        ClientKeyRequest {
+            name: name.to_string(),
+            ..ClientKeyRequest::default()
+        }
+    }
+
+    mod store_tests {
+        use super::*;
+
+        #[test]
+        fn test_keys_are_stored_hashed() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(CLIENT_KEYS_FILE);
+            let store = ClientKeyStore::open(path.clone());
+            let created = store.create(request("cursor")).unwrap();
+            assert!(created.key.starts_with(KEY_PREFIX));
+            assert!(created
+                .key
+                .starts_with(created.client.hint.trim_end_matches('…')));
+
+            let saved = fs::read_to_string(&path).unwrap();
+            assert!(!saved.contains(&created.key));
+            assert!(saved.contains(&hash_key(&created.key)));
+
+            let reopened = ClientKeyStore::open(path);
+            assert_eq!(reopened.list().keys, vec![created.client]);
+        }
+
+        #[test]
+        fn test_create_validates_the_request() {
+            let store = ClientKeyStore::in_memory();
+            store.create(request("cursor")).unwrap();
+            assert!(matches!(
+                store.create(request(" cursor ")),
+                Err(ClientKeyError::DuplicateName(_))
+            ));
+            assert!(matches!(
+                store.create(request("  ")),
+                Err(ClientKeyError::EmptyName)
+            ));
+            let overdrawn = ClientKeyRequest {
+                budget: BudgetLimit {
+                    soft_tokens: Some(10),
+                    hard_tokens: Some(5),
+                },
+                ..request("script")
+            };
+            assert!(matches!(
+                store.create(overdrawn),
+                Err(ClientKeyError::InvalidBudget { soft: 10, hard: 5 })
+            ));
+        }
+
+        #[test]
+        fn test_revoke_and_budgets() {
+            let store = ClientKeyStore::in_memory();
+            let limit = BudgetLimit {
+                soft_tokens: None,
+                hard_tokens: Some(100),
+            };
+            let cursor = store
+                .create(ClientKeyRequest {
+                    budget: limit,
+                    ..request("cursor")
+                })
+                .unwrap();
+            store.create(request("script")).unwrap();
+            assert_eq!(
+                store.budgets(),
+                HashMap::from([("cursor".to_string(), limit)])
+            );
+
+            assert_eq!(store.revoke(&cursor.client.id).unwrap().name, "cursor");
+            assert!(store.budgets().is_empty());
+            assert!(matches!(
+                store.revoke(&cursor.client.id),
+                Err(ClientKeyError::UnknownKey(_))
+            ));
+        }
+
+        #[test]
+        fn test_models_are_glob_patterns() {
+            let mut key = ClientKeyStore::in_memory()
+                .create(request("cursor"))
+                .unwrap()
+                .client;
+            assert!(key.allows_model("gpt-4o"));
+            key.models = vec!["gpt-4o*".to_string(), "ollama/*".to_string()];
+            assert!(key.allows_model("gpt-4o-mini"));
+            assert!(key.allows_model("ollama/llama3.2"));
+            assert!(!key.allows_model("claude-sonnet-4"));
+        }
+    }
+
+    mod authenticate_tests {
+        use super::*;
+
+        #[test]
+        fn test_key_is_found_in_any_auth_header_and_removed() {
+            let store = ClientKeyStore::in_memory();
+            let created = store.create(request("cursor")).unwrap();
+
+            let mut headers = HeaderMap::new();
+            let bearer = format!("Bearer {}", created.key);
+            headers.insert("authorization", HeaderValue::from_str(&bearer).unwrap());
+            assert_eq!(
+                store.authenticate(&mut headers, &mut Uri::default()),
+                KeyCheck::Valid(created.client.clone())
+            );
+            assert!(headers.is_empty());
+
+            headers.insert("x-api-key", HeaderValue::from_str(&created.key).unwrap());
+            assert_eq!(
+                store.authenticate(&mut headers, &mut Uri::default()),
+                KeyCheck::Valid(created.client)
+            );
+        }
+
+        #[test]
+        fn test_query_key_is_found_and_removed() {
+            let store = ClientKeyStore::in_memory();
+            let created = store.create(request("gemini-cli")).unwrap();
+            let mut uri: Uri = format!(
+                "/v1beta/models/gemini:streamGenerateContent?key={}&alt=sse",
+                created.key
+            )
+            .parse()
+            .unwrap();
+            assert_eq!(
+                store.authenticate(&mut HeaderMap::new(), &mut uri),
+                KeyCheck::Valid(created.client)
+            );
+            assert_eq!(uri, "/v1beta/models/gemini:streamGenerateContent?alt=sse");
+
+            let mut uri: Uri = "/v1beta/models/gemini:generateContent?key=AIza123"
+                .parse()
+                .unwrap();
+            assert_eq!(
+                store.authenticate(&mut HeaderMap::new(), &mut uri),
+                KeyCheck::Anonymous
+            );
+            assert_eq!(uri.query(), Some("key=AIza123"));
+        }
+
+        #[test]
+        fn test_provider_keys_are_left_alone() {
+            let store = ClientKeyStore::in_memory();
+            let mut headers = HeaderMap::new();
+            headers.insert("authorization", HeaderValue::from_static("Bearer sk-abc"));
+            assert_eq!(
+                store.authenticate(&mut headers, &mut Uri::default()),
+                KeyCheck::Anonymous
+            );
+            assert_eq!(headers.len(), 1);
+        }
+
+        #[test]
+        fn test_revoked_key_is_invalid() {
+            let store = ClientKeyStore::in_memory();
+            let created = store.create(request("cursor")).unwrap();
+            store.revoke(&created.client.id).unwrap();
+
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "x-goog-api-key",
+                HeaderValue::from_str(&created.key).unwrap(),
+            );
+            assert_eq!(
+                store.authenticate(&mut headers, &mut Uri::default()),
+                KeyCheck::Invalid
+            );
+            assert!(headers.is_empty());
+        }
+
+        #[test]
+        fn test_required_keys_turn_away_anonymous_requests() {
+            let store = ClientKeyStore::in_memory();
+            let created = store.create(request("cursor")).unwrap();
+            let anonymous = || {
+                let mut headers = HeaderMap::new();
+                headers.insert("authorization", HeaderValue::from_static("Bearer sk-abc"));
+                headers
+            };
+            assert_eq!(store.admit(&mut anonymous(), &mut Uri::default()), Ok(None));
+
+            store.set_required(true).unwrap();
+            assert!(store.admit(&mut anonymous(), &mut Uri::default()).is_err());
+            let mut uri: Uri = format!("/v1/models?key={}", created.key).parse().unwrap();
+            assert_eq!(
+                store.admit(&mut HeaderMap::new(), &mut uri),
+                Ok(Some(created.client.clone()))
+            );
+
+            store.revoke(&created.client.id).unwrap();
+            let mut uri: Uri = format!("/v1/models?key={}", created.key).parse().unwrap();
+            let rejected = store.admit(&mut HeaderMap::new(), &mut uri);
+            assert!(rejected.unwrap_err().contains("revoked"));
+        }
+    }
+}
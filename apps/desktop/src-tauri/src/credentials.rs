@@ -0,0 +1,712 @@
+//! Encrypted store for upstream API keys
+//!
+//! Provider keys such as `OPENAI_API_KEY` or `GITHUB_TOKEN` are kept in
+//! [`CREDENTIALS_FILE`] under the app data dir, each sealed with AES-256-GCM
+//! and bound to its name. The master key is either generated and kept next
+//! to the store in [`MASTER_KEY_FILE`], readable by the user only, or
+//! derived from a passphrase with Argon2id. A passphrase-protected store
+//! starts locked and must be unlocked before its keys can be used.
+//!
+//! Upstreams use a stored key with `auth = { type = "credential", name = "..." }`
+//! and the proxy decrypts it only when forwarding a request. Commands report
+//! names and dates; the plaintext never goes back to the webview.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use axum::http::{header, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::{Provider, DEFAULT_ANTHROPIC_VERSION};
+use crate::routing::{Upstream, UpstreamKind};
+use crate::sync::lock;
+
+/// Encrypted credentials under the app data dir
+pub const CREDENTIALS_FILE: &str = "credentials.json";
+/// Generated master key under the app data dir, unused with a passphrase
+pub const MASTER_KEY_FILE: &str = "credentials.key";
+
+/// Sealed with the master key so a wrong passphrase is noticed at once
+const CHECK_PLAINTEXT: &[u8] = b"blackbox-credentials";
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+/// How the master key is obtained
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Protection {
+    /// Random key in [`MASTER_KEY_FILE`]
+    KeyFile,
+    /// Argon2id of a passphrase and this hex salt
+    Passphrase { salt: String },
+}
+
+/// Ciphertext with its nonce, both hex
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Sealed {
+    nonce: String,
+    data: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    sealed: Sealed,
+    updated_at: String,
+}
+
+/// Layout of [`CREDENTIALS_FILE`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Vault {
+    protection: Protection,
+    check: Sealed,
+    #[serde(default)]
+    entries: BTreeMap<String, Entry>,
+}
+
+/// A stored credential as shown in the UI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialInfo {
+    pub name: String,
+    /// RFC 3339 time of the last change
+    pub updated_at: String,
+}
+
+/// State of the store, returned by `get_credentials`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialStatus {
+    /// `key_file` or `passphrase`
+    pub protection: String,
+    pub locked: bool,
+    pub credentials: Vec<CredentialInfo>,
+}
+
+/// Result of trying a credential against its service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialTest {
+    pub ok: bool,
+    /// URL that was asked
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CredentialError {
+    #[error("the credential store is locked, unlock it with its passphrase")]
+    Locked,
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+    #[error("passphrase must not be empty")]
+    EmptyPassphrase,
+    #[error("no credential named {0}")]
+    NotFound(String),
+    #[error("invalid credential name {0:?}, use letters, digits, '_', '-' and '.'")]
+    InvalidName(String),
+    #[error("credential {0} must not be empty")]
+    EmptyValue(String),
+    #[error("{CREDENTIALS_FILE} is damaged: {0}")]
+    Corrupt(String),
+    #[error("failed to access the credential store: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to encode {CREDENTIALS_FILE}: {0}")]
+    Encode(#[from] serde_json::Error),
+}
+
+struct State {
+    vault: Vault,
+    /// `None` while locked
+    key: Option<[u8; KEY_LEN]>,
+}
+
+/// Keeps upstream secrets encrypted at rest
+pub struct CredentialStore {
+    /// `None` keeps everything in memory
+    dir: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+impl CredentialStore {
+    /// Creates an unlocked store that forgets its credentials on restart
+    pub fn in_memory() -> Self {
+        let key = random_key();
+        Self {
+            dir: None,
+            state: Mutex::new(State {
+                vault: Vault::new(Protection::KeyFile, &key),
+                key: Some(key),
+            }),
+        }
+    }
+
+    /// Opens the store in `dir`, creating it with a generated master key on first use
+    pub fn open(dir: PathBuf) -> Result<Self, CredentialError> {
+        let path = dir.join(CREDENTIALS_FILE);
+        let state = match fs::read(&path) {
+            Ok(bytes) => {
+                let vault: Vault = serde_json::from_slice(&bytes)
+                    .map_err(|e| CredentialError::Corrupt(e.to_string()))?;
+                let key = match vault.protection {
+                    Protection::KeyFile => Some(read_key_file(&dir.join(MASTER_KEY_FILE))?),
+                    Protection::Passphrase { .. } => None,
+                };
+                if let Some(key) = &key {
+                    vault.verify(key)?;
+                }
+                State { vault, key }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir_all(&dir)?;
+                let key = random_key();
+                write_private(&dir.join(MASTER_KEY_FILE), &key)?;
+                let state = State {
+                    vault: Vault::new(Protection::KeyFile, &key),
+                    key: Some(key),
+                };
+                write_private(&path, &serde_json::to_vec_pretty(&state.vault)?)?;
+                state
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            dir: Some(dir),
+            state: Mutex::new(state),
+        })
+    }
+
+    fn save(&self, vault: &Vault) -> Result<(), CredentialError> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let staging = dir.join(CREDENTIALS_FILE).with_extension("tmp");
+        write_private(&staging, &serde_json::to_vec_pretty(vault)?)?;
+        fs::rename(&staging, dir.join(CREDENTIALS_FILE))?;
+        Ok(())
+    }
+
+    /// Lists the stored names and whether the store is locked
+    pub fn status(&self) -> CredentialStatus {
+        let state = lock(&self.state);
+        CredentialStatus {
+            protection: match state.vault.protection {
+                Protection::KeyFile => "key_file",
+                Protection::Passphrase { .. } => "passphrase",
+            }
+            .to_string(),
+            locked: state.key.is_none(),
+            credentials: state
+                .vault
+                .entries
+                .iter()
+                .map(|(name, entry)| CredentialInfo {
+                    name: name.clone(),
+                    updated_at: entry.updated_at.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Unlocks a passphrase-protected store
+    pub fn unlock(&self, passphrase: &str) -> Result<(), CredentialError> {
+        let mut state = lock(&self.state);
+        let Protection::Passphrase { salt } = &state.vault.protection else {
+            return Ok(());
+        };
+        let key = derive_key(passphrase, &from_hex(salt)?)?;
+        state.vault.verify(&key)?;
+        state.key = Some(key);
+        Ok(())
+    }
+
+    /// Forgets the master key of a passphrase-protected store until the next unlock
+    pub fn lock(&self) {
+        let mut state = lock(&self.state);
+        if matches!(state.vault.protection, Protection::Passphrase { .. }) {
+            state.key = None;
+        }
+    }
+
+    /// Re-encrypts every credential under a passphrase, or under a generated key for `None`
+    pub fn set_passphrase(&self, passphrase: Option<&str>) -> Result<(), CredentialError> {
+        let mut state = lock(&self.state);
+        let old_key = state.key.ok_or(CredentialError::Locked)?;
+        let (protection, key) = match passphrase {
+            Some("") => return Err(CredentialError::EmptyPassphrase),
+            Some(passphrase) => {
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                let key = derive_key(passphrase, &salt)?;
+                let salt = to_hex(&salt);
+                (Protection::Passphrase { salt }, key)
+            }
+            None => (Protection::KeyFile, random_key()),
+        };
+
+        let mut vault = Vault::new(protection, &key);
+        for (name, entry) in &state.vault.entries {
+            let plaintext = open(&old_key, name, &entry.sealed)?;
+            let entry = Entry {
+                sealed: seal(&key, name, &plaintext),
+                updated_at: entry.updated_at.clone(),
+            };
+            vault.entries.insert(name.clone(), entry);
+        }
+        let key_file = self.dir.as_ref().map(|dir| dir.join(MASTER_KEY_FILE));
+        if let (Some(path), Protection::KeyFile) = (&key_file, &vault.protection) {
+            write_private(path, &key)?;
+        }
+        self.save(&vault)?;
+        if let (Some(path), Protection::Passphrase { .. }) = (&key_file, &vault.protection) {
+            // The old key could otherwise still decrypt copies of the previous file
+            if let Err(e) = fs::remove_file(path) {
+                log::warn!("Failed to remove {}: {}", MASTER_KEY_FILE, e);
+            }
+        }
+        *state = State {
+            vault,
+            key: Some(key),
+        };
+        Ok(())
+    }
+
+    /// Stores or replaces a credential
+    pub fn set(&self, name: &str, value: &str) -> Result<CredentialInfo, CredentialError> {
+        validate_name(name)?;
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(CredentialError::EmptyValue(name.to_string()));
+        }
+        let mut state = lock(&self.state);
+        let key = state.key.ok_or(CredentialError::Locked)?;
+        let mut vault = state.vault.clone();
+        let entry = Entry {
+            sealed: seal(&key, name, value.as_bytes()),
+            updated_at: chrono::Utc::now().to_rfc3339(),
+        };
+        let info = CredentialInfo {
+            name: name.to_string(),
+            updated_at: entry.updated_at.clone(),
+        };
+        vault.entries.insert(name.to_string(), entry);
+        self.save(&vault)?;
+        state.vault = vault;
+        Ok(info)
+    }
+
+    /// Removes a credential
+    pub fn delete(&self, name: &str) -> Result<(), CredentialError> {
+        let mut state = lock(&self.state);
+        let mut vault = state.vault.clone();
+        if vault.entries.remove(name).is_none() {
+            return Err(CredentialError::NotFound(name.to_string()));
+        }
+        self.save(&vault)?;
+        state.vault = vault;
+        Ok(())
+    }
+
+    /// Decrypts a credential for use in an upstream request
+    pub fn get(&self, name: &str) -> Result<String, CredentialError> {
+        let state = lock(&self.state);
+        let entry = state
+            .vault
+            .entries
+            .get(name)
+            .ok_or_else(|| CredentialError::NotFound(name.to_string()))?;
+        let key = state.key.ok_or(CredentialError::Locked)?;
+        let plaintext = open(&key, name, &entry.sealed)?;
+        String::from_utf8(plaintext).map_err(|e| CredentialError::Corrupt(e.to_string()))
+    }
+
+    /// Tries credential `name` against `upstream`, or the service its name suggests
+    pub async fn test(
+        &self,
+        client: &reqwest::Client,
+        name: &str,
+        upstream: Option<&Upstream>,
+    ) -> Result<CredentialTest, CredentialError> {
+        let secret = self.get(name)?;
+        let Some(probe) = Probe::new(name, upstream, &secret) else {
+            return Ok(CredentialTest {
+                ok: false,
+                url: String::new(),
+                status: None,
+                message: format!("No known service for {}, pick an upstream to test it", name),
+            });
+        };
+        let url = probe.url.clone();
+        let result = client
+            .get(&probe.url)
+            .headers(probe.headers.into_iter().collect())
+            .send()
+            .await;
+        Ok(match result {
+            Ok(response) => {
+                let status = response.status();
+                CredentialTest {
+                    ok: status.is_success(),
+                    url,
+                    status: Some(status.as_u16()),
+                    message: match status.as_u16() {
+                        200..=299 => "Accepted".to_string(),
+                        401 | 403 => "Rejected, the key is invalid or lacks access".to_string(),
+                        _ => format!("Unexpected answer {}", status),
+                    },
+                }
+            }
+            Err(e) => CredentialTest {
+                ok: false,
+                url,
+                status: None,
+                message: format!("Could not reach the service: {}", e),
+            },
+        })
+    }
+}
+
+/// Cheap authenticated request checking whether a key is accepted
+struct Probe {
+    url: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Probe {
+    fn new(name: &str, upstream: Option<&Upstream>, secret: &str) -> Option<Self> {
+        let guessed;
+        let upstream = match upstream {
+            Some(upstream) => upstream,
+            None if name.starts_with("GITHUB") => {
+                let mut headers = vec![(header::USER_AGENT, HeaderValue::from_static("Blackbox"))];
+                headers.extend(Provider::OpenAi.auth_header(secret));
+                return Some(Self {
+                    url: "https://api.github.com/user".to_string(),
+                    headers,
+                });
+            }
+            None => {
+                guessed = guess_upstream(name)?;
+                &guessed
+            }
+        };
+        let (provider, path) = match upstream.kind {
+            UpstreamKind::Anthropic => (Provider::Anthropic, "v1/models"),
+            UpstreamKind::Gemini => (Provider::Gemini, "v1beta/models"),
+            UpstreamKind::OpenAi | UpstreamKind::Ollama | UpstreamKind::LiteLlm => {
+                (Provider::OpenAi, "v1/models")
+            }
+        };
+        let mut headers: Vec<_> = provider.auth_header(secret).into_iter().collect();
+        if provider == Provider::Anthropic {
+            headers.push((
+                HeaderName::from_static("anthropic-version"),
+                HeaderValue::from_static(DEFAULT_ANTHROPIC_VERSION),
+            ));
+        }
+        Some(Self {
+            url: upstream.endpoint(path),
+            headers,
+        })
+    }
+}
+
+/// Official API of the provider a conventional key name belongs to
+fn guess_upstream(name: &str) -> Option<Upstream> {
+    let (kind, base_url) = match name.split('_').next()? {
+        "OPENAI" => (UpstreamKind::OpenAi, "https://api.openai.com"),
+        "ANTHROPIC" => (UpstreamKind::Anthropic, "https://api.anthropic.com"),
+        "GEMINI" | "GOOGLE" => (
+            UpstreamKind::Gemini,
+            "https://generativelanguage.googleapis.com",
+        ),
+        _ => return None,
+    };
+    Some(Upstream {
+        name: name.to_string(),
+        base_url: base_url.to_string(),
+        kind,
+        auth: Default::default(),
+    })
+}
+
+impl Vault {
+    fn new(protection: Protection, key: &[u8; KEY_LEN]) -> Self {
+        Self {
+            protection,
+            check: seal(key, "", CHECK_PLAINTEXT),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    fn verify(&self, key: &[u8; KEY_LEN]) -> Result<(), CredentialError> {
+        match open(key, "", &self.check) {
+            Ok(check) if check == CHECK_PLAINTEXT => Ok(()),
+            _ => Err(CredentialError::WrongPassphrase),
+        }
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), CredentialError> {
+    let valid = !name.is_empty()
+        && name.len() <= 128
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(CredentialError::InvalidName(name.to_string()))
+    }
+}
+
+fn random_key() -> [u8; KEY_LEN] {
+    Aes256Gcm::generate_key(OsRng).into()
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], CredentialError> {
+    if passphrase.is_empty() {
+        return Err(CredentialError::EmptyPassphrase);
+    }
+    let mut key = [0u8; KEY_LEN];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| CredentialError::Corrupt(e.to_string()))?;
+    Ok(key)
+}
+
+/// Encrypts `plaintext`, authenticating `name` with it so entries cannot be swapped
+fn seal(key: &[u8; KEY_LEN], name: &str, plaintext: &[u8]) -> Sealed {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: plaintext,
+        aad: name.as_bytes(),
+    };
+    let data = cipher
+        .encrypt(&nonce, payload)
+        .expect("AES-GCM encryption of an in-memory buffer cannot fail");
+    Sealed {
+        nonce: to_hex(&nonce),
+        data: to_hex(&data),
+    }
+}
+
+fn open(key: &[u8; KEY_LEN], name: &str, sealed: &Sealed) -> Result<Vec<u8>, CredentialError> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = from_hex(&sealed.nonce)?;
+    if nonce.len() != 12 {
+        return Err(CredentialError::Corrupt(format!("bad nonce for {}", name)));
+    }
+    let payload = Payload {
+        msg: &from_hex(&sealed.data)?,
+        aad: name.as_bytes(),
+    };
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), payload)
+        .map_err(|_| CredentialError::Corrupt(format!("cannot decrypt {:?}", name)))
+}
+
+fn read_key_file(path: &Path) -> Result<[u8; KEY_LEN], CredentialError> {
+    fs::read(path)?
+        .try_into()
+        .map_err(|_| CredentialError::Corrupt(format!("{} has the wrong length", MASTER_KEY_FILE)))
+}
+
+/// Writes a file only the current user can read
+pub(crate) fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` only applies to new files
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    io::Write::write_all(&mut options.open(path)?, bytes)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, CredentialError> {
+    let corrupt = || CredentialError::Corrupt("invalid hex".to_string());
+    if !hex.len().is_multiple_of(2) {
+        return Err(corrupt());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(hex.get(i..i + 2).ok_or_else(corrupt)?, 16).map_err(|_| corrupt())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod store_tests {
+        use super::*;
+
+        #[test]
+        fn test_credentials_are_encrypted_at_rest() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CredentialStore::open(dir.path().to_path_buf()).unwrap();
+            store.set("OPENAI_API_KEY", " sk-secret-value ").unwrap();
+
+            let saved = fs::read_to_string(dir.path().join(CREDENTIALS_FILE)).unwrap();
+            assert!(saved.contains("OPENAI_API_KEY"));
+            assert!(!saved.contains("sk-secret-value"));
+
+            let reopened = CredentialStore::open(dir.path().to_path_buf()).unwrap();
+            assert_eq!(reopened.get("OPENAI_API_KEY").unwrap(), "sk-secret-value");
+            let status = reopened.status();
+            assert_eq!(status.protection, "key_file");
+            assert!(!status.locked);
+            assert_eq!(status.credentials[0].name, "OPENAI_API_KEY");
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn test_files_are_private() {
+            use std::os::unix::fs::PermissionsExt;
+            let dir = tempfile::tempdir().unwrap();
+            let store = CredentialStore::open(dir.path().to_path_buf()).unwrap();
+            store.set("GITHUB_TOKEN", "ghp_x").unwrap();
+            for file in [CREDENTIALS_FILE, MASTER_KEY_FILE] {
+                let mode = fs::metadata(dir.path().join(file))
+                    .unwrap()
+                    .permissions()
+                    .mode();
+                assert_eq!(mode & 0o777, 0o600, "{}", file);
+            }
+        }
+
+        #[test]
+        fn test_passphrase_locks_the_store() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CredentialStore::open(dir.path().to_path_buf()).unwrap();
+            store.set("ANTHROPIC_API_KEY", "sk-ant-1").unwrap();
+            store.set_passphrase(Some("correct horse")).unwrap();
+            assert!(!dir.path().join(MASTER_KEY_FILE).exists());
+
+            let reopened = CredentialStore::open(dir.path().to_path_buf()).unwrap();
+            assert!(reopened.status().locked);
+            assert!(matches!(
+                reopened.get("ANTHROPIC_API_KEY"),
+                Err(CredentialError::Locked)
+            ));
+            assert!(matches!(
+                reopened.unlock("wrong"),
+                Err(CredentialError::WrongPassphrase)
+            ));
+            reopened.unlock("correct horse").unwrap();
+            assert_eq!(reopened.get("ANTHROPIC_API_KEY").unwrap(), "sk-ant-1");
+
+            reopened.set_passphrase(None).unwrap();
+            let reopened = CredentialStore::open(dir.path().to_path_buf()).unwrap();
+            assert!(!reopened.status().locked);
+            assert_eq!(reopened.get("ANTHROPIC_API_KEY").unwrap(), "sk-ant-1");
+        }
+
+        #[test]
+        fn test_entries_cannot_be_swapped() {
+            let store = CredentialStore::in_memory();
+            store.set("A", "first").unwrap();
+            store.set("B", "second").unwrap();
+            {
+                let mut state = lock(&store.state);
+                let a = state.vault.entries["A"].clone();
+                state.vault.entries.insert("B".to_string(), a);
+            }
+            assert!(matches!(store.get("B"), Err(CredentialError::Corrupt(_))));
+        }
+
+        #[test]
+        fn test_names_and_values_are_validated() {
+            let store = CredentialStore::in_memory();
+            assert!(matches!(
+                store.set("bad name", "x"),
+                Err(CredentialError::InvalidName(_))
+            ));
+            assert!(matches!(
+                store.set("EMPTY", "  "),
+                Err(CredentialError::EmptyValue(_))
+            ));
+            assert!(matches!(
+                store.delete("MISSING"),
+                Err(CredentialError::NotFound(_))
+            ));
+        }
+    }
+
+    mod test_tests {
+        use super::*;
+        use crate::proxy::testing::spawn_upstream;
+        use crate::routing::RouteAuth;
+        use axum::{http::HeaderMap, http::StatusCode, routing::get, Router};
+
+        #[tokio::test]
+        async fn test_probe_uses_the_upstream_format() {
+            let models = Router::new().route(
+                "/v1/models",
+                get(|headers: HeaderMap| async move {
+                    match headers.get("x-api-key").map(|v| v.to_str().unwrap()) {
+                        Some("sk-ant-good") if headers.contains_key("anthropic-version") => {
+                            StatusCode::OK
+                        }
+                        _ => StatusCode::UNAUTHORIZED,
+                    }
+                }),
+            );
+            let upstream = Upstream {
+                name: "claude".to_string(),
+                base_url: spawn_upstream(models).await,
+                kind: UpstreamKind::Anthropic,
+                auth: RouteAuth::None,
+            };
+            let store = CredentialStore::in_memory();
+            let client = reqwest::Client::new();
+
+            store.set("ANTHROPIC_API_KEY", "sk-ant-good").unwrap();
+            let test = store
+                .test(&client, "ANTHROPIC_API_KEY", Some(&upstream))
+                .await
+                .unwrap();
+            assert!(test.ok, "{:?}", test);
+
+            store.set("ANTHROPIC_API_KEY", "sk-ant-bad").unwrap();
+            let test = store
+                .test(&client, "ANTHROPIC_API_KEY", Some(&upstream))
+                .await
+                .unwrap();
+            assert_eq!((test.ok, test.status), (false, Some(401)));
+            assert!(!test.message.contains("sk-ant"));
+        }
+
+        #[tokio::test]
+        async fn test_unknown_service_needs_an_upstream() {
+            let store = CredentialStore::in_memory();
+            store.set("MY_TOKEN", "x").unwrap();
+            let test = store
+                .test(&reqwest::Client::new(), "MY_TOKEN", None)
+                .await
+                .unwrap();
+            assert!(!test.ok);
+            assert_eq!(
+                guess_upstream("OPENAI_API_KEY").unwrap().kind,
+                UpstreamKind::OpenAi
+            );
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Event delivery from background subsystems to the frontend
+//!
+//! The proxy and its helpers run outside of Tauri command handlers, so they
+//! publish through this trait instead of holding an `AppHandle` directly.
+//! `run()` wires the real app handle in; tests use [`RecordingSink`].
+
+use serde_json::Value;
+
+/// Receives named events destined for the webview
+pub trait EventSink: Send + Sync {
+    /// Publishes an event with a JSON payload
+    fn emit(&self, event: &str, payload: Value);
+}
+
+/// Sink that drops every event
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopSink;
+
+impl EventSink for NoopSink {
+    fn emit(&self, _event: &str, _payload: Value) {}
+}
+
+/// Sink that keeps every event in memory, for tests
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingSink {
+    events: std::sync::Mutex<Vec<(String, Value)>>,
+}
+
+#[cfg(test)]
+impl RecordingSink {
+    /// Returns the names of all recorded events in order
+    pub fn names(&self) -> Vec<String> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Returns all recorded events in order
+    pub fn events(&self) -> Vec<(String, Value)> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl EventSink for RecordingSink {
+    fn emit(&self, event: &str, payload: Value) {
+        self.events
+            .lock()
+            .unwrap()
+            .push((event.to_string(), payload));
+    }
+}
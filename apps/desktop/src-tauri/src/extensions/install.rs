@@ -0,0 +1,518 @@
+//! Installing extensions into the app data dir
+//!
+//! An extension is installed by copying its directory, or extracting its
+//! zip, into `<app data>/extensions/<id>/` after its manifest validated.
+//! Installed extensions are activated right away and again on every
+//! launch by [`ExtensionStore::load_all`].
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+use super::manifest::{self, Entrypoint, ExtensionManifest, ManifestIssue};
+use super::process::ProcessExtension;
+use super::wasm::WasmHost;
+use super::{ExtensionError, ExtensionPlugin};
+use crate::pipeline::{ExtensionKind, Pipeline};
+
+/// Directories created while extracting a zip start with this prefix
+const STAGING_PREFIX: &str = ".staging-";
+
+/// Why an extension could not be installed or removed
+#[derive(Debug, thiserror::Error)]
+pub enum InstallError {
+    #[error("no blackbox-extension.toml or blackbox-extension.json found in {path}")]
+    ManifestNotFound { path: String },
+    #[error("could not parse {path}: {message}")]
+    ManifestSyntax { path: String, message: String },
+    #[error("invalid manifest: {}", describe(.issues))]
+    InvalidManifest { issues: Vec<ManifestIssue> },
+    #[error("extension {id} is already installed")]
+    AlreadyInstalled { id: String },
+    #[error("extension {id} is not installed")]
+    NotInstalled { id: String },
+    #[error("archive entry {path} would be extracted outside the extension")]
+    UnsafeArchive { path: String },
+    #[error("extension {id} could not be started: {message}")]
+    Activation { id: String, message: String },
+    #[error("{message}")]
+    Io { message: String },
+}
+
+fn describe(issues: &[ManifestIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| format!("{} {}", issue.field, issue.message))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl InstallError {
+    /// Machine-readable error kind sent to the frontend
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InstallError::ManifestNotFound { .. } => "manifest_not_found",
+            InstallError::ManifestSyntax { .. } => "manifest_syntax",
+            InstallError::InvalidManifest { .. } => "invalid_manifest",
+            InstallError::AlreadyInstalled { .. } => "already_installed",
+            InstallError::NotInstalled { .. } => "not_installed",
+            InstallError::UnsafeArchive { .. } => "unsafe_archive",
+            InstallError::Activation { .. } => "activation_failed",
+            InstallError::Io { .. } => "io",
+        }
+    }
+}
+
+impl From<io::Error> for InstallError {
+    fn from(error: io::Error) -> Self {
+        InstallError::Io {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for InstallError {
+    fn from(error: zip::result::ZipError) -> Self {
+        InstallError::Io {
+            message: format!("invalid zip archive: {}", error),
+        }
+    }
+}
+
+/// Serialized as `{kind, message, issues?}` so the frontend can point at bad fields
+impl Serialize for InstallError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("InstallError", 3)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        match self {
+            InstallError::InvalidManifest { issues } => state.serialize_field("issues", issues)?,
+            _ => state.skip_field("issues")?,
+        }
+        state.end()
+    }
+}
+
+/// Installed extensions on disk
+pub struct ExtensionStore {
+    root: PathBuf,
+    wasm: WasmHost,
+}
+
+impl ExtensionStore {
+    /// Opens the store rooted at `root`, usually `<app data>/extensions`
+    pub fn new(root: PathBuf) -> Result<Self, InstallError> {
+        fs::create_dir_all(&root)?;
+        let wasm = WasmHost::new().map_err(|e| InstallError::Io {
+            message: e.to_string(),
+        })?;
+        Ok(Self { root, wasm })
+    }
+
+    /// Directory extensions are installed into
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// WASM engine used for installed and loose modules
+    pub fn wasm_host(&self) -> &WasmHost {
+        &self.wasm
+    }
+
+    /// Reads and validates the manifest of an extension directory
+    pub fn read_manifest(dir: &Path) -> Result<ExtensionManifest, InstallError> {
+        let path = manifest::find_manifest(dir).ok_or_else(|| InstallError::ManifestNotFound {
+            path: dir.display().to_string(),
+        })?;
+        let text = fs::read_to_string(&path)?;
+        let json = path.extension().is_some_and(|ext| ext == "json");
+        let manifest = manifest::parse(&text, json)
+            .map_err(|message| InstallError::ManifestSyntax {
+                path: path.display().to_string(),
+                message,
+            })?
+            .map_err(|issues| InstallError::InvalidManifest { issues })?;
+
+        if let Entrypoint::Wasm { path: module, .. } = &manifest.entrypoint {
+            if !dir.join(module).is_file() {
+                return Err(InstallError::InvalidManifest {
+                    issues: vec![ManifestIssue {
+                        field: "entrypoint.path".to_string(),
+                        message: format!("{} does not exist", module.display()),
+                    }],
+                });
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Installs an extension from a directory or zip and activates it
+    pub fn install(
+        &self,
+        source: &Path,
+        pipeline: &Pipeline,
+    ) -> Result<ExtensionManifest, InstallError> {
+        let staging = self
+            .root
+            .join(format!("{}{}", STAGING_PREFIX, uuid::Uuid::new_v4()));
+        let result = self.install_from(source, &staging, pipeline);
+        let _ = fs::remove_dir_all(&staging);
+        result
+    }
+
+    fn install_from(
+        &self,
+        source: &Path,
+        staging: &Path,
+        pipeline: &Pipeline,
+    ) -> Result<ExtensionManifest, InstallError> {
+        let source_dir = if source.is_dir() {
+            source.to_path_buf()
+        } else {
+            extract_zip(source, staging)?;
+            extension_root(staging)
+        };
+        let manifest = Self::read_manifest(&source_dir)?;
+
+        let target = self.root.join(&manifest.id);
+        if target.exists() || pipeline.list().iter().any(|e| e.id == manifest.id) {
+            return Err(InstallError::AlreadyInstalled { id: manifest.id });
+        }
+        if source_dir.starts_with(staging) {
+            fs::rename(&source_dir, &target)?;
+        } else {
+            copy_dir(&source_dir, &target)?;
+        }
+
+        if let Err(e) = self.activate(&target, &manifest, pipeline) {
+            let _ = fs::remove_dir_all(&target);
+            return Err(e);
+        }
+        Ok(manifest)
+    }
+
+    /// Deactivates an installed extension and deletes its files
+    pub fn uninstall(&self, id: &str, pipeline: &Pipeline) -> Result<(), InstallError> {
+        let dir = self.root.join(id);
+        let installed = !id.is_empty()
+            && !id.contains(['/', '\\'])
+            && !id.starts_with('.')
+            && manifest::find_manifest(&dir).is_some();
+        if !installed {
+            return Err(InstallError::NotInstalled { id: id.to_string() });
+        }
+        let manifest = Self::read_manifest(&dir).ok();
+        // Builtin entrypoints only configure an existing plugin, so it stays registered
+        let is_builtin = matches!(
+            manifest.map(|m| m.entrypoint),
+            Some(Entrypoint::Builtin { .. })
+        );
+        if !is_builtin {
+            let _ = pipeline.unregister(id);
+        }
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    /// Activates every installed extension, returning the ones that failed
+    pub fn load_all(&self, pipeline: &Pipeline) -> Vec<(PathBuf, InstallError)> {
+        let Ok(entries) = fs::read_dir(&self.root) else {
+            return Vec::new();
+        };
+        let mut dirs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_dir())
+            .collect();
+        dirs.sort();
+
+        let mut failures = Vec::new();
+        for dir in dirs {
+            let is_staging = dir
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(STAGING_PREFIX));
+            if is_staging {
+                // Left over from an interrupted install
+                let _ = fs::remove_dir_all(&dir);
+                continue;
+            }
+            let result = Self::read_manifest(&dir)
+                .and_then(|manifest| self.activate(&dir, &manifest, pipeline));
+            if let Err(e) = result {
+                failures.push((dir, e));
+            }
+        }
+        failures
+    }
+
+    /// Registers an installed extension with the pipeline
+    fn activate(
+        &self,
+        dir: &Path,
+        manifest: &ExtensionManifest,
+        pipeline: &Pipeline,
+    ) -> Result<(), InstallError> {
+        let activation = |e: ExtensionError| InstallError::Activation {
+            id: manifest.id.clone(),
+            message: e.to_string(),
+        };
+        let (runner, kind): (Box<dyn super::HookRunner>, ExtensionKind) = match &manifest.entrypoint
+        {
+            Entrypoint::Wasm { path, limits } => {
+                let module = self
+                    .wasm
+                    .load_file(&dir.join(path), limits.clone())
+                    .map_err(activation)?;
+                (Box::new(module), ExtensionKind::Wasm)
+            }
+            Entrypoint::Process { .. } => {
+                let config = manifest.process_config(dir).unwrap_or_default();
+                let process = ProcessExtension::spawn(&manifest.id, config).map_err(activation)?;
+                (Box::new(process), ExtensionKind::Process)
+            }
+            Entrypoint::Builtin { plugin } => {
+                if pipeline.list().iter().any(|e| &e.id == plugin) {
+                    return Ok(());
+                }
+                return Err(InstallError::Activation {
+                    id: manifest.id.clone(),
+                    message: format!("no builtin plugin named {}", plugin),
+                });
+            }
+        };
+        let plugin =
+            ExtensionPlugin::new(&manifest.id, &manifest.name, &manifest.description, runner)
+                .with_version(&manifest.version);
+        pipeline
+            .register(Arc::new(plugin), kind)
+            .map_err(|_| InstallError::AlreadyInstalled {
+                id: manifest.id.clone(),
+            })
+    }
+}
+
+/// Returns the directory holding the manifest: the archive root or its single top-level folder
+fn extension_root(extracted: &Path) -> PathBuf {
+    if manifest::find_manifest(extracted).is_some() {
+        return extracted.to_path_buf();
+    }
+    let children: Vec<PathBuf> = fs::read_dir(extracted)
+        .map(|entries| entries.filter_map(|e| e.ok().map(|e| e.path())).collect())
+        .unwrap_or_default();
+    match children.as_slice() {
+        [only] if only.is_dir() => only.clone(),
+        _ => extracted.to_path_buf(),
+    }
+}
+
+fn extract_zip(archive: &Path, target: &Path) -> Result<(), InstallError> {
+    let file = fs::File::open(archive).map_err(|e| InstallError::Io {
+        message: format!("cannot open {}: {}", archive.display(), e),
+    })?;
+    let mut zip = zip::ZipArchive::new(file)?;
+    fs::create_dir_all(target)?;
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index)?;
+        // Reject entries like `../../evil` instead of silently skipping them
+        let relative = entry
+            .enclosed_name()
+            .ok_or_else(|| InstallError::UnsafeArchive {
+                path: entry.name().to_string(),
+            })?;
+        let path = target.join(relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out = fs::File::create(&path)?;
+        io::copy(&mut entry, &mut out)?;
+    }
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const WASM_MANIFEST: &str = r#"
+        id = "acme.noop"
+        name = "Noop"
+        version = "1.0.0"
+        hooks = ["on_request"]
+        entrypoint = { kind = "wasm", path = "plugin.wasm" }
+    "#;
+
+    fn noop_module() -> Vec<u8> {
+        wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "on_request") (param i32 i32) (result i64) (i64.const 0)))"#,
+        )
+        .unwrap()
+    }
+
+    fn extension_dir(manifest: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("blackbox-extension.toml"), manifest).unwrap();
+        fs::write(dir.path().join("plugin.wasm"), noop_module()).unwrap();
+        dir
+    }
+
+    fn store() -> (tempfile::TempDir, ExtensionStore) {
+        let data = tempfile::tempdir().unwrap();
+        let store = ExtensionStore::new(data.path().join("extensions")).unwrap();
+        (data, store)
+    }
+
+    mod install_tests {
+        use super::*;
+
+        #[test]
+        fn test_install_directory_and_uninstall() {
+            let (_data, store) = store();
+            let pipeline = Pipeline::new();
+            let source = extension_dir(WASM_MANIFEST);
+
+            let manifest = store.install(source.path(), &pipeline).unwrap();
+            assert_eq!(manifest.id, "acme.noop");
+            assert!(store.root().join("acme.noop/plugin.wasm").is_file());
+            let listed = pipeline.list();
+            assert_eq!(listed[0].kind, ExtensionKind::Wasm);
+            assert_eq!(listed[0].version.as_deref(), Some("1.0.0"));
+
+            assert!(matches!(
+                store.install(source.path(), &pipeline),
+                Err(InstallError::AlreadyInstalled { .. })
+            ));
+
+            store.uninstall("acme.noop", &pipeline).unwrap();
+            assert!(pipeline.list().is_empty());
+            assert!(!store.root().join("acme.noop").exists());
+            assert!(matches!(
+                store.uninstall("acme.noop", &pipeline),
+                Err(InstallError::NotInstalled { .. })
+            ));
+        }
+
+        #[test]
+        fn test_install_zip_with_top_level_folder() {
+            let (data, store) = store();
+            let archive = data.path().join("noop.zip");
+            let mut zip = zip::ZipWriter::new(fs::File::create(&archive).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            zip.start_file("noop/blackbox-extension.toml", options)
+                .unwrap();
+            zip.write_all(WASM_MANIFEST.as_bytes()).unwrap();
+            zip.start_file("noop/plugin.wasm", options).unwrap();
+            zip.write_all(&noop_module()).unwrap();
+            zip.finish().unwrap();
+
+            let pipeline = Pipeline::new();
+            store.install(&archive, &pipeline).unwrap();
+            assert!(store
+                .root()
+                .join("acme.noop/blackbox-extension.toml")
+                .is_file());
+            // Staging directories are cleaned up
+            assert_eq!(fs::read_dir(store.root()).unwrap().count(), 1);
+        }
+
+        #[test]
+        fn test_rejects_zip_slip() {
+            let (data, store) = store();
+            let archive = data.path().join("evil.zip");
+            let mut zip = zip::ZipWriter::new(fs::File::create(&archive).unwrap());
+            zip.start_file("../evil.txt", zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"x").unwrap();
+            zip.finish().unwrap();
+
+            assert!(matches!(
+                store.install(&archive, &Pipeline::new()),
+                Err(InstallError::UnsafeArchive { .. })
+            ));
+            assert!(!data.path().join("evil.txt").exists());
+        }
+
+        #[test]
+        fn test_load_all_activates_installed_extensions() {
+            let (_data, store) = store();
+            store
+                .install(extension_dir(WASM_MANIFEST).path(), &Pipeline::new())
+                .unwrap();
+
+            let pipeline = Pipeline::new();
+            assert!(store.load_all(&pipeline).is_empty());
+            assert_eq!(pipeline.list()[0].id, "acme.noop");
+        }
+    }
+
+    mod error_tests {
+        use super::*;
+
+        #[test]
+        fn test_missing_manifest() {
+            let (_data, store) = store();
+            let empty = tempfile::tempdir().unwrap();
+            let error = store.install(empty.path(), &Pipeline::new()).unwrap_err();
+            assert_eq!(error.kind(), "manifest_not_found");
+        }
+
+        #[test]
+        fn test_invalid_manifest_serializes_issues() {
+            let (_data, store) = store();
+            let source = extension_dir(&WASM_MANIFEST.replace("1.0.0", "one"));
+            let error = store.install(source.path(), &Pipeline::new()).unwrap_err();
+
+            let json = serde_json::to_value(&error).unwrap();
+            assert_eq!(json["kind"], "invalid_manifest");
+            assert_eq!(json["issues"][0]["field"], "version");
+            assert!(json["message"].as_str().unwrap().contains("version"));
+        }
+
+        #[test]
+        fn test_missing_wasm_module() {
+            let (_data, store) = store();
+            let source = extension_dir(&WASM_MANIFEST.replace("plugin.wasm", "other.wasm"));
+            match store.install(source.path(), &Pipeline::new()).unwrap_err() {
+                InstallError::InvalidManifest { issues } => {
+                    assert_eq!(issues[0].field, "entrypoint.path")
+                }
+                other => panic!("unexpected error {:?}", other),
+            }
+        }
+
+        #[test]
+        fn test_uninstall_rejects_path_ids() {
+            let (_data, store) = store();
+            assert!(matches!(
+                store.uninstall("../extensions", &Pipeline::new()),
+                Err(InstallError::NotInstalled { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,496 @@
+//! Extension manifest format
+//!
+//! Every installable extension ships a `blackbox-extension.toml` (or
+//! `.json`) at its root:
+//!
+//! ```toml
+//! id = "acme.redactor"
+//! name = "Redactor"
+//! version = "1.2.0"
+//! description = "Masks customer ids"
+//! hooks = ["on_request", "on_response"]
+//!
+//! [entrypoint]
+//! kind = "process"          # wasm | process | builtin
+//! command = "bun"
+//! args = ["run", "plugin.ts"]
+//!
+//! [config_schema]           # JSON Schema of the extension settings
+//! type = "object"
+//! ```
+//!
+//! Parsing is lenient so that [`validate`] can report every problem at
+//! once as a list of [`ManifestIssue`]s.
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::process::ProcessConfig;
+use super::wasm::WasmLimits;
+use super::Hook;
+
+/// Manifest file names, in lookup order
+pub const MANIFEST_FILES: [&str; 2] = ["blackbox-extension.toml", "blackbox-extension.json"];
+
+/// How an extension is run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Entrypoint {
+    /// A WASM module inside the extension directory
+    Wasm {
+        path: PathBuf,
+        #[serde(default)]
+        limits: WasmLimits,
+    },
+    /// A child process speaking JSON-RPC on stdio, run from the extension directory
+    Process {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    /// A plugin compiled into the app, identified by its pipeline id
+    Builtin { plugin: String },
+}
+
+/// A validated manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExtensionManifest {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub entrypoint: Entrypoint,
+    pub hooks: Vec<Hook>,
+    /// JSON Schema describing the extension's settings
+    pub config_schema: Option<Value>,
+}
+
+impl ExtensionManifest {
+    /// Launch settings for a process entrypoint run from `dir`
+    pub fn process_config(&self, dir: &Path) -> Option<ProcessConfig> {
+        let Entrypoint::Process {
+            command,
+            args,
+            env,
+            timeout_ms,
+        } = &self.entrypoint
+        else {
+            return None;
+        };
+        let defaults = ProcessConfig::default();
+        Some(ProcessConfig {
+            command: command.clone(),
+            args: args.clone(),
+            cwd: Some(dir.to_path_buf()),
+            env: env.clone(),
+            hooks: self.hooks.clone(),
+            timeout_ms: timeout_ms.unwrap_or(defaults.timeout_ms),
+            ..defaults
+        })
+    }
+}
+
+/// One problem found in a manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestIssue {
+    /// Dotted path of the offending field, e.g. `entrypoint.path`
+    pub field: String,
+    pub message: String,
+}
+
+impl ManifestIssue {
+    fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Manifest as written on disk, before validation
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawManifest {
+    id: String,
+    name: String,
+    version: String,
+    description: String,
+    entrypoint: Option<Value>,
+    hooks: Vec<String>,
+    config_schema: Option<Value>,
+}
+
+/// Finds the manifest file in an extension directory
+pub fn find_manifest(dir: &Path) -> Option<PathBuf> {
+    MANIFEST_FILES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Parses manifest text; `json` selects the JSON syntax over TOML.
+/// Returns the syntax error message when the file cannot be parsed at all.
+pub fn parse(
+    text: &str,
+    json: bool,
+) -> Result<Result<ExtensionManifest, Vec<ManifestIssue>>, String> {
+    let raw: RawManifest = if json {
+        serde_json::from_str(text).map_err(|e| e.to_string())?
+    } else {
+        toml::from_str(text).map_err(|e| e.to_string())?
+    };
+    Ok(validate(raw))
+}
+
+fn validate(raw: RawManifest) -> Result<ExtensionManifest, Vec<ManifestIssue>> {
+    let mut issues = Vec::new();
+
+    if !is_valid_id(&raw.id) {
+        issues.push(ManifestIssue::new(
+            "id",
+            "must be 1-64 lowercase letters, digits, '.', '-' or '_', starting with a letter",
+        ));
+    }
+    if raw.name.trim().is_empty() {
+        issues.push(ManifestIssue::new("name", "must not be empty"));
+    }
+    if !is_semver(&raw.version) {
+        issues.push(ManifestIssue::new(
+            "version",
+            format!("'{}' is not a semantic version like 1.2.3", raw.version),
+        ));
+    }
+
+    let mut hooks = Vec::new();
+    for name in &raw.hooks {
+        match Hook::parse(name) {
+            Some(hook) if !hooks.contains(&hook) => hooks.push(hook),
+            Some(_) => issues.push(ManifestIssue::new(
+                "hooks",
+                format!("'{}' is listed twice", name),
+            )),
+            None => issues.push(ManifestIssue::new(
+                "hooks",
+                format!("unknown hook '{}'", name),
+            )),
+        }
+    }
+
+    let entrypoint = match raw.entrypoint {
+        None => {
+            issues.push(ManifestIssue::new("entrypoint", "is required"));
+            None
+        }
+        Some(value) => match serde_json::from_value::<Entrypoint>(value) {
+            Ok(entrypoint) => {
+                issues.extend(entrypoint_issues(&entrypoint));
+                Some(entrypoint)
+            }
+            Err(e) => {
+                issues.push(ManifestIssue::new("entrypoint", e.to_string()));
+                None
+            }
+        },
+    };
+    if hooks.is_empty() && !matches!(entrypoint, Some(Entrypoint::Builtin { .. })) {
+        issues.push(ManifestIssue::new("hooks", "must list at least one hook"));
+    }
+
+    if let Some(schema) = &raw.config_schema {
+        issues.extend(schema_issues(schema));
+    }
+
+    match entrypoint {
+        Some(entrypoint) if issues.is_empty() => Ok(ExtensionManifest {
+            id: raw.id,
+            name: raw.name,
+            version: raw.version,
+            description: raw.description,
+            entrypoint,
+            hooks,
+            config_schema: raw.config_schema,
+        }),
+        _ => Err(issues),
+    }
+}
+
+fn entrypoint_issues(entrypoint: &Entrypoint) -> Vec<ManifestIssue> {
+    let mut issues = Vec::new();
+    match entrypoint {
+        Entrypoint::Wasm { path, limits } => {
+            if !is_relative_inside(path) {
+                issues.push(ManifestIssue::new(
+                    "entrypoint.path",
+                    "must be a relative path inside the extension",
+                ));
+            } else if path.extension().is_none_or(|ext| ext != "wasm") {
+                issues.push(ManifestIssue::new(
+                    "entrypoint.path",
+                    "must point to a .wasm file",
+                ));
+            }
+            if limits.fuel == 0 || limits.memory_bytes == 0 {
+                issues.push(ManifestIssue::new(
+                    "entrypoint.limits",
+                    "must be greater than zero",
+                ));
+            }
+        }
+        Entrypoint::Process {
+            command,
+            timeout_ms,
+            ..
+        } => {
+            if command.trim().is_empty() {
+                issues.push(ManifestIssue::new(
+                    "entrypoint.command",
+                    "must not be empty",
+                ));
+            }
+            if *timeout_ms == Some(0) {
+                issues.push(ManifestIssue::new(
+                    "entrypoint.timeout_ms",
+                    "must be greater than zero",
+                ));
+            }
+        }
+        Entrypoint::Builtin { plugin } => {
+            if plugin.trim().is_empty() {
+                issues.push(ManifestIssue::new("entrypoint.plugin", "must not be empty"));
+            }
+        }
+    }
+    issues
+}
+
+/// Checks the parts of a JSON Schema the settings UI relies on
+fn schema_issues(schema: &Value) -> Vec<ManifestIssue> {
+    let Some(schema) = schema.as_object() else {
+        return vec![ManifestIssue::new("config_schema", "must be an object")];
+    };
+    let mut issues = Vec::new();
+    if let Some(kind) = schema.get("type") {
+        if kind != "object" {
+            issues.push(ManifestIssue::new(
+                "config_schema.type",
+                "must be \"object\"",
+            ));
+        }
+    }
+    match schema.get("properties") {
+        None => {}
+        Some(Value::Object(properties)) => {
+            for (name, property) in properties {
+                if !property.is_object() {
+                    issues.push(ManifestIssue::new(
+                        &format!("config_schema.properties.{}", name),
+                        "must be a schema object",
+                    ));
+                }
+            }
+            if let Some(required) = schema.get("required") {
+                issues.extend(required_issues(required, properties));
+            }
+        }
+        Some(_) => issues.push(ManifestIssue::new(
+            "config_schema.properties",
+            "must be an object",
+        )),
+    }
+    issues
+}
+
+fn required_issues(required: &Value, properties: &Map<String, Value>) -> Vec<ManifestIssue> {
+    let Some(required) = required.as_array() else {
+        return vec![ManifestIssue::new(
+            "config_schema.required",
+            "must be an array",
+        )];
+    };
+    required
+        .iter()
+        .filter(|name| !name.as_str().is_some_and(|n| properties.contains_key(n)))
+        .map(|name| {
+            ManifestIssue::new(
+                "config_schema.required",
+                format!("{} is not a declared property", name),
+            )
+        })
+        .collect()
+}
+
+fn is_valid_id(id: &str) -> bool {
+    id.len() <= 64
+        && id.chars().next().is_some_and(|c| c.is_ascii_lowercase())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '-' | '_'))
+}
+
+/// Accepts `MAJOR.MINOR.PATCH` with optional pre-release and build suffixes
+fn is_semver(version: &str) -> bool {
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let parts: Vec<&str> = core.split('.').collect();
+    parts.len() == 3
+        && parts.iter().all(|part| {
+            !part.is_empty()
+                && part.chars().all(|c| c.is_ascii_digit())
+                && (part.len() == 1 || !part.starts_with('0'))
+        })
+        && !version.ends_with(['-', '+'])
+}
+
+fn is_relative_inside(path: &Path) -> bool {
+    !path.as_os_str().is_empty() && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"
+        id = "acme.redactor"
+        name = "Redactor"
+        version = "1.2.0"
+        hooks = ["on_request", "on_response"]
+
+        [entrypoint]
+        kind = "wasm"
+        path = "plugin.wasm"
+
+        [config_schema]
+        type = "object"
+        required = ["pattern"]
+
+        [config_schema.properties.pattern]
+        type = "string"
+    "#;
+
+    fn issues(text: &str) -> Vec<ManifestIssue> {
+        parse(text, false).unwrap().unwrap_err()
+    }
+
+    mod parse_tests {
+        use super::*;
+
+        #[test]
+        fn test_parses_toml() {
+            let manifest = parse(VALID, false).unwrap().unwrap();
+            assert_eq!(manifest.id, "acme.redactor");
+            assert_eq!(manifest.hooks, vec![Hook::Request, Hook::Response]);
+            assert_eq!(
+                manifest.entrypoint,
+                Entrypoint::Wasm {
+                    path: PathBuf::from("plugin.wasm"),
+                    limits: WasmLimits::default()
+                }
+            );
+            assert!(manifest.config_schema.is_some());
+        }
+
+        #[test]
+        fn test_parses_json_process_entrypoint() {
+            let text = r#"{
+                "id": "acme.evaluate",
+                "name": "Evaluate",
+                "version": "0.1.0-beta.1",
+                "hooks": ["on_response"],
+                "entrypoint": {"kind": "process", "command": "bun", "args": ["run", "plugin.ts"]}
+            }"#;
+            let manifest = parse(text, true).unwrap().unwrap();
+            let config = manifest.process_config(Path::new("/ext")).unwrap();
+            assert_eq!(config.command, "bun");
+            assert_eq!(config.cwd, Some(PathBuf::from("/ext")));
+            assert_eq!(config.hooks, vec![Hook::Response]);
+        }
+
+        #[test]
+        fn test_syntax_errors_are_reported_separately() {
+            assert!(parse("id = ", false).is_err());
+            assert!(parse("{", true).is_err());
+        }
+    }
+
+    mod validate_tests {
+        use super::*;
+
+        #[test]
+        fn test_reports_every_issue() {
+            let fields: Vec<String> = issues(
+                r#"
+                id = "Bad Id"
+                version = "1.0"
+                hooks = ["on_request", "on_nothing"]
+                [entrypoint]
+                kind = "wasm"
+                path = "../escape.wasm"
+            "#,
+            )
+            .into_iter()
+            .map(|issue| issue.field)
+            .collect();
+            assert_eq!(
+                fields,
+                vec!["id", "name", "version", "hooks", "entrypoint.path"]
+            );
+        }
+
+        #[test]
+        fn test_requires_entrypoint_and_hooks() {
+            let fields: Vec<String> = issues(
+                r#"id = "a"
+                name = "A"
+                version = "1.0.0""#,
+            )
+            .into_iter()
+            .map(|issue| issue.field)
+            .collect();
+            assert_eq!(fields, vec!["entrypoint", "hooks"]);
+        }
+
+        #[test]
+        fn test_rejects_unknown_entrypoint_kind() {
+            let found = issues(
+                r#"id = "a"
+                name = "A"
+                version = "1.0.0"
+                hooks = ["on_request"]
+                entrypoint = { kind = "python" }"#,
+            );
+            assert_eq!(found[0].field, "entrypoint");
+        }
+
+        #[test]
+        fn test_checks_config_schema() {
+            let found = issues(
+                r#"id = "a"
+                name = "A"
+                version = "1.0.0"
+                hooks = ["on_request"]
+                entrypoint = { kind = "process", command = "bun" }
+                [config_schema]
+                type = "array"
+                required = ["missing"]
+                properties = { ok = { type = "string" } }"#,
+            );
+            let fields: Vec<_> = found.iter().map(|issue| issue.field.as_str()).collect();
+            assert_eq!(fields, vec!["config_schema.type", "config_schema.required"]);
+        }
+
+        #[test]
+        fn test_semver() {
+            assert!(is_semver("1.2.3"));
+            assert!(is_semver("0.1.0-rc.1+build.5"));
+            assert!(!is_semver("1.2"));
+            assert!(!is_semver("01.2.3"));
+            assert!(!is_semver("1.2.3-"));
+        }
+    }
+}
@@ -0,0 +1,569 @@
+//! Third-party extensions
+//!
+//! Extensions are plugins that live outside the desktop binary. Whatever
+//! runs them, they speak the same JSON hook protocol: each hook receives a
+//! payload with the call context and the value being processed, and
+//! answers with a [`HookReply`] telling the pipeline how to proceed.
+//!
+//! ```json
+//! {"hook": "on_request", "context": {...}, "request": {"headers": {...}, "body": {...}}}
+//! {"action": "continue", "body": {...}, "metadata": {"tagged": true}}
+//! ```
+//!
+//! A misbehaving extension never breaks the proxy: failures are logged and
+//! the hook is treated as `continue`.
+
+pub mod install;
+pub mod manifest;
+pub mod process;
+pub mod wasm;
+
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::pipeline::{
+    ChunkOutcome, ExtensionKind, HookOutcome, Pipeline, Plugin, PluginContext, PluginRequest,
+    PluginResponse, Rejection, UpstreamFailure,
+};
+use crate::proxy::SseEvent;
+
+/// Directory under the app data dir extensions are loaded from
+pub const EXTENSIONS_DIR: &str = "extensions";
+
+/// Request headers never shown to extensions
+const HIDDEN_HEADERS: &[&str] = &["authorization", "x-api-key", "x-goog-api-key", "cookie"];
+
+/// Errors raised while loading or calling an extension
+#[derive(Debug, thiserror::Error)]
+pub enum ExtensionError {
+    #[error("failed to read extension: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("wasm error: {0}")]
+    Wasm(String),
+    #[error("extension ran out of fuel")]
+    FuelExhausted,
+    #[error("extension does not export `{0}`")]
+    MissingExport(String),
+    #[error("extension returned an invalid reply: {0}")]
+    InvalidReply(String),
+    #[error("extension did not answer within {0} ms")]
+    Timeout(u64),
+    #[error("extension is unavailable: {0}")]
+    Unavailable(String),
+    #[error("extension returned error {code}: {message}")]
+    Rpc { code: i64, message: String },
+}
+
+/// Pipeline hooks an extension can implement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Hook {
+    #[serde(rename = "on_request")]
+    Request,
+    #[serde(rename = "on_response")]
+    Response,
+    #[serde(rename = "on_stream_chunk")]
+    StreamChunk,
+    #[serde(rename = "on_error")]
+    Error,
+}
+
+impl Hook {
+    /// Every hook, in lifecycle order
+    pub const ALL: [Hook; 4] = [
+        Hook::Request,
+        Hook::Response,
+        Hook::StreamChunk,
+        Hook::Error,
+    ];
+
+    /// Name used on the wire and as the WASM export
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Hook::Request => "on_request",
+            Hook::Response => "on_response",
+            Hook::StreamChunk => "on_stream_chunk",
+            Hook::Error => "on_error",
+        }
+    }
+
+    /// Parses a wire name
+    pub fn parse(name: &str) -> Option<Self> {
+        Hook::ALL.into_iter().find(|hook| hook.as_str() == name)
+    }
+}
+
+/// What an extension wants the pipeline to do next
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyAction {
+    #[default]
+    Continue,
+    Respond,
+    Reject,
+    Skip,
+}
+
+/// Answer to a hook call; an empty reply means `continue` unchanged
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HookReply {
+    pub action: ReplyAction,
+    /// Replacement request/response body, or the body of a `respond`
+    pub body: Option<Value>,
+    /// Request headers to set before forwarding
+    pub headers: Map<String, Value>,
+    /// Status of a `respond` or `reject`, or replacement response status
+    pub status: Option<u16>,
+    /// Error type of a `reject`
+    pub kind: Option<String>,
+    /// Error message of a `reject`
+    pub message: Option<String>,
+    /// Replacement `event:` field of a stream chunk
+    pub event: Option<String>,
+    /// Replacement `data:` field of a stream chunk
+    pub data: Option<String>,
+    /// Notes merged into the captured call
+    pub metadata: Map<String, Value>,
+}
+
+impl HookReply {
+    /// Parses raw reply bytes, treating an empty reply as `continue`
+    pub fn parse(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Self::default());
+        }
+        serde_json::from_slice(bytes).map_err(|e| ExtensionError::InvalidReply(e.to_string()))
+    }
+
+    /// Parses a reply already decoded as JSON, treating `null` as `continue`
+    pub fn from_value(value: Value) -> Result<Self, ExtensionError> {
+        if value.is_null() {
+            return Ok(Self::default());
+        }
+        serde_json::from_value(value).map_err(|e| ExtensionError::InvalidReply(e.to_string()))
+    }
+
+    fn rejection(&self) -> Rejection {
+        Rejection::new(
+            self.status.unwrap_or(400),
+            self.kind
+                .clone()
+                .unwrap_or_else(|| "extension_rejected".to_string()),
+            self.message
+                .clone()
+                .unwrap_or_else(|| "Request rejected by an extension".to_string()),
+        )
+    }
+
+    fn response(&mut self) -> PluginResponse {
+        PluginResponse {
+            status: self.status.unwrap_or(200),
+            body: self.body.take().unwrap_or(Value::Null),
+        }
+    }
+
+    /// Applies the reply to a request hook
+    pub fn apply_request(
+        mut self,
+        ctx: &mut PluginContext,
+        request: &mut PluginRequest,
+    ) -> HookOutcome {
+        ctx.metadata.append(&mut self.metadata);
+        match self.action {
+            ReplyAction::Continue | ReplyAction::Skip => {
+                if let Some(body) = self.body {
+                    request.body = body;
+                }
+                for (name, value) in &self.headers {
+                    let parsed = (
+                        HeaderName::try_from(name.as_str()),
+                        value.as_str().map(HeaderValue::from_str),
+                    );
+                    if let (Ok(name), Some(Ok(value))) = parsed {
+                        request.headers.insert(name, value);
+                    }
+                }
+                HookOutcome::Continue
+            }
+            ReplyAction::Respond => HookOutcome::Respond(self.response()),
+            ReplyAction::Reject => HookOutcome::Reject(self.rejection()),
+        }
+    }
+
+    /// Applies the reply to a response hook
+    pub fn apply_response(
+        mut self,
+        ctx: &mut PluginContext,
+        response: &mut PluginResponse,
+    ) -> HookOutcome {
+        ctx.metadata.append(&mut self.metadata);
+        match self.action {
+            ReplyAction::Continue | ReplyAction::Skip => {
+                if let Some(body) = self.body {
+                    response.body = body;
+                }
+                if let Some(status) = self.status {
+                    response.status = status;
+                }
+                HookOutcome::Continue
+            }
+            ReplyAction::Respond => HookOutcome::Respond(self.response()),
+            ReplyAction::Reject => HookOutcome::Reject(self.rejection()),
+        }
+    }
+
+    /// Applies the reply to a stream chunk hook; `respond` is not possible mid-stream
+    pub fn apply_chunk(mut self, ctx: &mut PluginContext, event: &mut SseEvent) -> ChunkOutcome {
+        ctx.metadata.append(&mut self.metadata);
+        match self.action {
+            ReplyAction::Continue | ReplyAction::Respond => {
+                if let Some(name) = self.event {
+                    event.event = Some(name);
+                }
+                if let Some(data) = self.data {
+                    event.data = data;
+                }
+                ChunkOutcome::Continue
+            }
+            ReplyAction::Skip => ChunkOutcome::Skip,
+            ReplyAction::Reject => ChunkOutcome::Reject(self.rejection()),
+        }
+    }
+
+    /// Applies the reply to an error hook
+    pub fn apply_error(mut self, ctx: &mut PluginContext) -> HookOutcome {
+        ctx.metadata.append(&mut self.metadata);
+        match self.action {
+            ReplyAction::Continue | ReplyAction::Skip => HookOutcome::Continue,
+            ReplyAction::Respond => HookOutcome::Respond(self.response()),
+            ReplyAction::Reject => HookOutcome::Reject(self.rejection()),
+        }
+    }
+}
+
+fn context_json(hook: Hook, ctx: &PluginContext) -> Map<String, Value> {
+    let mut payload = Map::new();
+    payload.insert("hook".to_string(), json!(hook.as_str()));
+    payload.insert(
+        "context".to_string(),
+        json!({
+            "call_id": ctx.call_id,
+            "provider": ctx.provider,
+            "endpoint": ctx.endpoint,
+            "model": ctx.model,
+            "metadata": ctx.metadata,
+        }),
+    );
+    payload
+}
+
+/// Headers as a JSON object, without credentials
+fn headers_json(headers: &HeaderMap) -> Value {
+    let visible: Map<String, Value> = headers
+        .iter()
+        .filter(|(name, _)| !HIDDEN_HEADERS.contains(&name.as_str()))
+        .filter_map(|(name, value)| Some((name.to_string(), json!(value.to_str().ok()?))))
+        .collect();
+    Value::Object(visible)
+}
+
+/// Builds the payload of a request hook
+pub fn request_payload(ctx: &PluginContext, request: &PluginRequest) -> Value {
+    let mut payload = context_json(Hook::Request, ctx);
+    payload.insert(
+        "request".to_string(),
+        json!({"headers": headers_json(&request.headers), "body": request.body}),
+    );
+    Value::Object(payload)
+}
+
+/// Builds the payload of a response hook
+pub fn response_payload(ctx: &PluginContext, response: &PluginResponse) -> Value {
+    let mut payload = context_json(Hook::Response, ctx);
+    payload.insert(
+        "response".to_string(),
+        json!({"status": response.status, "body": response.body}),
+    );
+    Value::Object(payload)
+}
+
+/// Builds the payload of a stream chunk hook
+pub fn chunk_payload(ctx: &PluginContext, event: &SseEvent) -> Value {
+    let mut payload = context_json(Hook::StreamChunk, ctx);
+    payload.insert(
+        "event".to_string(),
+        json!({"event": event.event, "data": event.data}),
+    );
+    Value::Object(payload)
+}
+
+/// Builds the payload of an error hook
+pub fn error_payload(ctx: &PluginContext, error: &UpstreamFailure) -> Value {
+    let mut payload = context_json(Hook::Error, ctx);
+    payload.insert(
+        "error".to_string(),
+        json!({"status": error.status, "message": error.message}),
+    );
+    Value::Object(payload)
+}
+
+/// Something that can run an extension's hooks
+#[async_trait]
+pub trait HookRunner: Send + Sync {
+    /// Returns true when the extension implements `hook`
+    fn supports(&self, hook: Hook) -> bool;
+
+    /// Runs a hook with the given payload
+    async fn call(&self, hook: Hook, payload: Value) -> Result<HookReply, ExtensionError>;
+}
+
+/// Adapts an extension to the [`Plugin`] trait
+pub struct ExtensionPlugin {
+    id: String,
+    name: String,
+    description: String,
+    version: Option<String>,
+    runner: Box<dyn HookRunner>,
+}
+
+impl ExtensionPlugin {
+    /// Wraps a hook runner
+    pub fn new(id: &str, name: &str, description: &str, runner: Box<dyn HookRunner>) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            description: description.to_string(),
+            version: None,
+            runner,
+        }
+    }
+
+    /// Sets the version reported to the frontend
+    pub fn with_version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_string());
+        self
+    }
+
+    /// Calls a hook, logging failures and turning them into `None`
+    async fn call(&self, hook: Hook, payload: impl FnOnce() -> Value) -> Option<HookReply> {
+        if !self.runner.supports(hook) {
+            return None;
+        }
+        match self.runner.call(hook, payload()).await {
+            Ok(reply) => Some(reply),
+            Err(e) => {
+                log::warn!("Extension {} failed in {}: {}", self.id, hook.as_str(), e);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for ExtensionPlugin {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    async fn on_request(
+        &self,
+        ctx: &mut PluginContext,
+        request: &mut PluginRequest,
+    ) -> HookOutcome {
+        match self
+            .call(Hook::Request, || request_payload(ctx, request))
+            .await
+        {
+            Some(reply) => reply.apply_request(ctx, request),
+            None => HookOutcome::Continue,
+        }
+    }
+
+    async fn on_response(
+        &self,
+        ctx: &mut PluginContext,
+        response: &mut PluginResponse,
+    ) -> HookOutcome {
+        match self
+            .call(Hook::Response, || response_payload(ctx, response))
+            .await
+        {
+            Some(reply) => reply.apply_response(ctx, response),
+            None => HookOutcome::Continue,
+        }
+    }
+
+    async fn on_stream_chunk(&self, ctx: &mut PluginContext, event: &mut SseEvent) -> ChunkOutcome {
+        match self
+            .call(Hook::StreamChunk, || chunk_payload(ctx, event))
+            .await
+        {
+            Some(reply) => reply.apply_chunk(ctx, event),
+            None => ChunkOutcome::Continue,
+        }
+    }
+
+    async fn on_error(&self, ctx: &mut PluginContext, error: &UpstreamFailure) -> HookOutcome {
+        match self.call(Hook::Error, || error_payload(ctx, error)).await {
+            Some(reply) => reply.apply_error(ctx),
+            None => HookOutcome::Continue,
+        }
+    }
+}
+
+/// Loads every `.wasm` file in `dir` into the pipeline and returns the registered ids.
+/// Files that fail to load are logged and skipped.
+pub fn load_wasm_plugins(
+    dir: &Path,
+    host: &wasm::WasmHost,
+    limits: &wasm::WasmLimits,
+    pipeline: &Pipeline,
+) -> Result<Vec<String>, ExtensionError> {
+    let mut ids = Vec::new();
+    if !dir.exists() {
+        return Ok(ids);
+    }
+    let mut paths: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let module = match host.load_file(&path, limits.clone()) {
+            Ok(module) => module,
+            Err(e) => {
+                log::warn!("Skipping extension {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let id = format!("wasm.{}", stem);
+        let plugin = ExtensionPlugin::new(&id, stem, "WebAssembly extension", Box::new(module));
+        match pipeline.register(Arc::new(plugin), ExtensionKind::Wasm) {
+            Ok(()) => ids.push(id),
+            Err(e) => log::warn!("Skipping extension {}: {}", path.display(), e),
+        }
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::testing::context;
+
+    mod hook_reply_tests {
+        use super::*;
+
+        #[test]
+        fn test_empty_reply_continues() {
+            assert_eq!(HookReply::parse(b"").unwrap(), HookReply::default());
+            assert_eq!(
+                HookReply::from_value(Value::Null).unwrap(),
+                HookReply::default()
+            );
+            assert!(HookReply::parse(b"not json").is_err());
+        }
+
+        #[test]
+        fn test_request_reply_rewrites_body_headers_and_metadata() {
+            let reply = HookReply::from_value(json!({
+                "body": {"model": "gpt-4o-mini"},
+                "headers": {"x-extension": "yes"},
+                "metadata": {"rewritten": true},
+            }))
+            .unwrap();
+            let mut ctx = context();
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({"model": "gpt-4o"}),
+            };
+
+            assert_eq!(
+                reply.apply_request(&mut ctx, &mut request),
+                HookOutcome::Continue
+            );
+            assert_eq!(request.body["model"], "gpt-4o-mini");
+            assert_eq!(request.headers["x-extension"], "yes");
+            assert_eq!(ctx.metadata["rewritten"], true);
+        }
+
+        #[test]
+        fn test_reject_reply_has_defaults() {
+            let reply = HookReply::from_value(json!({"action": "reject"})).unwrap();
+            match reply.apply_error(&mut context()) {
+                HookOutcome::Reject(rejection) => {
+                    assert_eq!(rejection.status, 400);
+                    assert_eq!(rejection.kind, "extension_rejected");
+                }
+                other => panic!("unexpected outcome {:?}", other),
+            }
+        }
+
+        #[test]
+        fn test_chunk_reply() {
+            let mut event = SseEvent {
+                event: None,
+                data: "a".to_string(),
+            };
+            let skip = HookReply::from_value(json!({"action": "skip"})).unwrap();
+            assert_eq!(
+                skip.apply_chunk(&mut context(), &mut event),
+                ChunkOutcome::Skip
+            );
+
+            let rewrite = HookReply::from_value(json!({"data": "b"})).unwrap();
+            rewrite.apply_chunk(&mut context(), &mut event);
+            assert_eq!(event.data, "b");
+        }
+    }
+
+    mod payload_tests {
+        use super::*;
+
+        #[test]
+        fn test_request_payload_hides_credentials() {
+            let mut headers = HeaderMap::new();
+            headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+            headers.insert("x-trace", HeaderValue::from_static("1"));
+            let request = PluginRequest {
+                headers,
+                body: json!({"messages": []}),
+            };
+
+            let payload = request_payload(&context(), &request);
+            assert_eq!(payload["hook"], "on_request");
+            assert_eq!(payload["context"]["model"], "gpt-4o");
+            assert_eq!(payload["request"]["headers"], json!({"x-trace": "1"}));
+            assert_eq!(payload["request"]["body"], json!({"messages": []}));
+        }
+
+        #[test]
+        fn test_hook_names_round_trip() {
+            for hook in Hook::ALL {
+                assert_eq!(Hook::parse(hook.as_str()), Some(hook));
+            }
+            assert_eq!(Hook::parse("on_nothing"), None);
+        }
+    }
+}
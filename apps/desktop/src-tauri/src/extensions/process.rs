@@ -0,0 +1,479 @@
+//! Out-of-process extensions over JSON-RPC on stdio
+//!
+//! Lets extensions written in any language (e.g. `bun run plugin.ts` next
+//! to the TypeScript packages) hook into the pipeline. The host writes one
+//! JSON-RPC 2.0 request per line to the child's stdin, with the hook name
+//! as method and the hook payload as params, and reads one response per
+//! line from its stdout:
+//!
+//! ```json
+//! {"id":1,"jsonrpc":"2.0","method":"on_request","params":{"hook":"on_request",...}}
+//! {"jsonrpc":"2.0","id":1,"result":{"action":"continue"}}
+//! ```
+//!
+//! Requests time out individually, stderr lines go to the app log, and a
+//! child that exits is restarted with exponential backoff until it has
+//! crashed `max_restarts` times in a row.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{oneshot, Notify};
+
+use super::{ExtensionError, Hook, HookReply, HookRunner};
+use crate::sync::lock;
+
+/// Default time a hook call may take
+pub const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+/// Default number of consecutive crashes before giving up
+pub const DEFAULT_MAX_RESTARTS: u32 = 5;
+/// Default delay before the first restart, doubled on every further crash
+pub const DEFAULT_BACKOFF_MS: u64 = 500;
+/// Longest delay between restarts
+pub const MAX_BACKOFF_MS: u64 = 30_000;
+
+/// How to launch and supervise a process extension
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessConfig {
+    /// Executable, e.g. `bun`
+    pub command: String,
+    pub args: Vec<String>,
+    /// Working directory, defaults to the app's
+    pub cwd: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    /// Hooks the extension implements
+    pub hooks: Vec<Hook>,
+    /// Per-call timeout in milliseconds
+    pub timeout_ms: u64,
+    /// Consecutive crashes tolerated before the extension is disabled
+    pub max_restarts: u32,
+    /// Delay before the first restart in milliseconds
+    pub backoff_ms: u64,
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            args: Vec::new(),
+            cwd: None,
+            env: BTreeMap::new(),
+            hooks: Hook::ALL.to_vec(),
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            max_restarts: DEFAULT_MAX_RESTARTS,
+            backoff_ms: DEFAULT_BACKOFF_MS,
+        }
+    }
+}
+
+impl ProcessConfig {
+    /// Delay before restart number `attempt` (starting at 1)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(16);
+        Duration::from_millis(self.backoff_ms.saturating_mul(factor).min(MAX_BACKOFF_MS))
+    }
+}
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value, ExtensionError>>>>>;
+
+/// Pipes of one running child
+struct Connection {
+    stdin: tokio::sync::Mutex<ChildStdin>,
+    pending: Pending,
+}
+
+struct Shared {
+    id: String,
+    config: ProcessConfig,
+    connection: Mutex<Option<Arc<Connection>>>,
+    /// Crashes since the last successful call
+    crashes: AtomicU32,
+    next_request: AtomicU64,
+    stopped: AtomicBool,
+    stop: Notify,
+}
+
+/// A supervised child process running an extension
+pub struct ProcessExtension {
+    shared: Arc<Shared>,
+}
+
+impl ProcessExtension {
+    /// Spawns the child; fails when it cannot be started at all
+    pub fn spawn(id: &str, config: ProcessConfig) -> Result<Self, ExtensionError> {
+        let shared = Arc::new(Shared {
+            id: id.to_string(),
+            config,
+            connection: Mutex::new(None),
+            crashes: AtomicU32::new(0),
+            next_request: AtomicU64::new(1),
+            stopped: AtomicBool::new(false),
+            stop: Notify::new(),
+        });
+        let child = start(&shared)?;
+        tokio::spawn(supervise(shared.clone(), child));
+        Ok(Self { shared })
+    }
+
+    /// Returns true while a child is running
+    pub fn is_running(&self) -> bool {
+        self.shared.connection().is_some()
+    }
+
+    /// Crashes since the last successful call
+    pub fn crashes(&self) -> u32 {
+        self.shared.crashes.load(Ordering::Relaxed)
+    }
+
+    /// Sends a JSON-RPC request and waits for its result
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value, ExtensionError> {
+        let shared = &self.shared;
+        let connection = shared
+            .connection()
+            .ok_or_else(|| ExtensionError::Unavailable(format!("{} is not running", shared.id)))?;
+
+        let id = shared.next_request.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        lock(&connection.pending).insert(id, tx);
+        let mut line =
+            json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}).to_string();
+        line.push('\n');
+
+        let written = {
+            let mut stdin = connection.stdin.lock().await;
+            match stdin.write_all(line.as_bytes()).await {
+                Ok(()) => stdin.flush().await,
+                Err(e) => Err(e),
+            }
+        };
+        if let Err(e) = written {
+            lock(&connection.pending).remove(&id);
+            return Err(ExtensionError::Io(e));
+        }
+
+        let timeout = Duration::from_millis(shared.config.timeout_ms);
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(result)) => {
+                shared.crashes.store(0, Ordering::Relaxed);
+                result
+            }
+            Ok(Err(_)) => Err(ExtensionError::Unavailable(format!(
+                "{} exited before answering",
+                shared.id
+            ))),
+            Err(_) => {
+                lock(&connection.pending).remove(&id);
+                Err(ExtensionError::Timeout(shared.config.timeout_ms))
+            }
+        }
+    }
+
+    /// Stops the child and disables restarts
+    pub fn shutdown(&self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        self.shared.stop.notify_waiters();
+        self.shared.stop.notify_one();
+    }
+}
+
+impl Drop for ProcessExtension {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[async_trait]
+impl HookRunner for ProcessExtension {
+    fn supports(&self, hook: Hook) -> bool {
+        self.shared.config.hooks.contains(&hook)
+    }
+
+    async fn call(&self, hook: Hook, payload: Value) -> Result<HookReply, ExtensionError> {
+        HookReply::from_value(self.request(hook.as_str(), payload).await?)
+    }
+}
+
+impl Shared {
+    fn connection(&self) -> Option<Arc<Connection>> {
+        lock(&self.connection).clone()
+    }
+}
+
+/// Spawns the child and wires its pipes, returning it for supervision
+fn start(shared: &Arc<Shared>) -> Result<Child, ExtensionError> {
+    let config = &shared.config;
+    let mut command = Command::new(&config.command);
+    command
+        .args(&config.args)
+        .envs(&config.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(cwd) = &config.cwd {
+        command.current_dir(cwd);
+    }
+    let mut child = command.spawn()?;
+
+    let (Some(stdin), Some(stdout), Some(stderr)) =
+        (child.stdin.take(), child.stdout.take(), child.stderr.take())
+    else {
+        return Err(ExtensionError::Unavailable(format!(
+            "{} has no stdio pipes",
+            shared.id
+        )));
+    };
+
+    let pending: Pending = Arc::default();
+    tokio::spawn(read_responses(shared.id.clone(), stdout, pending.clone()));
+    tokio::spawn(forward_stderr(shared.id.clone(), stderr));
+    *lock(&shared.connection) = Some(Arc::new(Connection {
+        stdin: tokio::sync::Mutex::new(stdin),
+        pending,
+    }));
+    Ok(child)
+}
+
+/// Waits for the child to exit and restarts it with backoff
+async fn supervise(shared: Arc<Shared>, mut child: Child) {
+    loop {
+        tokio::select! {
+            status = child.wait() => {
+                let connection = lock(&shared.connection).take();
+                fail_pending(connection);
+                if shared.stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+                log::warn!("Extension {} exited ({:?})", shared.id, status.ok());
+            }
+            _ = shared.stop.notified() => {
+                let _ = child.kill().await;
+                fail_pending(lock(&shared.connection).take());
+                return;
+            }
+        }
+
+        child = loop {
+            let attempt = shared.crashes.fetch_add(1, Ordering::Relaxed) + 1;
+            if attempt > shared.config.max_restarts {
+                log::error!(
+                    "Extension {} crashed {} times in a row, giving up",
+                    shared.id,
+                    attempt - 1
+                );
+                return;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(shared.config.backoff(attempt)) => {}
+                _ = shared.stop.notified() => return,
+            }
+            if shared.stopped.load(Ordering::Relaxed) {
+                return;
+            }
+            match start(&shared) {
+                Ok(child) => break child,
+                Err(e) => log::warn!("Failed to restart extension {}: {}", shared.id, e),
+            }
+        };
+    }
+}
+
+fn fail_pending(connection: Option<Arc<Connection>>) {
+    if let Some(connection) = connection {
+        // Dropping the senders wakes every waiting caller
+        lock(&connection.pending).clear();
+    }
+}
+
+/// Routes JSON-RPC responses from stdout to their callers
+async fn read_responses(id: String, stdout: tokio::process::ChildStdout, pending: Pending) {
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            log::info!("[{}] {}", id, line);
+            continue;
+        };
+        let Some(request_id) = message.get("id").and_then(Value::as_u64) else {
+            continue;
+        };
+        let Some(tx) = lock(&pending).remove(&request_id) else {
+            continue;
+        };
+        let result = match message.get("error") {
+            Some(error) => Err(ExtensionError::Rpc {
+                code: error.get("code").and_then(Value::as_i64).unwrap_or(0),
+                message: error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+            }),
+            None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+        };
+        let _ = tx.send(result);
+    }
+}
+
+/// Copies the child's stderr into the app log
+async fn forward_stderr(id: String, stderr: tokio::process::ChildStderr) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        log::warn!(target: "extension", "[{}] {}", id, line);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// Shell one-liner answering every request with `result`.
+    /// Request keys are serialized sorted, so `id` always comes first.
+    fn responder(result: &str, after_reply: &str) -> ProcessConfig {
+        let script = format!(
+            r#"while IFS= read -r line; do
+                id=$(printf '%s' "$line" | sed 's/^{{"id":\([0-9]*\).*/\1/')
+                printf '{{"jsonrpc":"2.0","id":%s,"result":%s}}\n' "$id" '{result}'
+                {after_reply}
+            done"#
+        );
+        ProcessConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script],
+            timeout_ms: 2_000,
+            backoff_ms: 10,
+            ..ProcessConfig::default()
+        }
+    }
+
+    async fn wait_until_running(extension: &ProcessExtension) {
+        for _ in 0..200 {
+            if extension.is_running() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("extension did not restart");
+    }
+
+    mod backoff_tests {
+        use super::*;
+
+        #[test]
+        fn test_backoff_doubles_up_to_max() {
+            let config = ProcessConfig {
+                backoff_ms: 100,
+                ..ProcessConfig::default()
+            };
+            assert_eq!(config.backoff(1), Duration::from_millis(100));
+            assert_eq!(config.backoff(3), Duration::from_millis(400));
+            assert_eq!(config.backoff(40), Duration::from_millis(MAX_BACKOFF_MS));
+        }
+
+        #[test]
+        fn test_config_deserializes_hook_names() {
+            let config: ProcessConfig =
+                serde_json::from_value(json!({"command": "bun", "hooks": ["on_request"]})).unwrap();
+            assert_eq!(config.hooks, vec![Hook::Request]);
+            assert_eq!(config.timeout_ms, DEFAULT_TIMEOUT_MS);
+        }
+    }
+
+    mod rpc_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_round_trip() {
+            let extension =
+                ProcessExtension::spawn("echo", responder(r#"{"action":"reject"}"#, "")).unwrap();
+            let reply = extension
+                .call(Hook::Request, json!({"hook": "on_request"}))
+                .await
+                .unwrap();
+            assert_eq!(reply.action, crate::extensions::ReplyAction::Reject);
+
+            // Concurrent calls are matched to their own responses
+            let (a, b) = tokio::join!(
+                extension.request("on_response", json!({})),
+                extension.request("on_error", json!({}))
+            );
+            assert!(a.is_ok() && b.is_ok());
+        }
+
+        #[tokio::test]
+        async fn test_timeout() {
+            let mut config = responder("null", "");
+            config.args[1] = "while read -r line; do sleep 5; done".to_string();
+            config.timeout_ms = 50;
+            let extension = ProcessExtension::spawn("slow", config).unwrap();
+
+            let result = extension.request("on_request", json!({})).await;
+            assert!(matches!(result, Err(ExtensionError::Timeout(50))));
+        }
+
+        #[tokio::test]
+        async fn test_rpc_error() {
+            let mut config = responder("null", "");
+            config.args[1] = r#"while IFS= read -r line; do
+                id=$(printf '%s' "$line" | sed 's/^{"id":\([0-9]*\).*/\1/')
+                printf '{"jsonrpc":"2.0","id":%s,"error":{"code":-32601,"message":"nope"}}\n' "$id"
+            done"#
+                .to_string();
+            let extension = ProcessExtension::spawn("err", config).unwrap();
+
+            let result = extension.request("on_request", json!({})).await;
+            assert!(matches!(
+                result,
+                Err(ExtensionError::Rpc { code: -32601, .. })
+            ));
+        }
+
+        #[tokio::test]
+        async fn test_restarts_after_crash() {
+            let extension = ProcessExtension::spawn("crashy", responder("null", "exit 1")).unwrap();
+            extension.request("on_request", json!({})).await.unwrap();
+
+            // The child exits after answering and comes back after the backoff
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            wait_until_running(&extension).await;
+            extension.request("on_request", json!({})).await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_gives_up_after_max_restarts() {
+            let mut config = responder("null", "");
+            config.args[1] = "exit 1".to_string();
+            config.max_restarts = 2;
+            let extension = ProcessExtension::spawn("broken", config).unwrap();
+
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            assert!(!extension.is_running());
+            assert_eq!(extension.crashes(), 3);
+            assert!(matches!(
+                extension.request("on_request", json!({})).await,
+                Err(ExtensionError::Unavailable(_))
+            ));
+        }
+
+        #[tokio::test]
+        async fn test_spawn_fails_for_missing_command() {
+            let config = ProcessConfig {
+                command: "blackbox-no-such-binary".to_string(),
+                ..ProcessConfig::default()
+            };
+            assert!(matches!(
+                ProcessExtension::spawn("missing", config),
+                Err(ExtensionError::Io(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! WebAssembly extension host
+//!
+//! WASM extensions are core modules run by wasmtime without any imports,
+//! so they cannot touch the filesystem or network. The ABI is deliberately
+//! small so any language that compiles to WASM can implement it:
+//!
+//! - export `memory` and `alloc(len: i32) -> i32`, which returns a buffer
+//!   the host writes the JSON hook payload into
+//! - export any of `on_request`, `on_response`, `on_stream_chunk` and
+//!   `on_error` as `(ptr: i32, len: i32) -> i64`
+//! - return `(reply_ptr << 32) | reply_len` pointing at a JSON
+//!   [`HookReply`](super::HookReply), or `0` to continue unchanged
+//!
+//! Every hook call runs in a fresh instance with its own fuel budget and
+//! memory cap, so extensions are stateless and a runaway loop or
+//! allocation only fails that one call.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use wasmtime::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap};
+
+use super::{ExtensionError, Hook, HookReply, HookRunner};
+
+/// Default fuel per hook call, roughly tens of milliseconds of work
+pub const DEFAULT_FUEL: u64 = 50_000_000;
+/// Default linear memory cap per instance
+pub const DEFAULT_MEMORY_BYTES: usize = 32 * 1024 * 1024;
+
+/// Resource limits applied to every hook call of one extension
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WasmLimits {
+    /// Instructions budget, see wasmtime fuel metering
+    pub fuel: u64,
+    /// Maximum linear memory in bytes
+    pub memory_bytes: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: DEFAULT_FUEL,
+            memory_bytes: DEFAULT_MEMORY_BYTES,
+        }
+    }
+}
+
+impl From<wasmtime::Error> for ExtensionError {
+    fn from(error: wasmtime::Error) -> Self {
+        if error.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) {
+            return ExtensionError::FuelExhausted;
+        }
+        ExtensionError::Wasm(format!("{:#}", error))
+    }
+}
+
+/// Shared wasmtime engine that compiles extension modules
+#[derive(Clone)]
+pub struct WasmHost {
+    engine: Engine,
+}
+
+impl WasmHost {
+    /// Creates an engine with fuel metering enabled
+    pub fn new() -> Result<Self, ExtensionError> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        Ok(Self {
+            engine: Engine::new(&config)?,
+        })
+    }
+
+    /// Compiles a module from a `.wasm` file
+    pub fn load_file(&self, path: &Path, limits: WasmLimits) -> Result<WasmModule, ExtensionError> {
+        self.load_bytes(&std::fs::read(path)?, limits)
+    }
+
+    /// Compiles a module from its binary
+    pub fn load_bytes(
+        &self,
+        bytes: &[u8],
+        limits: WasmLimits,
+    ) -> Result<WasmModule, ExtensionError> {
+        let module = Module::new(&self.engine, bytes)?;
+        if module.get_export("alloc").is_none() {
+            return Err(ExtensionError::MissingExport("alloc".to_string()));
+        }
+        if module.get_export("memory").is_none() {
+            return Err(ExtensionError::MissingExport("memory".to_string()));
+        }
+        let hooks = Hook::ALL
+            .into_iter()
+            .filter(|hook| module.get_export(hook.as_str()).is_some())
+            .collect();
+        Ok(WasmModule {
+            inner: Arc::new(Compiled {
+                engine: self.engine.clone(),
+                module,
+                limits,
+                hooks,
+            }),
+        })
+    }
+}
+
+struct Compiled {
+    engine: Engine,
+    module: Module,
+    limits: WasmLimits,
+    hooks: Vec<Hook>,
+}
+
+struct StoreState {
+    limits: StoreLimits,
+}
+
+/// A compiled extension ready to run hooks
+#[derive(Clone)]
+pub struct WasmModule {
+    inner: Arc<Compiled>,
+}
+
+impl WasmModule {
+    /// Hooks the module exports
+    pub fn hooks(&self) -> &[Hook] {
+        &self.inner.hooks
+    }
+
+    /// Runs a hook synchronously and returns the raw reply bytes
+    pub fn invoke(&self, hook: Hook, input: &[u8]) -> Result<Vec<u8>, ExtensionError> {
+        let compiled = &self.inner;
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(compiled.limits.memory_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&compiled.engine, StoreState { limits });
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(compiled.limits.fuel)?;
+
+        // No imports are linked, so the module runs fully sandboxed
+        let instance = Linker::new(&compiled.engine).instantiate(&mut store, &compiled.module)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| ExtensionError::MissingExport("memory".to_string()))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let run = instance.get_typed_func::<(i32, i32), i64>(&mut store, hook.as_str())?;
+
+        let len = i32::try_from(input.len())
+            .map_err(|_| ExtensionError::Wasm("hook payload too large".to_string()))?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, input)
+            .map_err(|e| ExtensionError::Wasm(e.to_string()))?;
+
+        let packed = run.call(&mut store, (ptr, len))? as u64;
+        if packed == 0 {
+            return Ok(Vec::new());
+        }
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        // Bounds are checked before copying so a bogus length allocates nothing
+        out_ptr
+            .checked_add(out_len)
+            .and_then(|end| memory.data(&store).get(out_ptr..end))
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| ExtensionError::InvalidReply("reply points outside memory".to_string()))
+    }
+}
+
+#[async_trait]
+impl HookRunner for WasmModule {
+    fn supports(&self, hook: Hook) -> bool {
+        self.inner.hooks.contains(&hook)
+    }
+
+    async fn call(&self, hook: Hook, payload: Value) -> Result<HookReply, ExtensionError> {
+        let module = self.clone();
+        let input = payload.to_string().into_bytes();
+        // Compiled code blocks the thread until it returns or runs out of fuel
+        let output = tokio::task::spawn_blocking(move || module.invoke(hook, &input))
+            .await
+            .map_err(|e| ExtensionError::Wasm(e.to_string()))??;
+        HookReply::parse(&output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ExtensionPlugin;
+    use super::*;
+    use crate::pipeline::{HookOutcome, Plugin, PluginContext, PluginRequest};
+    use crate::proxy::Provider;
+    use axum::http::HeaderMap;
+    use serde_json::json;
+
+    /// Module exporting `on_request` with the given body and a static reply
+    fn module_with(body: &str, reply: &str) -> Vec<u8> {
+        let escaped = reply.replace('"', "\\\"");
+        wat::parse_str(format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 4096))
+                (data (i32.const 0) "{escaped}")
+                (func (export "alloc") (param $len i32) (result i32)
+                    (local $ptr i32)
+                    (local.set $ptr (global.get $next))
+                    (global.set $next (i32.add (global.get $next) (local.get $len)))
+                    (if (i32.gt_u (global.get $next) (i32.mul (memory.size) (i32.const 65536)))
+                        (then (drop (memory.grow (i32.const 16)))))
+                    (local.get $ptr))
+                (func (export "on_request") (param $ptr i32) (param $len i32) (result i64)
+                    {body}
+                    (i64.const {len})))"#,
+            len = reply.len(),
+        ))
+        .unwrap()
+    }
+
+    fn load(bytes: &[u8], limits: WasmLimits) -> WasmModule {
+        WasmHost::new().unwrap().load_bytes(bytes, limits).unwrap()
+    }
+
+    fn payload() -> Value {
+        json!({"hook": "on_request", "request": {"body": {"model": "gpt-4o"}}})
+    }
+
+    mod host_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_static_reply() {
+            let module = load(
+                &module_with("", r#"{"action":"reject","message":"nope"}"#),
+                WasmLimits::default(),
+            );
+            assert_eq!(module.hooks(), &[Hook::Request]);
+            assert!(!module.supports(Hook::Response));
+
+            let reply = module.call(Hook::Request, payload()).await.unwrap();
+            assert_eq!(reply.action, crate::extensions::ReplyAction::Reject);
+            assert_eq!(reply.message.as_deref(), Some("nope"));
+        }
+
+        #[tokio::test]
+        async fn test_zero_means_continue() {
+            let bytes = wat::parse_str(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                    (func (export "on_request") (param i32 i32) (result i64) (i64.const 0)))"#,
+            )
+            .unwrap();
+            let reply = load(&bytes, WasmLimits::default())
+                .call(Hook::Request, payload())
+                .await
+                .unwrap();
+            assert_eq!(reply, HookReply::default());
+        }
+
+        #[test]
+        fn test_infinite_loop_runs_out_of_fuel() {
+            let module = load(
+                &module_with("(loop $spin (br $spin))", "{}"),
+                WasmLimits {
+                    fuel: 100_000,
+                    ..WasmLimits::default()
+                },
+            );
+            assert!(matches!(
+                module.invoke(Hook::Request, b"{}"),
+                Err(ExtensionError::FuelExhausted)
+            ));
+        }
+
+        #[test]
+        fn test_memory_limit_stops_growth() {
+            let grow =
+                "(if (i32.eq (memory.grow (i32.const 1024)) (i32.const -1)) (then unreachable))";
+            let module = load(
+                &module_with(grow, "{}"),
+                WasmLimits {
+                    memory_bytes: 1024 * 1024,
+                    ..WasmLimits::default()
+                },
+            );
+            assert!(matches!(
+                module.invoke(Hook::Request, b"{}"),
+                Err(ExtensionError::Wasm(_))
+            ));
+        }
+
+        #[test]
+        fn test_reply_outside_memory_is_invalid() {
+            let bytes = wat::parse_str(
+                r#"(module
+                    (memory (export "memory") 1)
+                    (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                    (func (export "on_request") (param i32 i32) (result i64)
+                        (i64.const 0xffffffff)))"#,
+            )
+            .unwrap();
+            assert!(matches!(
+                load(&bytes, WasmLimits::default()).invoke(Hook::Request, b"{}"),
+                Err(ExtensionError::InvalidReply(_))
+            ));
+        }
+
+        #[test]
+        fn test_rejects_modules_without_alloc() {
+            let bytes = wat::parse_str(r#"(module (memory (export "memory") 1))"#).unwrap();
+            let result = WasmHost::new()
+                .unwrap()
+                .load_bytes(&bytes, WasmLimits::default());
+            assert!(matches!(result, Err(ExtensionError::MissingExport(name)) if name == "alloc"));
+        }
+    }
+
+    mod plugin_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_plugin_applies_reply() {
+            let module = load(
+                &module_with(
+                    "",
+                    r#"{"body":{"model":"llama3.2:3b"},"metadata":{"wasm":true}}"#,
+                ),
+                WasmLimits::default(),
+            );
+            let plugin = ExtensionPlugin::new("wasm.test", "test", "", Box::new(module));
+            let mut ctx =
+                PluginContext::new("call", Provider::OpenAi, "/v1/chat/completions", "gpt-4o");
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({"model": "gpt-4o"}),
+            };
+
+            assert_eq!(
+                plugin.on_request(&mut ctx, &mut request).await,
+                HookOutcome::Continue
+            );
+            assert_eq!(request.body["model"], "llama3.2:3b");
+            assert_eq!(ctx.metadata["wasm"], true);
+        }
+
+        #[tokio::test]
+        async fn test_runaway_plugin_fails_open() {
+            let module = load(
+                &module_with("(loop $spin (br $spin))", r#"{"action":"reject"}"#),
+                WasmLimits {
+                    fuel: 100_000,
+                    ..WasmLimits::default()
+                },
+            );
+            let plugin = ExtensionPlugin::new("wasm.spin", "spin", "", Box::new(module));
+            let mut ctx =
+                PluginContext::new("call", Provider::OpenAi, "/v1/chat/completions", "gpt-4o");
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({}),
+            };
+
+            assert_eq!(
+                plugin.on_request(&mut ctx, &mut request).await,
+                HookOutcome::Continue
+            );
+        }
+    }
+}
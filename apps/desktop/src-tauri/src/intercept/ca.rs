@@ -0,0 +1,429 @@
+//! Local certificate authority
+//!
+//! Users generate a Blackbox root certificate from Settings, which the
+//! [`CaStore`] keeps under the app data dir with a key only they can read.
+//! Once the root is trusted, leaf certificates for intercepted hosts are
+//! minted on the fly and kept in memory; rotating the root throws them away
+//! with it. The root's private key never leaves the machine.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair,
+    KeyUsagePurpose,
+};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use crate::credentials::write_private;
+use crate::sync::{lock, read, write};
+
+/// Directory under the app data dir holding the root certificate
+pub const CA_DIR: &str = "ca";
+/// Root certificate, the file users import into their trust store
+pub const CA_CERT_FILE: &str = "blackbox-ca.pem";
+/// Private key of the root certificate, readable by the current user only
+pub const CA_KEY_FILE: &str = "blackbox-ca-key.pem";
+
+/// Common name users see in their trust store
+const CA_NAME: &str = "Blackbox Local CA";
+const CA_VALIDITY_DAYS: i64 = 10 * 365;
+/// Kept short since clients cap the lifetime of leaf certificates
+const LEAF_VALIDITY_DAYS: i64 = 90;
+/// Leaves are minted again once this old, long before they expire
+const LEAF_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Number of hosts whose leaves are kept before the cache starts over
+const MAX_LEAVES: usize = 1024;
+
+/// Errors raised while loading the root or minting leaves
+#[derive(Debug, thiserror::Error)]
+pub enum CaError {
+    #[error("failed to access the CA files: {0}")]
+    Io(#[from] io::Error),
+    #[error("certificate error: {0}")]
+    Certificate(#[from] rcgen::Error),
+    #[error("invalid CA certificate: {0}")]
+    Pem(#[from] rustls::pki_types::pem::Error),
+    #[error("TLS configuration error: {0}")]
+    Tls(#[from] rustls::Error),
+    #[error("no root certificate has been generated")]
+    Missing,
+}
+
+/// Encoding of an exported root certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaFormat {
+    Pem,
+    Der,
+}
+
+/// Details of the root certificate shown in Settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaInfo {
+    pub subject: String,
+    /// SHA-256 of the DER encoding, colon separated as trust stores show it
+    pub fingerprint: String,
+    pub created_at: String,
+    pub expires_at: String,
+    /// File users import into their trust store, `None` when kept in memory
+    pub cert_path: Option<String>,
+}
+
+struct Leaf {
+    config: Arc<ServerConfig>,
+    minted: Instant,
+}
+
+/// Root certificate and the leaves minted with it
+pub struct CertificateAuthority {
+    cert_pem: String,
+    cert_der: CertificateDer<'static>,
+    issuer: Issuer<'static, KeyPair>,
+    not_before: OffsetDateTime,
+    not_after: OffsetDateTime,
+    leaves: Mutex<HashMap<String, Leaf>>,
+}
+
+impl CertificateAuthority {
+    /// Generates a new root certificate kept in memory only
+    pub fn generate() -> Result<Self, CaError> {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, CA_NAME);
+        params
+            .distinguished_name
+            .push(DnType::OrganizationName, "Blackbox");
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        // Whole seconds, as certificates store them; the start doubles as creation date
+        let now = OffsetDateTime::now_utc();
+        let now = now - time::Duration::nanoseconds(now.nanosecond().into());
+        params.not_before = now;
+        params.not_after = now + time::Duration::days(CA_VALIDITY_DAYS);
+
+        let key = KeyPair::generate()?;
+        let cert = params.self_signed(&key)?;
+        Ok(Self {
+            cert_pem: cert.pem(),
+            cert_der: cert.der().clone(),
+            not_before: params.not_before,
+            not_after: params.not_after,
+            issuer: Issuer::new(params, key),
+            leaves: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Loads the root from PEM encoded certificate and key
+    pub fn from_pem(cert_pem: &str, key_pem: &str) -> Result<Self, CaError> {
+        let key = KeyPair::from_pem(key_pem)?;
+        let cert_der = CertificateDer::from_pem_slice(cert_pem.as_bytes())?;
+        let (_, parsed) = x509_parser::parse_x509_certificate(&cert_der)
+            .map_err(|_| rcgen::Error::CouldNotParseCertificate)?;
+        let validity = parsed.validity();
+        let timestamp = |time: x509_parser::time::ASN1Time| {
+            OffsetDateTime::from_unix_timestamp(time.timestamp())
+                .map_err(|_| rcgen::Error::CouldNotParseCertificate)
+        };
+        Ok(Self {
+            cert_pem: cert_pem.to_string(),
+            not_before: timestamp(validity.not_before)?,
+            not_after: timestamp(validity.not_after)?,
+            issuer: Issuer::from_ca_cert_pem(cert_pem, key)?,
+            cert_der,
+            leaves: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// PEM encoded root certificate
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    /// Root certificate in the given encoding
+    pub fn export(&self, format: CaFormat) -> Vec<u8> {
+        match format {
+            CaFormat::Pem => self.cert_pem.as_bytes().to_vec(),
+            CaFormat::Der => self.cert_der.to_vec(),
+        }
+    }
+
+    /// Subject, fingerprint and validity of the root
+    pub fn info(&self) -> CaInfo {
+        let rfc3339 = |time: OffsetDateTime| {
+            chrono::DateTime::from_timestamp(time.unix_timestamp(), 0)
+                .unwrap_or_default()
+                .to_rfc3339()
+        };
+        CaInfo {
+            subject: CA_NAME.to_string(),
+            fingerprint: Sha256::digest(&self.cert_der)
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(":"),
+            created_at: rfc3339(self.not_before),
+            expires_at: rfc3339(self.not_after),
+            cert_path: None,
+        }
+    }
+
+    /// Forgets every leaf minted so far
+    fn clear_leaves(&self) {
+        lock(&self.leaves).clear();
+    }
+
+    /// TLS settings presenting a leaf certificate for `host`, minted on first use
+    pub fn server_config(&self, host: &str) -> Result<Arc<ServerConfig>, CaError> {
+        let mut leaves = lock(&self.leaves);
+        if let Some(leaf) = leaves.get(host) {
+            if leaf.minted.elapsed() < LEAF_MAX_AGE {
+                return Ok(leaf.config.clone());
+            }
+        }
+        let config = Arc::new(self.mint(host)?);
+        if leaves.len() >= MAX_LEAVES {
+            leaves.clear();
+        }
+        leaves.insert(
+            host.to_string(),
+            Leaf {
+                config: config.clone(),
+                minted: Instant::now(),
+            },
+        );
+        Ok(config)
+    }
+
+    fn mint(&self, host: &str) -> Result<ServerConfig, CaError> {
+        // IP addresses become IP subject alternative names
+        let mut params = CertificateParams::new(vec![host.to_string()])?;
+        params.distinguished_name.push(DnType::CommonName, host);
+        params.use_authority_key_identifier_extension = true;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        // Backdated for clock skew, but never before the root itself
+        let now = OffsetDateTime::now_utc();
+        params.not_before = (now - time::Duration::days(1)).max(self.not_before);
+        params.not_after = (now + time::Duration::days(LEAF_VALIDITY_DAYS)).min(self.not_after);
+
+        let key = KeyPair::generate()?;
+        let cert = params.signed_by(&key, &self.issuer)?;
+        let mut config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![cert.der().clone(), self.cert_der.clone()],
+                    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+                )?;
+        // Decrypted traffic is served over HTTP/1.1 only
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+/// The current root certificate, saved under the app data dir
+pub struct CaStore {
+    /// `None` keeps the root in memory only
+    dir: Option<PathBuf>,
+    current: RwLock<Option<Arc<CertificateAuthority>>>,
+}
+
+impl CaStore {
+    /// Creates a store without a root that never touches the disk
+    pub fn in_memory() -> Self {
+        Self {
+            dir: None,
+            current: RwLock::new(None),
+        }
+    }
+
+    /// Opens the store in `dir`, loading the root saved there if any
+    pub fn open(dir: PathBuf) -> Result<Self, CaError> {
+        let cert_path = dir.join(CA_CERT_FILE);
+        let key_path = dir.join(CA_KEY_FILE);
+        let current = if cert_path.exists() && key_path.exists() {
+            Some(Arc::new(CertificateAuthority::from_pem(
+                &fs::read_to_string(cert_path)?,
+                &fs::read_to_string(key_path)?,
+            )?))
+        } else {
+            None
+        };
+        Ok(Self {
+            dir: Some(dir),
+            current: RwLock::new(current),
+        })
+    }
+
+    /// The root in use, `None` until one is generated
+    pub fn current(&self) -> Option<Arc<CertificateAuthority>> {
+        read(&self.current).clone()
+    }
+
+    /// Details of the root in use
+    pub fn info(&self) -> Option<CaInfo> {
+        let mut info = self.current()?.info();
+        info.cert_path = self
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(CA_CERT_FILE).display().to_string());
+        Some(info)
+    }
+
+    /// Generates a root when there is none yet, otherwise keeps the current one
+    pub fn generate(&self) -> Result<CaInfo, CaError> {
+        if self.current().is_none() {
+            self.replace()?;
+        }
+        self.info().ok_or(CaError::Missing)
+    }
+
+    /// Replaces the root with a new one; leaves minted by the old root are discarded
+    pub fn rotate(&self) -> Result<CaInfo, CaError> {
+        if let Some(old) = self.replace()? {
+            old.clear_leaves();
+        }
+        self.info().ok_or(CaError::Missing)
+    }
+
+    /// Root certificate in the given encoding
+    pub fn export(&self, format: CaFormat) -> Result<Vec<u8>, CaError> {
+        Ok(self.current().ok_or(CaError::Missing)?.export(format))
+    }
+
+    /// Saves and switches to a new root, returning the previous one
+    fn replace(&self) -> Result<Option<Arc<CertificateAuthority>>, CaError> {
+        let ca = CertificateAuthority::generate()?;
+        if let Some(dir) = &self.dir {
+            save(dir, &ca)?;
+        }
+        let mut current = write(&self.current);
+        Ok(current.replace(Arc::new(ca)))
+    }
+}
+
+/// Writes the root to `dir`, swapping files in only once both are written
+fn save(dir: &Path, ca: &CertificateAuthority) -> Result<(), CaError> {
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    let key_path = dir.join(CA_KEY_FILE);
+    let cert_path = dir.join(CA_CERT_FILE);
+    let key_tmp = key_path.with_extension("pem.tmp");
+    let cert_tmp = cert_path.with_extension("pem.tmp");
+    write_private(&key_tmp, ca.issuer.key().serialize_pem().as_bytes())?;
+    fs::write(&cert_tmp, &ca.cert_pem)?;
+    fs::rename(key_tmp, key_path)?;
+    fs::rename(cert_tmp, cert_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod ca_tests {
+        use super::*;
+
+        #[test]
+        fn test_generate_saves_root_with_private_key() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CaStore::open(dir.path().join(CA_DIR)).unwrap();
+            assert!(store.info().is_none());
+            assert!(matches!(store.export(CaFormat::Pem), Err(CaError::Missing)));
+
+            let info = store.generate().unwrap();
+            assert_eq!(store.generate().unwrap(), info);
+            let reopened = CaStore::open(dir.path().join(CA_DIR)).unwrap();
+            assert_eq!(reopened.info().unwrap(), info);
+            assert_eq!(info.fingerprint.len(), 32 * 3 - 1);
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = |name: &str| {
+                    fs::metadata(dir.path().join(CA_DIR).join(name))
+                        .unwrap()
+                        .permissions()
+                        .mode()
+                        & 0o777
+                };
+                assert_eq!(mode(CA_KEY_FILE), 0o600);
+                assert_eq!(mode(""), 0o700);
+            }
+        }
+
+        #[test]
+        fn test_info_and_export() {
+            let store = CaStore::in_memory();
+            let info = store.generate().unwrap();
+            let created = chrono::DateTime::parse_from_rfc3339(&info.created_at).unwrap();
+            let expires = chrono::DateTime::parse_from_rfc3339(&info.expires_at).unwrap();
+            assert_eq!((expires - created).num_days(), CA_VALIDITY_DAYS);
+            assert!((chrono::Utc::now() - created.to_utc()).num_seconds() < 60);
+            assert_eq!(info.cert_path, None);
+
+            let pem = String::from_utf8(store.export(CaFormat::Pem).unwrap()).unwrap();
+            let der = store.export(CaFormat::Der).unwrap();
+            assert!(pem.starts_with("-----BEGIN CERTIFICATE-----"));
+            assert_eq!(
+                CertificateDer::from_pem_slice(pem.as_bytes())
+                    .unwrap()
+                    .as_ref(),
+                der.as_slice()
+            );
+            let digest = Sha256::digest(&der);
+            assert!(info.fingerprint.starts_with(&format!("{:02X}:", digest[0])));
+        }
+
+        #[test]
+        fn test_rotation_discards_old_leaves() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = CaStore::open(dir.path().to_path_buf()).unwrap();
+            let before = store.generate().unwrap();
+            let old = store.current().unwrap();
+            let old_leaf = old.server_config("api.openai.com").unwrap();
+
+            let after = store.rotate().unwrap();
+            assert_ne!(before.fingerprint, after.fingerprint);
+            assert!(old.leaves.lock().unwrap().is_empty());
+            let new_leaf = store
+                .current()
+                .unwrap()
+                .server_config("api.openai.com")
+                .unwrap();
+            assert!(!Arc::ptr_eq(&old_leaf, &new_leaf));
+
+            let reopened = CaStore::open(dir.path().to_path_buf()).unwrap();
+            assert_eq!(reopened.info().unwrap().fingerprint, after.fingerprint);
+        }
+
+        #[test]
+        fn test_leaves_are_cached_per_host() {
+            let ca = CertificateAuthority::generate().unwrap();
+            let first = ca.server_config("api.openai.com").unwrap();
+            let again = ca.server_config("api.openai.com").unwrap();
+            let other = ca.server_config("127.0.0.1").unwrap();
+            assert!(Arc::ptr_eq(&first, &again));
+            assert!(!Arc::ptr_eq(&first, &other));
+            assert_eq!(first.alpn_protocols, vec![b"http/1.1".to_vec()]);
+        }
+    }
+}
@@ -0,0 +1,808 @@
+//! Transparent HTTPS interception
+//!
+//! Browsers and desktop apps that cannot be pointed at a base URL can use
+//! Blackbox as their HTTP proxy instead. The intercept server only speaks
+//! `CONNECT`: tunnels to allowlisted LLM domains are terminated with a leaf
+//! certificate minted by the local [`CertificateAuthority`], and the
+//! decrypted requests run through the same handlers as the embedded proxy
+//! before being forwarded to the real host. Every other tunnel is relayed
+//! byte for byte without being decrypted. Which domains are intercepted is
+//! up to the [`PolicyStore`], which also generates the PAC file served at
+//! [`PAC_PATH`] so browsers only send LLM domains to Blackbox.
+
+pub mod ca;
+pub mod policy;
+
+pub use ca::{CaError, CaFormat, CaInfo, CaStore, CertificateAuthority};
+pub use policy::{Decision, InterceptPolicy, PolicyError, PolicyStore};
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::Router;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_rustls::TlsAcceptor;
+
+use crate::clients::ClientKeyStore;
+use crate::events::NoopSink;
+use crate::proxy::{self, CircuitBreakers, ProxyConfig, ProxyContext, ProxyServices};
+use crate::routing::{RouteStore, RoutingTable};
+use crate::sync::lock;
+
+/// Default localhost port of the intercepting proxy, next to the API proxy
+pub const DEFAULT_INTERCEPT_PORT: u16 = 7214;
+/// Path the PAC file is served at
+pub const PAC_PATH: &str = "/proxy.pac";
+
+/// Longest `CONNECT` request head accepted from a client
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+/// How long a client may take to send its `CONNECT` request
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long `stop` waits for the accept loop before aborting it
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Interception settings, persisted in the settings store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InterceptConfig {
+    /// Localhost port to listen on, 0 picks a free port
+    pub port: u16,
+    /// Whether interception starts together with the app
+    pub auto_start: bool,
+}
+
+impl Default for InterceptConfig {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_INTERCEPT_PORT,
+            // Nothing can be decrypted until the user trusts the CA
+            auto_start: false,
+        }
+    }
+}
+
+/// Snapshot of the intercepting proxy reported to the frontend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InterceptStatus {
+    pub running: bool,
+    /// Address the server is bound to, e.g. `127.0.0.1:7214`
+    pub address: Option<String>,
+    pub port: u16,
+    /// Tunnels decrypted since the server was started
+    pub intercepted: u64,
+    /// Tunnels relayed without decryption since the server was started
+    pub tunneled: u64,
+    /// URL of the generated PAC file while running
+    pub pac_url: Option<String>,
+}
+
+/// Errors raised while starting the intercepting proxy
+#[derive(Debug, thiserror::Error)]
+pub enum InterceptError {
+    #[error("failed to bind interception port {port}: {source}")]
+    Bind {
+        port: u16,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to build upstream client: {0}")]
+    Client(#[from] reqwest::Error),
+    #[error(transparent)]
+    Ca(#[from] CaError),
+}
+
+/// State shared by every connection of a running server
+struct InterceptContext {
+    address: SocketAddr,
+    ca: Arc<CaStore>,
+    policy: Arc<PolicyStore>,
+    services: ProxyServices,
+    client: reqwest::Client,
+    /// Request handlers per intercepted origin
+    origins: Mutex<HashMap<String, Router>>,
+    intercepted: AtomicU64,
+    tunneled: AtomicU64,
+}
+
+impl InterceptContext {
+    /// Handlers forwarding decrypted requests to `host`
+    fn router(&self, host: &str, port: u16) -> Router {
+        let origin = match port {
+            443 => format!("https://{}", host),
+            _ => format!("https://{}:{}", host, port),
+        };
+        let mut origins = lock(&self.origins);
+        origins
+            .entry(origin.clone())
+            .or_insert_with(|| {
+                let (policy, host) = (self.policy.clone(), host.to_string());
+                let captured: proxy::PathFilter =
+                    Arc::new(move |path| policy.captures(&host, path));
+                // The client's own credentials are passed through to the real host
+                let config = ProxyConfig {
+                    upstream_url: origin,
+                    ..ProxyConfig::default()
+                };
+                let context =
+                    ProxyContext::with_client(config, &self.services, self.client.clone());
+                proxy::intercept_router(Arc::new(context), captured)
+            })
+            .clone()
+    }
+}
+
+struct RunningIntercept {
+    address: SocketAddr,
+    context: Arc<InterceptContext>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+/// Owns the intercepting proxy lifecycle; managed as Tauri state
+pub struct InterceptServer {
+    config: Mutex<InterceptConfig>,
+    ca: Arc<CaStore>,
+    policy: Arc<PolicyStore>,
+    services: ProxyServices,
+    /// Extra roots trusted for upstream connections
+    upstream_roots: Vec<reqwest::Certificate>,
+    running: tokio::sync::Mutex<Option<RunningIntercept>>,
+}
+
+impl InterceptServer {
+    /// Creates a stopped server minting leaves with the root in `ca`
+    ///
+    /// Decrypted requests always go to the host they were sent to, so the
+    /// routing table, client keys and breakers of `services` are left out.
+    pub fn new(config: InterceptConfig, ca: Arc<CaStore>, services: ProxyServices) -> Self {
+        let services = services
+            .with_routes(RouteStore::in_memory(RoutingTable::default()))
+            .with_clients(ClientKeyStore::in_memory())
+            .with_breakers(CircuitBreakers::new(Arc::new(NoopSink)));
+        Self {
+            config: Mutex::new(config),
+            ca,
+            policy: Arc::new(PolicyStore::in_memory(InterceptPolicy::default())),
+            services,
+            upstream_roots: Vec::new(),
+            running: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Replaces the policy, which intercepts nothing by default
+    pub fn with_policy(mut self, policy: PolicyStore) -> Self {
+        self.policy = Arc::new(policy);
+        self
+    }
+
+    /// Also trusts `root` for upstream connections, e.g. a corporate TLS inspection root
+    pub fn with_upstream_root(mut self, root: reqwest::Certificate) -> Self {
+        self.upstream_roots.push(root);
+        self
+    }
+
+    /// Returns the stored configuration
+    pub fn config(&self) -> InterceptConfig {
+        lock(&self.config).clone()
+    }
+
+    /// Live interception policy
+    pub fn policy(&self) -> Arc<PolicyStore> {
+        self.policy.clone()
+    }
+
+    /// Root certificate store, shared with the CA commands
+    pub fn ca(&self) -> Arc<CaStore> {
+        self.ca.clone()
+    }
+
+    /// Starts the server, restarting it when already running.
+    /// When `config` is given it replaces the stored configuration.
+    pub async fn start(
+        &self,
+        config: Option<InterceptConfig>,
+    ) -> Result<InterceptStatus, InterceptError> {
+        let config = config.unwrap_or_else(|| self.config());
+        if self.ca.current().is_none() {
+            return Err(CaError::Missing.into());
+        }
+
+        let mut running = self.running.lock().await;
+        if let Some(previous) = running.take() {
+            shutdown(previous).await;
+        }
+
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, config.port))
+            .await
+            .map_err(|source| InterceptError::Bind {
+                port: config.port,
+                source,
+            })?;
+        let address = listener
+            .local_addr()
+            .map_err(|source| InterceptError::Bind {
+                port: config.port,
+                source,
+            })?;
+
+        // Never route our own upstream requests back through a system proxy
+        let mut client = proxy::upstream_client(&ProxyConfig::default()).no_proxy();
+        for root in &self.upstream_roots {
+            client = client.add_root_certificate(root.clone());
+        }
+        let context = Arc::new(InterceptContext {
+            address,
+            ca: self.ca.clone(),
+            policy: self.policy.clone(),
+            services: self.services.clone(),
+            client: client.build()?,
+            origins: Mutex::new(HashMap::new()),
+            intercepted: AtomicU64::new(0),
+            tunneled: AtomicU64::new(0),
+        });
+
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+        let accepting = context.clone();
+        let task = tokio::spawn(async move {
+            // Dropping the set on shutdown closes every open tunnel
+            let mut connections = JoinSet::new();
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    accepted = listener.accept() => {
+                        let Ok((stream, _)) = accepted else { continue };
+                        let context = accepting.clone();
+                        connections.spawn(async move {
+                            if let Err(e) = handle_connection(context, stream).await {
+                                log::debug!("Intercepted connection failed: {}", e);
+                            }
+                        });
+                    }
+                }
+                while connections.try_join_next().is_some() {}
+            }
+        });
+
+        *lock(&self.config) = config;
+        *running = Some(RunningIntercept {
+            address,
+            context,
+            shutdown: shutdown_tx,
+            task,
+        });
+        Ok(status_of(&self.config(), running.as_ref()))
+    }
+
+    /// Stops the server if it is running
+    pub async fn stop(&self) -> InterceptStatus {
+        let mut running = self.running.lock().await;
+        if let Some(previous) = running.take() {
+            shutdown(previous).await;
+        }
+        status_of(&self.config(), None)
+    }
+
+    /// Reports whether the server is running and what it has handled
+    pub async fn status(&self) -> InterceptStatus {
+        let running = self.running.lock().await;
+        status_of(&self.config(), running.as_ref())
+    }
+}
+
+async fn shutdown(server: RunningIntercept) {
+    let _ = server.shutdown.send(());
+    let mut task = server.task;
+    if tokio::time::timeout(SHUTDOWN_GRACE, &mut task)
+        .await
+        .is_err()
+    {
+        task.abort();
+    }
+}
+
+fn status_of(config: &InterceptConfig, running: Option<&RunningIntercept>) -> InterceptStatus {
+    match running {
+        Some(server) => InterceptStatus {
+            running: true,
+            address: Some(server.address.to_string()),
+            port: server.address.port(),
+            intercepted: server.context.intercepted.load(Ordering::Relaxed),
+            tunneled: server.context.tunneled.load(Ordering::Relaxed),
+            pac_url: Some(format!("http://{}{}", server.address, PAC_PATH)),
+        },
+        None => InterceptStatus {
+            running: false,
+            address: None,
+            port: config.port,
+            intercepted: 0,
+            tunneled: 0,
+            pac_url: None,
+        },
+    }
+}
+
+async fn handle_connection(context: Arc<InterceptContext>, stream: TcpStream) -> io::Result<()> {
+    // Buffered so bytes sent right after the request head are not lost
+    let mut stream = BufReader::new(stream);
+    let head = tokio::time::timeout(HEAD_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no request head"))??;
+    let mut words = head.split_whitespace();
+    let (Some(method), Some(target)) = (words.next(), words.next()) else {
+        return respond(&mut stream, "400 Bad Request").await;
+    };
+    if method == "GET" && target == PAC_PATH {
+        let pac = context.policy.pac(&context.address.to_string());
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/x-ns-proxy-autoconfig\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            pac.len(),
+            pac
+        );
+        return stream.get_mut().write_all(response.as_bytes()).await;
+    }
+    if !method.eq_ignore_ascii_case("CONNECT") {
+        return respond(&mut stream, "405 Method Not Allowed").await;
+    }
+    let Some((host, port)) = split_authority(target) else {
+        return respond(&mut stream, "400 Bad Request").await;
+    };
+
+    let decision = context.policy.decide(&host);
+    if decision == Decision::Deny {
+        return respond(&mut stream, "403 Forbidden").await;
+    }
+    if decision == Decision::Intercept {
+        // Looked up per tunnel so a rotated root takes effect right away
+        let Some(ca) = context.ca.current() else {
+            respond(&mut stream, "502 Bad Gateway").await?;
+            return Err(io::Error::other(CaError::Missing));
+        };
+        let tls_config = ca.server_config(&host).map_err(io::Error::other)?;
+        respond(&mut stream, "200 Connection Established").await?;
+        context.intercepted.fetch_add(1, Ordering::Relaxed);
+        let tls = TlsAcceptor::from(tls_config).accept(stream).await?;
+        serve_http(tls, context.router(&host, port)).await
+    } else {
+        let upstream = match TcpStream::connect((host.as_str(), port)).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                respond(&mut stream, "502 Bad Gateway").await?;
+                return Err(e);
+            }
+        };
+        respond(&mut stream, "200 Connection Established").await?;
+        context.tunneled.fetch_add(1, Ordering::Relaxed);
+        tunnel(stream, upstream).await
+    }
+}
+
+/// Reads the request line and headers, returning the request line
+async fn read_head(stream: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut limited = stream.take(MAX_HEAD_BYTES);
+    let mut request_line = String::new();
+    if limited.read_line(&mut request_line).await? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    loop {
+        let mut line = String::new();
+        if limited.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if line.trim().is_empty() {
+            return Ok(request_line);
+        }
+    }
+}
+
+async fn respond(stream: &mut BufReader<TcpStream>, status: &str) -> io::Result<()> {
+    let response = format!("HTTP/1.1 {}\r\n\r\n", status);
+    stream.get_mut().write_all(response.as_bytes()).await
+}
+
+/// Splits `host:port` from a `CONNECT` target, unwrapping IPv6 brackets
+fn split_authority(target: &str) -> Option<(String, u16)> {
+    let (host, port) = target.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_ascii_lowercase(), port.parse().ok()?))
+}
+
+/// Serves HTTP/1.1 requests arriving on `stream` with `router`
+pub(crate) async fn serve_http<S>(stream: S, router: Router) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    hyper::server::conn::http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), TowerToHyperService::new(router))
+        .await
+        .map_err(io::Error::other)
+}
+
+async fn tunnel(client: BufReader<TcpStream>, mut upstream: TcpStream) -> io::Result<()> {
+    let pending = client.buffer().to_vec();
+    upstream.write_all(&pending).await?;
+    let mut client = client.into_inner();
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::Json;
+    use serde_json::{json, Value};
+
+    use crate::proxy::testing::services;
+
+    mod connect_head_tests {
+        use super::*;
+
+        #[test]
+        fn test_split_authority() {
+            assert_eq!(
+                split_authority("api.openai.com:443"),
+                Some(("api.openai.com".to_string(), 443))
+            );
+            assert_eq!(
+                split_authority("[::1]:8443"),
+                Some(("::1".to_string(), 8443))
+            );
+            assert_eq!(split_authority("api.openai.com"), None);
+            assert_eq!(split_authority(":443"), None);
+        }
+    }
+
+    mod connect_tests {
+        use super::*;
+
+        /// Serves `app` over TLS as `localhost`, returning its port and the root to trust
+        async fn spawn_tls_upstream(app: Router) -> (u16, reqwest::Certificate) {
+            let ca = Arc::new(CertificateAuthority::generate().unwrap());
+            let root = reqwest::Certificate::from_pem(ca.cert_pem().as_bytes()).unwrap();
+            let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+                .await
+                .unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let (ca, app) = (ca.clone(), app.clone());
+                    tokio::spawn(async move {
+                        let acceptor = TlsAcceptor::from(ca.server_config("localhost").unwrap());
+                        if let Ok(tls) = acceptor.accept(stream).await {
+                            let _ = serve_http(tls, app).await;
+                        }
+                    });
+                }
+            });
+            (port, root)
+        }
+
+        fn openai_upstream() -> Router {
+            Router::new().route(
+                "/v1/chat/completions",
+                post(|Json(body): Json<Value>| async move {
+                    Json(json!({
+                        "id": "chatcmpl-1",
+                        "model": body["model"],
+                        "choices": [{
+                            "index": 0,
+                            "message": {"role": "assistant", "content": "Hi from upstream"},
+                            "finish_reason": "stop",
+                        }],
+                        "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8},
+                    }))
+                }),
+            )
+        }
+
+        /// A policy intercepting exactly `domains`
+        fn intercepting(domains: &[&str]) -> InterceptPolicy {
+            InterceptPolicy {
+                domains: domains
+                    .iter()
+                    .map(|d| policy::DomainRule {
+                        pattern: d.to_string(),
+                        action: policy::DomainAction::Intercept,
+                        paths: Vec::new(),
+                        exclude_paths: Vec::new(),
+                    })
+                    .collect(),
+                ..InterceptPolicy::default()
+            }
+        }
+
+        async fn start_intercept(
+            policy: InterceptPolicy,
+            upstream_root: reqwest::Certificate,
+            services: ProxyServices,
+        ) -> (InterceptServer, String) {
+            let ca = Arc::new(CaStore::in_memory());
+            ca.generate().unwrap();
+            let server = InterceptServer::new(
+                InterceptConfig {
+                    port: 0,
+                    auto_start: false,
+                },
+                ca,
+                services,
+            )
+            .with_policy(PolicyStore::in_memory(policy))
+            .with_upstream_root(upstream_root);
+            let status = server.start(None).await.unwrap();
+            let address = format!("http://{}", status.address.unwrap());
+            (server, address)
+        }
+
+        /// Root certificate currently used by `server`
+        fn blackbox_root(server: &InterceptServer) -> reqwest::Certificate {
+            let pem = server.ca().export(CaFormat::Pem).unwrap();
+            reqwest::Certificate::from_pem(&pem).unwrap()
+        }
+
+        /// A client using the intercepting proxy that trusts only `root`
+        fn client(proxy: &str, root: reqwest::Certificate) -> reqwest::Client {
+            reqwest::Client::builder()
+                .proxy(reqwest::Proxy::all(proxy).unwrap())
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(root)
+                .build()
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn test_allowlisted_host_is_decrypted_and_captured() {
+            let (port, upstream_root) = spawn_tls_upstream(openai_upstream()).await;
+            let services = services();
+            let captures = services.captures.clone();
+            let (server, proxy) =
+                start_intercept(intercepting(&["localhost"]), upstream_root, services).await;
+
+            // Only the Blackbox root is trusted, so the tunnel must have been decrypted
+            let body: Value = client(&proxy, blackbox_root(&server))
+                .post(format!("https://localhost:{}/v1/chat/completions", port))
+                .json(&json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "Hi"}]}))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(body["choices"][0]["message"]["content"], "Hi from upstream");
+
+            let mut call = None;
+            for _ in 0..200 {
+                call = captures.recent(1).pop();
+                if call.is_some() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            let call = call.expect("no call was captured");
+            assert_eq!(call.model, "gpt-4o");
+            assert_eq!(call.response.content.as_deref(), Some("Hi from upstream"));
+
+            let status = server.status().await;
+            assert_eq!((status.intercepted, status.tunneled), (1, 0));
+        }
+
+        #[tokio::test]
+        async fn test_routes_and_client_keys_do_not_apply() {
+            let upstream = Router::new().route(
+                "/v1/chat/completions",
+                post(|headers: axum::http::HeaderMap, Json(body): Json<Value>| async move {
+                    let auth = headers.get("authorization").map(|v| v.to_str().unwrap().to_string());
+                    Json(json!({
+                        "model": body["model"],
+                        "choices": [{"index": 0, "message": {"role": "assistant", "content": auth}}],
+                    }))
+                }),
+            );
+            let (port, upstream_root) = spawn_tls_upstream(upstream).await;
+            let table = RoutingTable::parse(
+                r#"
+                [aliases]
+                "gpt-4" = "ollama/llama3.2"
+
+                [[upstreams]]
+                name = "ollama"
+                base_url = "http://127.0.0.1:9"
+                kind = "ollama"
+
+                [[routes]]
+                pattern = "ollama/*"
+                upstream = "ollama"
+                strip_prefix = true
+                "#,
+            )
+            .unwrap();
+            let clients = ClientKeyStore::in_memory();
+            clients.set_required(true).unwrap();
+            let services = services()
+                .with_routes(RouteStore::in_memory(table))
+                .with_clients(clients);
+            let (server, proxy) =
+                start_intercept(intercepting(&["localhost"]), upstream_root, services).await;
+
+            let response = client(&proxy, blackbox_root(&server))
+                .post(format!("https://localhost:{}/v1/chat/completions", port))
+                .bearer_auth("sk-browser")
+                .json(&json!({"model": "gpt-4", "messages": [{"role": "user", "content": "Hi"}]}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["model"], "gpt-4");
+            assert_eq!(
+                body["choices"][0]["message"]["content"],
+                "Bearer sk-browser"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_other_hosts_are_tunneled_untouched() {
+            let (port, upstream_root) = spawn_tls_upstream(openai_upstream()).await;
+            let services = services();
+            let captures = services.captures.clone();
+            let (server, proxy) = start_intercept(
+                intercepting(&["api.openai.com"]),
+                upstream_root.clone(),
+                services,
+            )
+            .await;
+
+            // Only the stand-in's own root is trusted, so the tunnel must be end to end
+            let response = client(&proxy, upstream_root)
+                .post(format!("https://localhost:{}/v1/chat/completions", port))
+                .json(&json!({"model": "gpt-4o", "messages": []}))
+                .send()
+                .await
+                .unwrap();
+            assert!(response.status().is_success());
+            assert!(captures.recent(1).is_empty());
+
+            let status = server.status().await;
+            assert_eq!((status.intercepted, status.tunneled), (0, 1));
+        }
+
+        #[tokio::test]
+        async fn test_rotated_root_is_used_for_new_tunnels() {
+            let (port, upstream_root) = spawn_tls_upstream(openai_upstream()).await;
+            let (server, proxy) =
+                start_intercept(intercepting(&["localhost"]), upstream_root, services()).await;
+            let old_root = blackbox_root(&server);
+            let url = format!("https://localhost:{}/v1/chat/completions", port);
+            let request = json!({"model": "gpt-4o", "messages": []});
+
+            server.ca().rotate().unwrap();
+            let stale = client(&proxy, old_root)
+                .post(&url)
+                .json(&request)
+                .send()
+                .await;
+            assert!(stale.is_err());
+            let fresh = client(&proxy, blackbox_root(&server))
+                .post(&url)
+                .json(&request)
+                .send()
+                .await
+                .unwrap();
+            assert!(fresh.status().is_success());
+        }
+
+        #[tokio::test]
+        async fn test_start_requires_a_root() {
+            let server = InterceptServer::new(
+                InterceptConfig {
+                    port: 0,
+                    ..InterceptConfig::default()
+                },
+                Arc::new(CaStore::in_memory()),
+                services(),
+            );
+            assert!(matches!(
+                server.start(None).await,
+                Err(InterceptError::Ca(CaError::Missing))
+            ));
+        }
+
+        #[tokio::test]
+        async fn test_denied_hosts_are_refused() {
+            let (port, upstream_root) = spawn_tls_upstream(openai_upstream()).await;
+            let mut policy = intercepting(&["localhost"]);
+            policy.deny.push("localhost".to_string());
+            let (server, proxy) = start_intercept(policy, upstream_root.clone(), services()).await;
+
+            let response = client(&proxy, upstream_root)
+                .post(format!("https://localhost:{}/v1/chat/completions", port))
+                .json(&json!({"model": "gpt-4o", "messages": []}))
+                .send()
+                .await;
+            assert!(response.is_err());
+            let status = server.status().await;
+            assert_eq!((status.intercepted, status.tunneled), (0, 0));
+        }
+
+        #[tokio::test]
+        async fn test_policy_changes_apply_while_running() {
+            let (port, upstream_root) = spawn_tls_upstream(openai_upstream()).await;
+            let services = services();
+            let captures = services.captures.clone();
+            let (server, proxy) =
+                start_intercept(InterceptPolicy::default(), upstream_root, services).await;
+            let url = format!("https://localhost:{}/v1/chat/completions", port);
+            let request = json!({"model": "gpt-4o", "messages": []});
+
+            // Excluded paths are decrypted but forwarded without being captured
+            let mut policy = intercepting(&["localhost"]);
+            policy.domains[0].exclude_paths = vec!["/v1/chat/*".to_string()];
+            server.policy().set(policy).unwrap();
+            let response = client(&proxy, blackbox_root(&server))
+                .post(&url)
+                .json(&request)
+                .send()
+                .await
+                .unwrap();
+            assert!(response.status().is_success());
+            assert!(captures.recent(1).is_empty());
+
+            server.policy().set(intercepting(&["localhost"])).unwrap();
+            client(&proxy, blackbox_root(&server))
+                .post(&url)
+                .json(&request)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(captures.recent(10).len(), 1);
+            assert_eq!(server.status().await.intercepted, 2);
+        }
+
+        #[tokio::test]
+        async fn test_pac_file_is_served() {
+            let (_, upstream_root) = spawn_tls_upstream(openai_upstream()).await;
+            let (server, proxy) =
+                start_intercept(intercepting(&["api.openai.com"]), upstream_root, services()).await;
+            let pac_url = server.status().await.pac_url.unwrap();
+            assert_eq!(pac_url, format!("{}{}", proxy, PAC_PATH));
+
+            let response = reqwest::get(&pac_url).await.unwrap();
+            assert_eq!(
+                response.headers()["content-type"],
+                "application/x-ns-proxy-autoconfig"
+            );
+            let pac = response.text().await.unwrap();
+            assert!(pac.contains("shExpMatch(host, \"api.openai.com\")"));
+            assert!(pac.contains(&format!("PROXY {}", proxy.trim_start_matches("http://"))));
+        }
+
+        #[tokio::test]
+        async fn test_plain_requests_are_refused() {
+            let (_, upstream_root) = spawn_tls_upstream(openai_upstream()).await;
+            let (_server, proxy) =
+                start_intercept(InterceptPolicy::default(), upstream_root, services()).await;
+            let response = reqwest::Client::builder()
+                .proxy(reqwest::Proxy::http(&proxy).unwrap())
+                .build()
+                .unwrap()
+                .get("http://example.com/")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 405);
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! What the intercepting proxy touches
+//!
+//! The policy in `intercept-policy.toml` under the app data dir lists the
+//! domains routed through Blackbox. Each one is either intercepted, meaning
+//! decrypted and captured, or tunneled untouched; path filters narrow down
+//! which requests of an intercepted domain are captured, the rest are
+//! forwarded as they are. Denied domains are refused outright. Rules match
+//! host glob patterns in order and the first match wins; hosts matching none
+//! are tunneled. The same rules generate the PAC file browsers use to send
+//! only LLM domains to Blackbox. The file is re-read whenever it changes.
+//!
+//! ```toml
+//! deny = ["*.internal.example.com"]
+//!
+//! [[domains]]
+//! pattern = "api.openai.com"
+//! action = "intercept"
+//! paths = ["/v1/*"]
+//! exclude_paths = ["/v1/files*"]
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::routing::glob_match;
+use crate::sync::lock;
+
+/// Policy file under the app data dir
+pub const POLICY_FILE: &str = "intercept-policy.toml";
+
+/// Written on first launch so the format is discoverable
+const DEFAULT_POLICY: &str = r#"# Blackbox interception policy, reloaded automatically when saved.
+#
+# Domains listed here are routed through Blackbox by the PAC file. Rules are
+# tried in order and the first pattern matching the host wins; patterns
+# support `*` and `?`. `intercept` decrypts and captures the traffic,
+# `tunnel` passes it through untouched. Hosts matching no rule are tunneled.
+# Denied hosts are refused and always left out of the PAC file.
+
+deny = []
+
+[[domains]]
+pattern = "api.openai.com"
+action = "intercept"
+# Only matching paths are captured, others are forwarded untouched
+# paths = ["/v1/*"]
+# exclude_paths = ["/v1/files*"]
+
+[[domains]]
+pattern = "api.anthropic.com"
+action = "intercept"
+
+[[domains]]
+pattern = "generativelanguage.googleapis.com"
+action = "intercept"
+
+# Browser sessions, routed through Blackbox without being decrypted
+[[domains]]
+pattern = "chatgpt.com"
+action = "tunnel"
+
+[[domains]]
+pattern = "claude.ai"
+action = "tunnel"
+
+[[domains]]
+pattern = "gemini.google.com"
+action = "tunnel"
+"#;
+
+/// Errors raised while loading or saving the policy
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("failed to access {POLICY_FILE}: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid {POLICY_FILE}: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("failed to write {POLICY_FILE}: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("{0:?} is not a host name pattern")]
+    InvalidDomain(String),
+    #[error("path filter {0:?} must start with / or *")]
+    InvalidPath(String),
+}
+
+/// What happens to tunnels to a listed domain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainAction {
+    /// Decrypted, with requests run through the proxy handlers
+    #[default]
+    Intercept,
+    /// Relayed byte for byte
+    Tunnel,
+}
+
+/// A domain routed through Blackbox
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DomainRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub action: DomainAction,
+    /// Paths captured when intercepted, all when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    /// Paths forwarded untouched even when matching `paths`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_paths: Vec<String>,
+}
+
+impl DomainRule {
+    fn matches(&self, host: &str) -> bool {
+        glob_match(&self.pattern.to_ascii_lowercase(), host)
+    }
+
+    /// Whether requests to `path` are captured
+    pub fn captures(&self, path: &str) -> bool {
+        (self.paths.is_empty() || self.paths.iter().any(|p| glob_match(p, path)))
+            && !self.exclude_paths.iter().any(|p| glob_match(p, path))
+    }
+}
+
+/// How a tunnel to a host is handled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Refused
+    Deny,
+    Intercept,
+    Tunnel,
+}
+
+/// Contents of `intercept-policy.toml`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InterceptPolicy {
+    /// Hosts refused by the proxy, ahead of any domain rule
+    pub deny: Vec<String>,
+    /// Hosts routed through Blackbox
+    pub domains: Vec<DomainRule>,
+}
+
+impl InterceptPolicy {
+    /// Parses and validates a policy
+    pub fn parse(text: &str) -> Result<Self, PolicyError> {
+        let policy: InterceptPolicy = toml::from_str(text)?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Checks that domains are host name patterns and paths absolute
+    pub fn validate(&self) -> Result<(), PolicyError> {
+        let patterns = self
+            .deny
+            .iter()
+            .chain(self.domains.iter().map(|rule| &rule.pattern));
+        for pattern in patterns {
+            let valid = !pattern.is_empty()
+                && pattern
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '*' | '?'));
+            if !valid {
+                return Err(PolicyError::InvalidDomain(pattern.clone()));
+            }
+        }
+        let paths = self
+            .domains
+            .iter()
+            .flat_map(|rule| rule.paths.iter().chain(&rule.exclude_paths));
+        for path in paths {
+            if !path.starts_with(['/', '*']) {
+                return Err(PolicyError::InvalidPath(path.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn rule(&self, host: &str) -> Option<&DomainRule> {
+        self.domains.iter().find(|rule| rule.matches(host))
+    }
+
+    /// Decides what happens to a tunnel to `host`
+    pub fn decide(&self, host: &str) -> Decision {
+        let host = normalize(host);
+        if self
+            .deny
+            .iter()
+            .any(|p| glob_match(&p.to_ascii_lowercase(), &host))
+        {
+            return Decision::Deny;
+        }
+        match self.rule(&host).map(|rule| rule.action) {
+            Some(DomainAction::Intercept) => Decision::Intercept,
+            Some(DomainAction::Tunnel) | None => Decision::Tunnel,
+        }
+    }
+
+    /// Whether a decrypted request to `path` on `host` is captured
+    pub fn captures(&self, host: &str, path: &str) -> bool {
+        self.rule(&normalize(host))
+            .is_some_and(|rule| rule.captures(path))
+    }
+
+    /// Generates a PAC file sending listed domains to the proxy at `address`
+    pub fn pac(&self, address: &str) -> String {
+        let conditions = |patterns: Vec<&String>| {
+            patterns
+                .iter()
+                .map(|p| {
+                    let literal = serde_json::Value::from(p.to_ascii_lowercase()).to_string();
+                    format!("shExpMatch(host, {})", literal)
+                })
+                .collect::<Vec<_>>()
+                .join(" ||\n      ")
+        };
+        let mut pac = String::from(
+            "// Generated by Blackbox from intercept-policy.toml\n\
+             function FindProxyForURL(url, host) {\n  host = host.toLowerCase();\n",
+        );
+        if !self.deny.is_empty() {
+            pac.push_str(&format!(
+                "  if ({}) {{\n    return \"DIRECT\";\n  }}\n",
+                conditions(self.deny.iter().collect())
+            ));
+        }
+        if !self.domains.is_empty() {
+            pac.push_str(&format!(
+                "  if ({}) {{\n    return \"PROXY {}\";\n  }}\n",
+                conditions(self.domains.iter().map(|rule| &rule.pattern).collect()),
+                address
+            ));
+        }
+        pac.push_str("  return \"DIRECT\";\n}\n");
+        pac
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+struct Loaded {
+    policy: InterceptPolicy,
+    /// Modification time of the file the policy was read from
+    modified: Option<SystemTime>,
+}
+
+/// Live interception policy, reloaded when its file changes
+pub struct PolicyStore {
+    /// `None` keeps the policy in memory only
+    path: Option<PathBuf>,
+    loaded: Mutex<Loaded>,
+}
+
+impl PolicyStore {
+    /// Creates a store serving `policy` that never reloads
+    pub fn in_memory(policy: InterceptPolicy) -> Self {
+        Self {
+            path: None,
+            loaded: Mutex::new(Loaded {
+                policy,
+                modified: None,
+            }),
+        }
+    }
+
+    /// Opens the policy at `path`, writing the default policy when there is none
+    pub fn open(path: PathBuf) -> Result<Self, PolicyError> {
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, DEFAULT_POLICY)?;
+        }
+        let store = Self {
+            path: Some(path),
+            ..Self::in_memory(InterceptPolicy::default())
+        };
+        store.reload()?;
+        Ok(store)
+    }
+
+    /// Re-reads the file, keeping the current policy when the new one is invalid
+    pub fn reload(&self) -> Result<(), PolicyError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let modified = fs::metadata(path)?.modified().ok();
+        let policy = InterceptPolicy::parse(&fs::read_to_string(path)?);
+        let mut loaded = lock(&self.loaded);
+        // Remember the broken version too so it is not re-parsed on every tunnel
+        loaded.modified = modified;
+        loaded.policy = policy?;
+        Ok(())
+    }
+
+    fn refresh(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let modified = fs::metadata(path).ok().and_then(|m| m.modified().ok());
+        if modified == lock(&self.loaded).modified {
+            return;
+        }
+        if let Err(e) = self.reload() {
+            log::warn!("Keeping the previous interception policy: {}", e);
+        }
+    }
+
+    /// Returns the current policy
+    pub fn policy(&self) -> InterceptPolicy {
+        self.refresh();
+        lock(&self.loaded).policy.clone()
+    }
+
+    /// Validates, saves and applies `policy`
+    pub fn set(&self, policy: InterceptPolicy) -> Result<(), PolicyError> {
+        policy.validate()?;
+        let mut loaded = lock(&self.loaded);
+        if let Some(path) = &self.path {
+            fs::write(path, toml::to_string(&policy)?)?;
+            loaded.modified = fs::metadata(path)?.modified().ok();
+        }
+        loaded.policy = policy;
+        Ok(())
+    }
+
+    /// Decides what happens to a tunnel to `host`
+    pub fn decide(&self, host: &str) -> Decision {
+        self.refresh();
+        lock(&self.loaded).policy.decide(host)
+    }
+
+    /// Whether a decrypted request to `path` on `host` is captured
+    pub fn captures(&self, host: &str, path: &str) -> bool {
+        self.refresh();
+        lock(&self.loaded).policy.captures(host, path)
+    }
+
+    /// Generates the PAC file for the proxy at `address`
+    pub fn pac(&self, address: &str) -> String {
+        self.refresh();
+        lock(&self.loaded).policy.pac(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> InterceptPolicy {
+        InterceptPolicy::parse(
+            r#"
+            deny = ["*.corp.example.com"]
+
+            [[domains]]
+            pattern = "*.example.com"
+            action = "intercept"
+            paths = ["/v1/*"]
+            exclude_paths = ["/v1/files*"]
+
+            [[domains]]
+            pattern = "chat.example.org"
+            action = "tunnel"
+            "#,
+        )
+        .unwrap()
+    }
+
+    mod policy_tests {
+        use super::*;
+
+        #[test]
+        fn test_default_policy_intercepts_llm_apis() {
+            let policy = InterceptPolicy::parse(DEFAULT_POLICY).unwrap();
+            assert_eq!(policy.decide("api.openai.com"), Decision::Intercept);
+            assert_eq!(policy.decide("API.Anthropic.com."), Decision::Intercept);
+            assert_eq!(policy.decide("claude.ai"), Decision::Tunnel);
+            assert_eq!(policy.decide("github.com"), Decision::Tunnel);
+        }
+
+        #[test]
+        fn test_deny_wins_and_first_rule_decides() {
+            let policy = policy();
+            assert_eq!(policy.decide("llm.example.com"), Decision::Intercept);
+            assert_eq!(policy.decide("api.corp.example.com"), Decision::Deny);
+            assert_eq!(policy.decide("chat.example.org"), Decision::Tunnel);
+        }
+
+        #[test]
+        fn test_path_filters() {
+            let policy = policy();
+            assert!(policy.captures("llm.example.com", "/v1/chat/completions"));
+            assert!(!policy.captures("llm.example.com", "/v1/files/abc"));
+            assert!(!policy.captures("llm.example.com", "/health"));
+            assert!(!policy.captures("github.com", "/v1/chat/completions"));
+        }
+
+        #[test]
+        fn test_invalid_policies_are_rejected() {
+            let domain = "[[domains]]\npattern = \"https://api.openai.com\"\n";
+            assert!(matches!(
+                InterceptPolicy::parse(domain),
+                Err(PolicyError::InvalidDomain(_))
+            ));
+            let path = "[[domains]]\npattern = \"api.openai.com\"\npaths = [\"v1\"]\n";
+            assert!(matches!(
+                InterceptPolicy::parse(path),
+                Err(PolicyError::InvalidPath(_))
+            ));
+        }
+
+        #[test]
+        fn test_pac_routes_listed_domains_only() {
+            let pac = policy().pac("127.0.0.1:7214");
+            assert!(pac.contains("function FindProxyForURL(url, host)"));
+            assert!(pac.contains("shExpMatch(host, \"*.corp.example.com\")"));
+            assert!(pac.contains("shExpMatch(host, \"chat.example.org\")"));
+            assert!(pac.contains("return \"PROXY 127.0.0.1:7214\";"));
+            // Denied hosts are checked before the proxied ones
+            assert!(pac.find("corp.example.com") < pac.find("chat.example.org"));
+            assert!(pac.trim_end().ends_with("return \"DIRECT\";\n}"));
+        }
+    }
+
+    mod store_tests {
+        use super::*;
+
+        #[test]
+        fn test_changes_are_picked_up() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(POLICY_FILE);
+            let store = PolicyStore::open(path.clone()).unwrap();
+            assert_eq!(store.decide("api.openai.com"), Decision::Intercept);
+
+            fs::write(&path, "deny = [\"api.openai.com\"]\n").unwrap();
+            store.reload().unwrap();
+            assert_eq!(store.decide("api.openai.com"), Decision::Deny);
+
+            fs::write(&path, "deny = [").unwrap();
+            assert!(store.reload().is_err());
+            assert_eq!(store.decide("api.openai.com"), Decision::Deny);
+        }
+
+        #[test]
+        fn test_set_saves_and_applies() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(POLICY_FILE);
+            let store = PolicyStore::open(path.clone()).unwrap();
+            store.set(policy()).unwrap();
+            assert_eq!(store.decide("llm.example.com"), Decision::Intercept);
+            assert_eq!(PolicyStore::open(path).unwrap().policy(), policy());
+
+            let mut invalid = policy();
+            invalid.deny.push(String::new());
+            assert!(store.set(invalid).is_err());
+            assert_eq!(store.policy(), policy());
+        }
+    }
+}
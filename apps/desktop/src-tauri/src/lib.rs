@@ -1,13 +1,53 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tauri::{
     menu::{Menu, MenuItemBuilder, PredefinedMenuItem},
     tray::TrayIconBuilder,
     window::Color,
-    Emitter, Manager, WebviewUrl, WebviewWindowBuilder,
+    Emitter, Listener, Manager, WebviewUrl, WebviewWindowBuilder,
+};
+use tauri_plugin_store::StoreExt;
+
+pub mod budget;
+pub mod cache;
+pub mod capture;
+pub mod clients;
+pub mod credentials;
+pub mod events;
+pub mod extensions;
+pub mod intercept;
+pub mod pipeline;
+pub mod plugins;
+pub mod proxy;
+pub mod routing;
+mod sync;
+pub mod telemetry;
+pub mod toon;
+pub mod usage;
+
+use budget::{BudgetOverview, BudgetSettings, BudgetTracker};
+use cache::{CacheConfig, CacheStats, ResponseCache};
+use capture::{CaptureLog, DEFAULT_CAPTURE_CAPACITY};
+use clients::{ClientKey, ClientKeyList, ClientKeyRequest, ClientKeyStore, CreatedClientKey};
+use credentials::{CredentialInfo, CredentialStatus, CredentialStore, CredentialTest};
+use extensions::install::{ExtensionStore, InstallError};
+use intercept::{
+    CaFormat, CaInfo, CaStore, InterceptConfig, InterceptPolicy, InterceptServer, InterceptStatus,
+    PolicyStore,
 };
+use pipeline::{ExtensionInfo, PipelineSettings};
+use plugins::{
+    injection::InjectionSettings, redact::RedactionSettings, secrets::SecretGuardSettings, Builtins,
+};
+use proxy::{BreakerStatus, CircuitBreakers, ProxyConfig, ProxyServer, ProxyServices, ProxyStatus};
+use routing::{ResolvedRoute, RouteStore, RoutingTable};
+use telemetry::{TelemetryConfig, TelemetryExporter, TelemetryStatus};
+use usage::pricing::{PriceStore, PricingTable};
+use usage::{UsageGroup, UsageLedger, UsageRange, UsageSummary};
 
 #[cfg(desktop)]
 use tauri_plugin_autostart::AutoLaunchManager;
@@ -49,6 +89,467 @@ fn open_external_url(url: String, app: tauri::AppHandle) -> Result<(), String> {
         .map_err(|e| e.to_string())
 }
 
+/// Starts (or restarts) the embedded proxy, optionally with a new configuration
+#[tauri::command]
+async fn start_proxy(
+    config: Option<ProxyConfig>,
+    app: tauri::AppHandle,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<ProxyStatus, String> {
+    let mut config = config.unwrap_or_else(|| proxy.config());
+    config
+        .store_api_key(&proxy.credentials())
+        .map_err(|e| e.to_string())?;
+    let status = proxy.start(Some(config)).await.map_err(|e| e.to_string())?;
+    save_setting(&app, config::STORE_PROXY_KEY, &proxy.config())?;
+    Ok(status)
+}
+
+/// Stops the embedded proxy
+#[tauri::command]
+async fn stop_proxy(proxy: tauri::State<'_, ProxyServer>) -> Result<ProxyStatus, String> {
+    Ok(proxy.stop().await)
+}
+
+/// Returns whether the embedded proxy is running and where
+#[tauri::command]
+async fn proxy_status(proxy: tauri::State<'_, ProxyServer>) -> Result<ProxyStatus, String> {
+    Ok(proxy.status().await)
+}
+
+/// Starts (or restarts) HTTPS interception, optionally with a new configuration
+#[tauri::command]
+async fn start_interception(
+    config: Option<InterceptConfig>,
+    app: tauri::AppHandle,
+    intercept: tauri::State<'_, InterceptServer>,
+) -> Result<InterceptStatus, String> {
+    let status = intercept.start(config).await.map_err(|e| e.to_string())?;
+    save_setting(&app, config::STORE_INTERCEPT_KEY, &intercept.config())?;
+    Ok(status)
+}
+
+/// Stops HTTPS interception
+#[tauri::command]
+async fn stop_interception(
+    intercept: tauri::State<'_, InterceptServer>,
+) -> Result<InterceptStatus, String> {
+    Ok(intercept.stop().await)
+}
+
+/// Returns whether HTTPS interception is running and what it has handled
+#[tauri::command]
+async fn interception_status(
+    intercept: tauri::State<'_, InterceptServer>,
+) -> Result<InterceptStatus, String> {
+    Ok(intercept.status().await)
+}
+
+/// Returns which domains are intercepted, tunneled or refused
+#[tauri::command]
+fn get_intercept_policy(intercept: tauri::State<'_, InterceptServer>) -> InterceptPolicy {
+    intercept.policy().policy()
+}
+
+/// Saves the interception policy; running tunnels pick it up immediately
+#[tauri::command]
+fn set_intercept_policy(
+    policy: InterceptPolicy,
+    intercept: tauri::State<'_, InterceptServer>,
+) -> Result<InterceptPolicy, String> {
+    let store = intercept.policy();
+    store.set(policy).map_err(|e| e.to_string())?;
+    Ok(store.policy())
+}
+
+/// Generates the Blackbox root certificate unless one exists
+#[tauri::command]
+fn generate_ca(intercept: tauri::State<'_, InterceptServer>) -> Result<CaInfo, String> {
+    intercept.ca().generate().map_err(|e| e.to_string())
+}
+
+/// Returns the root certificate to import into a trust store
+#[tauri::command]
+fn export_ca(
+    format: CaFormat,
+    intercept: tauri::State<'_, InterceptServer>,
+) -> Result<Vec<u8>, String> {
+    intercept.ca().export(format).map_err(|e| e.to_string())
+}
+
+/// Replaces the root certificate; the old one stops being used immediately
+#[tauri::command]
+fn rotate_ca(intercept: tauri::State<'_, InterceptServer>) -> Result<CaInfo, String> {
+    intercept.ca().rotate().map_err(|e| e.to_string())
+}
+
+/// Returns fingerprint and validity of the root certificate, if generated
+#[tauri::command]
+fn ca_info(intercept: tauri::State<'_, InterceptServer>) -> Option<CaInfo> {
+    intercept.ca().info()
+}
+
+/// Lists pipeline extensions in the order they run
+#[tauri::command]
+fn list_extensions(proxy: tauri::State<'_, ProxyServer>) -> Vec<ExtensionInfo> {
+    proxy.pipeline().list()
+}
+
+/// Enables a pipeline extension
+#[tauri::command]
+fn enable_extension(
+    id: String,
+    app: tauri::AppHandle,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<Vec<ExtensionInfo>, String> {
+    let pipeline = proxy.pipeline();
+    pipeline.set_enabled(&id, true).map_err(|e| e.to_string())?;
+    save_setting(&app, config::STORE_EXTENSIONS_KEY, &pipeline.settings())?;
+    Ok(pipeline.list())
+}
+
+/// Disables a pipeline extension
+#[tauri::command]
+fn disable_extension(
+    id: String,
+    app: tauri::AppHandle,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<Vec<ExtensionInfo>, String> {
+    let pipeline = proxy.pipeline();
+    pipeline.set_enabled(&id, false).map_err(|e| e.to_string())?;
+    save_setting(&app, config::STORE_EXTENSIONS_KEY, &pipeline.settings())?;
+    Ok(pipeline.list())
+}
+
+/// Changes the order extensions run in; `ids` must list every extension once
+#[tauri::command]
+fn reorder_extensions(
+    ids: Vec<String>,
+    app: tauri::AppHandle,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<Vec<ExtensionInfo>, String> {
+    let pipeline = proxy.pipeline();
+    pipeline.reorder(&ids).map_err(|e| e.to_string())?;
+    save_setting(&app, config::STORE_EXTENSIONS_KEY, &pipeline.settings())?;
+    Ok(pipeline.list())
+}
+
+/// Installs an extension from a directory or zip with a blackbox-extension manifest
+#[tauri::command]
+async fn install_extension(
+    path: String,
+    app: tauri::AppHandle,
+    store: tauri::State<'_, ExtensionStore>,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<Vec<ExtensionInfo>, InstallError> {
+    let pipeline = proxy.pipeline();
+    store.install(std::path::Path::new(&path), &pipeline)?;
+    save_setting(&app, config::STORE_EXTENSIONS_KEY, &pipeline.settings())
+        .map_err(|message| InstallError::Io { message })?;
+    Ok(pipeline.list())
+}
+
+/// Removes an installed extension and its files
+#[tauri::command]
+async fn uninstall_extension(
+    id: String,
+    app: tauri::AppHandle,
+    store: tauri::State<'_, ExtensionStore>,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<Vec<ExtensionInfo>, InstallError> {
+    let pipeline = proxy.pipeline();
+    store.uninstall(&id, &pipeline)?;
+    save_setting(&app, config::STORE_EXTENSIONS_KEY, &pipeline.settings())
+        .map_err(|message| InstallError::Io { message })?;
+    Ok(pipeline.list())
+}
+
+/// Returns response cache counters
+#[tauri::command]
+fn cache_stats(proxy: tauri::State<'_, ProxyServer>) -> CacheStats {
+    proxy.cache().stats()
+}
+
+/// Removes every cached response
+#[tauri::command]
+fn clear_cache(proxy: tauri::State<'_, ProxyServer>) -> Result<CacheStats, String> {
+    let cache = proxy.cache();
+    cache.clear().map_err(|e| e.to_string())?;
+    Ok(cache.stats())
+}
+
+/// Returns the token budget limits and current usage
+#[tauri::command]
+fn get_budgets(proxy: tauri::State<'_, ProxyServer>) -> BudgetOverview {
+    proxy.budget().overview()
+}
+
+/// Replaces the token budget limits and persists them
+#[tauri::command]
+fn set_budgets(
+    limits: BudgetSettings,
+    app: tauri::AppHandle,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<BudgetOverview, String> {
+    limits.validate()?;
+    save_setting(&app, config::STORE_BUDGETS_KEY, &limits)?;
+    let budget = proxy.budget();
+    budget.set_settings(limits);
+    refresh_tray_tooltip(&app, &budget);
+    Ok(budget.overview())
+}
+
+/// Adds up calls, tokens and cost over a range of days by model, client or day
+#[tauri::command]
+fn get_usage_summary(
+    range: UsageRange,
+    group_by: UsageGroup,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> UsageSummary {
+    proxy.usage().summary(range, group_by)
+}
+
+/// Returns where and how spans of proxied calls are exported
+#[tauri::command]
+fn get_telemetry_settings(proxy: tauri::State<'_, ProxyServer>) -> TelemetryConfig {
+    proxy.telemetry().config()
+}
+
+/// Replaces the OTLP export settings and persists them
+#[tauri::command]
+fn set_telemetry_settings(
+    settings: TelemetryConfig,
+    app: tauri::AppHandle,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<TelemetryConfig, String> {
+    settings.validate().map_err(|e| e.to_string())?;
+    save_setting(&app, config::STORE_TELEMETRY_KEY, &settings)?;
+    proxy
+        .telemetry()
+        .set_config(settings)
+        .map_err(|e| e.to_string())?;
+    Ok(proxy.telemetry().config())
+}
+
+/// Returns how many spans are waiting, queued and exported
+#[tauri::command]
+fn telemetry_status(proxy: tauri::State<'_, ProxyServer>) -> TelemetryStatus {
+    proxy.telemetry().status()
+}
+
+/// Returns what the PII redaction plugin masks
+#[tauri::command]
+fn get_redaction_settings(builtins: tauri::State<'_, Builtins>) -> RedactionSettings {
+    builtins.redact.settings()
+}
+
+/// Replaces what the PII redaction plugin masks and persists it
+#[tauri::command]
+fn set_redaction_settings(
+    settings: RedactionSettings,
+    app: tauri::AppHandle,
+    builtins: tauri::State<'_, Builtins>,
+) -> Result<RedactionSettings, String> {
+    settings.validate()?;
+    save_setting(&app, config::STORE_REDACTION_KEY, &settings)?;
+    builtins.redact.set_settings(settings)?;
+    Ok(builtins.redact.settings())
+}
+
+/// Returns what the secret guard does with credentials found in prompts
+#[tauri::command]
+fn get_secret_guard_settings(builtins: tauri::State<'_, Builtins>) -> SecretGuardSettings {
+    builtins.secrets.settings()
+}
+
+/// Replaces the secret guard configuration and persists it
+#[tauri::command]
+fn set_secret_guard_settings(
+    settings: SecretGuardSettings,
+    app: tauri::AppHandle,
+    builtins: tauri::State<'_, Builtins>,
+) -> Result<SecretGuardSettings, String> {
+    settings.validate()?;
+    save_setting(&app, config::STORE_SECRET_GUARD_KEY, &settings)?;
+    builtins.secrets.set_settings(settings)?;
+    Ok(builtins.secrets.settings())
+}
+
+/// Returns what happens to tool results that look like prompt injections
+#[tauri::command]
+fn get_injection_settings(builtins: tauri::State<'_, Builtins>) -> InjectionSettings {
+    builtins.injection.settings()
+}
+
+/// Replaces the prompt-injection heuristics configuration and persists it
+#[tauri::command]
+fn set_injection_settings(
+    settings: InjectionSettings,
+    app: tauri::AppHandle,
+    builtins: tauri::State<'_, Builtins>,
+) -> Result<InjectionSettings, String> {
+    settings.validate()?;
+    save_setting(&app, config::STORE_INJECTION_KEY, &settings)?;
+    builtins.injection.set_settings(settings)?;
+    Ok(builtins.injection.settings())
+}
+
+/// Lists the virtual keys of clients, never the keys themselves
+#[tauri::command]
+fn list_client_keys(proxy: tauri::State<'_, ProxyServer>) -> ClientKeyList {
+    proxy.clients().list()
+}
+
+/// Issues a virtual key for a client; the returned key is not shown again
+#[tauri::command]
+fn create_client_key(
+    request: ClientKeyRequest,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<CreatedClientKey, String> {
+    let created = proxy.clients().create(request).map_err(|e| e.to_string())?;
+    sync_key_budgets(&proxy);
+    Ok(created)
+}
+
+/// Revokes a client's virtual key
+#[tauri::command]
+fn revoke_client_key(
+    id: String,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<ClientKey, String> {
+    let revoked = proxy.clients().revoke(&id).map_err(|e| e.to_string())?;
+    sync_key_budgets(&proxy);
+    Ok(revoked)
+}
+
+/// Decides whether requests without a valid virtual key are rejected
+#[tauri::command]
+fn set_client_keys_required(
+    required: bool,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<ClientKeyList, String> {
+    let clients = proxy.clients();
+    clients.set_required(required).map_err(|e| e.to_string())?;
+    Ok(clients.list())
+}
+
+/// Lists stored credentials by name; their values never leave the backend
+#[tauri::command]
+fn get_credentials(proxy: tauri::State<'_, ProxyServer>) -> CredentialStatus {
+    proxy.credentials().status()
+}
+
+/// Encrypts and stores an upstream API key
+#[tauri::command]
+fn set_credential(
+    name: String,
+    value: String,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<CredentialInfo, String> {
+    proxy
+        .credentials()
+        .set(&name, &value)
+        .map_err(|e| e.to_string())
+}
+
+/// Deletes a stored credential
+#[tauri::command]
+fn delete_credential(
+    name: String,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<CredentialStatus, String> {
+    let credentials = proxy.credentials();
+    credentials.delete(&name).map_err(|e| e.to_string())?;
+    Ok(credentials.status())
+}
+
+/// Checks a stored credential against `upstream` from the routing table, or the service its
+/// name suggests
+#[tauri::command]
+async fn test_credential(
+    name: String,
+    upstream: Option<String>,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<CredentialTest, String> {
+    let upstream = match upstream {
+        Some(upstream) if upstream == routing::DEFAULT_UPSTREAM => {
+            Some(proxy::default_upstream(&proxy.config()))
+        }
+        Some(upstream) => Some(
+            proxy
+                .routes()
+                .table()
+                .upstreams
+                .into_iter()
+                .find(|u| u.name == upstream)
+                .ok_or_else(|| format!("Unknown upstream {}", upstream))?,
+        ),
+        None => None,
+    };
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(15))
+        .build()
+        .map_err(|e| e.to_string())?;
+    proxy
+        .credentials()
+        .test(&client, &name, upstream.as_ref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Unlocks a passphrase-protected credential store
+#[tauri::command]
+fn unlock_credentials(
+    passphrase: String,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<CredentialStatus, String> {
+    let credentials = proxy.credentials();
+    credentials
+        .unlock(&passphrase)
+        .map_err(|e| e.to_string())?;
+    Ok(credentials.status())
+}
+
+/// Forgets the master key of a passphrase-protected store until it is unlocked again
+#[tauri::command]
+fn lock_credentials(proxy: tauri::State<'_, ProxyServer>) -> CredentialStatus {
+    let credentials = proxy.credentials();
+    credentials.lock();
+    credentials.status()
+}
+
+/// Protects the credentials with a passphrase, or with a generated key when `None`
+#[tauri::command]
+fn set_credentials_passphrase(
+    passphrase: Option<String>,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<CredentialStatus, String> {
+    let credentials = proxy.credentials();
+    credentials
+        .set_passphrase(passphrase.as_deref())
+        .map_err(|e| e.to_string())?;
+    Ok(credentials.status())
+}
+
+/// Shows which upstream a request for `model` would be sent to
+#[tauri::command]
+fn resolve_route(
+    model: String,
+    proxy: tauri::State<'_, ProxyServer>,
+) -> Result<ResolvedRoute, String> {
+    let default = proxy::default_upstream(&proxy.config());
+    proxy
+        .routes()
+        .resolve(&model, &default)
+        .map_err(|e| e.to_string())
+}
+
+/// Lists upstreams that failed recently and the state of their circuit breakers
+#[tauri::command]
+fn get_circuit_breakers(proxy: tauri::State<'_, ProxyServer>) -> Vec<BreakerStatus> {
+    proxy.breakers().statuses()
+}
+
 /// Enables launch at login
 #[cfg(desktop)]
 #[tauri::command]
@@ -90,6 +591,32 @@ pub mod config {
     /// Settings window height
     pub const SETTINGS_HEIGHT: f64 = 400.0;
 
+    /// Settings store shared with the frontend settings-store.ts
+    pub const SETTINGS_STORE: &str = "settings.json";
+    /// Store key holding the proxy configuration
+    pub const STORE_PROXY_KEY: &str = "proxy";
+    /// Store key holding the extension order and disabled extensions
+    pub const STORE_EXTENSIONS_KEY: &str = "extensions";
+    /// Store key holding the response cache configuration
+    pub const STORE_CACHE_KEY: &str = "cache";
+    /// Store key holding the token budget limits, next to the app settings
+    pub const STORE_BUDGETS_KEY: &str = "budgets";
+    /// Store key holding what the PII redaction plugin masks
+    pub const STORE_REDACTION_KEY: &str = "redaction";
+    /// Store key holding the secret guard action and rules
+    pub const STORE_SECRET_GUARD_KEY: &str = "secret_guard";
+    /// Store key holding what happens to suspected prompt injections
+    pub const STORE_INJECTION_KEY: &str = "injection";
+    /// Store key holding the HTTPS interception settings
+    pub const STORE_INTERCEPT_KEY: &str = "intercept";
+    /// Store key holding the OTLP span export settings
+    pub const STORE_TELEMETRY_KEY: &str = "telemetry";
+
+    /// Tray icon identifier
+    pub const TRAY_ID: &str = "main";
+    /// Tray tooltip while no budget needs attention
+    pub const TRAY_TOOLTIP: &str = "Blackbox";
+
     // Menu item IDs
     pub const MENU_OPEN_ID: &str = "open";
     pub const MENU_FEEDBACK_ID: &str = "feedback";
@@ -258,6 +785,180 @@ impl WindowAction {
     }
 }
 
+// Implemented by path: importing the trait would make `emit` ambiguous with `Emitter`
+impl<R: tauri::Runtime> events::EventSink for tauri::AppHandle<R> {
+    fn emit(&self, event: &str, payload: serde_json::Value) {
+        let _ = Emitter::emit(self, event, payload);
+    }
+}
+
+/// Reads a value from the settings store, falling back to its default
+fn load_setting<T: DeserializeOwned + Default>(app: &tauri::AppHandle, key: &str) -> T {
+    app.store(config::SETTINGS_STORE)
+        .ok()
+        .and_then(|store| store.get(key))
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default()
+}
+
+/// Writes a value to the settings store and flushes it to disk
+fn save_setting<T: Serialize>(app: &tauri::AppHandle, key: &str, value: &T) -> Result<(), String> {
+    let store = app
+        .store(config::SETTINGS_STORE)
+        .map_err(|e| e.to_string())?;
+    store.set(key, serde_json::to_value(value).map_err(|e| e.to_string())?);
+    store.save().map_err(|e| e.to_string())
+}
+
+/// Opens the extension store in the app data dir and activates what is installed
+fn load_extensions(app: &tauri::AppHandle, services: &ProxyServices) -> Option<ExtensionStore> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .ok()?
+        .join(extensions::EXTENSIONS_DIR);
+    let store = match ExtensionStore::new(dir) {
+        Ok(store) => store,
+        Err(e) => {
+            log::error!("Failed to open the extension store: {}", e);
+            return None;
+        }
+    };
+
+    // Process extensions spawn their supervisor on the async runtime
+    tauri::async_runtime::block_on(async {
+        for (dir, e) in store.load_all(&services.pipeline) {
+            log::warn!("Skipping extension {}: {}", dir.display(), e);
+        }
+    });
+    let limits = extensions::wasm::WasmLimits::default();
+    if let Err(e) =
+        extensions::load_wasm_plugins(store.root(), store.wasm_host(), &limits, &services.pipeline)
+    {
+        log::warn!("Failed to load loose WASM extensions: {}", e);
+    }
+    Some(store)
+}
+
+/// Opens the response cache in the app data dir, falling back to no caching
+fn open_cache(app: &tauri::AppHandle) -> ResponseCache {
+    let config: CacheConfig = load_setting(app, config::STORE_CACHE_KEY);
+    let Ok(dir) = app.path().app_data_dir() else {
+        return ResponseCache::disabled();
+    };
+    ResponseCache::open(dir.join(cache::CACHE_DIR), config).unwrap_or_else(|e| {
+        log::error!("Failed to open the response cache: {}", e);
+        ResponseCache::disabled()
+    })
+}
+
+/// Opens the token budget tracker, keeping daily usage in the app data dir
+fn open_budget(app: &tauri::AppHandle, events: Arc<dyn events::EventSink>) -> BudgetTracker {
+    let limits: BudgetSettings = load_setting(app, config::STORE_BUDGETS_KEY);
+    match app.path().app_data_dir() {
+        Ok(dir) => BudgetTracker::open(dir.join(budget::BUDGET_USAGE_FILE), limits, events),
+        Err(_) => BudgetTracker::in_memory(limits, events),
+    }
+}
+
+/// Opens the routing table in the app data dir, falling back to no routes
+fn open_routes(app: &tauri::AppHandle) -> RouteStore {
+    let Ok(dir) = app.path().app_data_dir() else {
+        return RouteStore::in_memory(RoutingTable::default());
+    };
+    RouteStore::open(dir.join(routing::ROUTES_FILE)).unwrap_or_else(|e| {
+        log::error!("Failed to open the routing table: {}", e);
+        RouteStore::in_memory(RoutingTable::default())
+    })
+}
+
+/// Opens the client keys in the app data dir, keeping them in memory without one
+fn open_clients(app: &tauri::AppHandle) -> ClientKeyStore {
+    match app.path().app_data_dir() {
+        Ok(dir) => ClientKeyStore::open(dir.join(clients::CLIENT_KEYS_FILE)),
+        Err(_) => ClientKeyStore::in_memory(),
+    }
+}
+
+/// Opens the encrypted credential store in the app data dir, keeping keys in memory without one
+fn open_credentials(app: &tauri::AppHandle) -> CredentialStore {
+    let Ok(dir) = app.path().app_data_dir() else {
+        return CredentialStore::in_memory();
+    };
+    CredentialStore::open(dir).unwrap_or_else(|e| {
+        log::error!("Failed to open the credential store: {}", e);
+        CredentialStore::in_memory()
+    })
+}
+
+/// Opens the usage history and pricing table in the app data dir, pricing nothing without one
+fn open_usage(app: &tauri::AppHandle) -> UsageLedger {
+    let Ok(dir) = app.path().app_data_dir() else {
+        return UsageLedger::in_memory(PriceStore::in_memory(PricingTable::default()));
+    };
+    let prices = PriceStore::open(dir.join(usage::pricing::PRICING_FILE)).unwrap_or_else(|e| {
+        log::error!("Failed to open the pricing table: {}", e);
+        PriceStore::in_memory(PricingTable::default())
+    });
+    UsageLedger::open(dir.join(usage::USAGE_HISTORY_FILE), prices)
+}
+
+/// Opens the local root CA store in the app data dir, keeping roots in memory without one
+fn open_ca(app: &tauri::AppHandle) -> CaStore {
+    let Ok(dir) = app.path().app_data_dir() else {
+        return CaStore::in_memory();
+    };
+    CaStore::open(dir.join(intercept::ca::CA_DIR)).unwrap_or_else(|e| {
+        log::error!("Failed to open the local CA: {}", e);
+        CaStore::in_memory()
+    })
+}
+
+/// Opens the interception policy in the app data dir, intercepting nothing without one
+fn open_intercept_policy(app: &tauri::AppHandle) -> PolicyStore {
+    let Ok(dir) = app.path().app_data_dir() else {
+        return PolicyStore::in_memory(InterceptPolicy::default());
+    };
+    PolicyStore::open(dir.join(intercept::policy::POLICY_FILE)).unwrap_or_else(|e| {
+        log::error!("Failed to open the interception policy: {}", e);
+        PolicyStore::in_memory(InterceptPolicy::default())
+    })
+}
+
+/// Opens the span exporter with its retry queue in the app data dir, queueing in memory without one
+fn open_telemetry(app: &tauri::AppHandle) -> TelemetryExporter {
+    let mut settings: TelemetryConfig = load_setting(app, config::STORE_TELEMETRY_KEY);
+    if let Err(e) = settings.validate() {
+        log::warn!("Ignoring saved telemetry settings: {}", e);
+        settings = TelemetryConfig::default();
+    }
+    let Ok(dir) = app.path().app_data_dir() else {
+        return TelemetryExporter::in_memory(settings);
+    };
+    TelemetryExporter::open(dir.join(telemetry::TELEMETRY_QUEUE_DIR), settings.clone())
+        .unwrap_or_else(|e| {
+            log::error!("Failed to open the telemetry queue: {}", e);
+            TelemetryExporter::in_memory(settings)
+        })
+}
+
+/// Hands the budgets of the clients' virtual keys to the budget tracker
+fn sync_key_budgets(proxy: &ProxyServer) {
+    proxy.budget().set_key_limits(proxy.clients().budgets());
+}
+
+/// Shows the most used up token budget in the tray tooltip
+fn refresh_tray_tooltip(app: &tauri::AppHandle, budget: &BudgetTracker) {
+    let Some(tray) = app.tray_by_id(config::TRAY_ID) else {
+        return;
+    };
+    let tooltip = match budget.tooltip() {
+        Some(status) => format!("{} · {}", config::TRAY_TOOLTIP, status),
+        None => config::TRAY_TOOLTIP.to_string(),
+    };
+    let _ = tray.set_tooltip(Some(tooltip));
+}
+
 /// Helper to open a URL in the default browser
 fn open_url_helper(app: &tauri::AppHandle, url: &str) {
     use tauri_plugin_opener::OpenerExt;
@@ -305,6 +1006,12 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_store::Builder::new().build())
+        // App log, also receives extension stderr
+        .plugin(
+            tauri_plugin_log::Builder::new()
+                .level(log::LevelFilter::Info)
+                .build(),
+        )
         .plugin(tauri_plugin_autostart::init(
             tauri_plugin_autostart::MacosLauncher::LaunchAgent,
             None,
@@ -314,11 +1021,142 @@ pub fn run() {
             open_external_url,
             enable_autostart,
             disable_autostart,
-            is_autostart_enabled
+            is_autostart_enabled,
+            start_proxy,
+            stop_proxy,
+            proxy_status,
+            start_interception,
+            stop_interception,
+            interception_status,
+            get_intercept_policy,
+            set_intercept_policy,
+            generate_ca,
+            export_ca,
+            rotate_ca,
+            ca_info,
+            list_extensions,
+            enable_extension,
+            disable_extension,
+            reorder_extensions,
+            install_extension,
+            uninstall_extension,
+            cache_stats,
+            clear_cache,
+            get_budgets,
+            set_budgets,
+            get_usage_summary,
+            get_telemetry_settings,
+            set_telemetry_settings,
+            telemetry_status,
+            get_redaction_settings,
+            set_redaction_settings,
+            get_secret_guard_settings,
+            set_secret_guard_settings,
+            get_injection_settings,
+            set_injection_settings,
+            resolve_route,
+            get_circuit_breakers,
+            list_client_keys,
+            create_client_key,
+            revoke_client_key,
+            set_client_keys_required,
+            get_credentials,
+            set_credential,
+            delete_credential,
+            test_credential,
+            unlock_credentials,
+            lock_credentials,
+            set_credentials_passphrase
         ])
         .setup(|app| {
             let version = env!("CARGO_PKG_VERSION");
 
+            // Start the embedded proxy so LLM traffic can flow through Blackbox
+            let event_sink: Arc<dyn events::EventSink> = Arc::new(app.handle().clone());
+            let captures = Arc::new(CaptureLog::new(
+                DEFAULT_CAPTURE_CAPACITY,
+                event_sink.clone(),
+            ));
+            let services = ProxyServices::new(captures)
+                .with_cache(open_cache(app.handle()))
+                .with_budget(open_budget(app.handle(), event_sink.clone()))
+                .with_routes(open_routes(app.handle()))
+                .with_breakers(CircuitBreakers::new(event_sink.clone()))
+                .with_clients(open_clients(app.handle()))
+                .with_credentials(open_credentials(app.handle()))
+                .with_usage(open_usage(app.handle()))
+                .with_telemetry(Arc::new(open_telemetry(app.handle())));
+            tauri::async_runtime::spawn(services.telemetry.clone().run());
+            services.budget.set_key_limits(services.clients.budgets());
+            let builtins = plugins::register_builtins(&services.pipeline, event_sink);
+            let redaction: RedactionSettings =
+                load_setting(app.handle(), config::STORE_REDACTION_KEY);
+            if let Err(e) = builtins.redact.set_settings(redaction) {
+                log::warn!("Ignoring saved redaction settings: {}", e);
+            }
+            let secret_guard: SecretGuardSettings =
+                load_setting(app.handle(), config::STORE_SECRET_GUARD_KEY);
+            if let Err(e) = builtins.secrets.set_settings(secret_guard) {
+                log::warn!("Ignoring saved secret guard settings: {}", e);
+            }
+            let injection: InjectionSettings =
+                load_setting(app.handle(), config::STORE_INJECTION_KEY);
+            if let Err(e) = builtins.injection.set_settings(injection) {
+                log::warn!("Ignoring saved injection settings: {}", e);
+            }
+            app.manage(builtins);
+            if let Some(store) = load_extensions(app.handle(), &services) {
+                app.manage(store);
+            }
+            let extensions: PipelineSettings =
+                load_setting(app.handle(), config::STORE_EXTENSIONS_KEY);
+            services.pipeline.apply_settings(&extensions);
+            let intercept_config: InterceptConfig =
+                load_setting(app.handle(), config::STORE_INTERCEPT_KEY);
+            let intercept_auto_start = intercept_config.auto_start;
+            app.manage(InterceptServer::new(
+                intercept_config,
+                Arc::new(open_ca(app.handle())),
+                services.clone(),
+            )
+            .with_policy(open_intercept_policy(app.handle())));
+            let mut proxy_config: ProxyConfig = load_setting(app.handle(), config::STORE_PROXY_KEY);
+            // Earlier versions kept the upstream key in plain text in the settings store
+            if proxy_config.upstream_api_key.is_some() {
+                let stored = proxy_config
+                    .store_api_key(&services.credentials)
+                    .map_err(|e| e.to_string())
+                    .and_then(|()| {
+                        save_setting(app.handle(), config::STORE_PROXY_KEY, &proxy_config)
+                    });
+                if let Err(e) = stored {
+                    log::warn!(
+                        "Failed to move the upstream API key to the credential store: {}",
+                        e
+                    );
+                }
+            }
+            let auto_start = proxy_config.auto_start;
+            app.manage(ProxyServer::new(proxy_config, services));
+            if auto_start {
+                let handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let proxy = handle.state::<ProxyServer>();
+                    if let Err(e) = proxy.start(None).await {
+                        log::error!("Failed to start Blackbox proxy: {}", e);
+                    }
+                });
+            }
+            if intercept_auto_start {
+                let handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let intercept = handle.state::<InterceptServer>();
+                    if let Err(e) = intercept.start(None).await {
+                        log::error!("Failed to start HTTPS interception: {}", e);
+                    }
+                });
+            }
+
             // Create menu items
             let open_item = MenuItemBuilder::with_id(config::MENU_OPEN_ID, "Open Blackbox")
                 .accelerator("CmdOrCtrl+Space")
@@ -385,10 +1223,10 @@ pub fn run() {
             )?;
 
             // Build the tray icon
-            let _tray = TrayIconBuilder::with_id("main")
+            let _tray = TrayIconBuilder::with_id(config::TRAY_ID)
                 .icon(tauri::include_image!("icons/tray-icon.png"))
                 .icon_as_template(true)
-                .tooltip("Blackbox")
+                .tooltip(config::TRAY_TOOLTIP)
                 .menu(&menu)
                 .show_menu_on_left_click(true)
                 .on_menu_event(|app, event| {
@@ -448,6 +1286,15 @@ pub fn run() {
                 })
                 .build(app)?;
 
+            // Budget alerts are also shown in the tray tooltip
+            let budget = app.state::<ProxyServer>().budget();
+            refresh_tray_tooltip(app.handle(), &budget);
+            for event in [budget::BUDGET_WARNING_EVENT, budget::BUDGET_EXCEEDED_EVENT] {
+                let handle = app.handle().clone();
+                let budget = budget.clone();
+                app.listen_any(event, move |_| refresh_tray_tooltip(&handle, &budget));
+            }
+
             // Register global shortcut (Cmd+Space on macOS, Ctrl+Space on other platforms)
             #[cfg(desktop)]
             {
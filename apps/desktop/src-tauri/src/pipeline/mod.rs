@@ -0,0 +1,826 @@
+//! Plugin pipeline run for every request going through the proxy
+//!
+//! A [`Plugin`] hooks into the request/response lifecycle described in
+//! VISION.md. The [`Pipeline`] keeps plugins in a user-defined order:
+//! request hooks run first to last, response and stream hooks run last to
+//! first so paired transforms (e.g. JSON → TOON and back) nest correctly.
+//! Any hook can modify what it is given, answer the client directly, or
+//! reject the request.
+
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::proxy::{Provider, SseEvent};
+use crate::sync::{read, write};
+
+/// Context shared by all hooks of a single proxied call
+#[derive(Debug, Clone)]
+pub struct PluginContext {
+    /// Id of the captured call this exchange is recorded as
+    pub call_id: String,
+    pub provider: Provider,
+    /// Proxy route, e.g. `/v1/chat/completions`
+    pub endpoint: String,
+    /// Model requested by the client
+    pub model: String,
+    /// Notes plugins want stored on the captured call
+    pub metadata: Map<String, Value>,
+    /// Ids of the plugins allowed to run on this call, every enabled one when unset
+    pub plugins: Option<Vec<String>>,
+    /// Whether the request is routed to a local upstream only, see
+    /// [`crate::routing::ResolvedRoute::is_local`]. Request hooks see the
+    /// route of the requested model, later hooks the final one
+    pub local: bool,
+}
+
+impl PluginContext {
+    /// Creates a context for a new call
+    pub fn new(call_id: &str, provider: Provider, endpoint: &str, model: &str) -> Self {
+        Self {
+            call_id: call_id.to_string(),
+            provider,
+            endpoint: endpoint.to_string(),
+            model: model.to_string(),
+            metadata: Map::new(),
+            plugins: None,
+            local: false,
+        }
+    }
+}
+
+/// Request about to be forwarded upstream
+#[derive(Debug, Clone)]
+pub struct PluginRequest {
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+/// Response about to be returned to the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+/// Failure reported to `on_error` hooks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamFailure {
+    /// Status that will be returned to the client
+    pub status: u16,
+    pub message: String,
+}
+
+/// Reason a plugin refused a request or response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rejection {
+    pub status: u16,
+    /// Error type reported to the client, e.g. `invalid_request_error`
+    pub kind: String,
+    pub message: String,
+}
+
+impl Rejection {
+    /// Creates a rejection with the given status, kind and message
+    pub fn new(status: u16, kind: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            kind: kind.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// What the pipeline does after a request, response or error hook
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookOutcome {
+    /// Run the next plugin; the hook may have modified its input in place
+    Continue,
+    /// Skip the remaining plugins and answer the client with this response
+    Respond(PluginResponse),
+    /// Skip the remaining plugins and fail with an error
+    Reject(Rejection),
+}
+
+/// What the pipeline does after a stream chunk hook
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkOutcome {
+    /// Forward the (possibly modified) event
+    Continue,
+    /// Drop the event without forwarding it
+    Skip,
+    /// Forward these events, then the (possibly modified) event
+    Insert(Vec<SseEvent>),
+    /// Abort the stream with an error event
+    Reject(Rejection),
+}
+
+/// A pipeline extension; every hook defaults to a no-op
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    /// Stable identifier, e.g. `builtin.toon`
+    fn id(&self) -> &str;
+
+    /// Human readable name shown in the Extensions tab
+    fn name(&self) -> &str;
+
+    /// Short description shown in the Extensions tab
+    fn description(&self) -> &str {
+        ""
+    }
+
+    /// Version shown for installed extensions
+    fn version(&self) -> Option<&str> {
+        None
+    }
+
+    /// Runs before the request is forwarded upstream
+    async fn on_request(
+        &self,
+        _ctx: &mut PluginContext,
+        _request: &mut PluginRequest,
+    ) -> HookOutcome {
+        HookOutcome::Continue
+    }
+
+    /// Runs on a complete, non-streamed JSON response
+    async fn on_response(
+        &self,
+        _ctx: &mut PluginContext,
+        _response: &mut PluginResponse,
+    ) -> HookOutcome {
+        HookOutcome::Continue
+    }
+
+    /// Runs on every server-sent event of a streamed response
+    async fn on_stream_chunk(
+        &self,
+        _ctx: &mut PluginContext,
+        _event: &mut SseEvent,
+    ) -> ChunkOutcome {
+        ChunkOutcome::Continue
+    }
+
+    /// Runs when the upstream fails; `Respond` recovers with a fallback answer
+    async fn on_error(&self, _ctx: &mut PluginContext, _error: &UpstreamFailure) -> HookOutcome {
+        HookOutcome::Continue
+    }
+}
+
+/// Where an extension comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtensionKind {
+    Builtin,
+    Wasm,
+    Process,
+}
+
+/// Extension summary for the frontend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtensionInfo {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub kind: ExtensionKind,
+    pub enabled: bool,
+}
+
+/// Persisted order and enablement, stored in the settings store
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PipelineSettings {
+    pub order: Vec<String>,
+    pub disabled: Vec<String>,
+}
+
+/// Errors raised while managing the pipeline
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum PipelineError {
+    #[error("unknown extension: {0}")]
+    UnknownExtension(String),
+    #[error("extension already registered: {0}")]
+    DuplicateExtension(String),
+    #[error("new order must list every extension exactly once")]
+    InvalidOrder,
+}
+
+struct Entry {
+    plugin: Arc<dyn Plugin>,
+    kind: ExtensionKind,
+    enabled: bool,
+}
+
+/// Ordered set of plugins
+#[derive(Default)]
+pub struct Pipeline {
+    entries: RwLock<Vec<Entry>>,
+}
+
+impl Pipeline {
+    /// Creates an empty pipeline
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an enabled plugin
+    pub fn register(
+        &self,
+        plugin: Arc<dyn Plugin>,
+        kind: ExtensionKind,
+    ) -> Result<(), PipelineError> {
+        self.insert(plugin, kind, true)
+    }
+
+    /// Appends a plugin that stays off until the user enables it
+    pub fn register_disabled(
+        &self,
+        plugin: Arc<dyn Plugin>,
+        kind: ExtensionKind,
+    ) -> Result<(), PipelineError> {
+        self.insert(plugin, kind, false)
+    }
+
+    fn insert(
+        &self,
+        plugin: Arc<dyn Plugin>,
+        kind: ExtensionKind,
+        enabled: bool,
+    ) -> Result<(), PipelineError> {
+        let mut entries = write(&self.entries);
+        if entries.iter().any(|e| e.plugin.id() == plugin.id()) {
+            return Err(PipelineError::DuplicateExtension(plugin.id().to_string()));
+        }
+        entries.push(Entry {
+            plugin,
+            kind,
+            enabled,
+        });
+        Ok(())
+    }
+
+    /// Removes a plugin
+    pub fn unregister(&self, id: &str) -> Result<(), PipelineError> {
+        let mut entries = write(&self.entries);
+        let index = entries
+            .iter()
+            .position(|e| e.plugin.id() == id)
+            .ok_or_else(|| PipelineError::UnknownExtension(id.to_string()))?;
+        entries.remove(index);
+        Ok(())
+    }
+
+    /// Lists all plugins in pipeline order
+    pub fn list(&self) -> Vec<ExtensionInfo> {
+        let entries = read(&self.entries);
+        entries
+            .iter()
+            .map(|e| ExtensionInfo {
+                id: e.plugin.id().to_string(),
+                name: e.plugin.name().to_string(),
+                description: e.plugin.description().to_string(),
+                version: e.plugin.version().map(str::to_string),
+                kind: e.kind,
+                enabled: e.enabled,
+            })
+            .collect()
+    }
+
+    /// Enables or disables a plugin
+    pub fn set_enabled(&self, id: &str, enabled: bool) -> Result<(), PipelineError> {
+        let mut entries = write(&self.entries);
+        let entry = entries
+            .iter_mut()
+            .find(|e| e.plugin.id() == id)
+            .ok_or_else(|| PipelineError::UnknownExtension(id.to_string()))?;
+        entry.enabled = enabled;
+        Ok(())
+    }
+
+    /// Reorders plugins; `ids` must be a permutation of the registered ids
+    pub fn reorder(&self, ids: &[String]) -> Result<(), PipelineError> {
+        let mut entries = write(&self.entries);
+        let is_permutation = ids.len() == entries.len()
+            && entries
+                .iter()
+                .all(|e| ids.iter().filter(|id| *id == e.plugin.id()).count() == 1);
+        if !is_permutation {
+            return Err(PipelineError::InvalidOrder);
+        }
+        entries.sort_by_key(|e| ids.iter().position(|id| id == e.plugin.id()));
+        Ok(())
+    }
+
+    /// Returns the persisted form of the current order and enablement
+    pub fn settings(&self) -> PipelineSettings {
+        let entries = read(&self.entries);
+        PipelineSettings {
+            order: entries.iter().map(|e| e.plugin.id().to_string()).collect(),
+            disabled: entries
+                .iter()
+                .filter(|e| !e.enabled)
+                .map(|e| e.plugin.id().to_string())
+                .collect(),
+        }
+    }
+
+    /// Applies persisted settings, ignoring ids that are no longer registered.
+    /// Plugins missing from the saved order keep their position at the end
+    /// and the enablement they were registered with.
+    pub fn apply_settings(&self, settings: &PipelineSettings) {
+        let mut entries = write(&self.entries);
+        let rank = |id: &str| {
+            settings
+                .order
+                .iter()
+                .position(|o| o == id)
+                .unwrap_or(usize::MAX)
+        };
+        entries.sort_by_key(|e| rank(e.plugin.id()));
+        for entry in entries.iter_mut() {
+            let id = entry.plugin.id();
+            if settings.disabled.iter().any(|d| d == id) {
+                entry.enabled = false;
+            } else if settings.order.iter().any(|o| o == id) {
+                entry.enabled = true;
+            }
+        }
+    }
+
+    /// Returns the enabled plugins in order, as a snapshot for one call
+    pub fn active(&self) -> Vec<Arc<dyn Plugin>> {
+        let entries = read(&self.entries);
+        entries
+            .iter()
+            .filter(|e| e.enabled)
+            .map(|e| e.plugin.clone())
+            .collect()
+    }
+
+    /// Returns the enabled plugins allowed to run on the call of `ctx`
+    fn active_for(&self, ctx: &PluginContext) -> Vec<Arc<dyn Plugin>> {
+        let mut active = self.active();
+        if let Some(allowed) = &ctx.plugins {
+            active.retain(|plugin| allowed.iter().any(|id| id == plugin.id()));
+        }
+        active
+    }
+
+    /// Returns true when no plugin would run on the call of `ctx`
+    pub fn is_idle(&self, ctx: &PluginContext) -> bool {
+        self.active_for(ctx).is_empty()
+    }
+
+    /// Runs request hooks first to last
+    pub async fn run_request(
+        &self,
+        ctx: &mut PluginContext,
+        request: &mut PluginRequest,
+    ) -> HookOutcome {
+        for plugin in self.active_for(ctx) {
+            match plugin.on_request(ctx, request).await {
+                HookOutcome::Continue => {}
+                outcome => return outcome,
+            }
+        }
+        HookOutcome::Continue
+    }
+
+    /// Runs response hooks last to first
+    pub async fn run_response(
+        &self,
+        ctx: &mut PluginContext,
+        response: &mut PluginResponse,
+    ) -> HookOutcome {
+        for plugin in self.active_for(ctx).into_iter().rev() {
+            match plugin.on_response(ctx, response).await {
+                HookOutcome::Continue => {}
+                outcome => return outcome,
+            }
+        }
+        HookOutcome::Continue
+    }
+
+    /// Runs stream hooks last to first. Events a plugin inserts go through the
+    /// plugins after it and come back in `Insert`, ahead of `event`.
+    pub async fn run_stream_chunk(
+        &self,
+        ctx: &mut PluginContext,
+        event: &mut SseEvent,
+    ) -> ChunkOutcome {
+        let mut inserted: Vec<SseEvent> = Vec::new();
+        let mut skipped = false;
+        for plugin in self.active_for(ctx).into_iter().rev() {
+            let mut kept = Vec::with_capacity(inserted.len());
+            for mut earlier in std::mem::take(&mut inserted) {
+                match plugin.on_stream_chunk(ctx, &mut earlier).await {
+                    ChunkOutcome::Continue => kept.push(earlier),
+                    ChunkOutcome::Skip => {}
+                    ChunkOutcome::Insert(more) => {
+                        kept.extend(more);
+                        kept.push(earlier);
+                    }
+                    outcome @ ChunkOutcome::Reject(_) => return outcome,
+                }
+            }
+            inserted = kept;
+            if skipped {
+                continue;
+            }
+            match plugin.on_stream_chunk(ctx, event).await {
+                ChunkOutcome::Continue => {}
+                ChunkOutcome::Skip => skipped = true,
+                ChunkOutcome::Insert(more) => inserted.extend(more),
+                outcome @ ChunkOutcome::Reject(_) => return outcome,
+            }
+        }
+        if skipped {
+            // The last inserted event stands in for the dropped one
+            match inserted.pop() {
+                Some(last) => *event = last,
+                None => return ChunkOutcome::Skip,
+            }
+        }
+        if inserted.is_empty() {
+            ChunkOutcome::Continue
+        } else {
+            ChunkOutcome::Insert(inserted)
+        }
+    }
+
+    /// Runs error hooks first to last until one recovers or rejects
+    pub async fn run_error(&self, ctx: &mut PluginContext, error: &UpstreamFailure) -> HookOutcome {
+        for plugin in self.active_for(ctx) {
+            match plugin.on_error(ctx, error).await {
+                HookOutcome::Continue => {}
+                outcome => return outcome,
+            }
+        }
+        HookOutcome::Continue
+    }
+}
+
+/// Plugins and contexts used by tests across the crate
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    /// Context of an OpenAI chat call to `gpt-4o`
+    pub fn context() -> PluginContext {
+        PluginContext::new("call", Provider::OpenAi, "/v1/chat/completions", "gpt-4o")
+    }
+
+    /// Plugin built from closures, for tests
+    pub struct FnPlugin {
+        pub id: String,
+        pub on_request: Box<dyn Fn(&mut PluginRequest) -> HookOutcome + Send + Sync>,
+        pub on_response: Box<dyn Fn(&mut PluginResponse) -> HookOutcome + Send + Sync>,
+        pub on_chunk: Box<dyn Fn(&mut SseEvent) -> ChunkOutcome + Send + Sync>,
+        pub on_error: Box<dyn Fn(&UpstreamFailure) -> HookOutcome + Send + Sync>,
+    }
+
+    impl FnPlugin {
+        /// Creates a plugin whose hooks all continue
+        pub fn new(id: &str) -> Self {
+            Self {
+                id: id.to_string(),
+                on_request: Box::new(|_| HookOutcome::Continue),
+                on_response: Box::new(|_| HookOutcome::Continue),
+                on_chunk: Box::new(|_| ChunkOutcome::Continue),
+                on_error: Box::new(|_| HookOutcome::Continue),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Plugin for FnPlugin {
+        fn id(&self) -> &str {
+            &self.id
+        }
+
+        fn name(&self) -> &str {
+            &self.id
+        }
+
+        async fn on_request(
+            &self,
+            _ctx: &mut PluginContext,
+            request: &mut PluginRequest,
+        ) -> HookOutcome {
+            (self.on_request)(request)
+        }
+
+        async fn on_response(
+            &self,
+            _ctx: &mut PluginContext,
+            response: &mut PluginResponse,
+        ) -> HookOutcome {
+            (self.on_response)(response)
+        }
+
+        async fn on_stream_chunk(
+            &self,
+            _ctx: &mut PluginContext,
+            event: &mut SseEvent,
+        ) -> ChunkOutcome {
+            (self.on_chunk)(event)
+        }
+
+        async fn on_error(&self, _ctx: &mut PluginContext, error: &UpstreamFailure) -> HookOutcome {
+            (self.on_error)(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{context, FnPlugin};
+    use super::*;
+    use serde_json::json;
+
+    fn request() -> PluginRequest {
+        PluginRequest {
+            headers: HeaderMap::new(),
+            body: json!({"trace": []}),
+        }
+    }
+
+    fn tracing_plugin(id: &'static str) -> FnPlugin {
+        let mut plugin = FnPlugin::new(id);
+        plugin.on_request = Box::new(move |r| {
+            r.body["trace"].as_array_mut().unwrap().push(json!(id));
+            HookOutcome::Continue
+        });
+        plugin.on_response = Box::new(move |r| {
+            r.body["trace"].as_array_mut().unwrap().push(json!(id));
+            HookOutcome::Continue
+        });
+        plugin
+    }
+
+    fn pipeline(ids: &[&'static str]) -> Pipeline {
+        let pipeline = Pipeline::new();
+        for id in ids {
+            pipeline
+                .register(Arc::new(tracing_plugin(id)), ExtensionKind::Builtin)
+                .unwrap();
+        }
+        pipeline
+    }
+
+    mod management_tests {
+        use super::*;
+
+        #[test]
+        fn test_register_rejects_duplicates() {
+            let pipeline = pipeline(&["a"]);
+            assert_eq!(
+                pipeline.register(Arc::new(FnPlugin::new("a")), ExtensionKind::Builtin),
+                Err(PipelineError::DuplicateExtension("a".to_string()))
+            );
+        }
+
+        #[test]
+        fn test_enable_disable() {
+            let pipeline = pipeline(&["a", "b"]);
+            pipeline.set_enabled("a", false).unwrap();
+            assert_eq!(pipeline.active().len(), 1);
+            assert!(!pipeline.list()[0].enabled);
+            assert_eq!(
+                pipeline.set_enabled("zzz", true),
+                Err(PipelineError::UnknownExtension("zzz".to_string()))
+            );
+        }
+
+        #[test]
+        fn test_reorder() {
+            let pipeline = pipeline(&["a", "b", "c"]);
+            pipeline
+                .reorder(&["c".to_string(), "a".to_string(), "b".to_string()])
+                .unwrap();
+            let ids: Vec<_> = pipeline.list().into_iter().map(|e| e.id).collect();
+            assert_eq!(ids, vec!["c", "a", "b"]);
+        }
+
+        #[test]
+        fn test_reorder_rejects_partial_or_unknown_lists() {
+            let pipeline = pipeline(&["a", "b"]);
+            assert_eq!(
+                pipeline.reorder(&["a".to_string()]),
+                Err(PipelineError::InvalidOrder)
+            );
+            assert_eq!(
+                pipeline.reorder(&["a".to_string(), "x".to_string()]),
+                Err(PipelineError::InvalidOrder)
+            );
+            let ids: Vec<_> = pipeline.list().into_iter().map(|e| e.id).collect();
+            assert_eq!(ids, vec!["a", "b"]);
+        }
+
+        #[test]
+        fn test_settings_round_trip() {
+            let pipeline = pipeline(&["a", "b", "c"]);
+            pipeline.apply_settings(&PipelineSettings {
+                order: vec!["b".to_string(), "gone".to_string(), "a".to_string()],
+                disabled: vec!["a".to_string()],
+            });
+
+            let ids: Vec<_> = pipeline.list().into_iter().map(|e| e.id).collect();
+            assert_eq!(ids, vec!["b", "a", "c"]);
+            assert_eq!(
+                pipeline.settings(),
+                PipelineSettings {
+                    order: vec!["b".to_string(), "a".to_string(), "c".to_string()],
+                    disabled: vec!["a".to_string()],
+                }
+            );
+        }
+
+        #[test]
+        fn test_new_plugins_keep_default_enablement() {
+            let pipeline = pipeline(&["a"]);
+            pipeline
+                .register_disabled(Arc::new(FnPlugin::new("b")), ExtensionKind::Builtin)
+                .unwrap();
+            pipeline.apply_settings(&PipelineSettings {
+                order: vec!["a".to_string()],
+                disabled: vec![],
+            });
+            let enabled: Vec<_> = pipeline.list().into_iter().map(|e| e.enabled).collect();
+            assert_eq!(enabled, vec![true, false]);
+
+            // Once saved, the user's choice wins
+            pipeline.apply_settings(&PipelineSettings {
+                order: vec!["b".to_string(), "a".to_string()],
+                disabled: vec![],
+            });
+            assert!(pipeline.list().iter().all(|e| e.enabled));
+        }
+    }
+
+    mod hook_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_request_hooks_run_in_order_and_responses_in_reverse() {
+            let pipeline = pipeline(&["a", "b"]);
+            let mut ctx = context();
+
+            let mut req = request();
+            assert_eq!(
+                pipeline.run_request(&mut ctx, &mut req).await,
+                HookOutcome::Continue
+            );
+            assert_eq!(req.body["trace"], json!(["a", "b"]));
+
+            let mut response = PluginResponse {
+                status: 200,
+                body: json!({"trace": []}),
+            };
+            pipeline.run_response(&mut ctx, &mut response).await;
+            assert_eq!(response.body["trace"], json!(["b", "a"]));
+        }
+
+        #[tokio::test]
+        async fn test_reject_short_circuits() {
+            let pipeline = pipeline(&["a"]);
+            let mut blocker = FnPlugin::new("blocker");
+            blocker.on_request =
+                Box::new(|_| HookOutcome::Reject(Rejection::new(403, "blocked", "no")));
+            pipeline
+                .register(Arc::new(blocker), ExtensionKind::Builtin)
+                .unwrap();
+            pipeline
+                .register(Arc::new(tracing_plugin("after")), ExtensionKind::Builtin)
+                .unwrap();
+
+            let mut req = request();
+            let outcome = pipeline.run_request(&mut context(), &mut req).await;
+            assert_eq!(
+                outcome,
+                HookOutcome::Reject(Rejection::new(403, "blocked", "no"))
+            );
+            assert_eq!(req.body["trace"], json!(["a"]));
+        }
+
+        #[tokio::test]
+        async fn test_disabled_plugins_are_skipped() {
+            let pipeline = pipeline(&["a", "b"]);
+            pipeline.set_enabled("a", false).unwrap();
+
+            let mut req = request();
+            pipeline.run_request(&mut context(), &mut req).await;
+            assert_eq!(req.body["trace"], json!(["b"]));
+        }
+
+        #[tokio::test]
+        async fn test_call_can_narrow_the_plugins() {
+            let pipeline = pipeline(&["a", "b", "c"]);
+            pipeline.set_enabled("c", false).unwrap();
+            let mut ctx = context();
+            ctx.plugins = Some(vec!["b".to_string(), "c".to_string()]);
+
+            let mut req = request();
+            pipeline.run_request(&mut ctx, &mut req).await;
+            assert_eq!(req.body["trace"], json!(["b"]));
+            ctx.plugins = Some(Vec::new());
+            assert!(pipeline.is_idle(&ctx));
+            assert!(!pipeline.is_idle(&context()));
+        }
+
+        #[tokio::test]
+        async fn test_stream_chunk_skip() {
+            let pipeline = Pipeline::new();
+            let mut dropper = FnPlugin::new("dropper");
+            dropper.on_chunk = Box::new(|e| {
+                if e.data == "drop" {
+                    ChunkOutcome::Skip
+                } else {
+                    e.data.make_ascii_uppercase();
+                    ChunkOutcome::Continue
+                }
+            });
+            pipeline
+                .register(Arc::new(dropper), ExtensionKind::Builtin)
+                .unwrap();
+
+            let mut event = SseEvent {
+                event: None,
+                data: "drop".to_string(),
+            };
+            assert_eq!(
+                pipeline.run_stream_chunk(&mut context(), &mut event).await,
+                ChunkOutcome::Skip
+            );
+            event.data = "keep".to_string();
+            pipeline.run_stream_chunk(&mut context(), &mut event).await;
+            assert_eq!(event.data, "KEEP");
+        }
+
+        #[tokio::test]
+        async fn test_inserted_events_reach_later_plugins() {
+            let pipeline = Pipeline::new();
+            let mut upper = FnPlugin::new("upper");
+            upper.on_chunk = Box::new(|e| {
+                e.data.make_ascii_uppercase();
+                ChunkOutcome::Continue
+            });
+            let mut splitter = FnPlugin::new("splitter");
+            splitter.on_chunk = Box::new(|e| {
+                let (head, tail) = e.data.split_at(1);
+                let head = SseEvent {
+                    event: None,
+                    data: head.to_string(),
+                };
+                e.data = tail.to_string();
+                ChunkOutcome::Insert(vec![head])
+            });
+            // Stream hooks run last to first, so the splitter goes before `upper`
+            for plugin in [upper, splitter] {
+                pipeline
+                    .register(Arc::new(plugin), ExtensionKind::Builtin)
+                    .unwrap();
+            }
+
+            let mut event = SseEvent {
+                event: None,
+                data: "ab".to_string(),
+            };
+            let outcome = pipeline.run_stream_chunk(&mut context(), &mut event).await;
+            let ChunkOutcome::Insert(inserted) = outcome else {
+                panic!("expected inserted events, got {:?}", outcome);
+            };
+            assert_eq!(inserted.len(), 1);
+            assert_eq!((inserted[0].data.as_str(), event.data.as_str()), ("A", "B"));
+        }
+
+        #[tokio::test]
+        async fn test_error_hook_can_recover() {
+            let pipeline = Pipeline::new();
+            let mut fallback = FnPlugin::new("fallback");
+            fallback.on_error = Box::new(|e| {
+                HookOutcome::Respond(PluginResponse {
+                    status: 200,
+                    body: json!({"recovered": e.message}),
+                })
+            });
+            pipeline
+                .register(Arc::new(fallback), ExtensionKind::Builtin)
+                .unwrap();
+
+            let failure = UpstreamFailure {
+                status: 502,
+                message: "down".to_string(),
+            };
+            match pipeline.run_error(&mut context(), &failure).await {
+                HookOutcome::Respond(response) => assert_eq!(response.body["recovered"], "down"),
+                other => panic!("unexpected outcome {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Built-in pipeline plugins
+//!
+//! Builtins ship with the app and are listed in the Extensions tab next to
+//! installed extensions. They change what the model sees, so each one is
+//! registered disabled and only runs once the user turns it on.
+
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::events::EventSink;
+use crate::pipeline::{ExtensionKind, Pipeline, Plugin};
+use crate::proxy::Provider;
+
+pub mod injection;
+pub mod redact;
+pub mod secrets;
+pub mod toon;
+
+/// Handles on the builtins that have settings of their own
+pub struct Builtins {
+    pub injection: Arc<injection::InjectionPlugin>,
+    pub redact: Arc<redact::RedactPlugin>,
+    pub secrets: Arc<secrets::SecretGuardPlugin>,
+}
+
+/// Registers every built-in plugin, disabled
+pub fn register_builtins(pipeline: &Pipeline, events: Arc<dyn EventSink>) -> Builtins {
+    let handles = Builtins {
+        injection: Arc::new(injection::InjectionPlugin::default()),
+        redact: Arc::new(redact::RedactPlugin::default()),
+        secrets: Arc::new(secrets::SecretGuardPlugin::new(events)),
+    };
+    // Secrets are caught before redaction could hide them
+    let builtins: Vec<Arc<dyn Plugin>> = vec![
+        handles.secrets.clone(),
+        handles.injection.clone(),
+        handles.redact.clone(),
+        Arc::new(toon::ToonPlugin),
+    ];
+    for plugin in builtins {
+        if let Err(e) = pipeline.register_disabled(plugin, ExtensionKind::Builtin) {
+            log::warn!("Failed to register builtin plugin: {}", e);
+        }
+    }
+    handles
+}
+
+/// Text fields of messages, tool results and legacy completion prompts in a request body
+pub(crate) fn request_texts(provider: Provider, body: &mut Value) -> Vec<&mut String> {
+    body_texts(provider, body, false)
+}
+
+/// Like [`request_texts`], plus the system prompt kept outside the messages
+/// and the arguments and results of earlier tool calls
+pub(crate) fn prompt_texts(provider: Provider, body: &mut Value) -> Vec<&mut String> {
+    body_texts(provider, body, true)
+}
+
+fn body_texts(provider: Provider, body: &mut Value, system: bool) -> Vec<&mut String> {
+    let mut texts = Vec::new();
+    let Some(fields) = body.as_object_mut() else {
+        return texts;
+    };
+    for (key, value) in fields.iter_mut() {
+        match (provider, key.as_str()) {
+            (Provider::OpenAi | Provider::Anthropic, "messages") => {
+                for message in value.as_array_mut().into_iter().flatten() {
+                    let Some(fields) = message.as_object_mut() else {
+                        continue;
+                    };
+                    for (key, field) in fields.iter_mut() {
+                        match key.as_str() {
+                            "content" => content_texts(field, system, &mut texts),
+                            "tool_calls" if system => tool_call_texts(field, &mut texts),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            // `/v1/completions` takes a string or a list of strings
+            (Provider::OpenAi, "prompt") => match value {
+                Value::String(text) => texts.push(text),
+                Value::Array(prompts) => {
+                    for prompt in prompts {
+                        if let Value::String(text) = prompt {
+                            texts.push(text);
+                        }
+                    }
+                }
+                _ => {}
+            },
+            (Provider::Gemini, "contents") => {
+                for content in value.as_array_mut().into_iter().flatten() {
+                    parts_texts(content, system, &mut texts);
+                }
+            }
+            (Provider::Anthropic, "system") if system => content_texts(value, false, &mut texts),
+            (Provider::Gemini, "systemInstruction") if system => {
+                parts_texts(value, false, &mut texts)
+            }
+            _ => {}
+        }
+    }
+    texts
+}
+
+/// Text fields of tool results in a request body, i.e. what tools fed back to the model
+pub(crate) fn tool_result_texts(provider: Provider, body: &mut Value) -> Vec<&mut String> {
+    let mut texts = Vec::new();
+    match provider {
+        Provider::OpenAi => {
+            if let Some(messages) = body.get_mut("messages").and_then(Value::as_array_mut) {
+                for message in messages {
+                    if message.get("role").and_then(Value::as_str) != Some("tool") {
+                        continue;
+                    }
+                    if let Some(content) = message.get_mut("content") {
+                        content_texts(content, false, &mut texts);
+                    }
+                }
+            }
+        }
+        Provider::Anthropic => {
+            if let Some(messages) = body.get_mut("messages").and_then(Value::as_array_mut) {
+                for message in messages {
+                    let Some(blocks) = message.get_mut("content").and_then(Value::as_array_mut)
+                    else {
+                        continue;
+                    };
+                    for block in blocks {
+                        if block.get("type").and_then(Value::as_str) != Some("tool_result") {
+                            continue;
+                        }
+                        if let Some(content) = block.get_mut("content") {
+                            content_texts(content, false, &mut texts);
+                        }
+                    }
+                }
+            }
+        }
+        Provider::Gemini => {
+            if let Some(contents) = body.get_mut("contents").and_then(Value::as_array_mut) {
+                for content in contents {
+                    let Some(parts) = content.get_mut("parts").and_then(Value::as_array_mut) else {
+                        continue;
+                    };
+                    for part in parts {
+                        if let Some(response) = part.pointer_mut("/functionResponse/response") {
+                            string_leaves(response, &mut texts);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    texts
+}
+
+/// Text fields of the answer in a non-streamed response body
+pub(crate) fn response_texts(provider: Provider, body: &mut Value) -> Vec<&mut String> {
+    let mut texts = Vec::new();
+    match provider {
+        Provider::OpenAi => {
+            if let Some(choices) = body.get_mut("choices").and_then(Value::as_array_mut) {
+                for choice in choices {
+                    if let Some(Value::String(text)) = choice.pointer_mut("/message/content") {
+                        texts.push(text);
+                    }
+                }
+            }
+        }
+        Provider::Anthropic => {
+            if let Some(content) = body.get_mut("content") {
+                content_texts(content, false, &mut texts);
+            }
+        }
+        Provider::Gemini => {
+            if let Some(candidates) = body.get_mut("candidates").and_then(Value::as_array_mut) {
+                for candidate in candidates {
+                    if let Some(content) = candidate.get_mut("content") {
+                        parts_texts(content, false, &mut texts);
+                    }
+                }
+            }
+        }
+    }
+    texts
+}
+
+/// Collects a message `content`: a string or a list of text and tool result
+/// blocks, and with `tools` the input of `tool_use` blocks
+fn content_texts<'a>(content: &'a mut Value, tools: bool, texts: &mut Vec<&'a mut String>) {
+    match content {
+        Value::String(text) => texts.push(text),
+        Value::Array(blocks) => {
+            for block in blocks {
+                let Some(block) = block.as_object_mut() else {
+                    continue;
+                };
+                let kind = block.get("type").and_then(Value::as_str);
+                let (is_tool_result, is_tool_use) =
+                    (kind == Some("tool_result"), kind == Some("tool_use"));
+                if is_tool_use {
+                    if let (true, Some(input)) = (tools, block.get_mut("input")) {
+                        string_leaves(input, texts);
+                    }
+                    continue;
+                }
+                let field = if is_tool_result { "content" } else { "text" };
+                match block.get_mut(field) {
+                    Some(Value::String(text)) => texts.push(text),
+                    Some(nested) if is_tool_result => content_texts(nested, tools, texts),
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Collects the JSON `arguments` of OpenAI `tool_calls`
+fn tool_call_texts<'a>(tool_calls: &'a mut Value, texts: &mut Vec<&'a mut String>) {
+    for call in tool_calls.as_array_mut().into_iter().flatten() {
+        if let Some(Value::String(arguments)) = call.pointer_mut("/function/arguments") {
+            texts.push(arguments);
+        }
+    }
+}
+
+/// Collects the `text` parts of a Gemini content, and with `tools` the
+/// arguments and results of function calls
+fn parts_texts<'a>(content: &'a mut Value, tools: bool, texts: &mut Vec<&'a mut String>) {
+    let Some(parts) = content.get_mut("parts").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for part in parts.iter_mut().filter_map(Value::as_object_mut) {
+        for (key, value) in part.iter_mut() {
+            let payload = match key.as_str() {
+                "text" => {
+                    if let Value::String(text) = value {
+                        texts.push(text);
+                    }
+                    continue;
+                }
+                "functionCall" if tools => value.get_mut("args"),
+                "functionResponse" if tools => value.get_mut("response"),
+                _ => None,
+            };
+            if let Some(payload) = payload {
+                string_leaves(payload, texts);
+            }
+        }
+    }
+}
+
+/// Collects every string nested anywhere in a value
+fn string_leaves<'a>(value: &'a mut Value, texts: &mut Vec<&'a mut String>) {
+    match value {
+        Value::String(text) => texts.push(text),
+        Value::Array(items) => items.iter_mut().for_each(|v| string_leaves(v, texts)),
+        Value::Object(fields) => fields.values_mut().for_each(|v| string_leaves(v, texts)),
+        _ => {}
+    }
+}
@@ -0,0 +1,940 @@
+//! PII redaction
+//!
+//! Masks email addresses, phone numbers, payment card numbers, IP addresses
+//! and user-defined patterns in message content, tool calls, tool results
+//! and completion prompts before a request leaves the machine. Every
+//! distinct value gets a placeholder such as `[EMAIL_1]`, which is swapped
+//! back in the response so the client sees the original text. The captured
+//! call records how many values of each kind were masked under `redaction`,
+//! never the values themselves.
+//! Streamed answers may split a placeholder across events, so text that ends
+//! like the start of one is held back until the next delta or the end of the
+//! choice or content block.
+//!
+//! Requests routed only to Ollama on this machine are exempt unless
+//! `exempt_local` is turned off. The proxy refuses an exempted request that
+//! plugins, a downgrade or the routing table then send anywhere else.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::{Mutex, RwLock};
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::prompt_texts;
+use crate::pipeline::{
+    ChunkOutcome, HookOutcome, Plugin, PluginContext, PluginRequest, PluginResponse,
+    UpstreamFailure,
+};
+use crate::proxy::{Provider, SseEvent};
+use crate::sync::{lock, read, write};
+
+/// Id used in the Extensions tab and pipeline settings
+pub const ID: &str = "builtin.redact";
+/// Metadata key the redaction counts are recorded under
+pub const METADATA_KEY: &str = "redaction";
+
+/// Calls whose placeholders are remembered while their answer is pending
+const MAX_PENDING: usize = 256;
+
+/// What to redact, persisted in the settings store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactionSettings {
+    pub emails: bool,
+    pub phones: bool,
+    pub cards: bool,
+    pub ip_addresses: bool,
+    /// Extra patterns, applied before the built-in ones
+    pub patterns: Vec<CustomPattern>,
+    /// Leave requests routed to local Ollama models alone
+    pub exempt_local: bool,
+    /// Put the original values back into responses
+    pub restore: bool,
+}
+
+impl Default for RedactionSettings {
+    fn default() -> Self {
+        Self {
+            emails: true,
+            phones: true,
+            cards: true,
+            ip_addresses: true,
+            patterns: Vec::new(),
+            exempt_local: true,
+            restore: true,
+        }
+    }
+}
+
+/// User-defined pattern; matches become `[NAME_n]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomPattern {
+    pub name: String,
+    pub regex: String,
+}
+
+impl RedactionSettings {
+    /// Checks that custom patterns compile and have usable names
+    pub fn validate(&self) -> Result<(), String> {
+        self.detectors().map(|_| ())
+    }
+
+    fn detectors(&self) -> Result<Vec<Detector>, String> {
+        let mut detectors = Vec::new();
+        for pattern in &self.patterns {
+            let valid_name = !pattern.name.is_empty()
+                && pattern
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid_name {
+                return Err(format!(
+                    "pattern name {:?} may only use letters, digits and '_'",
+                    pattern.name
+                ));
+            }
+            let regex = Regex::new(&pattern.regex)
+                .map_err(|e| format!("pattern {}: {}", pattern.name, e))?;
+            detectors.push(Detector {
+                label: pattern.name.to_ascii_uppercase(),
+                regex,
+                accept: |_| true,
+            });
+        }
+        // Cards and addresses go before phones, whose pattern would take their digits
+        let builtins: [(bool, &str, &str, Accept); 5] = [
+            (
+                self.emails,
+                "EMAIL",
+                r"(?i)\b[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b",
+                |_| true,
+            ),
+            (self.cards, "CARD", r"\b(?:\d[ -]?){12,18}\d\b", is_card),
+            (
+                self.ip_addresses,
+                "IP",
+                r"\b(?:\d{1,3}\.){3}\d{1,3}\b",
+                |m| m.parse::<Ipv4Addr>().is_ok(),
+            ),
+            (
+                self.ip_addresses,
+                "IP",
+                r"(?i)[0-9a-f]{0,4}(?::[0-9a-f]{0,4}){2,7}",
+                |m| m.contains(':') && m.len() > 2 && m.parse::<Ipv6Addr>().is_ok(),
+            ),
+            (
+                self.phones,
+                "PHONE",
+                r"(?:\+\d{1,3}[ .-]?|\b)(?:\(\d{1,4}\)[ .-]?)?\d{2,4}(?:[ .-]?\d{2,4}){2,4}\b",
+                is_phone,
+            ),
+        ];
+        for (enabled, label, pattern, accept) in builtins {
+            if enabled {
+                detectors.push(Detector {
+                    label: label.to_string(),
+                    regex: Regex::new(pattern).expect("built-in patterns are valid"),
+                    accept,
+                });
+            }
+        }
+        Ok(detectors)
+    }
+}
+
+/// Second look at a match, e.g. the Luhn check for cards
+type Accept = fn(&str) -> bool;
+
+struct Detector {
+    /// Placeholder prefix, e.g. `EMAIL`
+    label: String,
+    regex: Regex,
+    accept: Accept,
+}
+
+/// Placeholders handed out for one call
+#[derive(Debug, Default)]
+struct Redactions {
+    /// Placeholder and original value
+    values: Vec<(String, String)>,
+    /// Masked occurrences by kind
+    counts: BTreeMap<String, usize>,
+}
+
+impl Redactions {
+    fn redact(&mut self, detectors: &[Detector], text: &str) -> Option<String> {
+        let mut current = text.to_string();
+        for detector in detectors {
+            let replaced = detector
+                .regex
+                .replace_all(&current, |caps: &regex::Captures| {
+                    let found = &caps[0];
+                    if !(detector.accept)(found) {
+                        return found.to_string();
+                    }
+                    *self
+                        .counts
+                        .entry(detector.label.to_lowercase())
+                        .or_default() += 1;
+                    self.placeholder(&detector.label, found)
+                });
+            if let std::borrow::Cow::Owned(replaced) = replaced {
+                current = replaced;
+            }
+        }
+        (current != text).then_some(current)
+    }
+
+    fn placeholder(&mut self, label: &str, value: &str) -> String {
+        if let Some((placeholder, _)) = self.values.iter().find(|(_, v)| v == value) {
+            return placeholder.clone();
+        }
+        let prefix = format!("[{}_", label);
+        let n = self
+            .values
+            .iter()
+            .filter(|(p, _)| p.starts_with(&prefix))
+            .count();
+        let placeholder = format!("{}{}]", prefix, n + 1);
+        self.values.push((placeholder.clone(), value.to_string()));
+        placeholder
+    }
+}
+
+/// Placeholders of a call whose answer is pending
+#[derive(Default)]
+struct Restore {
+    /// Placeholder and original value
+    values: Vec<(String, String)>,
+    /// Streamed text held back by choice or content block index
+    carry: BTreeMap<u64, String>,
+}
+
+#[derive(Default)]
+struct Pending {
+    order: VecDeque<String>,
+    calls: HashMap<String, Restore>,
+}
+
+/// Whether redaction was skipped because the request looked local
+pub fn exempted(ctx: &PluginContext) -> bool {
+    ctx.metadata
+        .get(METADATA_KEY)
+        .and_then(|redaction| redaction.get("exempt"))
+        .and_then(Value::as_str)
+        == Some("local")
+}
+
+/// Built-in plugin masking personal data in outbound prompts
+pub struct RedactPlugin {
+    settings: RwLock<(RedactionSettings, Vec<Detector>)>,
+    pending: Mutex<Pending>,
+}
+
+impl Default for RedactPlugin {
+    fn default() -> Self {
+        let settings = RedactionSettings::default();
+        let detectors = settings
+            .detectors()
+            .expect("default settings have no custom patterns");
+        Self {
+            settings: RwLock::new((settings, detectors)),
+            pending: Mutex::new(Pending::default()),
+        }
+    }
+}
+
+impl RedactPlugin {
+    /// Returns what is being redacted
+    pub fn settings(&self) -> RedactionSettings {
+        read(&self.settings).0.clone()
+    }
+
+    /// Replaces what is redacted, failing on invalid custom patterns
+    pub fn set_settings(&self, settings: RedactionSettings) -> Result<(), String> {
+        let detectors = settings.detectors()?;
+        *write(&self.settings) = (settings, detectors);
+        Ok(())
+    }
+
+    fn remember(&self, call_id: &str, values: Vec<(String, String)>) {
+        let mut pending = lock(&self.pending);
+        // Streams never say when they end, so the oldest calls make room
+        while pending.order.len() >= MAX_PENDING {
+            if let Some(oldest) = pending.order.pop_front() {
+                pending.calls.remove(&oldest);
+            }
+        }
+        pending.order.push_back(call_id.to_string());
+        let restore = Restore {
+            values,
+            ..Restore::default()
+        };
+        pending.calls.insert(call_id.to_string(), restore);
+    }
+
+    fn forget(&self, call_id: &str) -> Option<Vec<(String, String)>> {
+        let mut pending = lock(&self.pending);
+        pending.order.retain(|id| id != call_id);
+        pending.calls.remove(call_id).map(|restore| restore.values)
+    }
+
+    fn recall(&self, call_id: &str) -> Option<Vec<(String, String)>> {
+        let pending = lock(&self.pending);
+        pending
+            .calls
+            .get(call_id)
+            .map(|restore| restore.values.clone())
+    }
+}
+
+#[async_trait]
+impl Plugin for RedactPlugin {
+    fn id(&self) -> &str {
+        ID
+    }
+
+    fn name(&self) -> &str {
+        "PII redaction"
+    }
+
+    fn description(&self) -> &str {
+        "Masks emails, phone numbers, card numbers and IP addresses before prompts leave the machine"
+    }
+
+    async fn on_request(
+        &self,
+        ctx: &mut PluginContext,
+        request: &mut PluginRequest,
+    ) -> HookOutcome {
+        let mut redactions = Redactions::default();
+        let restore = {
+            let settings = read(&self.settings);
+            let (settings, detectors) = &*settings;
+            if settings.exempt_local && ctx.local {
+                ctx.metadata
+                    .insert(METADATA_KEY.to_string(), json!({"exempt": "local"}));
+                return HookOutcome::Continue;
+            }
+            for text in prompt_texts(ctx.provider, &mut request.body) {
+                if let Some(redacted) = redactions.redact(detectors, text) {
+                    *text = redacted;
+                }
+            }
+            settings.restore
+        };
+        if redactions.values.is_empty() {
+            return HookOutcome::Continue;
+        }
+        ctx.metadata.insert(
+            METADATA_KEY.to_string(),
+            json!({
+                "counts": redactions.counts,
+                "placeholders": redactions.values.len(),
+            }),
+        );
+        if restore {
+            self.remember(&ctx.call_id, redactions.values);
+        }
+        HookOutcome::Continue
+    }
+
+    async fn on_response(
+        &self,
+        ctx: &mut PluginContext,
+        response: &mut PluginResponse,
+    ) -> HookOutcome {
+        let Some(values) = self.forget(&ctx.call_id) else {
+            return HookOutcome::Continue;
+        };
+        let restored = restore_value(&mut response.body, &values);
+        if let Some(Value::Object(entry)) = ctx.metadata.get_mut(METADATA_KEY) {
+            entry.insert("restored".to_string(), json!(restored));
+        }
+        HookOutcome::Continue
+    }
+
+    async fn on_stream_chunk(&self, ctx: &mut PluginContext, event: &mut SseEvent) -> ChunkOutcome {
+        let mut pending = lock(&self.pending);
+        let Some(restore) = pending.calls.get_mut(&ctx.call_id) else {
+            return ChunkOutcome::Continue;
+        };
+        if restore.carry.is_empty() && !event.data.contains('[') {
+            return ChunkOutcome::Continue;
+        }
+        let flushed = restore.restore_event(ctx, event);
+        if flushed.is_empty() {
+            ChunkOutcome::Continue
+        } else {
+            ChunkOutcome::Insert(flushed)
+        }
+    }
+
+    async fn on_error(&self, ctx: &mut PluginContext, _error: &UpstreamFailure) -> HookOutcome {
+        self.forget(&ctx.call_id);
+        HookOutcome::Continue
+    }
+}
+
+impl Restore {
+    /// Restores the text deltas of a streamed event, holding back partial placeholders.
+    /// Returns events carrying text released by the end of a choice or content block.
+    fn restore_event(&mut self, ctx: &PluginContext, event: &mut SseEvent) -> Vec<SseEvent> {
+        let shape = StreamShape::of(ctx);
+        let Some(mut data) = event.json() else {
+            // `[DONE]` ends every choice at once
+            let carry = std::mem::take(&mut self.carry);
+            return carry
+                .into_iter()
+                .map(|(index, text)| text_event(shape, None, index, text))
+                .collect();
+        };
+        let mut changed = false;
+        let mut released = Vec::new();
+        for delta in stream_deltas(shape, &mut data) {
+            let carried = self.carry.remove(&delta.index).unwrap_or_default();
+            let Some(text) = delta.text else {
+                if !carried.is_empty() {
+                    released.push((delta.index, carried));
+                }
+                continue;
+            };
+            let mut full = carried + text.as_str();
+            restore_text(&mut full, &self.values);
+            if !delta.done {
+                if let Some(at) = partial_placeholder(&full, &self.values) {
+                    self.carry.insert(delta.index, full.split_off(at));
+                }
+            }
+            if *text != full {
+                *text = full;
+                changed = true;
+            }
+        }
+        if changed {
+            event.data = data.to_string();
+        }
+        released
+            .into_iter()
+            .map(|(index, text)| text_event(shape, Some(&data), index, text))
+            .collect()
+    }
+}
+
+/// Where a stream carries its text
+#[derive(Debug, Clone, Copy)]
+enum StreamShape {
+    /// `choices[].delta.content`, or `choices[].text` for legacy completions
+    OpenAi {
+        legacy: bool,
+    },
+    Anthropic,
+    Gemini,
+}
+
+impl StreamShape {
+    fn of(ctx: &PluginContext) -> Self {
+        match ctx.provider {
+            Provider::OpenAi => StreamShape::OpenAi {
+                legacy: ctx.endpoint == "/v1/completions",
+            },
+            Provider::Anthropic => StreamShape::Anthropic,
+            Provider::Gemini => StreamShape::Gemini,
+        }
+    }
+}
+
+/// Streamed text of one choice or content block
+struct Delta<'a> {
+    index: u64,
+    text: Option<&'a mut String>,
+    /// Whether no more text follows for this index
+    done: bool,
+}
+
+/// Text deltas of a streamed event
+fn stream_deltas(shape: StreamShape, event: &mut Value) -> Vec<Delta<'_>> {
+    match shape {
+        StreamShape::OpenAi { legacy } => event
+            .get_mut("choices")
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(position, choice)| Delta {
+                index: choice
+                    .get("index")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(position as u64),
+                done: choice.get("finish_reason").is_some_and(|r| !r.is_null()),
+                text: match choice.pointer_mut(if legacy { "/text" } else { "/delta/content" }) {
+                    Some(Value::String(text)) => Some(text),
+                    _ => None,
+                },
+            })
+            .collect(),
+        StreamShape::Anthropic => {
+            let index = event.get("index").and_then(Value::as_u64).unwrap_or(0);
+            match event.get("type").and_then(Value::as_str) {
+                Some("content_block_delta") => match event.pointer_mut("/delta/text") {
+                    Some(Value::String(text)) => vec![Delta {
+                        index,
+                        text: Some(text),
+                        done: false,
+                    }],
+                    _ => Vec::new(),
+                },
+                Some("content_block_stop") => vec![Delta {
+                    index,
+                    text: None,
+                    done: true,
+                }],
+                _ => Vec::new(),
+            }
+        }
+        StreamShape::Gemini => event
+            .get_mut("candidates")
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(position, candidate)| Delta {
+                index: candidate
+                    .get("index")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(position as u64),
+                done: candidate.get("finishReason").is_some(),
+                text: candidate
+                    .get_mut("content")
+                    .and_then(|content| content.get_mut("parts"))
+                    .and_then(Value::as_array_mut)
+                    .into_iter()
+                    .flatten()
+                    .find_map(|part| match part.get_mut("text") {
+                        Some(Value::String(text)) => Some(text),
+                        _ => None,
+                    }),
+            })
+            .collect(),
+    }
+}
+
+/// Event streaming `text` for the choice or content block `index`, shaped like `template`
+fn text_event(shape: StreamShape, template: Option<&Value>, index: u64, text: String) -> SseEvent {
+    let (event, data) = match shape {
+        StreamShape::OpenAi { legacy } => {
+            let mut data = template.cloned().unwrap_or_else(|| json!({}));
+            if let Some(fields) = data.as_object_mut() {
+                fields.remove("usage");
+            }
+            let mut choice = json!({"index": index, "finish_reason": null});
+            if legacy {
+                choice["text"] = json!(text);
+            } else {
+                choice["delta"] = json!({"content": text});
+            }
+            data["choices"] = json!([choice]);
+            (None, data)
+        }
+        StreamShape::Anthropic => (
+            Some("content_block_delta".to_string()),
+            json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {"type": "text_delta", "text": text},
+            }),
+        ),
+        StreamShape::Gemini => (
+            None,
+            json!({"candidates": [{
+                "index": index,
+                "content": {"role": "model", "parts": [{"text": text}]},
+            }]}),
+        ),
+    };
+    SseEvent {
+        event,
+        data: data.to_string(),
+    }
+}
+
+/// Start of a trailing fragment of `text` that may still grow into a placeholder
+fn partial_placeholder(text: &str, values: &[(String, String)]) -> Option<usize> {
+    let start = text.rfind('[')?;
+    let tail = &text[start..];
+    values
+        .iter()
+        .any(|(placeholder, _)| placeholder.len() > tail.len() && placeholder.starts_with(tail))
+        .then_some(start)
+}
+
+/// Puts original values back into `text`, returning how many were restored
+fn restore_text(text: &mut String, values: &[(String, String)]) -> usize {
+    let mut restored = 0;
+    for (placeholder, original) in values {
+        let found = text.matches(placeholder.as_str()).count();
+        if found > 0 {
+            *text = text.replace(placeholder.as_str(), original);
+            restored += found;
+        }
+    }
+    restored
+}
+
+/// Puts original values back into every string of a response, returning how many were restored
+fn restore_value(value: &mut Value, values: &[(String, String)]) -> usize {
+    match value {
+        Value::String(text) => restore_text(text, values),
+        Value::Array(items) => items.iter_mut().map(|v| restore_value(v, values)).sum(),
+        Value::Object(fields) => fields.values_mut().map(|v| restore_value(v, values)).sum(),
+        _ => 0,
+    }
+}
+
+/// Luhn check over 13 to 19 digits
+fn is_card(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2 == 1, d * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// 9 to 15 digits written like a phone number rather than a bare id
+fn is_phone(candidate: &str) -> bool {
+    let digits = candidate.chars().filter(char::is_ascii_digit).count();
+    let formatted = candidate.starts_with('+') || candidate.contains([' ', '-', '.', '(']);
+    (9..=15).contains(&digits) && formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::testing::context;
+    use crate::proxy::Provider;
+    use axum::http::HeaderMap;
+
+    fn redact(settings: RedactionSettings, text: &str) -> (String, Redactions) {
+        let mut redactions = Redactions::default();
+        let detectors = settings.detectors().unwrap();
+        let redacted = redactions
+            .redact(&detectors, text)
+            .unwrap_or_else(|| text.to_string());
+        (redacted, redactions)
+    }
+
+    mod detector_tests {
+        use super::*;
+
+        #[test]
+        fn test_builtin_kinds() {
+            let text = "Mail jane.doe@example.com or call +1 415-555-0132, \
+                        card 4111 1111 1111 1111 from 192.168.1.20 and fe80::1ff:fe23:4567:890a";
+            let (redacted, redactions) = redact(RedactionSettings::default(), text);
+            assert_eq!(
+                redacted,
+                "Mail [EMAIL_1] or call [PHONE_1], card [CARD_1] from [IP_1] and [IP_2]"
+            );
+            assert_eq!(redactions.counts["ip"], 2);
+        }
+
+        #[test]
+        fn test_lookalikes_are_kept() {
+            let text = "Order 4111 1111 1111 1112, build 1234567890, version 300.1.2.4, at 12:30";
+            let (redacted, _) = redact(RedactionSettings::default(), text);
+            assert_eq!(redacted, text);
+        }
+
+        #[test]
+        fn test_repeated_values_share_a_placeholder() {
+            let text = "a@b.io wrote to c@d.io, then a@b.io again";
+            let (redacted, redactions) = redact(RedactionSettings::default(), text);
+            assert_eq!(
+                redacted,
+                "[EMAIL_1] wrote to [EMAIL_2], then [EMAIL_1] again"
+            );
+            assert_eq!(redactions.counts["email"], 3);
+            assert_eq!(redactions.values.len(), 2);
+        }
+
+        #[test]
+        fn test_custom_patterns_and_validation() {
+            let settings = RedactionSettings {
+                patterns: vec![CustomPattern {
+                    name: "employee_id".to_string(),
+                    regex: r"\bEMP-\d{5}\b".to_string(),
+                }],
+                emails: false,
+                ..RedactionSettings::default()
+            };
+            let (redacted, _) = redact(settings, "EMP-12345 is a@b.io");
+            assert_eq!(redacted, "[EMPLOYEE_ID_1] is a@b.io");
+
+            let broken = RedactionSettings {
+                patterns: vec![CustomPattern {
+                    name: "x".to_string(),
+                    regex: "(".to_string(),
+                }],
+                ..RedactionSettings::default()
+            };
+            assert!(broken.validate().unwrap_err().starts_with("pattern x"));
+        }
+    }
+
+    mod plugin_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_round_trip_restores_values() {
+            let plugin = RedactPlugin::default();
+            let mut ctx = context();
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({"messages": [
+                    {"role": "user", "content": "Email jane@example.com"},
+                    {"role": "tool", "content": "owner: jane@example.com, ip 10.0.0.7"},
+                ]}),
+            };
+            plugin.on_request(&mut ctx, &mut request).await;
+            assert_eq!(request.body["messages"][0]["content"], "Email [EMAIL_1]");
+            assert_eq!(
+                request.body["messages"][1]["content"],
+                "owner: [EMAIL_1], ip [IP_1]"
+            );
+            assert_eq!(ctx.metadata[METADATA_KEY]["counts"]["email"], 2);
+            assert!(!Value::Object(ctx.metadata.clone())
+                .to_string()
+                .contains("jane@"));
+
+            let mut response = PluginResponse {
+                status: 200,
+                body: json!({"choices": [{"message": {"content": "Sent to [EMAIL_1]"}}]}),
+            };
+            plugin.on_response(&mut ctx, &mut response).await;
+            assert_eq!(
+                response.body["choices"][0]["message"]["content"],
+                "Sent to jane@example.com"
+            );
+            assert_eq!(ctx.metadata[METADATA_KEY]["restored"], 1);
+            assert!(plugin.recall("call").is_none());
+        }
+
+        #[tokio::test]
+        async fn test_stream_chunks_are_restored() {
+            let plugin = RedactPlugin::default();
+            let mut ctx = context();
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({"messages": [{"role": "user", "content": "ping 10.1.2.3"}]}),
+            };
+            plugin.on_request(&mut ctx, &mut request).await;
+            let mut event = SseEvent {
+                event: None,
+                data: json!({"choices": [{"delta": {"content": "[IP_1] is up"}}]}).to_string(),
+            };
+            plugin.on_stream_chunk(&mut ctx, &mut event).await;
+            assert!(event.data.contains("10.1.2.3 is up"));
+        }
+
+        #[tokio::test]
+        async fn test_placeholder_split_across_events() {
+            let plugin = RedactPlugin::default();
+            let mut ctx = context();
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({"messages": [{"role": "user", "content": "mail jane@example.com"}]}),
+            };
+            plugin.on_request(&mut ctx, &mut request).await;
+            let delta = |content: &str| SseEvent {
+                event: None,
+                data: json!({"choices": [{"index": 0, "delta": {"content": content}}]}).to_string(),
+            };
+            let content =
+                |event: &SseEvent| event.json().unwrap()["choices"][0]["delta"]["content"].clone();
+
+            let mut first = delta("Sent to [EMAIL_");
+            assert_eq!(
+                plugin.on_stream_chunk(&mut ctx, &mut first).await,
+                ChunkOutcome::Continue
+            );
+            assert_eq!(content(&first), "Sent to ");
+            let mut second = delta("1] and [x");
+            plugin.on_stream_chunk(&mut ctx, &mut second).await;
+            assert_eq!(content(&second), "jane@example.com and [x");
+
+            // A fragment still held back when the stream ends is sent before `[DONE]`
+            let mut third = delta("bye [EMA");
+            plugin.on_stream_chunk(&mut ctx, &mut third).await;
+            assert_eq!(content(&third), "bye ");
+            let mut done = SseEvent {
+                event: None,
+                data: "[DONE]".to_string(),
+            };
+            let ChunkOutcome::Insert(flushed) = plugin.on_stream_chunk(&mut ctx, &mut done).await
+            else {
+                panic!("expected the held back text");
+            };
+            assert_eq!(content(&flushed[0]), "[EMA");
+            assert_eq!(done.data, "[DONE]");
+        }
+
+        #[tokio::test]
+        async fn test_local_routes_are_exempt() {
+            let plugin = RedactPlugin::default();
+            let mut ctx = context();
+            ctx.local = true;
+            let body = json!({"messages": [{"role": "user", "content": "jane@example.com"}]});
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: body.clone(),
+            };
+            plugin.on_request(&mut ctx, &mut request).await;
+            assert_eq!(request.body, body);
+            assert_eq!(ctx.metadata[METADATA_KEY]["exempt"], "local");
+
+            plugin
+                .set_settings(RedactionSettings {
+                    exempt_local: false,
+                    ..RedactionSettings::default()
+                })
+                .unwrap();
+            plugin.on_request(&mut ctx, &mut request).await;
+            assert_eq!(request.body["messages"][0]["content"], "[EMAIL_1]");
+        }
+
+        #[tokio::test]
+        async fn test_anthropic_system_prompt_is_redacted() {
+            let plugin = RedactPlugin::default();
+            let mut ctx = PluginContext::new("call", Provider::Anthropic, "/v1/messages", "claude");
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({
+                    "system": "The user is jane@example.com",
+                    "messages": [{"role": "user", "content": [
+                        {"type": "tool_result", "content": "call 020 7946 0958"},
+                    ]}],
+                }),
+            };
+            plugin.on_request(&mut ctx, &mut request).await;
+            assert_eq!(request.body["system"], "The user is [EMAIL_1]");
+            assert_eq!(
+                request.body["messages"][0]["content"][0]["content"],
+                "call [PHONE_1]"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_completion_prompt_is_redacted() {
+            let plugin = RedactPlugin::default();
+            let mut ctx = PluginContext::new("call", Provider::OpenAi, "/v1/completions", "gpt");
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({"prompt": ["Write to jane@example.com", "or 10.1.2.3"]}),
+            };
+            plugin.on_request(&mut ctx, &mut request).await;
+            assert_eq!(
+                request.body["prompt"],
+                json!(["Write to [EMAIL_1]", "or [IP_1]"])
+            );
+        }
+
+        #[tokio::test]
+        async fn test_tool_call_arguments_are_redacted() {
+            let plugin = RedactPlugin::default();
+            let mut ctx = context();
+            let arguments = json!({"to": "jane@example.com"}).to_string();
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({"messages": [{
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "send_mail", "arguments": arguments},
+                    }],
+                }]}),
+            };
+            plugin.on_request(&mut ctx, &mut request).await;
+            let arguments = request.body["messages"][0]["tool_calls"][0]["function"]["arguments"]
+                .as_str()
+                .unwrap();
+            assert_eq!(
+                serde_json::from_str::<Value>(arguments).unwrap(),
+                json!({"to": "[EMAIL_1]"})
+            );
+        }
+
+        #[tokio::test]
+        async fn test_anthropic_tool_use_input_is_redacted() {
+            let plugin = RedactPlugin::default();
+            let mut ctx = PluginContext::new("call", Provider::Anthropic, "/v1/messages", "claude");
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({"messages": [{"role": "assistant", "content": [{
+                    "type": "tool_use",
+                    "id": "toolu_1",
+                    "name": "send_mail",
+                    "input": {"to": ["jane@example.com"], "retries": 2},
+                }]}]}),
+            };
+            plugin.on_request(&mut ctx, &mut request).await;
+            assert_eq!(
+                request.body["messages"][0]["content"][0]["input"],
+                json!({"to": ["[EMAIL_1]"], "retries": 2})
+            );
+        }
+
+        #[tokio::test]
+        async fn test_gemini_function_payloads_are_redacted() {
+            let plugin = RedactPlugin::default();
+            let mut ctx = PluginContext::new(
+                "call",
+                Provider::Gemini,
+                "/v1beta/models/gemini-2.0-flash:generateContent",
+                "gemini-2.0-flash",
+            );
+            let mut request = PluginRequest {
+                headers: HeaderMap::new(),
+                body: json!({"contents": [
+                    {"role": "model", "parts": [{"functionCall": {
+                        "name": "lookup",
+                        "args": {"email": "jane@example.com"},
+                    }}]},
+                    {"role": "user", "parts": [{"functionResponse": {
+                        "name": "lookup",
+                        "response": {"phone": "020 7946 0958"},
+                    }}]},
+                ]}),
+            };
+            plugin.on_request(&mut ctx, &mut request).await;
+            let contents = &request.body["contents"];
+            assert_eq!(
+                contents[0]["parts"][0]["functionCall"]["args"]["email"],
+                "[EMAIL_1]"
+            );
+            assert_eq!(
+                contents[1]["parts"][0]["functionResponse"]["response"]["phone"],
+                "[PHONE_1]"
+            );
+            assert_eq!(
+                contents[1]["parts"][0]["functionResponse"]["name"],
+                "lookup"
+            );
+        }
+    }
+}
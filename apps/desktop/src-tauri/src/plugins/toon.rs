@@ -0,0 +1,361 @@
+//! JSON → TOON format conversion
+//!
+//! Rewrites JSON found in message content and tool results as fenced
+//! ` ```toon ` blocks before the request goes upstream, and turns ` ```toon `
+//! blocks in complete responses back into ` ```json `. Streamed responses
+//! are passed through, so their TOON blocks reach the client undecoded.
+//!
+//! A block is only rewritten when it gets cheaper, counted with the model's
+//! [`Tokenizer`], and the savings are stored on the captured call under `toon`.
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::{request_texts, response_texts};
+use crate::pipeline::{HookOutcome, Plugin, PluginContext, PluginRequest, PluginResponse};
+use crate::toon;
+use crate::usage::tokens::Tokenizer;
+
+/// Id used in the Extensions tab and pipeline settings
+pub const ID: &str = "builtin.toon";
+/// Metadata key the savings are recorded under
+const METADATA_KEY: &str = "toon";
+
+/// Built-in plugin converting JSON payloads to TOON and back
+pub struct ToonPlugin;
+
+#[async_trait]
+impl Plugin for ToonPlugin {
+    fn id(&self) -> &str {
+        ID
+    }
+
+    fn name(&self) -> &str {
+        "JSON → TOON"
+    }
+
+    fn description(&self) -> &str {
+        "Sends JSON in messages and tool results as TOON to save tokens"
+    }
+
+    async fn on_request(
+        &self,
+        ctx: &mut PluginContext,
+        request: &mut PluginRequest,
+    ) -> HookOutcome {
+        let mut savings = Savings::new(Tokenizer::for_model(&ctx.model));
+        for text in request_texts(ctx.provider, &mut request.body) {
+            if let Some(rewritten) = encode_text(text, &mut savings) {
+                *text = rewritten;
+            }
+        }
+        if savings.blocks > 0 {
+            ctx.metadata.insert(
+                METADATA_KEY.to_string(),
+                json!({
+                    "blocks": savings.blocks,
+                    "tokens_before": savings.before,
+                    "tokens_after": savings.after,
+                    "tokens_saved": savings.before - savings.after,
+                }),
+            );
+        }
+        HookOutcome::Continue
+    }
+
+    async fn on_response(
+        &self,
+        ctx: &mut PluginContext,
+        response: &mut PluginResponse,
+    ) -> HookOutcome {
+        let mut decoded = 0;
+        for text in response_texts(ctx.provider, &mut response.body) {
+            if let Some(rewritten) = decode_text(text, &mut decoded) {
+                *text = rewritten;
+            }
+        }
+        if decoded > 0 {
+            let entry = ctx
+                .metadata
+                .entry(METADATA_KEY)
+                .or_insert_with(|| json!({}));
+            if let Some(entry) = entry.as_object_mut() {
+                entry.insert("decoded_blocks".to_string(), json!(decoded));
+            }
+        }
+        HookOutcome::Continue
+    }
+}
+
+/// Token totals of the blocks that were rewritten
+#[derive(Debug, PartialEq, Eq)]
+struct Savings {
+    tokenizer: Tokenizer,
+    blocks: usize,
+    before: u64,
+    after: u64,
+}
+
+impl Savings {
+    fn new(tokenizer: Tokenizer) -> Self {
+        Self {
+            tokenizer,
+            blocks: 0,
+            before: 0,
+            after: 0,
+        }
+    }
+
+    /// Counts a replacement if it is cheaper, returning whether to apply it
+    fn record(&mut self, original: &str, replacement: &str) -> bool {
+        let before = self.tokenizer.count(original);
+        let after = self.tokenizer.count(replacement);
+        if after >= before {
+            return false;
+        }
+        self.blocks += 1;
+        self.before += before;
+        self.after += after;
+        true
+    }
+}
+
+/// Rewrites a text that is JSON, or its ` ```json ` blocks, as TOON
+fn encode_text(text: &str, savings: &mut Savings) -> Option<String> {
+    let trimmed = text.trim();
+    if trimmed.starts_with(['{', '[']) {
+        if let Ok(value) = serde_json::from_str::<Value>(trimmed) {
+            let replacement = fence("toon", &toon::encode(&value));
+            return savings.record(trimmed, &replacement).then_some(replacement);
+        }
+    }
+    replace_fences(text, "json", |original, body| {
+        let value = serde_json::from_str::<Value>(body).ok()?;
+        if !(value.is_object() || value.is_array()) {
+            return None;
+        }
+        let replacement = fence("toon", &toon::encode(&value));
+        savings
+            .record(original, &replacement)
+            .then_some(replacement)
+    })
+}
+
+/// Rewrites ` ```toon ` blocks as pretty-printed JSON
+fn decode_text(text: &str, decoded: &mut usize) -> Option<String> {
+    replace_fences(text, "toon", |_, body| {
+        let value = toon::decode(body).ok()?;
+        *decoded += 1;
+        Some(fence("json", &serde_json::to_string_pretty(&value).ok()?))
+    })
+}
+
+fn fence(lang: &str, body: &str) -> String {
+    format!("```{}\n{}\n```", lang, body)
+}
+
+/// Replaces fenced code blocks tagged `lang`; `replace` gets the whole block
+/// and its body and returns `None` to keep the block as is
+fn replace_fences(
+    text: &str,
+    lang: &str,
+    mut replace: impl FnMut(&str, &str) -> Option<String>,
+) -> Option<String> {
+    let open = format!("```{}", lang);
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut from = 0;
+    while let Some(offset) = text[from..].find(&open) {
+        let start = from + offset;
+        let header_end = start + open.len();
+        let Some(newline) = text[header_end..].find('\n').map(|i| header_end + i) else {
+            break;
+        };
+        // Skip blocks tagged e.g. `jsonc`
+        if !text[header_end..newline].trim().is_empty() {
+            from = header_end;
+            continue;
+        }
+        let Some(close) = text[newline..].find("```").map(|i| newline + i) else {
+            break;
+        };
+        let end = close + 3;
+        if let Some(replacement) = replace(&text[start..end], text[newline + 1..close].trim()) {
+            out.push_str(&text[copied..start]);
+            out.push_str(&replacement);
+            copied = end;
+        }
+        from = end;
+    }
+    if copied == 0 {
+        return None;
+    }
+    out.push_str(&text[copied..]);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::Provider;
+    use axum::http::HeaderMap;
+
+    fn context(provider: Provider) -> PluginContext {
+        PluginContext::new("call", provider, "/v1/chat/completions", "gpt-4o")
+    }
+
+    fn table_json() -> String {
+        let rows: Vec<Value> = (0..10)
+            .map(|i| json!({"id": i, "status": "open", "title": format!("Issue {}", i)}))
+            .collect();
+        json!({"issues": rows}).to_string()
+    }
+
+    async fn rewrite(provider: Provider, body: Value) -> (PluginContext, Value) {
+        let mut ctx = context(provider);
+        let mut request = PluginRequest {
+            headers: HeaderMap::new(),
+            body,
+        };
+        assert_eq!(
+            ToonPlugin.on_request(&mut ctx, &mut request).await,
+            HookOutcome::Continue
+        );
+        (ctx, request.body)
+    }
+
+    mod request_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_rewrites_json_message_and_records_savings() {
+            let (ctx, body) = rewrite(
+                Provider::OpenAi,
+                json!({"messages": [
+                    {"role": "user", "content": "Summarize these issues"},
+                    {"role": "tool", "tool_call_id": "1", "content": table_json()}
+                ]}),
+            )
+            .await;
+
+            assert_eq!(body["messages"][0]["content"], "Summarize these issues");
+            let content = body["messages"][1]["content"].as_str().unwrap();
+            assert!(content.starts_with("```toon\nissues[10]{id,status,title}:"));
+
+            let stats = &ctx.metadata["toon"];
+            assert_eq!(stats["blocks"], 1);
+            let saved = stats["tokens_saved"].as_u64().unwrap();
+            assert!(saved > 0);
+            assert_eq!(
+                stats["tokens_before"].as_u64().unwrap() - stats["tokens_after"].as_u64().unwrap(),
+                saved
+            );
+        }
+
+        #[tokio::test]
+        async fn test_rewrites_fenced_blocks_in_tool_results() {
+            let text = format!("Results:\n```json\n{}\n```\nDone.", table_json());
+            let (ctx, body) = rewrite(
+                Provider::Anthropic,
+                json!({"messages": [{"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "t1", "content": [{"type": "text", "text": text}]}
+                ]}]}),
+            )
+            .await;
+
+            let text = body["messages"][0]["content"][0]["content"][0]["text"]
+                .as_str()
+                .unwrap();
+            assert!(text.starts_with("Results:\n```toon\nissues[10]"));
+            assert!(text.ends_with("\n```\nDone."));
+            assert_eq!(ctx.metadata["toon"]["blocks"], 1);
+        }
+
+        #[tokio::test]
+        async fn test_rewrites_gemini_parts() {
+            let (_, body) = rewrite(
+                Provider::Gemini,
+                json!({"contents": [{"role": "user", "parts": [{"text": table_json()}]}]}),
+            )
+            .await;
+            assert!(body["contents"][0]["parts"][0]["text"]
+                .as_str()
+                .unwrap()
+                .starts_with("```toon"));
+        }
+
+        #[tokio::test]
+        async fn test_leaves_text_alone_when_nothing_is_saved() {
+            let original = json!({"messages": [
+                {"role": "user", "content": "{\"a\":1}"},
+                {"role": "user", "content": "not json {"},
+                {"role": "user", "content": "```json\n42\n```"}
+            ]});
+            let (ctx, body) = rewrite(Provider::OpenAi, original.clone()).await;
+            assert_eq!(body, original);
+            assert!(ctx.metadata.is_empty());
+        }
+    }
+
+    mod response_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_decodes_toon_blocks_back_to_json() {
+            let mut ctx = context(Provider::OpenAi);
+            let mut response = PluginResponse {
+                status: 200,
+                body: json!({"choices": [{"message": {
+                    "role": "assistant",
+                    "content": "Here:\n```toon\nusers[2]{id,name}:\n  1,Ada\n  2,Grace\n```"
+                }}]}),
+            };
+            ToonPlugin.on_response(&mut ctx, &mut response).await;
+
+            let content = response.body["choices"][0]["message"]["content"]
+                .as_str()
+                .unwrap();
+            let json_block = content
+                .strip_prefix("Here:\n```json\n")
+                .and_then(|rest| rest.strip_suffix("\n```"))
+                .unwrap();
+            assert_eq!(
+                serde_json::from_str::<Value>(json_block).unwrap(),
+                json!({"users": [{"id": 1, "name": "Ada"}, {"id": 2, "name": "Grace"}]})
+            );
+            assert_eq!(ctx.metadata["toon"]["decoded_blocks"], 1);
+        }
+
+        #[tokio::test]
+        async fn test_keeps_invalid_toon_blocks() {
+            let mut ctx = context(Provider::Anthropic);
+            let body = json!({"content": [{"type": "text", "text": "```toon\nitems[3]: a\n```"}]});
+            let mut response = PluginResponse {
+                status: 200,
+                body: body.clone(),
+            };
+            ToonPlugin.on_response(&mut ctx, &mut response).await;
+            assert_eq!(response.body, body);
+            assert!(ctx.metadata.is_empty());
+        }
+    }
+
+    mod savings_tests {
+        use super::*;
+
+        #[test]
+        fn test_counts_with_the_model_tokenizer() {
+            let json = table_json();
+            let encoded = fence("toon", &toon::encode(&serde_json::from_str(&json).unwrap()));
+            let tokenizer = Tokenizer::for_model("gpt-4o");
+            let mut savings = Savings::new(tokenizer);
+            assert!(savings.record(&json, &encoded));
+            assert!(!savings.record("{\"a\":1}", "```toon\na: 1\n```"));
+            assert_eq!(savings.blocks, 1);
+            assert_eq!(
+                (savings.before, savings.after),
+                (tokenizer.count(&json), tokenizer.count(&encoded))
+            );
+        }
+    }
+}
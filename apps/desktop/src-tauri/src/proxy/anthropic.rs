@@ -0,0 +1,632 @@
+//! Anthropic Messages API route (`/v1/messages`)
+
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, HeaderName, HeaderValue, Uri},
+    response::Response,
+};
+use serde_json::{json, Value};
+
+use super::relay::{relay, Provider, ProviderApi};
+use super::stream::{SseEvent, StreamAccumulator};
+use super::ProxyContext;
+use crate::capture::{CapturedCall, Message, MessageRole, ModelParameters, ToolCall, Usage};
+
+/// API version sent upstream when the client does not specify one
+pub const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+const ANTHROPIC_VERSION_HEADER: &str = "anthropic-version";
+
+/// Handles `POST /v1/messages`
+pub(crate) async fn messages(
+    State(context): State<Arc<ProxyContext>>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    relay(context, &AnthropicApi, uri, headers, body).await
+}
+
+/// Hooks for the Messages API wire format
+pub(crate) struct AnthropicApi;
+
+impl ProviderApi for AnthropicApi {
+    fn provider(&self) -> Provider {
+        Provider::Anthropic
+    }
+
+    fn capture_request(&self, call: &mut CapturedCall, _uri: &Uri, request: &Value) {
+        capture_request(call, request);
+    }
+
+    fn capture_response(&self, call: &mut CapturedCall, response: &Value) {
+        capture_response(call, response);
+    }
+
+    fn accumulator(&self) -> Box<dyn StreamAccumulator> {
+        Box::new(AnthropicAccumulator::default())
+    }
+
+    fn prepare_headers(&self, headers: &mut HeaderMap) {
+        let name = HeaderName::from_static(ANTHROPIC_VERSION_HEADER);
+        if !headers.contains_key(&name) {
+            headers.insert(name, HeaderValue::from_static(DEFAULT_ANTHROPIC_VERSION));
+        }
+    }
+}
+
+/// Joins the text of a string or an array of content blocks
+fn blocks_text(content: &Value) -> Option<String> {
+    match content {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(blocks) => {
+            let texts: Vec<&str> = blocks
+                .iter()
+                .filter(|b| b.get("type").and_then(Value::as_str) == Some("text"))
+                .filter_map(|b| b.get("text").and_then(Value::as_str))
+                .collect();
+            (!texts.is_empty()).then(|| texts.join("\n"))
+        }
+        _ => None,
+    }
+}
+
+fn tool_use_call(block: &Value) -> ToolCall {
+    let input = block.get("input").cloned().unwrap_or_else(|| json!({}));
+    ToolCall::function(
+        block.get("id").and_then(Value::as_str).unwrap_or_default(),
+        block
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default(),
+        input.to_string(),
+    )
+}
+
+fn blocks_of_type<'a>(content: &'a Value, kind: &'a str) -> impl Iterator<Item = &'a Value> {
+    content
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(move |b| b.get("type").and_then(Value::as_str) == Some(kind))
+}
+
+/// Converts one Anthropic message into normalised messages.
+/// `tool_result` blocks become separate `tool` messages, matching the
+/// OpenAI shape the rest of Blackbox works with.
+fn parse_message(value: &Value) -> Vec<Message> {
+    let role = MessageRole::parse(value.get("role").and_then(Value::as_str).unwrap_or("user"));
+    let content = value.get("content").unwrap_or(&Value::Null);
+
+    let mut messages: Vec<Message> = blocks_of_type(content, "tool_result")
+        .map(|block| Message {
+            role: MessageRole::Tool,
+            content: block.get("content").and_then(blocks_text),
+            name: None,
+            tool_calls: Vec::new(),
+            tool_call_id: block
+                .get("tool_use_id")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        })
+        .collect();
+
+    let text = blocks_text(content);
+    let tool_calls: Vec<ToolCall> = blocks_of_type(content, "tool_use")
+        .map(tool_use_call)
+        .collect();
+    if text.is_some() || !tool_calls.is_empty() || messages.is_empty() {
+        messages.push(Message {
+            role,
+            content: text,
+            name: None,
+            tool_calls,
+            tool_call_id: None,
+        });
+    }
+    messages
+}
+
+/// Fills the request side of a call from a Messages API body
+pub(crate) fn capture_request(call: &mut CapturedCall, request: &Value) {
+    call.model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    call.stream = request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    let parameters = ModelParameters {
+        temperature: request.get("temperature").and_then(Value::as_f64),
+        top_p: request.get("top_p").and_then(Value::as_f64),
+        max_tokens: request.get("max_tokens").and_then(Value::as_u64),
+        stop: request
+            .get("stop_sequences")
+            .filter(|v| !v.is_null())
+            .cloned(),
+        seed: None,
+    };
+    call.parameters = (!parameters.is_empty()).then_some(parameters);
+
+    call.messages.clear();
+    if let Some(system) = request.get("system").and_then(blocks_text) {
+        call.messages
+            .push(Message::text(MessageRole::System, system));
+    }
+    if let Some(messages) = request.get("messages").and_then(Value::as_array) {
+        call.messages
+            .extend(messages.iter().flat_map(parse_message));
+    }
+    call.tools = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+}
+
+fn parse_usage(usage: &Value) -> Usage {
+    let count = |key: &str| usage.get(key).and_then(Value::as_u64).unwrap_or(0);
+    // Cached prompt tokens are billed as input too
+    Usage::new(
+        count("input_tokens")
+            + count("cache_creation_input_tokens")
+            + count("cache_read_input_tokens"),
+        count("output_tokens"),
+    )
+}
+
+/// Fills the response side of a call from a Messages API body
+pub(crate) fn capture_response(call: &mut CapturedCall, response: &Value) {
+    let content = response.get("content").unwrap_or(&Value::Null);
+    call.response.content = blocks_text(content);
+    call.response.tool_calls = blocks_of_type(content, "tool_use")
+        .map(tool_use_call)
+        .collect();
+    call.response.finish_reason = response
+        .get("stop_reason")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    call.usage = response.get("usage").map(parse_usage);
+}
+
+/// A content block being rebuilt from stream deltas
+#[derive(Debug)]
+enum PartialBlock {
+    Text(String),
+    ToolUse {
+        id: String,
+        name: String,
+        input: String,
+    },
+    Other,
+}
+
+/// Rebuilds a message from `message_start` / `content_block_*` / `message_delta` events
+#[derive(Debug, Default)]
+pub(crate) struct AnthropicAccumulator {
+    blocks: Vec<PartialBlock>,
+    stop_reason: Option<String>,
+    input_tokens: u64,
+    output_tokens: u64,
+    has_usage: bool,
+    error: Option<String>,
+}
+
+impl AnthropicAccumulator {
+    fn block_mut(&mut self, event: &Value) -> Option<&mut PartialBlock> {
+        let index = event.get("index").and_then(Value::as_u64)? as usize;
+        self.blocks.get_mut(index)
+    }
+}
+
+impl StreamAccumulator for AnthropicAccumulator {
+    fn on_event(&mut self, event: &SseEvent) {
+        let Some(data) = event.json() else {
+            return;
+        };
+        let kind = event
+            .event
+            .as_deref()
+            .or_else(|| data.get("type").and_then(Value::as_str))
+            .unwrap_or_default();
+
+        match kind {
+            "message_start" => {
+                if let Some(usage) = data.pointer("/message/usage") {
+                    let usage = parse_usage(usage);
+                    self.input_tokens = usage.prompt_tokens;
+                    self.output_tokens = usage.completion_tokens;
+                    self.has_usage = true;
+                }
+            }
+            "content_block_start" => {
+                let index = data.get("index").and_then(Value::as_u64).unwrap_or(0);
+                // Blocks are numbered in order, so a gap means a malformed upstream
+                let Some(index) = usize::try_from(index)
+                    .ok()
+                    .filter(|index| *index <= self.blocks.len())
+                else {
+                    log::warn!("Ignoring content block with out-of-order index {}", index);
+                    return;
+                };
+                let block = data.get("content_block").unwrap_or(&Value::Null);
+                let partial = match block.get("type").and_then(Value::as_str) {
+                    Some("text") => PartialBlock::Text(
+                        block
+                            .get("text")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default()
+                            .to_string(),
+                    ),
+                    Some("tool_use") => PartialBlock::ToolUse {
+                        id: block
+                            .get("id")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default()
+                            .to_string(),
+                        name: block
+                            .get("name")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default()
+                            .to_string(),
+                        input: String::new(),
+                    },
+                    _ => PartialBlock::Other,
+                };
+                if self.blocks.len() == index {
+                    self.blocks.push(partial);
+                } else {
+                    self.blocks[index] = partial;
+                }
+            }
+            "content_block_delta" => {
+                let delta = data.get("delta").cloned().unwrap_or(Value::Null);
+                match (
+                    self.block_mut(&data),
+                    delta.get("type").and_then(Value::as_str),
+                ) {
+                    (Some(PartialBlock::Text(text)), Some("text_delta")) => {
+                        text.push_str(
+                            delta
+                                .get("text")
+                                .and_then(Value::as_str)
+                                .unwrap_or_default(),
+                        );
+                    }
+                    (Some(PartialBlock::ToolUse { input, .. }), Some("input_json_delta")) => {
+                        input.push_str(
+                            delta
+                                .get("partial_json")
+                                .and_then(Value::as_str)
+                                .unwrap_or_default(),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            "message_delta" => {
+                if let Some(reason) = data.pointer("/delta/stop_reason").and_then(Value::as_str) {
+                    self.stop_reason = Some(reason.to_string());
+                }
+                if let Some(output) = data.pointer("/usage/output_tokens").and_then(Value::as_u64) {
+                    self.output_tokens = output;
+                    self.has_usage = true;
+                }
+            }
+            "error" => {
+                self.error = data
+                    .pointer("/error/message")
+                    .and_then(Value::as_str)
+                    .map(str::to_string);
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(self: Box<Self>, call: &mut CapturedCall) {
+        let mut texts = Vec::new();
+        for block in self.blocks {
+            match block {
+                PartialBlock::Text(text) => texts.push(text),
+                PartialBlock::ToolUse { id, name, input } => {
+                    let input = if input.is_empty() {
+                        "{}".to_string()
+                    } else {
+                        input
+                    };
+                    call.response
+                        .tool_calls
+                        .push(ToolCall::function(id, name, input));
+                }
+                PartialBlock::Other => {}
+            }
+        }
+        call.response.content = (!texts.is_empty()).then(|| texts.join("\n"));
+        call.response.finish_reason = self.stop_reason;
+        if self.has_usage {
+            call.usage = Some(Usage::new(self.input_tokens, self.output_tokens));
+        }
+        if self.error.is_some() {
+            call.error = self.error;
+        }
+    }
+
+    fn error_chunk(&self, message: &str) -> Bytes {
+        let payload = json!({
+            "type": "error",
+            "error": {"type": "api_error", "message": message},
+        });
+        Bytes::from(format!("event: error\ndata: {}\n\n", payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::testing::{spawn_upstream, start_proxy, wait_for_capture};
+    use axum::{http::StatusCode, routing::post, Json, Router};
+
+    fn messages_response() -> Value {
+        json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4-5",
+            "content": [
+                {"type": "text", "text": "Reading it"},
+                {"type": "tool_use", "id": "toolu_1", "name": "read_file", "input": {"path": "a.rs"}}
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 20, "output_tokens": 8}
+        })
+    }
+
+    mod capture_tests {
+        use super::*;
+
+        #[test]
+        fn test_capture_request_with_system_and_tool_blocks() {
+            let mut call = CapturedCall::new("anthropic", "/v1/messages");
+            capture_request(
+                &mut call,
+                &json!({
+                    "model": "claude-sonnet-4-5",
+                    "max_tokens": 1024,
+                    "stop_sequences": ["END"],
+                    "system": [{"type": "text", "text": "Be brief"}],
+                    "messages": [
+                        {"role": "user", "content": "Read a.rs"},
+                        {"role": "assistant", "content": [
+                            {"type": "tool_use", "id": "toolu_1", "name": "read_file", "input": {"path": "a.rs"}}
+                        ]},
+                        {"role": "user", "content": [
+                            {"type": "tool_result", "tool_use_id": "toolu_1", "content": [{"type": "text", "text": "fn main() {}"}]}
+                        ]}
+                    ]
+                }),
+            );
+
+            assert_eq!(call.model, "claude-sonnet-4-5");
+            let roles: Vec<_> = call.messages.iter().map(|m| m.role).collect();
+            assert_eq!(
+                roles,
+                vec![
+                    MessageRole::System,
+                    MessageRole::User,
+                    MessageRole::Assistant,
+                    MessageRole::Tool
+                ]
+            );
+            assert_eq!(
+                call.messages[2].tool_calls[0].function.arguments,
+                r#"{"path":"a.rs"}"#
+            );
+            assert_eq!(call.messages[3].tool_call_id.as_deref(), Some("toolu_1"));
+            assert_eq!(call.messages[3].content.as_deref(), Some("fn main() {}"));
+            let parameters = call.parameters.unwrap();
+            assert_eq!(parameters.max_tokens, Some(1024));
+            assert_eq!(parameters.stop, Some(json!(["END"])));
+        }
+
+        #[test]
+        fn test_capture_response() {
+            let mut call = CapturedCall::new("anthropic", "/v1/messages");
+            capture_response(&mut call, &messages_response());
+
+            assert_eq!(call.response.content.as_deref(), Some("Reading it"));
+            assert_eq!(call.response.tool_calls[0].id, "toolu_1");
+            assert_eq!(call.response.finish_reason.as_deref(), Some("tool_use"));
+            assert_eq!(call.usage, Some(Usage::new(20, 8)));
+        }
+
+        #[test]
+        fn test_usage_includes_cached_input() {
+            let usage = parse_usage(&json!({
+                "input_tokens": 5,
+                "cache_read_input_tokens": 100,
+                "output_tokens": 1
+            }));
+            assert_eq!(usage, Usage::new(105, 1));
+        }
+
+        #[test]
+        fn test_prepare_headers_adds_default_version() {
+            let mut headers = HeaderMap::new();
+            AnthropicApi.prepare_headers(&mut headers);
+            assert_eq!(headers[ANTHROPIC_VERSION_HEADER], DEFAULT_ANTHROPIC_VERSION);
+
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                ANTHROPIC_VERSION_HEADER,
+                HeaderValue::from_static("2024-01-01"),
+            );
+            AnthropicApi.prepare_headers(&mut headers);
+            assert_eq!(headers[ANTHROPIC_VERSION_HEADER], "2024-01-01");
+        }
+    }
+
+    mod accumulator_tests {
+        use super::*;
+
+        fn event(kind: &str, data: Value) -> SseEvent {
+            SseEvent {
+                event: Some(kind.to_string()),
+                data: data.to_string(),
+            }
+        }
+
+        #[test]
+        fn test_rebuilds_text_and_tool_use() {
+            let mut accumulator = Box::new(AnthropicAccumulator::default());
+            for e in [
+                event(
+                    "message_start",
+                    json!({"type": "message_start", "message": {"usage": {"input_tokens": 9, "output_tokens": 1}}}),
+                ),
+                event(
+                    "content_block_start",
+                    json!({"index": 0, "content_block": {"type": "text", "text": ""}}),
+                ),
+                event(
+                    "content_block_delta",
+                    json!({"index": 0, "delta": {"type": "text_delta", "text": "Hi "}}),
+                ),
+                event(
+                    "content_block_delta",
+                    json!({"index": 0, "delta": {"type": "text_delta", "text": "there"}}),
+                ),
+                event("content_block_stop", json!({"index": 0})),
+                event(
+                    "content_block_start",
+                    json!({"index": 1, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "ls", "input": {}}}),
+                ),
+                event(
+                    "content_block_delta",
+                    json!({"index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"dir\":"}}),
+                ),
+                event(
+                    "content_block_delta",
+                    json!({"index": 1, "delta": {"type": "input_json_delta", "partial_json": "\".\"}"}}),
+                ),
+                event(
+                    "message_delta",
+                    json!({"delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 15}}),
+                ),
+                event("message_stop", json!({"type": "message_stop"})),
+            ] {
+                accumulator.on_event(&e);
+            }
+
+            let mut call = CapturedCall::new("anthropic", "/v1/messages");
+            accumulator.finish(&mut call);
+            assert_eq!(call.response.content.as_deref(), Some("Hi there"));
+            assert_eq!(
+                call.response.tool_calls[0].function.arguments,
+                r#"{"dir":"."}"#
+            );
+            assert_eq!(call.response.finish_reason.as_deref(), Some("tool_use"));
+            assert_eq!(call.usage, Some(Usage::new(9, 15)));
+            assert_eq!(call.error, None);
+        }
+
+        #[test]
+        fn test_ignores_block_indices_past_the_next() {
+            let mut accumulator = Box::new(AnthropicAccumulator::default());
+            for e in [
+                event(
+                    "content_block_start",
+                    json!({"index": 4000000000u64, "content_block": {"type": "text", "text": "far"}}),
+                ),
+                event(
+                    "content_block_start",
+                    json!({"index": 0, "content_block": {"type": "text", "text": "near"}}),
+                ),
+                event(
+                    "content_block_start",
+                    json!({"index": 2, "content_block": {"type": "text", "text": "gap"}}),
+                ),
+            ] {
+                accumulator.on_event(&e);
+            }
+            assert_eq!(accumulator.blocks.len(), 1);
+
+            let mut call = CapturedCall::new("anthropic", "/v1/messages");
+            accumulator.finish(&mut call);
+            assert_eq!(call.response.content.as_deref(), Some("near"));
+        }
+
+        #[test]
+        fn test_records_error_events() {
+            let mut accumulator = Box::new(AnthropicAccumulator::default());
+            accumulator.on_event(&event(
+                "error",
+                json!({"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}),
+            ));
+
+            let mut call = CapturedCall::new("anthropic", "/v1/messages");
+            accumulator.finish(&mut call);
+            assert_eq!(call.error.as_deref(), Some("Overloaded"));
+        }
+
+        #[test]
+        fn test_error_chunk_uses_error_event() {
+            let chunk = AnthropicAccumulator::default().error_chunk("boom");
+            let text = String::from_utf8(chunk.to_vec()).unwrap();
+            assert!(text.starts_with("event: error\n"));
+            assert!(text.contains("\"api_error\""));
+        }
+    }
+
+    mod route_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_messages_forwarded_with_api_key_and_version() {
+            let app = Router::new().route(
+                "/v1/messages",
+                post(|headers: HeaderMap| async move {
+                    assert_eq!(headers[ANTHROPIC_VERSION_HEADER], DEFAULT_ANTHROPIC_VERSION);
+                    assert_eq!(headers["x-api-key"], "sk-ant-test");
+                    Json(messages_response())
+                }),
+            );
+            let (server, base) = start_proxy(&spawn_upstream(app).await).await;
+
+            let response = reqwest::Client::new()
+                .post(format!("{}/v1/messages", base))
+                .header("x-api-key", "sk-ant-test")
+                .json(&json!({"model": "claude-sonnet-4-5", "max_tokens": 10, "messages": [{"role": "user", "content": "hi"}]}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.provider, "anthropic");
+            assert_eq!(call.usage, Some(Usage::new(20, 8)));
+        }
+
+        #[tokio::test]
+        async fn test_invalid_body_uses_anthropic_error_shape() {
+            let (_server, base) = start_proxy("http://127.0.0.1:9").await;
+            let body: Value = reqwest::Client::new()
+                .post(format!("{}/v1/messages", base))
+                .body("nope")
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+
+            assert_eq!(body["type"], "error");
+            assert_eq!(body["error"]["type"], "invalid_request_error");
+        }
+    }
+}
@@ -0,0 +1,601 @@
+//! Retries, fallbacks and circuit breakers
+//!
+//! A request that fails with a connection error or a retryable status is
+//! retried on the same upstream with jittered exponential backoff, waiting
+//! as long as a 429's `Retry-After` asks when that is reasonable. Once the
+//! retries are used up the route's fallbacks are tried in order.
+//!
+//! Every upstream has a circuit breaker. After enough consecutive failures
+//! it opens and the upstream is skipped until the open period ends; then a
+//! single request is let through to probe it. State changes are published
+//! as [`BREAKER_EVENT`] so the frontend can show which upstreams are down.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, Method},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{Provider, ProxyContext};
+use crate::events::EventSink;
+use crate::routing::{FailoverPolicy, ResolvedRoute, Upstream};
+use crate::sync::lock;
+
+/// Event published when an upstream's circuit breaker changes state
+pub const BREAKER_EVENT: &str = "circuit-breaker";
+/// Key under which the attempts of a retried call are recorded on it
+pub const METADATA_KEY: &str = "failover";
+
+/// Statuses worth trying again, possibly elsewhere
+const RETRYABLE_STATUSES: &[u16] = &[408, 429, 500, 502, 503, 504];
+
+/// State of one upstream's circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Requests flow normally
+    Closed,
+    /// The upstream is skipped
+    Open,
+    /// One probe request is allowed through
+    HalfOpen,
+}
+
+/// Breaker state reported to the frontend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BreakerStatus {
+    pub upstream: String,
+    pub state: BreakerState,
+    /// Consecutive failures
+    pub failures: u32,
+}
+
+struct Breaker {
+    state: BreakerState,
+    failures: u32,
+    opened_at: Option<Instant>,
+    /// Whether the half-open probe is in flight
+    probing: bool,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            failures: 0,
+            opened_at: None,
+            probing: false,
+        }
+    }
+}
+
+/// Circuit breakers of every upstream, kept across proxy restarts
+pub struct CircuitBreakers {
+    breakers: Mutex<HashMap<String, Breaker>>,
+    events: Arc<dyn EventSink>,
+}
+
+impl CircuitBreakers {
+    /// Creates breakers publishing state changes to `events`
+    pub fn new(events: Arc<dyn EventSink>) -> Self {
+        Self {
+            breakers: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+
+    fn update<T>(&self, upstream: &str, change: impl FnOnce(&mut Breaker) -> T) -> T {
+        let mut breakers = lock(&self.breakers);
+        let breaker = breakers.entry(upstream.to_string()).or_default();
+        let before = breaker.state;
+        let result = change(breaker);
+        if breaker.state != before {
+            let status = BreakerStatus {
+                upstream: upstream.to_string(),
+                state: breaker.state,
+                failures: breaker.failures,
+            };
+            drop(breakers);
+            self.events.emit(BREAKER_EVENT, json!(status));
+        }
+        result
+    }
+
+    /// Whether a request may be sent to `upstream` now
+    pub fn allow(&self, upstream: &str, policy: &FailoverPolicy) -> bool {
+        let open_for = Duration::from_secs(policy.breaker_open_secs);
+        self.update(upstream, |breaker| match breaker.state {
+            BreakerState::Closed => true,
+            BreakerState::Open if breaker.opened_at.is_some_and(|at| at.elapsed() < open_for) => {
+                false
+            }
+            BreakerState::Open => {
+                breaker.state = BreakerState::HalfOpen;
+                breaker.probing = true;
+                true
+            }
+            BreakerState::HalfOpen => !std::mem::replace(&mut breaker.probing, true),
+        })
+    }
+
+    /// Records a working answer, closing the breaker
+    pub fn success(&self, upstream: &str) {
+        self.update(upstream, |breaker| *breaker = Breaker::default());
+    }
+
+    /// Records a failure, opening the breaker once there were too many
+    pub fn failure(&self, upstream: &str, policy: &FailoverPolicy) {
+        self.update(upstream, |breaker| {
+            breaker.failures = breaker.failures.saturating_add(1);
+            breaker.probing = false;
+            let tripped = policy.breaker_threshold > 0
+                && (breaker.state == BreakerState::HalfOpen
+                    || breaker.failures >= policy.breaker_threshold);
+            if tripped {
+                breaker.state = BreakerState::Open;
+                breaker.opened_at = Some(Instant::now());
+            }
+        });
+    }
+
+    /// Returns every breaker that has seen a failure
+    pub fn statuses(&self) -> Vec<BreakerStatus> {
+        let breakers = lock(&self.breakers);
+        let mut statuses: Vec<BreakerStatus> = breakers
+            .iter()
+            .filter(|(_, breaker)| breaker.failures > 0)
+            .map(|(upstream, breaker)| BreakerStatus {
+                upstream: upstream.clone(),
+                state: breaker.state,
+                failures: breaker.failures,
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.upstream.cmp(&b.upstream));
+        statuses
+    }
+}
+
+/// One try at one upstream, recorded on retried calls
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attempt {
+    pub upstream: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of sending a request through retries and fallbacks
+pub(crate) struct Delivery {
+    /// Final upstream answer, or why none could be had
+    pub result: Result<reqwest::Response, String>,
+    pub attempts: Vec<Attempt>,
+}
+
+impl ProxyContext {
+    /// Sends a request to the route's upstream, retrying and falling back as its policy says
+    pub(crate) async fn deliver(
+        &self,
+        route: &ResolvedRoute,
+        provider: Provider,
+        path_and_query: &str,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> Delivery {
+        let policy = &route.failover;
+        let primary = (&route.target, None);
+        let fallbacks = route
+            .fallback_targets
+            .iter()
+            .filter(|fallback| fallback.upstream.kind.speaks(provider))
+            .map(|fallback| (&fallback.upstream, fallback.model.as_deref()));
+
+        let mut attempts = Vec::new();
+        let mut last_answer = None;
+        for (upstream, model) in std::iter::once(primary).chain(fallbacks) {
+            let body = match model {
+                Some(model) => with_model(&body, model),
+                None => body.clone(),
+            };
+            let tried = self
+                .try_upstream(
+                    upstream,
+                    provider,
+                    path_and_query,
+                    headers,
+                    body,
+                    policy,
+                    &mut attempts,
+                )
+                .await;
+            match tried {
+                Tried::Done(response) => {
+                    return Delivery {
+                        result: Ok(response),
+                        attempts,
+                    }
+                }
+                Tried::Failed(Some(response)) => last_answer = Some(response),
+                Tried::Failed(None) => {}
+            }
+        }
+        // Relay the last upstream error as is, it tells the client the most
+        let result = last_answer.ok_or_else(|| {
+            attempts
+                .last()
+                .and_then(|a| a.error.clone())
+                .unwrap_or_else(|| "no upstream available".to_string())
+        });
+        Delivery { result, attempts }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn try_upstream(
+        &self,
+        upstream: &Upstream,
+        provider: Provider,
+        path_and_query: &str,
+        headers: &HeaderMap,
+        body: Bytes,
+        policy: &FailoverPolicy,
+        attempts: &mut Vec<Attempt>,
+    ) -> Tried {
+        let mut last_answer = None;
+        for retry in 0..=policy.max_retries {
+            if !self.breakers.allow(&upstream.name, policy) {
+                attempts.push(Attempt {
+                    upstream: upstream.name.clone(),
+                    status: None,
+                    error: Some(format!("circuit breaker of {} is open", upstream.name)),
+                });
+                break;
+            }
+            let sent = self
+                .send(
+                    upstream,
+                    provider,
+                    Method::POST,
+                    path_and_query,
+                    headers,
+                    body.clone(),
+                )
+                .await;
+            let delay = match sent {
+                Ok(response) if !RETRYABLE_STATUSES.contains(&response.status().as_u16()) => {
+                    self.breakers.success(&upstream.name);
+                    attempts.push(Attempt {
+                        upstream: upstream.name.clone(),
+                        status: Some(response.status().as_u16()),
+                        error: None,
+                    });
+                    return Tried::Done(response);
+                }
+                Ok(response) => {
+                    self.breakers.failure(&upstream.name, policy);
+                    attempts.push(Attempt {
+                        upstream: upstream.name.clone(),
+                        status: Some(response.status().as_u16()),
+                        error: None,
+                    });
+                    let wait = retry_after(response.headers(), SystemTime::now());
+                    last_answer = Some(response);
+                    match wait {
+                        // Waiting that long is worse than asking someone else
+                        Some(wait) if wait.as_secs() > policy.max_retry_after_secs => break,
+                        Some(wait) => wait,
+                        None => backoff(policy, retry),
+                    }
+                }
+                Err(e) => {
+                    self.breakers.failure(&upstream.name, policy);
+                    attempts.push(Attempt {
+                        upstream: upstream.name.clone(),
+                        status: None,
+                        error: Some(e.to_string()),
+                    });
+                    backoff(policy, retry)
+                }
+            };
+            if retry < policy.max_retries {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        Tried::Failed(last_answer)
+    }
+}
+
+enum Tried {
+    Done(reqwest::Response),
+    /// Carries the last upstream answer, if there was one
+    Failed(Option<reqwest::Response>),
+}
+
+/// Returns `body` asking for `model` instead
+fn with_model(body: &Bytes, model: &str) -> Bytes {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut request) if request.get("model").is_some() => {
+            request["model"] = json!(model);
+            Bytes::from(request.to_string())
+        }
+        _ => body.clone(),
+    }
+}
+
+/// Reads `Retry-After` as seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let at = SystemTime::from(at);
+    Some(at.duration_since(now).unwrap_or_default())
+}
+
+/// Exponential backoff with full jitter over its upper half
+pub fn backoff(policy: &FailoverPolicy, retry: u32) -> Duration {
+    let ceiling = policy
+        .base_delay_ms
+        .saturating_mul(1u64 << retry.min(16))
+        .min(policy.max_delay_ms);
+    let jitter = RandomState::new().build_hasher().finish() % (ceiling / 2 + 1);
+    Duration::from_millis(ceiling - jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::RecordingSink;
+    use axum::http::HeaderValue;
+
+    fn policy() -> FailoverPolicy {
+        FailoverPolicy {
+            breaker_threshold: 2,
+            breaker_open_secs: 60,
+            ..FailoverPolicy::default()
+        }
+    }
+
+    mod timing_tests {
+        use super::*;
+
+        #[test]
+        fn test_retry_after_seconds_and_dates() {
+            let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_445_412_480);
+            let mut headers = HeaderMap::new();
+            assert_eq!(retry_after(&headers, now), None);
+            headers.insert(header::RETRY_AFTER, HeaderValue::from_static("7"));
+            assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(7)));
+            headers.insert(
+                header::RETRY_AFTER,
+                HeaderValue::from_static("Wed, 21 Oct 2015 07:28:10 GMT"),
+            );
+            assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(10)));
+            headers.insert(header::RETRY_AFTER, HeaderValue::from_static("soon"));
+            assert_eq!(retry_after(&headers, now), None);
+        }
+
+        #[test]
+        fn test_backoff_grows_with_jitter_and_is_capped() {
+            let policy = FailoverPolicy {
+                base_delay_ms: 100,
+                max_delay_ms: 1_000,
+                ..FailoverPolicy::default()
+            };
+            for _ in 0..50 {
+                let first = backoff(&policy, 0).as_millis();
+                assert!((50..=100).contains(&first), "{}", first);
+                let third = backoff(&policy, 2).as_millis();
+                assert!((200..=400).contains(&third), "{}", third);
+                assert!(backoff(&policy, 30).as_millis() <= 1_000);
+            }
+        }
+    }
+
+    mod breaker_tests {
+        use super::*;
+
+        #[test]
+        fn test_opens_after_threshold_and_reports_changes() {
+            let sink = Arc::new(RecordingSink::default());
+            let breakers = CircuitBreakers::new(sink.clone());
+            let policy = policy();
+
+            breakers.failure("ollama", &policy);
+            assert!(breakers.allow("ollama", &policy));
+            breakers.failure("ollama", &policy);
+            assert!(!breakers.allow("ollama", &policy));
+            assert!(breakers.allow("litellm", &policy));
+
+            let events = sink.events();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].0, BREAKER_EVENT);
+            assert_eq!(events[0].1["upstream"], "ollama");
+            assert_eq!(events[0].1["state"], "open");
+            assert_eq!(breakers.statuses()[0].failures, 2);
+        }
+
+        #[test]
+        fn test_half_open_lets_one_probe_through() {
+            let sink = Arc::new(RecordingSink::default());
+            let breakers = CircuitBreakers::new(sink.clone());
+            let policy = FailoverPolicy {
+                breaker_open_secs: 0,
+                ..policy()
+            };
+            breakers.failure("ollama", &policy);
+            breakers.failure("ollama", &policy);
+
+            assert!(breakers.allow("ollama", &policy));
+            assert!(!breakers.allow("ollama", &policy));
+            breakers.failure("ollama", &policy);
+            assert!(breakers.allow("ollama", &policy));
+            breakers.success("ollama");
+            assert!(breakers.allow("ollama", &policy));
+            assert!(breakers.statuses().is_empty());
+
+            let states: Vec<Value> = sink
+                .events()
+                .into_iter()
+                .map(|(_, p)| p["state"].clone())
+                .collect();
+            assert_eq!(states, ["open", "half_open", "open", "half_open", "closed"]);
+        }
+
+        #[test]
+        fn test_zero_threshold_never_opens() {
+            let breakers = CircuitBreakers::new(Arc::new(RecordingSink::default()));
+            let policy = FailoverPolicy {
+                breaker_threshold: 0,
+                ..policy()
+            };
+            for _ in 0..10 {
+                breakers.failure("ollama", &policy);
+            }
+            assert!(breakers.allow("ollama", &policy));
+        }
+    }
+
+    mod delivery_tests {
+        use super::*;
+        use crate::proxy::testing::{echo_upstream, services, spawn_upstream};
+        use crate::proxy::ProxyConfig;
+        use crate::routing::{RouteStore, RoutingTable, DEFAULT_UPSTREAM};
+        use axum::{http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Upstream failing with `status` until it has been hit `failures` times, asking for an
+        /// hour's wait on 429s
+        fn flaky_upstream(hits: Arc<AtomicUsize>, failures: usize, status: StatusCode) -> Router {
+            let answer = move |Json(body): Json<Value>| async move {
+                if hits.fetch_add(1, Ordering::SeqCst) < failures {
+                    let error = Json(json!({"error": {"message": "try later"}}));
+                    if status == StatusCode::TOO_MANY_REQUESTS {
+                        return (status, [(header::RETRY_AFTER, "3600")], error).into_response();
+                    }
+                    return (status, error).into_response();
+                }
+                let content = format!("primary {}", body["model"].as_str().unwrap());
+                Json(json!({"choices": [{"message": {"role": "assistant", "content": content}}]}))
+                    .into_response()
+            };
+            Router::new().route("/v1/chat/completions", post(answer))
+        }
+
+        /// Context sending to `primary`, retrying once and then falling back to a `backup`
+        /// upstream as model `small`
+        async fn context(primary: Router, events: Arc<RecordingSink>) -> ProxyContext {
+            let primary = spawn_upstream(primary).await;
+            let backup = spawn_upstream(echo_upstream("backup")).await;
+            let table = RoutingTable::parse(&format!(
+                r#"
+                fallbacks = [{{ upstream = "backup", model = "small" }}]
+
+                [[upstreams]]
+                name = "backup"
+                base_url = "{}"
+                kind = "openai"
+
+                [failover]
+                max_retries = 1
+                base_delay_ms = 1
+                breaker_threshold = 2
+                breaker_open_secs = 60
+                "#,
+                backup
+            ))
+            .unwrap();
+            let services = services()
+                .with_routes(RouteStore::in_memory(table))
+                .with_breakers(CircuitBreakers::new(events));
+            let config = ProxyConfig {
+                upstream_url: primary,
+                ..ProxyConfig::default()
+            };
+            ProxyContext::with_client(config, &services, reqwest::Client::new())
+        }
+
+        /// Delivers a gpt-4o request, returning the attempts and the answer's content
+        async fn deliver(context: &ProxyContext) -> (Vec<Attempt>, String) {
+            let route = context
+                .routes
+                .resolve("gpt-4o", &context.default_upstream())
+                .unwrap();
+            let body = json!({"model": "gpt-4o", "messages": []}).to_string();
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            );
+            let delivery = context
+                .deliver(
+                    &route,
+                    Provider::OpenAi,
+                    "/v1/chat/completions",
+                    &headers,
+                    Bytes::from(body),
+                )
+                .await;
+            let answer: Value = delivery.result.unwrap().json().await.unwrap();
+            let content = answer["choices"][0]["message"]["content"].as_str().unwrap();
+            (delivery.attempts, content.to_string())
+        }
+
+        #[tokio::test]
+        async fn test_server_error_is_retried() {
+            let hits = Arc::new(AtomicUsize::new(0));
+            let primary = flaky_upstream(hits.clone(), 1, StatusCode::SERVICE_UNAVAILABLE);
+            let events = Arc::new(RecordingSink::default());
+            let context = context(primary, events.clone()).await;
+
+            let (attempts, content) = deliver(&context).await;
+            assert_eq!(content, "primary gpt-4o");
+            assert_eq!(hits.load(Ordering::SeqCst), 2);
+            let statuses: Vec<_> = attempts.iter().map(|a| a.status).collect();
+            assert_eq!(statuses, [Some(503), Some(200)]);
+            assert!(events.names().is_empty());
+        }
+
+        #[tokio::test]
+        async fn test_long_retry_after_moves_to_the_fallback() {
+            let hits = Arc::new(AtomicUsize::new(0));
+            let primary = flaky_upstream(hits.clone(), 1, StatusCode::TOO_MANY_REQUESTS);
+            let context = context(primary, Arc::new(RecordingSink::default())).await;
+
+            let (_, content) = deliver(&context).await;
+            assert_eq!(content, "backup small");
+            assert_eq!(hits.load(Ordering::SeqCst), 1);
+        }
+
+        #[tokio::test]
+        async fn test_breaker_opens_and_skips_the_upstream() {
+            let hits = Arc::new(AtomicUsize::new(0));
+            let primary = flaky_upstream(hits.clone(), usize::MAX, StatusCode::BAD_GATEWAY);
+            let events = Arc::new(RecordingSink::default());
+            let context = context(primary, events.clone()).await;
+
+            // The retry is the second failure in a row, which opens the breaker
+            let (_, content) = deliver(&context).await;
+            assert_eq!(content, "backup small");
+            assert_eq!(hits.load(Ordering::SeqCst), 2);
+            let (attempts, content) = deliver(&context).await;
+            assert_eq!(content, "backup small");
+            assert_eq!(hits.load(Ordering::SeqCst), 2);
+            let skipped = attempts[0].error.as_deref().unwrap();
+            assert!(skipped.contains("circuit breaker"));
+
+            let recorded = events.events();
+            assert_eq!(recorded.len(), 1);
+            assert_eq!(recorded[0].0, BREAKER_EVENT);
+            assert_eq!(recorded[0].1["upstream"], DEFAULT_UPSTREAM);
+            assert_eq!(recorded[0].1["state"], "open");
+        }
+    }
+}
@@ -0,0 +1,510 @@
+//! Google Gemini REST routes (`generateContent` / `streamGenerateContent`)
+//!
+//! Gemini encodes the model and the action in the path, e.g.
+//! `/v1beta/models/gemini-2.0-flash:streamGenerateContent?alt=sse`.
+//! Streaming with `alt=sse` is passed through chunk by chunk; without it
+//! Gemini answers with a JSON array, which is buffered and then captured.
+//! Other actions such as `countTokens` are forwarded without capture.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, Method, Uri},
+    response::Response,
+};
+use serde_json::{json, Value};
+
+use super::relay::{passthrough, relay, Provider, ProviderApi};
+use super::stream::{SseEvent, StreamAccumulator};
+use super::ProxyContext;
+use crate::capture::{CapturedCall, Message, MessageRole, ModelParameters, ToolCall, Usage};
+
+/// Handles `POST /{version}/models/{model}:{action}`
+pub(crate) async fn models_action(
+    State(context): State<Arc<ProxyContext>>,
+    Path(model_action): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    match parse_model_action(&model_action) {
+        Some((_, Action::Generate | Action::StreamGenerate)) => {
+            relay(context, &GeminiApi, uri, headers, body).await
+        }
+        _ => {
+            passthrough(
+                &context,
+                Provider::Gemini,
+                Method::POST,
+                &uri,
+                &headers,
+                body,
+            )
+            .await
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Generate,
+    StreamGenerate,
+    Other,
+}
+
+/// Splits `gemini-2.0-flash:generateContent` into the model and the action
+fn parse_model_action(segment: &str) -> Option<(&str, Action)> {
+    let (model, action) = segment.rsplit_once(':')?;
+    let action = match action {
+        "generateContent" => Action::Generate,
+        "streamGenerateContent" => Action::StreamGenerate,
+        _ => Action::Other,
+    };
+    Some((model, action))
+}
+
+/// Hooks for the Gemini wire format
+pub(crate) struct GeminiApi;
+
+impl ProviderApi for GeminiApi {
+    fn provider(&self) -> Provider {
+        Provider::Gemini
+    }
+
+    fn capture_request(&self, call: &mut CapturedCall, uri: &Uri, request: &Value) {
+        let segment = uri.path().rsplit('/').next().unwrap_or_default();
+        if let Some((model, action)) = parse_model_action(segment) {
+            call.model = model.to_string();
+            call.stream = action == Action::StreamGenerate;
+        }
+        capture_request(call, request);
+    }
+
+    fn capture_response(&self, call: &mut CapturedCall, response: &Value) {
+        let mut accumulator = GeminiAccumulator::default();
+        match response {
+            // Non-SSE streaming answers with an array of chunks
+            Value::Array(chunks) => chunks.iter().for_each(|c| accumulator.merge(c)),
+            chunk => accumulator.merge(chunk),
+        }
+        Box::new(accumulator).finish(call);
+    }
+
+    fn accumulator(&self) -> Box<dyn StreamAccumulator> {
+        Box::new(GeminiAccumulator::default())
+    }
+}
+
+fn parts(content: &Value) -> impl Iterator<Item = &Value> {
+    content
+        .get("parts")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+fn parts_text(content: &Value) -> Option<String> {
+    let texts: Vec<&str> = parts(content)
+        // Thought summaries are not part of the answer
+        .filter(|p| p.get("thought").and_then(Value::as_bool) != Some(true))
+        .filter_map(|p| p.get("text").and_then(Value::as_str))
+        .collect();
+    (!texts.is_empty()).then(|| texts.join(""))
+}
+
+fn function_call(part: &Value) -> Option<ToolCall> {
+    let call = part.get("functionCall")?;
+    let name = call.get("name").and_then(Value::as_str).unwrap_or_default();
+    Some(ToolCall::function(
+        call.get("id").and_then(Value::as_str).unwrap_or(name),
+        name,
+        call.get("args")
+            .cloned()
+            .unwrap_or_else(|| json!({}))
+            .to_string(),
+    ))
+}
+
+/// Converts one `contents` entry; `functionResponse` parts become `tool` messages
+fn parse_content(content: &Value) -> Vec<Message> {
+    let role = MessageRole::parse(
+        content
+            .get("role")
+            .and_then(Value::as_str)
+            .unwrap_or("user"),
+    );
+
+    let mut messages: Vec<Message> = parts(content)
+        .filter_map(|p| p.get("functionResponse"))
+        .map(|response| {
+            let name = response
+                .get("name")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            Message {
+                role: MessageRole::Tool,
+                content: response.get("response").map(Value::to_string),
+                tool_call_id: response
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .or_else(|| name.clone()),
+                name,
+                tool_calls: Vec::new(),
+            }
+        })
+        .collect();
+
+    let text = parts_text(content);
+    let tool_calls: Vec<ToolCall> = parts(content).filter_map(function_call).collect();
+    if text.is_some() || !tool_calls.is_empty() || messages.is_empty() {
+        messages.push(Message {
+            role,
+            content: text,
+            name: None,
+            tool_calls,
+            tool_call_id: None,
+        });
+    }
+    messages
+}
+
+/// Fills the request side of a call from a `generateContent` body.
+/// The model is taken from the URL, see [`GeminiApi`].
+pub(crate) fn capture_request(call: &mut CapturedCall, request: &Value) {
+    let config = request.get("generationConfig").unwrap_or(&Value::Null);
+    let parameters = ModelParameters {
+        temperature: config.get("temperature").and_then(Value::as_f64),
+        top_p: config.get("topP").and_then(Value::as_f64),
+        max_tokens: config.get("maxOutputTokens").and_then(Value::as_u64),
+        stop: config
+            .get("stopSequences")
+            .filter(|v| !v.is_null())
+            .cloned(),
+        seed: config.get("seed").and_then(Value::as_i64),
+    };
+    call.parameters = (!parameters.is_empty()).then_some(parameters);
+
+    call.messages.clear();
+    let system = request
+        .get("systemInstruction")
+        .or_else(|| request.get("system_instruction"));
+    if let Some(system) = system.and_then(parts_text) {
+        call.messages
+            .push(Message::text(MessageRole::System, system));
+    }
+    if let Some(contents) = request.get("contents").and_then(Value::as_array) {
+        call.messages
+            .extend(contents.iter().flat_map(parse_content));
+    }
+    call.tools = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+}
+
+/// Rebuilds a response from `GenerateContentResponse` chunks
+#[derive(Debug, Default)]
+pub(crate) struct GeminiAccumulator {
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    finish_reason: Option<String>,
+    usage: Option<Usage>,
+    error: Option<String>,
+}
+
+impl GeminiAccumulator {
+    fn merge(&mut self, chunk: &Value) {
+        if let Some(message) = chunk.pointer("/error/message").and_then(Value::as_str) {
+            self.error = Some(message.to_string());
+        }
+        if let Some(candidate) = chunk.pointer("/candidates/0") {
+            if let Some(content) = candidate.get("content") {
+                if let Some(text) = parts_text(content) {
+                    self.content.push_str(&text);
+                }
+                self.tool_calls
+                    .extend(parts(content).filter_map(function_call));
+            }
+            if let Some(reason) = candidate.get("finishReason").and_then(Value::as_str) {
+                self.finish_reason = Some(reason.to_string());
+            }
+        }
+        if let Some(usage) = chunk.get("usageMetadata") {
+            let count = |key: &str| usage.get(key).and_then(Value::as_u64).unwrap_or(0);
+            // Thinking tokens are billed as output
+            self.usage = Some(Usage::new(
+                count("promptTokenCount"),
+                count("candidatesTokenCount") + count("thoughtsTokenCount"),
+            ));
+        }
+    }
+}
+
+impl StreamAccumulator for GeminiAccumulator {
+    fn on_event(&mut self, event: &SseEvent) {
+        if let Some(chunk) = event.json() {
+            self.merge(&chunk);
+        }
+    }
+
+    fn finish(self: Box<Self>, call: &mut CapturedCall) {
+        call.response.content = (!self.content.is_empty()).then_some(self.content);
+        call.response.tool_calls = self.tool_calls;
+        call.response.finish_reason = self.finish_reason;
+        call.usage = self.usage;
+        if self.error.is_some() {
+            call.error = self.error;
+        }
+    }
+
+    fn error_chunk(&self, message: &str) -> Bytes {
+        let payload = json!({"error": {"code": 502, "message": message, "status": "UNAVAILABLE"}});
+        Bytes::from(format!("data: {}\n\n", payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::testing::{spawn_upstream, start_proxy, wait_for_capture};
+    use axum::{response::IntoResponse, routing::post, Json, Router};
+    use std::time::Duration;
+
+    fn chunk(text: &str) -> Value {
+        json!({"candidates": [{"content": {"role": "model", "parts": [{"text": text}]}}]})
+    }
+
+    mod parse_tests {
+        use super::*;
+
+        #[test]
+        fn test_parse_model_action() {
+            assert_eq!(
+                parse_model_action("gemini-2.0-flash:generateContent"),
+                Some(("gemini-2.0-flash", Action::Generate))
+            );
+            assert_eq!(
+                parse_model_action("gemini-2.0-flash:streamGenerateContent"),
+                Some(("gemini-2.0-flash", Action::StreamGenerate))
+            );
+            assert_eq!(
+                parse_model_action("gemini-2.0-flash:countTokens"),
+                Some(("gemini-2.0-flash", Action::Other))
+            );
+            assert_eq!(parse_model_action("gemini-2.0-flash"), None);
+        }
+
+        #[test]
+        fn test_capture_request_with_function_parts() {
+            let mut call = CapturedCall::new("gemini", "/v1beta/models/m:generateContent");
+            capture_request(
+                &mut call,
+                &json!({
+                    "systemInstruction": {"parts": [{"text": "Be brief"}]},
+                    "contents": [
+                        {"role": "user", "parts": [{"text": "Weather?"}]},
+                        {"role": "model", "parts": [{"functionCall": {"name": "weather", "args": {"city": "Oslo"}}}]},
+                        {"role": "user", "parts": [{"functionResponse": {"name": "weather", "response": {"temp": 3}}}]}
+                    ],
+                    "generationConfig": {"temperature": 0.2, "maxOutputTokens": 100, "stopSequences": ["x"]},
+                    "tools": [{"functionDeclarations": [{"name": "weather"}]}]
+                }),
+            );
+
+            let roles: Vec<_> = call.messages.iter().map(|m| m.role).collect();
+            assert_eq!(
+                roles,
+                vec![
+                    MessageRole::System,
+                    MessageRole::User,
+                    MessageRole::Assistant,
+                    MessageRole::Tool
+                ]
+            );
+            assert_eq!(
+                call.messages[2].tool_calls[0].function.arguments,
+                r#"{"city":"Oslo"}"#
+            );
+            assert_eq!(call.messages[3].name.as_deref(), Some("weather"));
+            assert_eq!(call.messages[3].content.as_deref(), Some(r#"{"temp":3}"#));
+            assert_eq!(call.parameters.unwrap().max_tokens, Some(100));
+            assert_eq!(call.tools.len(), 1);
+        }
+
+        #[test]
+        fn test_capture_request_reads_model_from_uri() {
+            let mut call = CapturedCall::new("gemini", "");
+            let uri: Uri = "/v1beta/models/gemini-2.0-flash:streamGenerateContent?alt=sse"
+                .parse()
+                .unwrap();
+            GeminiApi.capture_request(&mut call, &uri, &json!({"contents": []}));
+            assert_eq!(call.model, "gemini-2.0-flash");
+            assert!(call.stream);
+        }
+
+        #[test]
+        fn test_capture_response_array_and_usage() {
+            let mut call = CapturedCall::new("gemini", "");
+            let mut last = chunk("lo");
+            last["candidates"][0]["finishReason"] = json!("STOP");
+            last["usageMetadata"] =
+                json!({"promptTokenCount": 7, "candidatesTokenCount": 2, "thoughtsTokenCount": 3});
+            GeminiApi.capture_response(&mut call, &json!([chunk("Hel"), last]));
+
+            assert_eq!(call.response.content.as_deref(), Some("Hello"));
+            assert_eq!(call.response.finish_reason.as_deref(), Some("STOP"));
+            assert_eq!(call.usage, Some(Usage::new(7, 5)));
+        }
+
+        #[test]
+        fn test_thought_parts_are_skipped() {
+            let content =
+                json!({"parts": [{"text": "thinking", "thought": true}, {"text": "answer"}]});
+            assert_eq!(parts_text(&content).as_deref(), Some("answer"));
+        }
+    }
+
+    mod route_tests {
+        use super::*;
+        use crate::clients::{ClientKeyRequest, ClientKeyStore};
+        use crate::proxy::testing::{services, start_proxy_with};
+
+        #[tokio::test]
+        async fn test_generate_content_captured() {
+            let app = Router::new().route(
+                "/v1beta/models/{model}",
+                post(|headers: HeaderMap| async move {
+                    assert!(headers.get("x-goog-api-key").is_some());
+                    Json(chunk("Hi"))
+                }),
+            );
+            let (server, base) = start_proxy(&spawn_upstream(app).await).await;
+
+            let body: Value = reqwest::Client::new()
+                .post(format!(
+                    "{}/v1beta/models/gemini-2.0-flash:generateContent",
+                    base
+                ))
+                .header("x-goog-api-key", "key")
+                .json(&json!({"contents": [{"role": "user", "parts": [{"text": "hello"}]}]}))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(body, chunk("Hi"));
+
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.provider, "gemini");
+            assert_eq!(call.model, "gemini-2.0-flash");
+            assert_eq!(call.response.content.as_deref(), Some("Hi"));
+        }
+
+        #[tokio::test]
+        async fn test_stream_generate_content_sse() {
+            let app = Router::new().route(
+                "/v1beta/models/{model}",
+                post(|uri: Uri| async move {
+                    assert_eq!(uri.query(), Some("alt=sse"));
+                    let events = futures_util::stream::iter(["Hel", "lo"].map(|t| {
+                        Ok::<_, std::io::Error>(Bytes::from(format!("data: {}\r\n\r\n", chunk(t))))
+                    }));
+                    (
+                        [("content-type", "text/event-stream")],
+                        axum::body::Body::from_stream(events),
+                    )
+                        .into_response()
+                }),
+            );
+            let (server, base) = start_proxy(&spawn_upstream(app).await).await;
+
+            let text = reqwest::Client::new()
+                .post(format!(
+                    "{}/v1beta/models/gemini-2.0-flash:streamGenerateContent?alt=sse",
+                    base
+                ))
+                .json(&json!({"contents": []}))
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            assert_eq!(text.matches("data: ").count(), 2);
+
+            let call = wait_for_capture(&server).await;
+            assert!(call.stream);
+            assert_eq!(call.response.content.as_deref(), Some("Hello"));
+        }
+
+        #[tokio::test]
+        async fn test_virtual_key_in_query_is_authenticated_and_stripped() {
+            let clients = ClientKeyStore::in_memory();
+            let created = clients
+                .create(ClientKeyRequest {
+                    name: "gemini-cli".to_string(),
+                    ..ClientKeyRequest::default()
+                })
+                .unwrap();
+            clients.set_required(true).unwrap();
+            let app = Router::new().route(
+                "/v1beta/models/{model}",
+                post(|uri: Uri| async move {
+                    assert_eq!(uri.query(), None);
+                    Json(chunk("Hi"))
+                }),
+            );
+            let upstream = spawn_upstream(app).await;
+            let (server, base) =
+                start_proxy_with(&upstream, services().with_clients(clients)).await;
+
+            let response = reqwest::Client::new()
+                .post(format!(
+                    "{}/v1beta/models/gemini-2.0-flash:generateContent?key={}",
+                    base, created.key
+                ))
+                .json(&json!({"contents": [{"role": "user", "parts": [{"text": "hello"}]}]}))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.client.as_deref(), Some("gemini-cli"));
+            assert_eq!(
+                call.endpoint,
+                "/v1beta/models/gemini-2.0-flash:generateContent"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_other_actions_are_not_captured() {
+            let app = Router::new().route(
+                "/v1beta/models/{model}",
+                post(|| async { Json(json!({"totalTokens": 3})) }),
+            );
+            let (server, base) = start_proxy(&spawn_upstream(app).await).await;
+
+            let body: Value = reqwest::Client::new()
+                .post(format!(
+                    "{}/v1beta/models/gemini-2.0-flash:countTokens",
+                    base
+                ))
+                .json(&json!({"contents": []}))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(body["totalTokens"], 3);
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert!(server.captures().is_empty());
+        }
+    }
+}
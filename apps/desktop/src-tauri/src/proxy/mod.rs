@@ -0,0 +1,898 @@
+//! Embedded LLM proxy server
+//!
+//! Every LLM request from IDEs, CLIs and scripts is meant to go through
+//! Blackbox. The proxy listens on a localhost port, forwards requests to the
+//! configured upstream (LiteLLM by default) and records each call in the
+//! [`CaptureLog`] so the desktop app can show live traffic. Streamed
+//! responses are passed through as they arrive, see [`stream`].
+//!
+//! Routes speak the OpenAI (`/v1/chat/completions`, `/v1/completions`,
+//! `/v1/models`), Anthropic (`/v1/messages`) and Gemini
+//! (`/v1beta/models/{model}:generateContent`) wire formats; all of them are
+//! normalised into the same [`crate::capture::CapturedCall`].
+//! On the way in and out every request runs through the plugin
+//! [`Pipeline`] held in [`ProxyServices`], and repeated requests can be
+//! answered from the [`ResponseCache`]. Token usage is charged to the
+//! [`BudgetTracker`], which can also turn requests away. The [`RouteStore`]
+//! decides which upstream serves each model; failed requests are retried
+//! and sent to fallbacks behind per-upstream [`CircuitBreakers`]. Clients
+//! identify themselves with virtual keys from the [`ClientKeyStore`].
+
+mod anthropic;
+mod failover;
+mod gemini;
+mod openai;
+mod relay;
+mod stream;
+
+pub use anthropic::DEFAULT_ANTHROPIC_VERSION;
+pub use failover::{BreakerState, BreakerStatus, CircuitBreakers, BREAKER_EVENT};
+pub use relay::Provider;
+pub use stream::{SseEvent, SseParser};
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{header, HeaderMap, HeaderName, Method, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::budget::{BudgetSettings, BudgetTracker};
+use crate::cache::ResponseCache;
+use crate::capture::{CaptureLog, CapturedCall};
+use crate::clients::ClientKeyStore;
+use crate::credentials::{CredentialError, CredentialStore};
+use crate::events::NoopSink;
+use crate::pipeline::Pipeline;
+use crate::routing::{self, RouteAuth, RouteStore, RoutingTable, Upstream, UpstreamKind};
+use crate::sync::lock;
+use crate::telemetry::{TelemetryConfig, TelemetryExporter};
+use crate::usage::pricing::{PriceStore, PricingTable};
+use crate::usage::UsageLedger;
+
+/// Default localhost port the proxy listens on
+pub const DEFAULT_PORT: u16 = 7213;
+/// Default upstream, the LiteLLM gateway from docker-compose.yml
+pub const DEFAULT_UPSTREAM_URL: &str = "http://localhost:4213";
+/// Default time to wait for upstream data before giving up
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
+/// Stored credential a plaintext `upstream_api_key` is moved into
+pub const UPSTREAM_KEY_CREDENTIAL: &str = "UPSTREAM_API_KEY";
+
+/// How long `stop` waits for in-flight requests before aborting the server
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Headers that only apply to a single connection and must not be forwarded
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+];
+
+/// Headers carrying client credentials in any supported API format
+const AUTH_HEADERS: &[&str] = &["authorization", "x-api-key", "x-goog-api-key"];
+
+/// Proxy configuration, persisted in the settings store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// Localhost port to listen on, 0 picks a free port
+    pub port: u16,
+    /// Base URL requests are forwarded to, without the `/v1` suffix
+    pub upstream_url: String,
+    /// Replaces the client's `Authorization` header when set. Write-only:
+    /// [`ProxyConfig::store_api_key`] moves it into the credential store
+    #[serde(skip_serializing)]
+    pub upstream_api_key: Option<String>,
+    /// Stored credential sent to the upstream, ahead of `upstream_api_key`
+    pub upstream_credential: Option<String>,
+    /// Whether the proxy starts together with the app
+    pub auto_start: bool,
+    /// Maximum silence from the upstream before the request fails
+    pub request_timeout_secs: u64,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT,
+            upstream_url: DEFAULT_UPSTREAM_URL.to_string(),
+            upstream_api_key: None,
+            upstream_credential: None,
+            auto_start: true,
+            request_timeout_secs: DEFAULT_REQUEST_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Checks that the upstream URL is an absolute http(s) URL
+    pub fn validate(&self) -> Result<(), ProxyError> {
+        let url = reqwest::Url::parse(&self.upstream_url)
+            .map_err(|e| ProxyError::InvalidConfig(format!("upstream_url: {}", e)))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(ProxyError::InvalidConfig(format!(
+                "upstream_url must use http or https, got {}",
+                url.scheme()
+            )));
+        }
+        if self.request_timeout_secs == 0 {
+            return Err(ProxyError::InvalidConfig(
+                "request_timeout_secs must be greater than zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves a plaintext `upstream_api_key` into `credentials` and points
+    /// `upstream_credential` at it
+    pub fn store_api_key(&mut self, credentials: &CredentialStore) -> Result<(), CredentialError> {
+        let Some(key) = self.upstream_api_key.as_deref() else {
+            return Ok(());
+        };
+        credentials.set(UPSTREAM_KEY_CREDENTIAL, key)?;
+        self.upstream_api_key = None;
+        self.upstream_credential = Some(UPSTREAM_KEY_CREDENTIAL.to_string());
+        Ok(())
+    }
+
+    /// Joins the upstream base URL with a request path and query
+    pub fn upstream_endpoint(&self, path_and_query: &str) -> String {
+        format!(
+            "{}/{}",
+            self.upstream_url.trim_end_matches('/'),
+            path_and_query.trim_start_matches('/')
+        )
+    }
+}
+
+/// Snapshot of the proxy state reported to the frontend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyStatus {
+    pub running: bool,
+    /// Address the server is bound to, e.g. `127.0.0.1:7213`
+    pub address: Option<String>,
+    pub port: u16,
+    pub upstream_url: String,
+    /// Requests handled since the server was started
+    pub requests_served: u64,
+}
+
+/// Errors raised while starting or stopping the proxy
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    #[error("invalid proxy configuration: {0}")]
+    InvalidConfig(String),
+    #[error("failed to bind proxy port {port}: {source}")]
+    Bind {
+        port: u16,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("failed to build upstream client: {0}")]
+    Client(#[from] reqwest::Error),
+}
+
+/// Long-lived services shared by the proxy and the Tauri commands
+#[derive(Clone)]
+pub struct ProxyServices {
+    pub captures: Arc<CaptureLog>,
+    pub pipeline: Arc<Pipeline>,
+    pub cache: Arc<ResponseCache>,
+    pub budget: Arc<BudgetTracker>,
+    pub routes: Arc<RouteStore>,
+    pub breakers: Arc<CircuitBreakers>,
+    pub clients: Arc<ClientKeyStore>,
+    pub credentials: Arc<CredentialStore>,
+    pub usage: Arc<UsageLedger>,
+    pub telemetry: Arc<TelemetryExporter>,
+}
+
+impl ProxyServices {
+    /// Creates services with an empty plugin pipeline, no response cache, no budget limits,
+    /// no routes, client keys, credentials or prices and no span export; breaker changes go nowhere
+    pub fn new(captures: Arc<CaptureLog>) -> Self {
+        Self {
+            captures,
+            pipeline: Arc::new(Pipeline::new()),
+            cache: Arc::new(ResponseCache::disabled()),
+            budget: Arc::new(BudgetTracker::in_memory(
+                BudgetSettings::default(),
+                Arc::new(NoopSink),
+            )),
+            routes: Arc::new(RouteStore::in_memory(RoutingTable::default())),
+            breakers: Arc::new(CircuitBreakers::new(Arc::new(NoopSink))),
+            clients: Arc::new(ClientKeyStore::in_memory()),
+            credentials: Arc::new(CredentialStore::in_memory()),
+            usage: Arc::new(UsageLedger::in_memory(PriceStore::in_memory(
+                PricingTable::default(),
+            ))),
+            telemetry: Arc::new(TelemetryExporter::in_memory(TelemetryConfig::default())),
+        }
+    }
+
+    /// Replaces the span exporter
+    pub fn with_telemetry(mut self, telemetry: Arc<TelemetryExporter>) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+
+    /// Replaces the usage ledger
+    pub fn with_usage(mut self, usage: UsageLedger) -> Self {
+        self.usage = Arc::new(usage);
+        self
+    }
+
+    /// Replaces the credential store
+    pub fn with_credentials(mut self, credentials: CredentialStore) -> Self {
+        self.credentials = Arc::new(credentials);
+        self
+    }
+
+    /// Replaces the client keys
+    pub fn with_clients(mut self, clients: ClientKeyStore) -> Self {
+        self.clients = Arc::new(clients);
+        self
+    }
+
+    /// Replaces the circuit breakers
+    pub fn with_breakers(mut self, breakers: CircuitBreakers) -> Self {
+        self.breakers = Arc::new(breakers);
+        self
+    }
+
+    /// Replaces the routing table
+    pub fn with_routes(mut self, routes: RouteStore) -> Self {
+        self.routes = Arc::new(routes);
+        self
+    }
+
+    /// Replaces the budget tracker
+    pub fn with_budget(mut self, budget: BudgetTracker) -> Self {
+        self.budget = Arc::new(budget);
+        self
+    }
+
+    /// Replaces the response cache
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Arc::new(cache);
+        self
+    }
+}
+
+/// State shared by every request handler of a running server
+pub(crate) struct ProxyContext {
+    pub config: ProxyConfig,
+    pub client: reqwest::Client,
+    pub captures: Arc<CaptureLog>,
+    pub pipeline: Arc<Pipeline>,
+    pub cache: Arc<ResponseCache>,
+    pub budget: Arc<BudgetTracker>,
+    pub routes: Arc<RouteStore>,
+    pub breakers: Arc<CircuitBreakers>,
+    pub clients: Arc<ClientKeyStore>,
+    pub credentials: Arc<CredentialStore>,
+    pub usage: Arc<UsageLedger>,
+    pub telemetry: Arc<TelemetryExporter>,
+    pub requests: AtomicU64,
+}
+
+impl ProxyContext {
+    fn new(config: ProxyConfig, services: &ProxyServices) -> Result<Self, ProxyError> {
+        let client = upstream_client(&config).build()?;
+        Ok(Self::with_client(config, services, client))
+    }
+
+    /// Creates a context sending upstream requests through `client`
+    pub fn with_client(
+        config: ProxyConfig,
+        services: &ProxyServices,
+        client: reqwest::Client,
+    ) -> Self {
+        Self {
+            config,
+            client,
+            captures: services.captures.clone(),
+            pipeline: services.pipeline.clone(),
+            cache: services.cache.clone(),
+            budget: services.budget.clone(),
+            routes: services.routes.clone(),
+            breakers: services.breakers.clone(),
+            clients: services.clients.clone(),
+            credentials: services.credentials.clone(),
+            usage: services.usage.clone(),
+            telemetry: services.telemetry.clone(),
+            requests: AtomicU64::new(0),
+        }
+    }
+
+    /// Prices a finished call, charges it to the budgets, exports its span
+    /// and stores it in the capture log
+    pub fn record(&self, mut call: CapturedCall) {
+        self.usage.account(&mut call);
+        self.budget.charge(&mut call);
+        self.telemetry.record(&call);
+        self.captures.record(call);
+    }
+
+    /// The upstream from the proxy settings, serving every model without a route
+    pub fn default_upstream(&self) -> Upstream {
+        default_upstream(&self.config)
+    }
+
+    /// Sends a request to `upstream`, forwarding the client's end-to-end headers
+    pub async fn send(
+        &self,
+        upstream: &Upstream,
+        provider: Provider,
+        method: Method,
+        path_and_query: &str,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+
+        let mut forwarded = filter_headers(headers);
+        // Ask for an uncompressed body so captured responses can be parsed
+        forwarded.remove(header::ACCEPT_ENCODING);
+        let key = match &upstream.auth {
+            RouteAuth::Passthrough => None,
+            RouteAuth::None => {
+                for name in AUTH_HEADERS {
+                    forwarded.remove(*name);
+                }
+                None
+            }
+            RouteAuth::Key { key } => Some(key.clone()),
+            RouteAuth::Env { var } => std::env::var(var)
+                .map_err(|_| log::warn!("{} is not set for upstream {}", var, upstream.name))
+                .ok(),
+            RouteAuth::Credential { name } => self
+                .credentials
+                .get(name)
+                .map_err(|e| log::warn!("No key for upstream {}: {}", upstream.name, e))
+                .ok(),
+        };
+        if let Some((name, value)) = key.and_then(|key| provider.auth_header(&key)) {
+            forwarded.insert(name, value);
+        }
+
+        self.client
+            .request(method, upstream.endpoint(path_and_query))
+            .headers(forwarded)
+            .body(body)
+            .send()
+            .await
+    }
+}
+
+/// Client settings for upstream requests under `config`
+pub(crate) fn upstream_client(config: &ProxyConfig) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .read_timeout(Duration::from_secs(config.request_timeout_secs))
+}
+
+/// Describes the upstream from the proxy settings as a routing target
+pub fn default_upstream(config: &ProxyConfig) -> Upstream {
+    Upstream {
+        name: routing::DEFAULT_UPSTREAM.to_string(),
+        base_url: config.upstream_url.clone(),
+        kind: UpstreamKind::LiteLlm,
+        auth: match (&config.upstream_credential, &config.upstream_api_key) {
+            (Some(name), _) => RouteAuth::Credential { name: name.clone() },
+            (None, Some(key)) => RouteAuth::Key { key: key.clone() },
+            (None, None) => RouteAuth::Passthrough,
+        },
+    }
+}
+
+/// Copies all headers except hop-by-hop ones
+pub(crate) fn filter_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut filtered = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        if !is_hop_by_hop(name) {
+            filtered.append(name.clone(), value.clone());
+        }
+    }
+    filtered
+}
+
+fn is_hop_by_hop(name: &HeaderName) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name.as_str())
+}
+
+/// Builds a client response from an upstream status, headers and body
+pub(crate) fn relay_response(status: StatusCode, headers: &HeaderMap, body: Body) -> Response {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    *response.headers_mut() = filter_headers(headers);
+    response
+}
+
+fn router(context: Arc<ProxyContext>) -> Router {
+    Router::new()
+        .route("/v1/chat/completions", post(openai::chat_completions))
+        .route("/v1/completions", post(openai::completions))
+        .route("/v1/models", get(openai::models))
+        .route("/v1/messages", post(anthropic::messages))
+        .route("/v1/models/{model_action}", post(gemini::models_action))
+        .route("/v1beta/models/{model_action}", post(gemini::models_action))
+        .with_state(context)
+}
+
+/// Decides by path whether a decrypted request is captured
+pub(crate) type PathFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Serves traffic decrypted by [`crate::intercept`]: the API routes are
+/// captured as usual when `captured` allows the path, anything else is
+/// forwarded to the host untouched
+pub(crate) fn intercept_router(context: Arc<ProxyContext>, captured: PathFilter) -> Router {
+    let forward = context.clone();
+    router(context.clone())
+        .fallback(move |request: Request| passthrough(context.clone(), request))
+        .layer(middleware::from_fn(move |request: Request, next: Next| {
+            let (context, captured) = (forward.clone(), captured.clone());
+            async move {
+                if captured(request.uri().path()) {
+                    next.run(request).await
+                } else {
+                    passthrough(context, request).await
+                }
+            }
+        }))
+}
+
+async fn passthrough(context: Arc<ProxyContext>, request: Request) -> Response {
+    context.requests.fetch_add(1, Ordering::Relaxed);
+    let (parts, body) = request.into_parts();
+    let path_and_query = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+    let result = context
+        .client
+        .request(
+            parts.method,
+            context.config.upstream_endpoint(path_and_query),
+        )
+        .headers(filter_headers(&parts.headers))
+        .body(reqwest::Body::wrap_stream(body.into_data_stream()))
+        .send()
+        .await;
+    match result {
+        Ok(response) => relay_response(
+            response.status(),
+            &response.headers().clone(),
+            Body::from_stream(response.bytes_stream()),
+        ),
+        Err(e) => {
+            let mut response = Response::new(Body::from(e.to_string()));
+            *response.status_mut() = StatusCode::BAD_GATEWAY;
+            response
+        }
+    }
+}
+
+struct RunningProxy {
+    address: SocketAddr,
+    context: Arc<ProxyContext>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+/// Owns the proxy server lifecycle; managed as Tauri state
+pub struct ProxyServer {
+    config: Mutex<ProxyConfig>,
+    services: ProxyServices,
+    running: tokio::sync::Mutex<Option<RunningProxy>>,
+}
+
+impl ProxyServer {
+    /// Creates a stopped server with the given configuration
+    pub fn new(config: ProxyConfig, services: ProxyServices) -> Self {
+        Self {
+            config: Mutex::new(config),
+            services,
+            running: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Returns the current configuration
+    pub fn config(&self) -> ProxyConfig {
+        lock(&self.config).clone()
+    }
+
+    /// Returns the capture log calls are recorded into
+    pub fn captures(&self) -> Arc<CaptureLog> {
+        self.services.captures.clone()
+    }
+
+    /// Returns the plugin pipeline every request runs through
+    pub fn pipeline(&self) -> Arc<Pipeline> {
+        self.services.pipeline.clone()
+    }
+
+    /// Returns the response cache
+    pub fn cache(&self) -> Arc<ResponseCache> {
+        self.services.cache.clone()
+    }
+
+    /// Returns the token budget tracker
+    pub fn budget(&self) -> Arc<BudgetTracker> {
+        self.services.budget.clone()
+    }
+
+    /// Returns the routing table
+    pub fn routes(&self) -> Arc<RouteStore> {
+        self.services.routes.clone()
+    }
+
+    /// Returns the per-upstream circuit breakers
+    pub fn breakers(&self) -> Arc<CircuitBreakers> {
+        self.services.breakers.clone()
+    }
+
+    /// Returns the virtual keys of clients
+    pub fn clients(&self) -> Arc<ClientKeyStore> {
+        self.services.clients.clone()
+    }
+
+    /// Returns the encrypted credential store
+    pub fn credentials(&self) -> Arc<CredentialStore> {
+        self.services.credentials.clone()
+    }
+
+    /// Returns the token and cost ledger
+    pub fn usage(&self) -> Arc<UsageLedger> {
+        self.services.usage.clone()
+    }
+
+    /// Returns the OTLP span exporter
+    pub fn telemetry(&self) -> Arc<TelemetryExporter> {
+        self.services.telemetry.clone()
+    }
+
+    /// Starts the server, restarting it when already running.
+    /// When `config` is given it replaces the stored configuration.
+    pub async fn start(&self, config: Option<ProxyConfig>) -> Result<ProxyStatus, ProxyError> {
+        let config = config.unwrap_or_else(|| self.config());
+        config.validate()?;
+
+        let mut running = self.running.lock().await;
+        if let Some(previous) = running.take() {
+            shutdown(previous).await;
+        }
+
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, config.port))
+            .await
+            .map_err(|source| ProxyError::Bind {
+                port: config.port,
+                source,
+            })?;
+        let address = listener.local_addr().map_err(|source| ProxyError::Bind {
+            port: config.port,
+            source,
+        })?;
+
+        let context = Arc::new(ProxyContext::new(config.clone(), &self.services)?);
+        let app = router(context.clone());
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let _ = axum::serve(listener, app)
+                .with_graceful_shutdown(async {
+                    let _ = shutdown_rx.await;
+                })
+                .await;
+        });
+
+        *lock(&self.config) = config;
+        *running = Some(RunningProxy {
+            address,
+            context,
+            shutdown: shutdown_tx,
+            task,
+        });
+        Ok(status_of(&self.config(), running.as_ref()))
+    }
+
+    /// Stops the server if it is running
+    pub async fn stop(&self) -> ProxyStatus {
+        let mut running = self.running.lock().await;
+        if let Some(previous) = running.take() {
+            shutdown(previous).await;
+        }
+        status_of(&self.config(), None)
+    }
+
+    /// Reports whether the server is running and where
+    pub async fn status(&self) -> ProxyStatus {
+        let running = self.running.lock().await;
+        status_of(&self.config(), running.as_ref())
+    }
+}
+
+async fn shutdown(proxy: RunningProxy) {
+    let _ = proxy.shutdown.send(());
+    let mut task = proxy.task;
+    if tokio::time::timeout(SHUTDOWN_GRACE, &mut task)
+        .await
+        .is_err()
+    {
+        task.abort();
+    }
+}
+
+fn status_of(config: &ProxyConfig, running: Option<&RunningProxy>) -> ProxyStatus {
+    match running {
+        Some(proxy) => ProxyStatus {
+            running: true,
+            address: Some(proxy.address.to_string()),
+            port: proxy.address.port(),
+            upstream_url: proxy.context.config.upstream_url.clone(),
+            requests_served: proxy.context.requests.load(Ordering::Relaxed),
+        },
+        None => ProxyStatus {
+            running: false,
+            address: None,
+            port: config.port,
+            upstream_url: config.upstream_url.clone(),
+            requests_served: 0,
+        },
+    }
+}
+
+/// Helpers for spinning up stand-in upstreams in tests
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use crate::events::NoopSink;
+    use axum::{response::IntoResponse, Json};
+    use serde_json::{json, Value};
+
+    /// Serves `app` on a free localhost port and returns its base URL
+    pub async fn spawn_upstream(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        format!("http://{}", address)
+    }
+
+    /// Upstream answering chat completions with `"<name> <model>"`, streamed when asked
+    /// for; answers also echo the model and the `authorization` header
+    pub fn echo_upstream(name: &'static str) -> Router {
+        let answer = move |headers: HeaderMap, Json(body): Json<Value>| async move {
+            let content = format!("{} {}", name, body["model"].as_str().unwrap_or_default());
+            if body["stream"] == true {
+                let chunk = json!({"choices": [{"delta": {"content": content}}]});
+                return Response::builder()
+                    .header("content-type", "text/event-stream")
+                    .body(Body::from(format!("data: {}\n\ndata: [DONE]\n\n", chunk)))
+                    .unwrap();
+            }
+            let authorization = headers.get("authorization").map(|v| v.to_str().unwrap());
+            Json(json!({
+                "model": body["model"],
+                "authorization": authorization,
+                "choices": [{"message": {"role": "assistant", "content": content}}],
+            }))
+            .into_response()
+        };
+        Router::new().route("/v1/chat/completions", post(answer))
+    }
+
+    /// Posts `body` as a chat completion to the proxy at `base`, along with `headers`
+    pub async fn chat(base: &str, body: Value, headers: &[(&str, &str)]) -> reqwest::Response {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", base))
+            .json(&body);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.send().await.unwrap()
+    }
+
+    /// Services backed by an in-memory capture log
+    pub fn services() -> ProxyServices {
+        ProxyServices::new(Arc::new(CaptureLog::new(100, Arc::new(NoopSink))))
+    }
+
+    /// Starts a proxy on a free port forwarding to `upstream_url`
+    pub async fn start_proxy(upstream_url: &str) -> (ProxyServer, String) {
+        start_proxy_with(upstream_url, services()).await
+    }
+
+    /// Starts a proxy with the given services on a free port
+    pub async fn start_proxy_with(
+        upstream_url: &str,
+        services: ProxyServices,
+    ) -> (ProxyServer, String) {
+        let server = ProxyServer::new(
+            ProxyConfig {
+                port: 0,
+                upstream_url: upstream_url.to_string(),
+                ..ProxyConfig::default()
+            },
+            services,
+        );
+        let status = server.start(None).await.unwrap();
+        let base = format!("http://{}", status.address.unwrap());
+        (server, base)
+    }
+
+    /// Waits until the proxy has recorded a call and returns the newest one
+    pub async fn wait_for_capture(server: &ProxyServer) -> crate::capture::CapturedCall {
+        for _ in 0..200 {
+            if let Some(call) = server.captures().recent(1).pop() {
+                return call;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no call was captured");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::{json, Value};
+
+    mod proxy_config_tests {
+        use super::*;
+
+        #[test]
+        fn test_default_config() {
+            let config = ProxyConfig::default();
+            assert_eq!(config.port, DEFAULT_PORT);
+            assert_eq!(config.upstream_url, DEFAULT_UPSTREAM_URL);
+            assert!(config.auto_start);
+            assert!(config.validate().is_ok());
+        }
+
+        #[test]
+        fn test_validate_rejects_bad_urls() {
+            let mut config = ProxyConfig {
+                upstream_url: "not a url".to_string(),
+                ..ProxyConfig::default()
+            };
+            assert!(config.validate().is_err());
+
+            config.upstream_url = "ftp://example.com".to_string();
+            assert!(config.validate().is_err());
+        }
+
+        #[test]
+        fn test_validate_rejects_zero_timeout() {
+            let config = ProxyConfig {
+                request_timeout_secs: 0,
+                ..ProxyConfig::default()
+            };
+            assert!(config.validate().is_err());
+        }
+
+        #[test]
+        fn test_upstream_endpoint_joins_slashes() {
+            let config = ProxyConfig {
+                upstream_url: "http://localhost:4213/".to_string(),
+                ..ProxyConfig::default()
+            };
+            assert_eq!(
+                config.upstream_endpoint("/v1/models"),
+                "http://localhost:4213/v1/models"
+            );
+        }
+
+        #[test]
+        fn test_api_key_moves_into_the_credential_store() {
+            let mut config: ProxyConfig =
+                serde_json::from_value(json!({"upstream_api_key": "sk-upstream"})).unwrap();
+            let credentials = CredentialStore::in_memory();
+            config.store_api_key(&credentials).unwrap();
+            assert_eq!(config.upstream_api_key, None);
+            assert_eq!(
+                config.upstream_credential.as_deref(),
+                Some(UPSTREAM_KEY_CREDENTIAL)
+            );
+            assert_eq!(
+                credentials.get(UPSTREAM_KEY_CREDENTIAL).unwrap(),
+                "sk-upstream"
+            );
+
+            // Never written back to the settings store
+            config.upstream_api_key = Some("sk-other".to_string());
+            let saved = serde_json::to_value(&config).unwrap();
+            assert!(saved.get("upstream_api_key").is_none());
+        }
+
+        #[test]
+        fn test_deserialize_missing_fields_uses_defaults() {
+            let config: ProxyConfig = serde_json::from_str(r#"{"port": 9000}"#).unwrap();
+            assert_eq!(config.port, 9000);
+            assert_eq!(config.upstream_url, DEFAULT_UPSTREAM_URL);
+        }
+    }
+
+    mod header_tests {
+        use super::*;
+
+        #[test]
+        fn test_filter_headers_drops_hop_by_hop() {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive"));
+            headers.insert(header::HOST, HeaderValue::from_static("localhost"));
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from_static("12"));
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer x"));
+
+            let filtered = filter_headers(&headers);
+            assert_eq!(filtered.len(), 1);
+            assert!(filtered.contains_key(header::AUTHORIZATION));
+        }
+    }
+
+    mod lifecycle_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_start_and_stop() {
+            let (server, _) = start_proxy("http://127.0.0.1:9").await;
+            let status = server.status().await;
+            assert!(status.running);
+            assert_ne!(status.port, 0);
+
+            let status = server.stop().await;
+            assert!(!status.running);
+            assert!(status.address.is_none());
+        }
+
+        #[tokio::test]
+        async fn test_start_rejects_invalid_config() {
+            let server = ProxyServer::new(ProxyConfig::default(), services());
+            let config = ProxyConfig {
+                upstream_url: "nope".to_string(),
+                ..ProxyConfig::default()
+            };
+            assert!(matches!(
+                server.start(Some(config)).await,
+                Err(ProxyError::InvalidConfig(_))
+            ));
+            assert!(!server.status().await.running);
+        }
+
+        #[tokio::test]
+        async fn test_upstream_unreachable_returns_bad_gateway() {
+            let (server, base) = start_proxy("http://127.0.0.1:9").await;
+            let response = reqwest::Client::new()
+                .post(format!("{}/v1/chat/completions", base))
+                .json(&json!({"model": "gpt-4o", "messages": []}))
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["error"]["type"], "upstream_error");
+            assert_eq!(server.captures().recent(1)[0].status, 502);
+        }
+    }
+}
@@ -0,0 +1,600 @@
+//! OpenAI-compatible routes: chat completions, legacy completions and models
+
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, Method, Uri},
+    response::Response,
+};
+use serde_json::Value;
+
+use super::relay::{passthrough, relay, Provider, ProviderApi};
+use super::stream::{SseEvent, StreamAccumulator};
+use super::ProxyContext;
+use crate::capture::{CapturedCall, Message, MessageRole, ModelParameters, ToolCall, Usage};
+
+/// Handles `POST /v1/chat/completions`
+pub(crate) async fn chat_completions(
+    State(context): State<Arc<ProxyContext>>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    relay(context, &OpenAiApi::Chat, uri, headers, body).await
+}
+
+/// Handles `POST /v1/completions`
+pub(crate) async fn completions(
+    State(context): State<Arc<ProxyContext>>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    relay(context, &OpenAiApi::Completions, uri, headers, body).await
+}
+
+/// Handles `GET /v1/models` by passing the upstream list through untouched
+pub(crate) async fn models(
+    State(context): State<Arc<ProxyContext>>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    passthrough(
+        &context,
+        Provider::OpenAi,
+        Method::GET,
+        &uri,
+        &headers,
+        Bytes::new(),
+    )
+    .await
+}
+
+/// The two OpenAI generation endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OpenAiApi {
+    Chat,
+    Completions,
+}
+
+impl ProviderApi for OpenAiApi {
+    fn provider(&self) -> Provider {
+        Provider::OpenAi
+    }
+
+    fn capture_request(&self, call: &mut CapturedCall, _uri: &Uri, request: &Value) {
+        match self {
+            OpenAiApi::Chat => capture_chat_request(call, request),
+            OpenAiApi::Completions => capture_completion_request(call, request),
+        }
+    }
+
+    fn capture_response(&self, call: &mut CapturedCall, response: &Value) {
+        match self {
+            OpenAiApi::Chat => capture_chat_response(call, response),
+            OpenAiApi::Completions => capture_completion_response(call, response),
+        }
+    }
+
+    fn accumulator(&self) -> Box<dyn StreamAccumulator> {
+        Box::new(OpenAiAccumulator::default())
+    }
+}
+
+/// Accumulates OpenAI chat and legacy completion chunks
+#[derive(Debug, Default)]
+pub(crate) struct OpenAiAccumulator {
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    finish_reason: Option<String>,
+    usage: Option<Usage>,
+}
+
+impl OpenAiAccumulator {
+    fn merge_tool_call(&mut self, delta: &Value) {
+        let index = delta.get("index").and_then(Value::as_u64).unwrap_or(0);
+        // Calls are numbered in order, so a gap means a malformed upstream
+        let Some(index) = usize::try_from(index)
+            .ok()
+            .filter(|index| *index <= self.tool_calls.len())
+        else {
+            log::warn!("Ignoring tool call delta with out-of-order index {}", index);
+            return;
+        };
+        if index == self.tool_calls.len() {
+            self.tool_calls.push(ToolCall::function("", "", ""));
+        }
+        let call = &mut self.tool_calls[index];
+        if let Some(id) = delta.get("id").and_then(Value::as_str) {
+            call.id = id.to_string();
+        }
+        if let Some(name) = delta.pointer("/function/name").and_then(Value::as_str) {
+            call.function.name.push_str(name);
+        }
+        if let Some(arguments) = delta.pointer("/function/arguments").and_then(Value::as_str) {
+            call.function.arguments.push_str(arguments);
+        }
+    }
+}
+
+impl StreamAccumulator for OpenAiAccumulator {
+    fn on_event(&mut self, event: &SseEvent) {
+        if event.data == "[DONE]" {
+            return;
+        }
+        let Some(chunk) = event.json() else {
+            return;
+        };
+        if let Some(choice) = chunk.pointer("/choices/0") {
+            let text = choice
+                .pointer("/delta/content")
+                .or_else(|| choice.get("text"))
+                .and_then(Value::as_str);
+            if let Some(text) = text {
+                self.content.push_str(text);
+            }
+            if let Some(deltas) = choice
+                .pointer("/delta/tool_calls")
+                .and_then(Value::as_array)
+            {
+                for delta in deltas {
+                    self.merge_tool_call(delta);
+                }
+            }
+            if let Some(reason) = choice.get("finish_reason").and_then(Value::as_str) {
+                self.finish_reason = Some(reason.to_string());
+            }
+        }
+        if let Some(usage) = chunk.get("usage").filter(|u| !u.is_null()) {
+            self.usage = Some(Usage::new(
+                usage
+                    .get("prompt_tokens")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(0),
+                usage
+                    .get("completion_tokens")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(0),
+            ));
+        }
+    }
+
+    fn finish(self: Box<Self>, call: &mut CapturedCall) {
+        call.response.content = (!self.content.is_empty()).then_some(self.content);
+        call.response.tool_calls = self.tool_calls;
+        call.response.finish_reason = self.finish_reason;
+        call.usage = self.usage;
+    }
+}
+
+/// Flattens a string or an array of content parts into plain text
+pub(crate) fn content_text(content: &Value) -> Option<String> {
+    match content {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(parts) => {
+            let texts: Vec<&str> = parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(Value::as_str))
+                .collect();
+            if texts.is_empty() {
+                None
+            } else {
+                Some(texts.join("\n"))
+            }
+        }
+        _ => None,
+    }
+}
+
+fn parse_tool_calls(value: Option<&Value>) -> Vec<ToolCall> {
+    value
+        .and_then(Value::as_array)
+        .map(|calls| {
+            calls
+                .iter()
+                .map(|call| {
+                    ToolCall::function(
+                        call.get("id").and_then(Value::as_str).unwrap_or_default(),
+                        call.pointer("/function/name")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default(),
+                        call.pointer("/function/arguments")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default(),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_message(value: &Value) -> Message {
+    Message {
+        role: MessageRole::parse(value.get("role").and_then(Value::as_str).unwrap_or("user")),
+        content: value.get("content").and_then(content_text),
+        name: value
+            .get("name")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        tool_calls: parse_tool_calls(value.get("tool_calls")),
+        tool_call_id: value
+            .get("tool_call_id")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    }
+}
+
+/// Reads the sampling parameters shared by both endpoints
+pub(crate) fn parse_parameters(request: &Value) -> Option<ModelParameters> {
+    let parameters = ModelParameters {
+        temperature: request.get("temperature").and_then(Value::as_f64),
+        top_p: request.get("top_p").and_then(Value::as_f64),
+        max_tokens: request
+            .get("max_tokens")
+            .or_else(|| request.get("max_completion_tokens"))
+            .and_then(Value::as_u64),
+        stop: request.get("stop").filter(|v| !v.is_null()).cloned(),
+        seed: request.get("seed").and_then(Value::as_i64),
+    };
+    (!parameters.is_empty()).then_some(parameters)
+}
+
+fn parse_usage(response: &Value) -> Option<Usage> {
+    let usage = response.get("usage")?;
+    let prompt = usage
+        .get("prompt_tokens")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let completion = usage
+        .get("completion_tokens")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    Some(Usage::new(prompt, completion))
+}
+
+fn capture_common(call: &mut CapturedCall, request: &Value) {
+    call.model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    call.parameters = parse_parameters(request);
+    call.stream = request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+}
+
+/// Fills the request side of a call from a chat completions body
+pub(crate) fn capture_chat_request(call: &mut CapturedCall, request: &Value) {
+    capture_common(call, request);
+    call.messages = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .map(|messages| messages.iter().map(parse_message).collect())
+        .unwrap_or_default();
+    call.tools = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+}
+
+/// Fills the request side of a call from a legacy completions body
+pub(crate) fn capture_completion_request(call: &mut CapturedCall, request: &Value) {
+    capture_common(call, request);
+    call.messages = match request.get("prompt") {
+        Some(Value::String(prompt)) => vec![Message::text(MessageRole::User, prompt.clone())],
+        Some(Value::Array(prompts)) => prompts
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|prompt| Message::text(MessageRole::User, prompt))
+            .collect(),
+        _ => Vec::new(),
+    };
+}
+
+/// Fills the response side of a call from a chat completions body
+pub(crate) fn capture_chat_response(call: &mut CapturedCall, response: &Value) {
+    let choice = response.pointer("/choices/0");
+    let message = choice.and_then(|c| c.get("message"));
+    call.response.content = message
+        .and_then(|m| m.get("content"))
+        .and_then(content_text);
+    call.response.tool_calls = parse_tool_calls(message.and_then(|m| m.get("tool_calls")));
+    call.response.finish_reason = choice
+        .and_then(|c| c.get("finish_reason"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    call.usage = parse_usage(response);
+}
+
+/// Fills the response side of a call from a legacy completions body
+pub(crate) fn capture_completion_response(call: &mut CapturedCall, response: &Value) {
+    let choice = response.pointer("/choices/0");
+    call.response.content = choice
+        .and_then(|c| c.get("text"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    call.response.finish_reason = choice
+        .and_then(|c| c.get("finish_reason"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    call.usage = parse_usage(response);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::testing::{spawn_upstream, start_proxy};
+    use axum::{http::StatusCode, routing::get, routing::post, Json, Router};
+    use serde_json::json;
+
+    fn chat_response() -> Value {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "read_file", "arguments": "{\"path\":\"a.rs\"}"}
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 5, "total_tokens": 17}
+        })
+    }
+
+    mod capture_tests {
+        use super::*;
+
+        #[test]
+        fn test_capture_chat_request() {
+            let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+            capture_chat_request(
+                &mut call,
+                &json!({
+                    "model": "gpt-4o",
+                    "temperature": 0,
+                    "max_completion_tokens": 64,
+                    "messages": [
+                        {"role": "system", "content": "Be brief"},
+                        {"role": "user", "content": [
+                            {"type": "text", "text": "Hello"},
+                            {"type": "image_url", "image_url": {"url": "data:"}}
+                        ]},
+                        {"role": "tool", "tool_call_id": "call_1", "content": "42"}
+                    ],
+                    "tools": [{"type": "function", "function": {"name": "read_file"}}]
+                }),
+            );
+
+            assert_eq!(call.model, "gpt-4o");
+            assert_eq!(call.messages.len(), 3);
+            assert_eq!(call.messages[0].role, MessageRole::System);
+            assert_eq!(call.messages[1].content.as_deref(), Some("Hello"));
+            assert_eq!(call.messages[2].tool_call_id.as_deref(), Some("call_1"));
+            assert_eq!(call.tools.len(), 1);
+            let parameters = call.parameters.unwrap();
+            assert_eq!(parameters.temperature, Some(0.0));
+            assert_eq!(parameters.max_tokens, Some(64));
+            assert!(!call.stream);
+        }
+
+        #[test]
+        fn test_capture_chat_response_with_tool_calls() {
+            let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+            capture_chat_response(&mut call, &chat_response());
+
+            assert_eq!(call.response.content, None);
+            assert_eq!(call.response.tool_calls[0].function.name, "read_file");
+            assert_eq!(call.response.finish_reason.as_deref(), Some("tool_calls"));
+            assert_eq!(call.usage, Some(Usage::new(12, 5)));
+        }
+
+        #[test]
+        fn test_capture_completion_prompts() {
+            let mut call = CapturedCall::new("openai", "/v1/completions");
+            capture_completion_request(&mut call, &json!({"model": "m", "prompt": ["a", "b"]}));
+            assert_eq!(call.messages.len(), 2);
+
+            capture_completion_response(
+                &mut call,
+                &json!({"choices": [{"text": "done", "finish_reason": "stop"}]}),
+            );
+            assert_eq!(call.response.content.as_deref(), Some("done"));
+            assert_eq!(call.usage, None);
+        }
+
+        #[test]
+        fn test_parse_parameters_empty() {
+            assert_eq!(parse_parameters(&json!({"model": "m"})), None);
+        }
+    }
+
+    mod openai_accumulator_tests {
+        use super::*;
+
+        fn event(value: Value) -> SseEvent {
+            SseEvent {
+                event: None,
+                data: value.to_string(),
+            }
+        }
+
+        #[test]
+        fn test_rebuilds_content_and_usage() {
+            let mut accumulator = Box::new(OpenAiAccumulator::default());
+            accumulator.on_event(&event(json!({"choices": [{"delta": {"content": "Hel"}}]})));
+            accumulator.on_event(&event(
+                json!({"choices": [{"delta": {"content": "lo"}, "finish_reason": "stop"}]}),
+            ));
+            accumulator.on_event(&event(
+                json!({"choices": [], "usage": {"prompt_tokens": 4, "completion_tokens": 2}}),
+            ));
+            accumulator.on_event(&SseEvent {
+                event: None,
+                data: "[DONE]".to_string(),
+            });
+
+            let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+            accumulator.finish(&mut call);
+            assert_eq!(call.response.content.as_deref(), Some("Hello"));
+            assert_eq!(call.response.finish_reason.as_deref(), Some("stop"));
+            assert_eq!(call.usage, Some(Usage::new(4, 2)));
+        }
+
+        #[test]
+        fn test_merges_tool_call_deltas_by_index() {
+            let mut accumulator = Box::new(OpenAiAccumulator::default());
+            accumulator.on_event(&event(json!({"choices": [{"delta": {"tool_calls": [
+                {"index": 0, "id": "call_1", "function": {"name": "read", "arguments": "{\"pa"}}
+            ]}}]})));
+            accumulator.on_event(&event(json!({"choices": [{"delta": {"tool_calls": [
+                {"index": 0, "function": {"arguments": "th\":1}"}},
+                {"index": 1, "id": "call_2", "function": {"name": "write", "arguments": "{}"}}
+            ]}}]})));
+
+            let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+            accumulator.finish(&mut call);
+            assert_eq!(call.response.content, None);
+            assert_eq!(call.response.tool_calls.len(), 2);
+            assert_eq!(
+                call.response.tool_calls[0].function.arguments,
+                "{\"path\":1}"
+            );
+            assert_eq!(call.response.tool_calls[1].function.name, "write");
+        }
+
+        #[test]
+        fn test_ignores_tool_call_indices_past_the_next() {
+            let mut accumulator = Box::new(OpenAiAccumulator::default());
+            accumulator.on_event(&event(json!({"choices": [{"delta": {"tool_calls": [
+                {"index": 4294967295u64, "id": "call_1", "function": {"name": "read"}},
+                {"index": 0, "id": "call_2", "function": {"name": "write"}},
+                {"index": 2, "function": {"arguments": "{}"}}
+            ]}}]})));
+
+            let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+            accumulator.finish(&mut call);
+            assert_eq!(call.response.tool_calls.len(), 1);
+            assert_eq!(call.response.tool_calls[0].id, "call_2");
+            assert_eq!(call.response.tool_calls[0].function.arguments, "");
+        }
+
+        #[test]
+        fn test_legacy_completion_text() {
+            let mut accumulator = Box::new(OpenAiAccumulator::default());
+            accumulator.on_event(&event(json!({"choices": [{"text": "a"}]})));
+            accumulator.on_event(&event(json!({"choices": [{"text": "b"}]})));
+
+            let mut call = CapturedCall::new("openai", "/v1/completions");
+            accumulator.finish(&mut call);
+            assert_eq!(call.response.content.as_deref(), Some("ab"));
+        }
+    }
+
+    mod route_tests {
+        use super::*;
+
+        async fn upstream() -> String {
+            let app = Router::new()
+                .route(
+                    "/v1/chat/completions",
+                    post(|headers: HeaderMap, Json(body): Json<Value>| async move {
+                        assert!(headers.get("accept-encoding").is_none());
+                        assert_eq!(body["model"], "gpt-4o");
+                        Json(chat_response())
+                    }),
+                )
+                .route(
+                    "/v1/completions",
+                    post(|| async {
+                        (
+                            StatusCode::TOO_MANY_REQUESTS,
+                            Json(json!({"error": {"message": "slow down"}})),
+                        )
+                    }),
+                )
+                .route(
+                    "/v1/models",
+                    get(|| async { Json(json!({"data": [{"id": "gpt-4o"}]})) }),
+                );
+            spawn_upstream(app).await
+        }
+
+        #[tokio::test]
+        async fn test_chat_completions_forwarded_and_captured() {
+            let (server, base) = start_proxy(&upstream().await).await;
+            let response = reqwest::Client::new()
+                .post(format!("{}/v1/chat/completions", base))
+                .header("accept-encoding", "gzip")
+                .json(&json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "hi"}]}))
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body, chat_response());
+
+            let call = &server.captures().recent(1)[0];
+            assert_eq!(call.model, "gpt-4o");
+            assert_eq!(call.status, 200);
+            assert_eq!(call.usage, Some(Usage::new(12, 5)));
+            assert_eq!(server.status().await.requests_served, 1);
+        }
+
+        #[tokio::test]
+        async fn test_upstream_errors_are_relayed() {
+            let (server, base) = start_proxy(&upstream().await).await;
+            let response = reqwest::Client::new()
+                .post(format!("{}/v1/completions", base))
+                .json(&json!({"model": "m", "prompt": "x"}))
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            let call = &server.captures().recent(1)[0];
+            assert_eq!(call.error.as_deref(), Some("slow down"));
+        }
+
+        #[tokio::test]
+        async fn test_invalid_json_is_rejected() {
+            let (server, base) = start_proxy(&upstream().await).await;
+            let response = reqwest::Client::new()
+                .post(format!("{}/v1/chat/completions", base))
+                .body("{not json")
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert!(server.captures().is_empty());
+        }
+
+        #[tokio::test]
+        async fn test_models_passthrough() {
+            let (server, base) = start_proxy(&upstream().await).await;
+            let body: Value = reqwest::get(format!("{}/v1/models", base))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+
+            assert_eq!(body["data"][0]["id"], "gpt-4o");
+            assert!(server.captures().is_empty());
+        }
+    }
+}
@@ -0,0 +1,1626 @@
+//! Provider-agnostic request relay
+//!
+//! Every LLM route follows the same steps: parse the body, capture the
+//! request, forward it, then either buffer or stream the answer back while
+//! capturing it. Only the wire format differs per provider, which is what
+//! [`ProviderApi`] describes. Plugin hooks from [`crate::pipeline`] run
+//! around each of those steps.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use futures_util::StreamExt;
+
+use super::stream::{is_event_stream, relay_stream, StreamAccumulator, StreamSource};
+use super::{failover, ProxyContext};
+use crate::budget::{self, Downgrade};
+use crate::cache::{self, CacheMode, CacheSlot, CachedResponse};
+use crate::capture::CapturedCall;
+use crate::clients;
+use crate::pipeline::{HookOutcome, PluginContext, PluginRequest, PluginResponse, UpstreamFailure};
+use crate::plugins::redact;
+use crate::routing::{self, ResolvedRoute};
+
+/// LLM API family a route speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    OpenAi,
+    Anthropic,
+    Gemini,
+}
+
+impl Provider {
+    /// Name recorded on captured calls
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Provider::OpenAi => "openai",
+            Provider::Anthropic => "anthropic",
+            Provider::Gemini => "gemini",
+        }
+    }
+
+    /// Header carrying an API key in this provider's convention
+    pub fn auth_header(self, key: &str) -> Option<(HeaderName, HeaderValue)> {
+        let (name, value) = match self {
+            Provider::OpenAi => ("authorization", format!("Bearer {}", key)),
+            Provider::Anthropic => ("x-api-key", key.to_string()),
+            Provider::Gemini => ("x-goog-api-key", key.to_string()),
+        };
+        Some((HeaderName::from_static(name), value.parse().ok()?))
+    }
+
+    /// Builds an error response in this provider's format
+    pub fn error_response(
+        self,
+        status: StatusCode,
+        kind: &str,
+        message: impl std::fmt::Display,
+    ) -> Response {
+        let message = message.to_string();
+        let body = match self {
+            Provider::OpenAi => json!({"error": {"message": message, "type": kind}}),
+            Provider::Anthropic => json!({
+                "type": "error",
+                "error": {"type": kind, "message": message},
+            }),
+            Provider::Gemini => json!({
+                "error": {"code": status.as_u16(), "message": message, "status": kind},
+            }),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Wire-format hooks for one provider endpoint
+pub(crate) trait ProviderApi: Send + Sync {
+    /// API family of the endpoint
+    fn provider(&self) -> Provider;
+
+    /// Fills the request side of a call
+    fn capture_request(&self, call: &mut CapturedCall, uri: &Uri, request: &Value);
+
+    /// Fills the response side of a call from a buffered body
+    fn capture_response(&self, call: &mut CapturedCall, response: &Value);
+
+    /// Creates the accumulator used for streamed answers
+    fn accumulator(&self) -> Box<dyn StreamAccumulator>;
+
+    /// Prepares provider-specific headers before forwarding
+    fn prepare_headers(&self, _headers: &mut HeaderMap) {}
+}
+
+/// Extracts the error message from an upstream error body, falling back to the raw text
+pub(crate) fn upstream_error_message(parsed: Option<&Value>, raw: &[u8]) -> String {
+    parsed
+        .and_then(|v| {
+            v.pointer("/error/message")
+                .or_else(|| v.pointer("/0/error/message"))
+        })
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| String::from_utf8_lossy(raw).into_owned())
+}
+
+/// Returns the path and query of a request URI
+pub(crate) fn path_and_query(uri: &Uri) -> String {
+    uri.path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| uri.path().to_string())
+}
+
+/// Names who a cached answer belongs to: the client key, or the client name
+/// without one, and the upstream that gave it
+fn cache_scope(ctx: &PluginContext, call: &CapturedCall, route: &ResolvedRoute) -> String {
+    let client = match ctx
+        .metadata
+        .get(clients::METADATA_KEY)
+        .and_then(Value::as_str)
+    {
+        Some(id) => format!("key:{}", id),
+        None => format!("client:{}", call.client.as_deref().unwrap_or_default()),
+    };
+    format!("{}\0{}", client, route.upstream)
+}
+
+/// Forwards one LLM request through the plugin pipeline and captures it
+pub(crate) async fn relay(
+    context: Arc<ProxyContext>,
+    api: &dyn ProviderApi,
+    mut uri: Uri,
+    mut headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let started = Instant::now();
+    let provider = api.provider();
+    let request: Value = match serde_json::from_slice(&body) {
+        Ok(value) => value,
+        Err(e) => {
+            return provider.error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                format!("Request body is not valid JSON: {}", e),
+            )
+        }
+    };
+    // Cache directives are meant for the proxy, not the upstream
+    let cache_mode = CacheMode::from_headers(&headers);
+    let cache_ttl = cache::ttl_override(&headers);
+    headers.remove(cache::CACHE_HEADER);
+    headers.remove(cache::CACHE_TTL_HEADER);
+    let key = match context.clients.admit(&mut headers, &mut uri) {
+        Ok(key) => key,
+        Err(message) => {
+            return provider.error_response(
+                StatusCode::UNAUTHORIZED,
+                "authentication_error",
+                message,
+            )
+        }
+    };
+    let client = key
+        .as_ref()
+        .map_or_else(|| budget::client_id(&headers), |key| key.name.clone());
+    headers.remove(budget::CLIENT_HEADER);
+
+    let mut call = CapturedCall::new(provider.as_str(), uri.path());
+    call.client = Some(client);
+    api.capture_request(&mut call, &uri, &request);
+    let mut ctx = PluginContext::new(&call.id, provider, uri.path(), &call.model);
+    if let Some(key) = key {
+        ctx.metadata
+            .insert(clients::METADATA_KEY.to_string(), json!(key.id));
+        ctx.plugins = key.plugins.clone();
+        if !key.allows_model(&call.model) {
+            let message = format!("Client {} may not use model {}", key.name, call.model);
+            return refuse(
+                &context,
+                api,
+                call,
+                ctx,
+                started,
+                StatusCode::FORBIDDEN,
+                "model_not_allowed",
+                message,
+            );
+        }
+    }
+
+    ctx.local = context
+        .routes
+        .resolve(&call.model, &context.default_upstream())
+        .is_ok_and(|route| route.is_local());
+
+    // Keep the client's exact bytes unless a plugin rewrote the body
+    let plugins_active = !context.pipeline.is_idle(&ctx);
+    let original = plugins_active.then(|| request.clone());
+    let mut plugin_request = PluginRequest {
+        headers,
+        body: request,
+    };
+    let outcome = context
+        .pipeline
+        .run_request(&mut ctx, &mut plugin_request)
+        .await;
+    if let Some(response) = conclude(api, &mut call, outcome) {
+        return finish(&context, call, ctx, started, response);
+    }
+    let PluginRequest {
+        mut headers,
+        body: mut request,
+    } = plugin_request;
+    let requested = request
+        .get("model")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    let downgrade = downgrade(&context, &call, &mut request);
+    let route = match resolve_route(&context, provider, &call, &mut request) {
+        Ok(route) => route,
+        Err(message) => {
+            let status = StatusCode::BAD_REQUEST;
+            return refuse(
+                &context,
+                api,
+                call,
+                ctx,
+                started,
+                status,
+                "routing_error",
+                message,
+            );
+        }
+    };
+    // Plugins and downgrades may have moved the request off the route it was
+    // exempted from redaction for
+    let local = route.is_local();
+    if !local && redact::exempted(&ctx) {
+        let message = format!(
+            "Model {} skipped redaction as a local model but is routed to upstream {}",
+            route.requested_model, route.upstream
+        );
+        return refuse(
+            &context,
+            api,
+            call,
+            ctx,
+            started,
+            StatusCode::BAD_REQUEST,
+            "routing_error",
+            message,
+        );
+    }
+    ctx.local = local;
+    let renamed = request.get("model").and_then(Value::as_str) != requested.as_deref();
+    let body = if renamed || original.is_some_and(|original| original != request) {
+        api.capture_request(&mut call, &uri, &request);
+        ctx.model = call.model.clone();
+        Bytes::from(request.to_string())
+    } else {
+        body
+    };
+    if renamed {
+        call.requested_model = requested;
+    }
+    if let Some(downgrade) = downgrade {
+        ctx.metadata
+            .insert(budget::DOWNGRADE_METADATA_KEY.to_string(), json!(downgrade));
+    }
+    ctx.metadata
+        .insert(routing::METADATA_KEY.to_string(), json!(route));
+    api.prepare_headers(&mut headers);
+
+    if !context.cache.config().enabled {
+        return forward(
+            context, api, uri, call, ctx, started, route, headers, body, None,
+        )
+        .await;
+    }
+    // Sampled answers differ per call, so replaying one would be wrong
+    let cache_mode = if context.cache.admits(call.parameters.as_ref()) {
+        cache_mode
+    } else {
+        CacheMode::Bypass
+    };
+    let path = path_and_query(&uri);
+    let scope = cache_scope(&ctx, &call, &route);
+    let key = cache::request_key(provider, &scope, &path, &headers, &request);
+    let mut hit = if cache_mode.reads() {
+        context.cache.lookup(&key)
+    } else {
+        None
+    };
+    // Exact misses fall back to the answer of the most similar earlier prompt
+    let mut similar = None;
+    let mut query = None;
+    if let (None, Some(semantic)) = (&hit, context.cache.semantic()) {
+        if cache_mode != CacheMode::Bypass {
+            query = semantic
+                .query(
+                    provider,
+                    &scope,
+                    &path,
+                    &headers,
+                    &request,
+                    &call.model,
+                    &call.messages,
+                )
+                .await;
+        }
+        if let (true, Some(query)) = (cache_mode.reads(), &query) {
+            if let Some((found, entry)) = context.cache.lookup_similar(query) {
+                similar = Some(found);
+                hit = Some(entry);
+            }
+        }
+    }
+    let outcome = match (&hit, &similar, cache_mode) {
+        (Some(_), Some(_), _) => "semantic-hit",
+        (Some(_), None, _) => "hit",
+        (None, _, CacheMode::Bypass) => "bypass",
+        (None, _, _) => "miss",
+    };
+    ctx.metadata
+        .insert(cache::METADATA_KEY.to_string(), json!(outcome));
+    let entry = similar
+        .as_ref()
+        .map_or(key.as_str(), |found| found.key.as_str());
+    let mut answer_headers = HeaderMap::new();
+    answer_headers.insert(cache::CACHE_HEADER, HeaderValue::from_static(outcome));
+    if hit.is_some() {
+        ctx.metadata
+            .insert(cache::METADATA_ENTRY_KEY.to_string(), json!(entry));
+        if let Ok(value) = HeaderValue::from_str(entry) {
+            answer_headers.insert(cache::CACHE_ENTRY_HEADER, value);
+        }
+    }
+    if let Some(found) = &similar {
+        ctx.metadata.insert(
+            cache::METADATA_SIMILARITY_KEY.to_string(),
+            json!(found.similarity),
+        );
+        if let Ok(value) = HeaderValue::from_str(&format!("{:.4}", found.similarity)) {
+            answer_headers.insert(cache::CACHE_SIMILARITY_HEADER, value);
+        }
+    }
+    let mut response = match hit {
+        Some(hit) => replay(context, api, call, ctx, started, hit).await,
+        None => {
+            let slot = context
+                .cache
+                .slot(&key, cache_mode, cache_ttl)
+                .map(|slot| slot.with_query(query));
+            forward(
+                context, api, uri, call, ctx, started, route, headers, body, slot,
+            )
+            .await
+        }
+    };
+    response.headers_mut().extend(answer_headers);
+    response
+}
+
+/// Sends the request along its route and relays the answer, storing it in `slot` when it
+/// succeeds
+#[allow(clippy::too_many_arguments)]
+async fn forward(
+    context: Arc<ProxyContext>,
+    api: &dyn ProviderApi,
+    uri: Uri,
+    call: CapturedCall,
+    mut ctx: PluginContext,
+    started: Instant,
+    route: ResolvedRoute,
+    headers: HeaderMap,
+    body: Bytes,
+    slot: Option<CacheSlot>,
+) -> Response {
+    // Answers served locally are free, so budgets are only enforced here.
+    // Past a hard limit only requests downgraded to a local or free model go on.
+    let client = call.client.as_deref().unwrap_or(budget::UNKNOWN_CLIENT);
+    let downgraded = ctx.metadata.contains_key(budget::DOWNGRADE_METADATA_KEY);
+    let free = downgraded && (route.is_local() || context.usage.is_free(&route.model));
+    if let (Err(exceeded), false) = (context.budget.admit(client), free) {
+        let status = StatusCode::TOO_MANY_REQUESTS;
+        let message = exceeded.to_string();
+        return refuse(
+            &context,
+            api,
+            call,
+            ctx,
+            started,
+            status,
+            "budget_exceeded",
+            message,
+        );
+    }
+    let delivery = context
+        .deliver(
+            &route,
+            api.provider(),
+            &path_and_query(&uri),
+            &headers,
+            body,
+        )
+        .await;
+    if delivery.attempts.len() > 1 {
+        ctx.metadata
+            .insert(failover::METADATA_KEY.to_string(), json!(delivery.attempts));
+    }
+    let upstream = match delivery.result {
+        Ok(upstream) => upstream,
+        Err(e) => return fail(&context, api, call, ctx, started, e).await,
+    };
+
+    let status = upstream.status();
+    let response_headers = upstream.headers().clone();
+    if call.stream && status.is_success() && is_event_stream(&response_headers) {
+        let chunks = upstream
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(|e| e.to_string()));
+        let source = StreamSource {
+            status,
+            headers: response_headers,
+            slot,
+        };
+        return relay_stream(
+            context,
+            call,
+            ctx,
+            started,
+            source,
+            chunks,
+            api.accumulator(),
+        );
+    }
+
+    let bytes = match upstream.bytes().await {
+        Ok(bytes) => bytes,
+        Err(e) => return fail(&context, api, call, ctx, started, e).await,
+    };
+    if let Some(slot) = slot {
+        slot.fill(status, &response_headers, false, &bytes);
+    }
+    complete(
+        &context,
+        api,
+        call,
+        ctx,
+        started,
+        status,
+        response_headers,
+        bytes,
+    )
+    .await
+}
+
+/// Answers from the cache as if the upstream had sent the stored response
+async fn replay(
+    context: Arc<ProxyContext>,
+    api: &dyn ProviderApi,
+    call: CapturedCall,
+    ctx: PluginContext,
+    started: Instant,
+    hit: CachedResponse,
+) -> Response {
+    let status = StatusCode::from_u16(hit.status).unwrap_or(StatusCode::OK);
+    let headers = hit.headers();
+    let body = Bytes::from(hit.body);
+    if hit.stream {
+        let chunks = futures_util::stream::iter([Ok(body)]);
+        let source = StreamSource {
+            status,
+            headers,
+            slot: None,
+        };
+        return relay_stream(
+            context,
+            call,
+            ctx,
+            started,
+            source,
+            chunks,
+            api.accumulator(),
+        );
+    }
+    complete(&context, api, call, ctx, started, status, headers, body).await
+}
+
+/// Runs response hooks over a buffered answer, or error hooks when it failed
+#[allow(clippy::too_many_arguments)]
+async fn complete(
+    context: &ProxyContext,
+    api: &dyn ProviderApi,
+    mut call: CapturedCall,
+    mut ctx: PluginContext,
+    started: Instant,
+    status: StatusCode,
+    response_headers: HeaderMap,
+    bytes: Bytes,
+) -> Response {
+    let parsed: Option<Value> = serde_json::from_slice(&bytes).ok();
+    if !status.is_success() {
+        let failure = UpstreamFailure {
+            status: status.as_u16(),
+            message: upstream_error_message(parsed.as_ref(), &bytes),
+        };
+        let response = super::relay_response(status, &response_headers, Body::from(bytes));
+        return recover(context, api, call, ctx, started, failure, response).await;
+    }
+
+    call.status = status.as_u16();
+    let Some(parsed) = parsed else {
+        let response = super::relay_response(status, &response_headers, Body::from(bytes));
+        return finish(context, call, ctx, started, response);
+    };
+    if context.pipeline.is_idle(&ctx) {
+        api.capture_response(&mut call, &parsed);
+        let response = super::relay_response(status, &response_headers, Body::from(bytes));
+        return finish(context, call, ctx, started, response);
+    }
+
+    let mut plugin_response = PluginResponse {
+        status: call.status,
+        body: parsed,
+    };
+    let original = plugin_response.clone();
+    let outcome = context
+        .pipeline
+        .run_response(&mut ctx, &mut plugin_response)
+        .await;
+    if let Some(response) = conclude(api, &mut call, outcome) {
+        return finish(context, call, ctx, started, response);
+    }
+    api.capture_response(&mut call, &plugin_response.body);
+    call.status = plugin_response.status;
+    let status = StatusCode::from_u16(plugin_response.status).unwrap_or(status);
+    let bytes = if plugin_response == original {
+        bytes
+    } else {
+        Bytes::from(plugin_response.body.to_string())
+    };
+    let response = super::relay_response(status, &response_headers, Body::from(bytes));
+    finish(context, call, ctx, started, response)
+}
+
+/// Forwards a non-generation request untouched and without capture
+pub(crate) async fn passthrough(
+    context: &ProxyContext,
+    provider: Provider,
+    method: Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Response {
+    let mut headers = headers.clone();
+    let mut uri = uri.clone();
+    if let Err(message) = context.clients.admit(&mut headers, &mut uri) {
+        return provider.error_response(StatusCode::UNAUTHORIZED, "authentication_error", message);
+    }
+    let upstream = match context
+        .send(
+            &context.default_upstream(),
+            provider,
+            method,
+            &path_and_query(&uri),
+            &headers,
+            body,
+        )
+        .await
+    {
+        Ok(upstream) => upstream,
+        Err(e) => return provider.error_response(StatusCode::BAD_GATEWAY, "upstream_error", e),
+    };
+    let status = upstream.status();
+    let headers = upstream.headers().clone();
+    match upstream.bytes().await {
+        Ok(bytes) => super::relay_response(status, &headers, Body::from(bytes)),
+        Err(e) => provider.error_response(StatusCode::BAD_GATEWAY, "upstream_error", e),
+    }
+}
+
+/// Turns a plugin's answer or rejection into the client response, `None` means carry on
+fn conclude(
+    api: &dyn ProviderApi,
+    call: &mut CapturedCall,
+    outcome: HookOutcome,
+) -> Option<Response> {
+    match outcome {
+        HookOutcome::Continue => None,
+        HookOutcome::Respond(response) => {
+            call.status = response.status;
+            call.error = None;
+            api.capture_response(call, &response.body);
+            let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::OK);
+            Some((status, Json(response.body)).into_response())
+        }
+        HookOutcome::Reject(rejection) => {
+            call.status = rejection.status;
+            call.error = Some(rejection.message.clone());
+            let status = StatusCode::from_u16(rejection.status).unwrap_or(StatusCode::BAD_REQUEST);
+            Some(
+                api.provider()
+                    .error_response(status, &rejection.kind, rejection.message),
+            )
+        }
+    }
+}
+
+/// Records a finished call together with the notes plugins attached to it
+fn finish(
+    context: &ProxyContext,
+    mut call: CapturedCall,
+    ctx: PluginContext,
+    started: Instant,
+    response: Response,
+) -> Response {
+    call.latency = started.elapsed().as_millis() as u64;
+    call.metadata = ctx.metadata;
+    context.record(call);
+    response
+}
+
+/// Rewrites the requested model to a cheaper one when the client's budget runs low
+fn downgrade(
+    context: &ProxyContext,
+    call: &CapturedCall,
+    request: &mut Value,
+) -> Option<Downgrade> {
+    let model = request.get("model").and_then(Value::as_str)?;
+    let client = call.client.as_deref().unwrap_or(budget::UNKNOWN_CLIENT);
+    let downgrade = context.budget.downgrade(client, model)?;
+    request["model"] = json!(downgrade.to);
+    Some(downgrade)
+}
+
+/// Looks up where the request goes and renames its model as the route says.
+/// Gemini names the model in the URL, so only its upstream can change.
+fn resolve_route(
+    context: &ProxyContext,
+    provider: Provider,
+    call: &CapturedCall,
+    request: &mut Value,
+) -> Result<ResolvedRoute, String> {
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or(&call.model);
+    let route = context
+        .routes
+        .resolve(model, &context.default_upstream())
+        .map_err(|e| e.to_string())?;
+    route.accepts(provider).map_err(|e| e.to_string())?;
+    if request.get("model").is_some() && route.model != route.requested_model {
+        request["model"] = json!(route.model);
+    }
+    Ok(route)
+}
+
+/// Answers a request the proxy will not forward with an error in the client's format
+#[allow(clippy::too_many_arguments)]
+fn refuse(
+    context: &ProxyContext,
+    api: &dyn ProviderApi,
+    mut call: CapturedCall,
+    ctx: PluginContext,
+    started: Instant,
+    status: StatusCode,
+    kind: &str,
+    message: String,
+) -> Response {
+    let response = api.provider().error_response(status, kind, &message);
+    call.status = status.as_u16();
+    call.error = Some(message);
+    finish(context, call, ctx, started, response)
+}
+
+/// Runs error hooks, returning `response` unless a plugin recovers or rejects
+async fn recover(
+    context: &ProxyContext,
+    api: &dyn ProviderApi,
+    mut call: CapturedCall,
+    mut ctx: PluginContext,
+    started: Instant,
+    failure: UpstreamFailure,
+    response: Response,
+) -> Response {
+    let outcome = context.pipeline.run_error(&mut ctx, &failure).await;
+    let response = match conclude(api, &mut call, outcome) {
+        Some(answer) => answer,
+        None => {
+            call.status = failure.status;
+            call.error = Some(failure.message);
+            response
+        }
+    };
+    finish(context, call, ctx, started, response)
+}
+
+async fn fail(
+    context: &ProxyContext,
+    api: &dyn ProviderApi,
+    call: CapturedCall,
+    ctx: PluginContext,
+    started: Instant,
+    error: impl std::fmt::Display,
+) -> Response {
+    let failure = UpstreamFailure {
+        status: StatusCode::BAD_GATEWAY.as_u16(),
+        message: error.to_string(),
+    };
+    let response = api
+        .provider()
+        .error_response(StatusCode::BAD_GATEWAY, "upstream_error", error);
+    recover(context, api, call, ctx, started, failure, response).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod provider_tests {
+        use super::*;
+
+        #[test]
+        fn test_auth_headers() {
+            let (name, value) = Provider::OpenAi.auth_header("k").unwrap();
+            assert_eq!(
+                (name.as_str(), value.to_str().unwrap()),
+                ("authorization", "Bearer k")
+            );
+            let (name, value) = Provider::Anthropic.auth_header("k").unwrap();
+            assert_eq!((name.as_str(), value.to_str().unwrap()), ("x-api-key", "k"));
+            let (name, _) = Provider::Gemini.auth_header("k").unwrap();
+            assert_eq!(name.as_str(), "x-goog-api-key");
+        }
+
+        #[test]
+        fn test_provider_serializes_lowercase() {
+            assert_eq!(serde_json::to_value(Provider::OpenAi).unwrap(), "openai");
+            assert_eq!(Provider::Anthropic.as_str(), "anthropic");
+        }
+
+        #[test]
+        fn test_upstream_error_message() {
+            let parsed = json!({"error": {"message": "rate limited"}});
+            assert_eq!(upstream_error_message(Some(&parsed), b""), "rate limited");
+            let gemini = json!([{"error": {"message": "quota"}}]);
+            assert_eq!(upstream_error_message(Some(&gemini), b""), "quota");
+            assert_eq!(upstream_error_message(None, b"oops"), "oops");
+        }
+    }
+
+    mod pipeline_tests {
+        use super::*;
+        use crate::pipeline::testing::FnPlugin;
+        use crate::pipeline::{ChunkOutcome, ExtensionKind, Plugin, Rejection};
+        use crate::proxy::testing::{
+            chat, echo_upstream, services, spawn_upstream, start_proxy_with, wait_for_capture,
+        };
+        use crate::proxy::ProxyServices;
+
+        fn with_plugin(plugin: FnPlugin) -> ProxyServices {
+            let services = services();
+            let plugin: Arc<dyn Plugin> = Arc::new(plugin);
+            services
+                .pipeline
+                .register(plugin, ExtensionKind::Builtin)
+                .unwrap();
+            services
+        }
+
+        /// Chat completion request for gpt-4o
+        fn request(stream: bool) -> Value {
+            json!({"model": "gpt-4o", "stream": stream, "messages": []})
+        }
+
+        #[tokio::test]
+        async fn test_request_hook_rewrites_forwarded_body() {
+            let mut plugin = FnPlugin::new("rewrite");
+            plugin.on_request = Box::new(|r| {
+                r.body["model"] = json!("gpt-4o-mini");
+                HookOutcome::Continue
+            });
+            let upstream = spawn_upstream(echo_upstream("echo")).await;
+            let (server, base) = start_proxy_with(&upstream, with_plugin(plugin)).await;
+
+            let body: Value = chat(&base, request(false), &[]).await.json().await.unwrap();
+            assert_eq!(body["model"], "gpt-4o-mini");
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.model, "gpt-4o-mini");
+        }
+
+        #[tokio::test]
+        async fn test_request_hook_can_reject() {
+            let mut plugin = FnPlugin::new("deny");
+            plugin.on_request = Box::new(|_| {
+                HookOutcome::Reject(Rejection::new(403, "permission_error", "blocked by policy"))
+            });
+            let (server, base) = start_proxy_with("http://127.0.0.1:9", with_plugin(plugin)).await;
+
+            let response = chat(&base, request(false), &[]).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["error"]["type"], "permission_error");
+
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.status, 403);
+            assert_eq!(call.error.as_deref(), Some("blocked by policy"));
+        }
+
+        #[tokio::test]
+        async fn test_request_hook_can_short_circuit() {
+            let mut plugin = FnPlugin::new("canned");
+            plugin.on_request = Box::new(|_| {
+                HookOutcome::Respond(PluginResponse {
+                    status: 200,
+                    body: json!({"choices": [{"message": {"role": "assistant", "content": "canned"}}]}),
+                })
+            });
+            // The upstream is unreachable, so only a short-circuit can succeed
+            let (server, base) = start_proxy_with("http://127.0.0.1:9", with_plugin(plugin)).await;
+
+            let response = chat(&base, request(false), &[]).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.response.content.as_deref(), Some("canned"));
+            assert_eq!(server.status().await.requests_served, 0);
+        }
+
+        #[tokio::test]
+        async fn test_response_hook_rewrites_body() {
+            let mut plugin = FnPlugin::new("shout");
+            plugin.on_response = Box::new(|r| {
+                r.body["choices"][0]["message"]["content"] = json!("GPT-4O");
+                HookOutcome::Continue
+            });
+            let upstream = spawn_upstream(echo_upstream("echo")).await;
+            let (server, base) = start_proxy_with(&upstream, with_plugin(plugin)).await;
+
+            let body: Value = chat(&base, request(false), &[]).await.json().await.unwrap();
+            assert_eq!(body["choices"][0]["message"]["content"], "GPT-4O");
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.response.content.as_deref(), Some("GPT-4O"));
+        }
+
+        #[tokio::test]
+        async fn test_error_hook_can_recover() {
+            let mut plugin = FnPlugin::new("fallback");
+            plugin.on_error = Box::new(|_| {
+                HookOutcome::Respond(PluginResponse {
+                    status: 200,
+                    body: json!({"choices": [{"message": {"role": "assistant", "content": "offline"}}]}),
+                })
+            });
+            let (server, base) = start_proxy_with("http://127.0.0.1:9", with_plugin(plugin)).await;
+
+            assert_eq!(
+                chat(&base, request(false), &[]).await.status(),
+                StatusCode::OK
+            );
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.error, None);
+            assert_eq!(call.response.content.as_deref(), Some("offline"));
+        }
+
+        #[tokio::test]
+        async fn test_stream_hook_rewrites_events() {
+            let mut plugin = FnPlugin::new("stream");
+            plugin.on_chunk = Box::new(|event| {
+                if event.data == "[DONE]" {
+                    return ChunkOutcome::Skip;
+                }
+                event.data = event.data.replace("gpt-4o", "rewritten");
+                ChunkOutcome::Continue
+            });
+            let upstream = spawn_upstream(echo_upstream("echo")).await;
+            let (server, base) = start_proxy_with(&upstream, with_plugin(plugin)).await;
+
+            let body = chat(&base, request(true), &[]).await.text().await.unwrap();
+            assert!(body.contains("rewritten"));
+            assert!(!body.contains("[DONE]"));
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.response.content.as_deref(), Some("echo rewritten"));
+        }
+    }
+
+    mod cache_tests {
+        use super::*;
+        use crate::cache::semantic::{self, SemanticConfig};
+        use crate::cache::{CacheConfig, ResponseCache};
+        use crate::proxy::testing::{
+            chat, services, spawn_upstream, start_proxy_with, wait_for_capture,
+        };
+        use crate::proxy::ProxyServices;
+        use axum::{extract::State, routing::post, Router};
+        use std::collections::HashMap;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        async fn numbered_answer(
+            State(hits): State<Arc<AtomicUsize>>,
+            Json(body): Json<Value>,
+        ) -> Response {
+            let content = format!("answer {}", hits.fetch_add(1, Ordering::SeqCst) + 1);
+            if body["stream"] == true {
+                let chunk = json!({"choices": [{"delta": {"content": content}}]});
+                return Response::builder()
+                    .header("content-type", "text/event-stream")
+                    .body(Body::from(format!("data: {}\n\ndata: [DONE]\n\n", chunk)))
+                    .unwrap();
+            }
+            Json(json!({"choices": [{"message": {"role": "assistant", "content": content}}]}))
+                .into_response()
+        }
+
+        /// Upstream numbering its answers so replays are recognisable
+        fn counting_upstream(hits: Arc<AtomicUsize>) -> Router {
+            Router::new()
+                .route("/v1/chat/completions", post(numbered_answer))
+                .with_state(hits)
+        }
+
+        fn cached_services(dir: &std::path::Path) -> ProxyServices {
+            let config = CacheConfig {
+                enabled: true,
+                ..CacheConfig::default()
+            };
+            let cache = ResponseCache::open(dir.to_path_buf(), config).unwrap();
+            services().with_cache(cache)
+        }
+
+        /// Deterministic gpt-4o request, so its answer may be cached
+        fn request(stream: bool) -> Value {
+            json!({"model": "gpt-4o", "stream": stream, "temperature": 0, "messages": []})
+        }
+
+        #[tokio::test]
+        async fn test_repeated_request_is_served_from_cache() {
+            let dir = tempfile::tempdir().unwrap();
+            let hits = Arc::new(AtomicUsize::new(0));
+            let upstream = spawn_upstream(counting_upstream(hits.clone())).await;
+            let (server, base) = start_proxy_with(&upstream, cached_services(dir.path())).await;
+
+            let first = chat(&base, request(false), &[]).await;
+            assert_eq!(first.headers()[cache::CACHE_HEADER], "miss");
+            let first: Value = first.json().await.unwrap();
+            let second = chat(&base, request(false), &[]).await;
+            assert_eq!(second.headers()[cache::CACHE_HEADER], "hit");
+            let second: Value = second.json().await.unwrap();
+
+            assert_eq!(first, second);
+            assert_eq!(hits.load(Ordering::SeqCst), 1);
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.metadata[cache::METADATA_KEY], "hit");
+            assert_eq!(call.response.content.as_deref(), Some("answer 1"));
+            let stats = server.cache().stats();
+            assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+        }
+
+        #[tokio::test]
+        async fn test_streams_are_replayed() {
+            let dir = tempfile::tempdir().unwrap();
+            let hits = Arc::new(AtomicUsize::new(0));
+            let upstream = spawn_upstream(counting_upstream(hits.clone())).await;
+            let (server, base) = start_proxy_with(&upstream, cached_services(dir.path())).await;
+
+            let first = chat(&base, request(true), &[]).await.text().await.unwrap();
+            // The entry is stored once the stream task finishes
+            wait_for_capture(&server).await;
+            let second = chat(&base, request(true), &[]).await;
+            assert_eq!(second.headers()[cache::CACHE_HEADER], "hit");
+            assert_eq!(second.headers()["content-type"], "text/event-stream");
+            assert_eq!(second.text().await.unwrap(), first);
+            assert_eq!(hits.load(Ordering::SeqCst), 1);
+        }
+
+        #[tokio::test]
+        async fn test_bypass_and_refresh_headers() {
+            let dir = tempfile::tempdir().unwrap();
+            let hits = Arc::new(AtomicUsize::new(0));
+            let upstream = spawn_upstream(counting_upstream(hits.clone())).await;
+            let (server, base) = start_proxy_with(&upstream, cached_services(dir.path())).await;
+
+            let response = chat(&base, request(false), &[(cache::CACHE_HEADER, "bypass")]).await;
+            assert_eq!(response.headers()[cache::CACHE_HEADER], "bypass");
+            assert_eq!(server.cache().stats().entries, 0);
+
+            chat(&base, request(false), &[]).await;
+            let refreshed: Value = chat(&base, request(false), &[(cache::CACHE_HEADER, "refresh")])
+                .await
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(refreshed["choices"][0]["message"]["content"], "answer 3");
+            let cached: Value = chat(&base, request(false), &[]).await.json().await.unwrap();
+            assert_eq!(cached["choices"][0]["message"]["content"], "answer 3");
+            assert_eq!(hits.load(Ordering::SeqCst), 3);
+        }
+
+        #[tokio::test]
+        async fn test_clients_and_sampled_requests_are_not_shared() {
+            let dir = tempfile::tempdir().unwrap();
+            let hits = Arc::new(AtomicUsize::new(0));
+            let upstream = spawn_upstream(counting_upstream(hits.clone())).await;
+            let (server, base) = start_proxy_with(&upstream, cached_services(dir.path())).await;
+            let send = |client: &'static str, temperature: f64| {
+                let base = base.clone();
+                async move {
+                    let request =
+                        json!({"model": "gpt-4o", "temperature": temperature, "messages": []});
+                    chat(&base, request, &[(budget::CLIENT_HEADER, client)]).await
+                }
+            };
+
+            send("cursor", 0.0).await;
+            let other = send("aider", 0.0).await;
+            assert_eq!(other.headers()[cache::CACHE_HEADER], "miss");
+            let again = send("cursor", 0.0).await;
+            assert_eq!(again.headers()[cache::CACHE_HEADER], "hit");
+
+            for _ in 0..2 {
+                let sampled = send("cursor", 0.7).await;
+                assert_eq!(sampled.headers()[cache::CACHE_HEADER], "bypass");
+            }
+            assert_eq!(hits.load(Ordering::SeqCst), 4);
+            assert_eq!(server.cache().stats().entries, 2);
+        }
+
+        fn question(model: &str, text: &str) -> Value {
+            json!({"model": model, "messages": [{"role": "user", "content": text}]})
+        }
+
+        #[tokio::test]
+        async fn test_reworded_prompt_is_served_from_semantic_cache() {
+            let dir = tempfile::tempdir().unwrap();
+            let hits = Arc::new(AtomicUsize::new(0));
+            let upstream = spawn_upstream(counting_upstream(hits.clone())).await;
+            let embeddings = spawn_upstream(semantic::testing::embeddings_upstream()).await;
+            let config = CacheConfig {
+                enabled: true,
+                sampled: true,
+                semantic: SemanticConfig {
+                    enabled: true,
+                    embeddings_url: format!("{}/v1", embeddings),
+                    thresholds: HashMap::from([("strict".to_string(), 1.1)]),
+                    ..SemanticConfig::default()
+                },
+                ..CacheConfig::default()
+            };
+            let cache = ResponseCache::open(dir.path().to_path_buf(), config).unwrap();
+            let (server, base) = start_proxy_with(&upstream, services().with_cache(cache)).await;
+
+            let first = chat(
+                &base,
+                question("gpt-4o", "What is the capital of France?"),
+                &[],
+            )
+            .await;
+            assert_eq!(first.headers()[cache::CACHE_HEADER], "miss");
+            let entry = cache::request_key(
+                Provider::OpenAi,
+                "client:unknown\0default",
+                "/v1/chat/completions",
+                &HeaderMap::new(),
+                &json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "What is the capital of France?"}]}),
+            );
+
+            let reworded = chat(
+                &base,
+                question("gpt-4o", "capital city of france, please"),
+                &[],
+            )
+            .await;
+            assert_eq!(reworded.headers()[cache::CACHE_HEADER], "semantic-hit");
+            assert_eq!(
+                reworded.headers()[cache::CACHE_ENTRY_HEADER],
+                entry.as_str()
+            );
+            assert!(reworded
+                .headers()
+                .contains_key(cache::CACHE_SIMILARITY_HEADER));
+            let reworded: Value = reworded.json().await.unwrap();
+            assert_eq!(reworded["choices"][0]["message"]["content"], "answer 1");
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.metadata[cache::METADATA_ENTRY_KEY], entry.as_str());
+
+            let other = chat(
+                &base,
+                question("gpt-4o", "How does the borrow checker work?"),
+                &[],
+            )
+            .await;
+            assert_eq!(other.headers()[cache::CACHE_HEADER], "miss");
+            // Another model never shares answers, and this one demands more than identity
+            chat(&base, question("strict", "capital of France"), &[]).await;
+            let strict = chat(&base, question("strict", "capital of France"), &[]).await;
+            assert_eq!(strict.headers()[cache::CACHE_HEADER], "hit");
+            let strict = chat(&base, question("strict", "France: capital?"), &[]).await;
+            assert_eq!(strict.headers()[cache::CACHE_HEADER], "miss");
+
+            assert_eq!(hits.load(Ordering::SeqCst), 4);
+            let stats = server.cache().stats();
+            assert_eq!((stats.semantic_hits, stats.semantic_entries), (1, 4));
+        }
+    }
+
+    mod budget_tests {
+        use super::*;
+        use crate::budget::{BudgetLimit, BudgetSettings, BudgetTracker};
+        use crate::capture::Usage;
+        use crate::events::RecordingSink;
+        use crate::proxy::testing::{chat, services, spawn_upstream, start_proxy_with};
+        use crate::usage::pricing::{PriceStore, PricingTable};
+        use crate::usage::UsageLedger;
+        use axum::{routing::post, Router};
+        use std::sync::Mutex;
+
+        fn usage_upstream() -> Router {
+            Router::new().route(
+                "/v1/chat/completions",
+                post(|headers: HeaderMap| async move {
+                    assert!(headers.get(budget::CLIENT_HEADER).is_none());
+                    Json(json!({
+                        "choices": [{"message": {"role": "assistant", "content": "ok"}}],
+                        "usage": {"prompt_tokens": 12, "completion_tokens": 5},
+                    }))
+                }),
+            )
+        }
+
+        fn request() -> Value {
+            json!({"model": "gpt-4o", "messages": []})
+        }
+
+        #[tokio::test]
+        async fn test_hard_limit_rejects_with_clear_error() {
+            let sink = Arc::new(RecordingSink::default());
+            let mut settings = BudgetSettings::default();
+            let limit = BudgetLimit {
+                soft_tokens: Some(10),
+                hard_tokens: Some(17),
+            };
+            settings.clients.insert("script".to_string(), limit);
+            let budget = BudgetTracker::in_memory(settings, sink.clone());
+            let upstream = spawn_upstream(usage_upstream()).await;
+            let (server, base) = start_proxy_with(&upstream, services().with_budget(budget)).await;
+
+            assert_eq!(
+                chat(&base, request(), &[(budget::CLIENT_HEADER, "script")])
+                    .await
+                    .status(),
+                StatusCode::OK
+            );
+            assert_eq!(sink.names(), vec![budget::BUDGET_EXCEEDED_EVENT]);
+            let rejected = chat(&base, request(), &[(budget::CLIENT_HEADER, "script")]).await;
+            assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+            let body: Value = rejected.json().await.unwrap();
+            assert_eq!(body["error"]["type"], "budget_exceeded");
+            let message = body["error"]["message"].as_str().unwrap();
+            assert!(message.contains("client \"script\": 17 of 17 tokens used"));
+
+            assert_eq!(
+                chat(&base, request(), &[(budget::CLIENT_HEADER, "cursor")])
+                    .await
+                    .status(),
+                StatusCode::OK
+            );
+            assert_eq!(server.status().await.requests_served, 2);
+            let calls = server.captures().recent(3);
+            assert_eq!(calls[1].client.as_deref(), Some("script"));
+            assert_eq!(calls[1].status, 429);
+            assert_eq!(server.budget().overview().daily.used.total(), 34);
+        }
+
+        /// Upstream answering as whichever model it was asked for, recording the models
+        fn model_upstream() -> (Router, Arc<Mutex<Vec<String>>>) {
+            let models = Arc::new(Mutex::new(Vec::new()));
+            let seen = models.clone();
+            let upstream = Router::new().route(
+                "/v1/chat/completions",
+                post(move |Json(body): Json<Value>| async move {
+                    seen.lock()
+                        .unwrap()
+                        .push(body["model"].as_str().unwrap().to_string());
+                    Json(json!({
+                        "model": body["model"],
+                        "choices": [{"message": {"role": "assistant", "content": "ok"}}],
+                        "usage": {"prompt_tokens": 12, "completion_tokens": 5},
+                    }))
+                }),
+            );
+            (upstream, models)
+        }
+
+        /// Daily budget of 30 tokens downgrading from the soft limit of 10 on
+        fn downgrading_budget() -> BudgetTracker {
+            let mut settings = BudgetSettings {
+                daily: BudgetLimit {
+                    soft_tokens: Some(10),
+                    hard_tokens: Some(30),
+                },
+                ..BudgetSettings::default()
+            };
+            settings.downgrade.enabled = true;
+            BudgetTracker::in_memory(settings, Arc::new(RecordingSink::default()))
+        }
+
+        #[tokio::test]
+        async fn test_low_budget_downgrades_instead_of_failing() {
+            let (upstream, models) = model_upstream();
+            let upstream = spawn_upstream(upstream).await;
+            let services = services().with_budget(downgrading_budget());
+            let (server, base) = start_proxy_with(&upstream, services).await;
+
+            for _ in 0..2 {
+                assert_eq!(
+                    chat(&base, request(), &[(budget::CLIENT_HEADER, "cli")])
+                        .await
+                        .status(),
+                    StatusCode::OK
+                );
+            }
+            assert_eq!(*models.lock().unwrap(), vec!["gpt-4o", "gpt-4o-mini"]);
+            let calls = server.captures().recent(2);
+            assert_eq!(calls[0].model, "gpt-4o-mini");
+            assert_eq!(calls[0].requested_model.as_deref(), Some("gpt-4o"));
+            assert_eq!(
+                calls[0].metadata[budget::DOWNGRADE_METADATA_KEY]["level"],
+                "warning"
+            );
+            assert_eq!(calls[1].requested_model, None);
+        }
+
+        #[tokio::test]
+        async fn test_downgrade_does_not_lift_hard_limit() {
+            let (upstream, models) = model_upstream();
+            let upstream = spawn_upstream(upstream).await;
+            let budget = downgrading_budget();
+            budget.charge(&mut {
+                let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+                call.usage = Some(Usage::new(30, 0));
+                call
+            });
+            let (_server, base) = start_proxy_with(&upstream, services().with_budget(budget)).await;
+
+            let rejected = chat(&base, request(), &[(budget::CLIENT_HEADER, "cli")]).await;
+            assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+            let body: Value = rejected.json().await.unwrap();
+            assert_eq!(body["error"]["type"], "budget_exceeded");
+            assert!(models.lock().unwrap().is_empty());
+        }
+
+        #[tokio::test]
+        async fn test_free_downgrade_goes_past_hard_limit() {
+            let (upstream, models) = model_upstream();
+            let upstream = spawn_upstream(upstream).await;
+            let budget = downgrading_budget();
+            budget.charge(&mut {
+                let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+                call.usage = Some(Usage::new(30, 0));
+                call
+            });
+            let prices = PricingTable::parse(
+                "[[prices]]\npattern = \"gpt-4o-mini\"\ninput = 0\noutput = 0\n",
+            )
+            .unwrap();
+            let services = services()
+                .with_budget(budget)
+                .with_usage(UsageLedger::in_memory(PriceStore::in_memory(prices)));
+            let (server, base) = start_proxy_with(&upstream, services).await;
+
+            assert_eq!(
+                chat(&base, request(), &[(budget::CLIENT_HEADER, "cli")])
+                    .await
+                    .status(),
+                StatusCode::OK
+            );
+            assert_eq!(*models.lock().unwrap(), vec!["gpt-4o-mini"]);
+            let call = &server.captures().recent(1)[0];
+            assert_eq!(
+                call.metadata[budget::DOWNGRADE_METADATA_KEY]["level"],
+                "exceeded"
+            );
+        }
+    }
+
+    mod usage_tests {
+        use super::*;
+        use crate::proxy::testing::{
+            chat, services, spawn_upstream, start_proxy_with, wait_for_capture,
+        };
+        use crate::usage::pricing::{PriceStore, PricingTable};
+        use crate::usage::{UsageGroup, UsageLedger, UsageRange};
+        use axum::{routing::post, Router};
+
+        /// Upstream streaming an answer without reporting usage
+        fn silent_stream_upstream() -> Router {
+            Router::new().route(
+                "/v1/chat/completions",
+                post(|| async {
+                    let chunk = json!({"choices": [{"delta": {"content": "Hello, world!"}}]});
+                    Response::builder()
+                        .header("content-type", "text/event-stream")
+                        .body(Body::from(format!("data: {}\n\ndata: [DONE]\n\n", chunk)))
+                        .unwrap()
+                }),
+            )
+        }
+
+        #[tokio::test]
+        async fn test_streams_without_usage_are_counted_and_priced() {
+            let table = PricingTable::parse(
+                "[[prices]]\npattern = \"gpt-4o\"\ninput = 1000000\noutput = 1000000\n",
+            )
+            .unwrap();
+            let usage = UsageLedger::in_memory(PriceStore::in_memory(table));
+            let upstream = spawn_upstream(silent_stream_upstream()).await;
+            let (server, base) = start_proxy_with(&upstream, services().with_usage(usage)).await;
+
+            let request = json!({
+                "model": "gpt-4o",
+                "stream": true,
+                "messages": [{"role": "user", "content": "Hello, world!"}],
+            });
+            chat(&base, request, &[]).await.text().await.unwrap();
+
+            let call = wait_for_capture(&server).await;
+            let usage = call.usage.unwrap();
+            assert_eq!((usage.prompt_tokens, usage.completion_tokens), (10, 4));
+            assert_eq!(call.cost, Some(14.0));
+            assert_eq!(call.metadata[crate::usage::METADATA_KEY]["estimated"], true);
+
+            let summary = server
+                .usage()
+                .summary(UsageRange::default(), UsageGroup::Model);
+            assert_eq!(summary.groups[0].key, "gpt-4o");
+            assert_eq!(summary.total.cost, 14.0);
+        }
+    }
+
+    mod routing_tests {
+        use super::*;
+        use crate::credentials::CredentialStore;
+        use crate::proxy::testing::{
+            chat, echo_upstream, services, spawn_upstream, start_proxy_with,
+        };
+        use crate::routing::{RouteStore, RoutingTable};
+
+        /// Auth header of a client sending its own provider key
+        const PROVIDER_KEY: [(&str, &str); 1] = [("authorization", "Bearer sk-client")];
+
+        fn request(model: &str) -> Value {
+            json!({"model": model, "messages": [{"role": "user", "content": "hi"}]})
+        }
+
+        #[tokio::test]
+        async fn test_alias_is_routed_to_its_upstream() {
+            let litellm = spawn_upstream(echo_upstream("litellm")).await;
+            let ollama = spawn_upstream(echo_upstream("ollama")).await;
+            let table = RoutingTable::parse(&format!(
+                r#"
+                [aliases]
+                "gpt-4" = "ollama/llama3.2"
+
+                [[upstreams]]
+                name = "ollama"
+                base_url = "{}"
+                kind = "ollama"
+                auth = {{ type = "none" }}
+
+                [[routes]]
+                pattern = "ollama/*"
+                upstream = "ollama"
+                strip_prefix = true
+                "#,
+                ollama
+            ))
+            .unwrap();
+            let services = services().with_routes(RouteStore::in_memory(table));
+            let (server, base) = start_proxy_with(&litellm, services).await;
+
+            let body: Value = chat(&base, request("gpt-4"), &PROVIDER_KEY)
+                .await
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(body["choices"][0]["message"]["content"], "ollama llama3.2");
+            assert_eq!(body["authorization"], Value::Null);
+            let call = &server.captures().recent(1)[0];
+            assert_eq!(call.model, "llama3.2");
+            assert_eq!(call.requested_model.as_deref(), Some("gpt-4"));
+            assert_eq!(call.metadata[routing::METADATA_KEY]["upstream"], "ollama");
+
+            let body: Value = chat(&base, request("gpt-4o"), &PROVIDER_KEY)
+                .await
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(body["choices"][0]["message"]["content"], "litellm gpt-4o");
+            assert_eq!(body["authorization"], "Bearer sk-client");
+            assert_eq!(server.captures().recent(1)[0].requested_model, None);
+        }
+
+        #[tokio::test]
+        async fn test_local_request_moved_to_the_cloud_is_refused() {
+            use crate::pipeline::testing::FnPlugin;
+            use crate::pipeline::ExtensionKind;
+            use crate::plugins::redact::RedactPlugin;
+
+            let litellm = spawn_upstream(echo_upstream("litellm")).await;
+            let ollama = spawn_upstream(echo_upstream("ollama")).await;
+            let table = RoutingTable::parse(&format!(
+                r#"
+                [[upstreams]]
+                name = "ollama"
+                base_url = "{}"
+                kind = "ollama"
+                auth = {{ type = "none" }}
+
+                [[routes]]
+                pattern = "llama*"
+                upstream = "ollama"
+                "#,
+                ollama
+            ))
+            .unwrap();
+            let services = services().with_routes(RouteStore::in_memory(table));
+            let mut retarget = FnPlugin::new("retarget");
+            retarget.on_request = Box::new(|r| {
+                r.body["model"] = json!("gpt-4o");
+                HookOutcome::Continue
+            });
+            for plugin in [
+                Arc::new(RedactPlugin::default()) as Arc<dyn crate::pipeline::Plugin>,
+                Arc::new(retarget),
+            ] {
+                services
+                    .pipeline
+                    .register(plugin, ExtensionKind::Builtin)
+                    .unwrap();
+            }
+            let (server, base) = start_proxy_with(&litellm, services).await;
+
+            let response = chat(&base, request("llama3.2"), &PROVIDER_KEY).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body: Value = response.json().await.unwrap();
+            assert!(body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("skipped redaction"));
+            assert_eq!(server.status().await.requests_served, 0);
+        }
+
+        #[tokio::test]
+        async fn test_stored_credential_is_injected() {
+            let litellm = spawn_upstream(echo_upstream("litellm")).await;
+            let openai = spawn_upstream(echo_upstream("openai")).await;
+            let table = RoutingTable::parse(&format!(
+                r#"
+                [[upstreams]]
+                name = "openai"
+                base_url = "{}"
+                kind = "openai"
+                auth = {{ type = "credential", name = "OPENAI_API_KEY" }}
+
+                [[routes]]
+                pattern = "gpt-5*"
+                upstream = "openai"
+                "#,
+                openai
+            ))
+            .unwrap();
+            let credentials = CredentialStore::in_memory();
+            credentials.set("OPENAI_API_KEY", "sk-stored").unwrap();
+            let services = services()
+                .with_routes(RouteStore::in_memory(table))
+                .with_credentials(credentials);
+            let (_server, base) = start_proxy_with(&litellm, services).await;
+
+            let body: Value = chat(&base, request("gpt-5"), &PROVIDER_KEY)
+                .await
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(body["choices"][0]["message"]["content"], "openai gpt-5");
+            assert_eq!(body["authorization"], "Bearer sk-stored");
+        }
+    }
+
+    mod failover_tests {
+        use super::*;
+        use crate::proxy::testing::{
+            chat, echo_upstream, services, spawn_upstream, start_proxy_with,
+        };
+        use crate::routing::{RouteStore, RoutingTable};
+
+        #[tokio::test]
+        async fn test_attempts_are_recorded_on_the_call() {
+            let backup = spawn_upstream(echo_upstream("backup")).await;
+            let table = RoutingTable::parse(&format!(
+                r#"
+                fallbacks = [{{ upstream = "backup", model = "small" }}]
+
+                [[upstreams]]
+                name = "backup"
+                base_url = "{}"
+                kind = "openai"
+
+                [failover]
+                max_retries = 1
+                base_delay_ms = 1
+                "#,
+                backup
+            ))
+            .unwrap();
+            let services = services().with_routes(RouteStore::in_memory(table));
+            // Nothing listens on the primary upstream
+            let (server, base) = start_proxy_with("http://127.0.0.1:9", services).await;
+
+            let request = json!({"model": "gpt-4o", "messages": []});
+            let body: Value = chat(&base, request, &[]).await.json().await.unwrap();
+            assert_eq!(body["choices"][0]["message"]["content"], "backup small");
+            let call = &server.captures().recent(1)[0];
+            let attempts = call.metadata[failover::METADATA_KEY].as_array().unwrap();
+            assert_eq!(attempts.len(), 3);
+            assert_eq!(attempts[0]["upstream"], routing::DEFAULT_UPSTREAM);
+            assert!(attempts[1]["error"].is_string());
+            assert_eq!(attempts[2]["upstream"], "backup");
+            assert_eq!(attempts[2]["status"], 200);
+        }
+    }
+
+    mod client_key_tests {
+        use super::*;
+        use crate::clients::{ClientKeyRequest, ClientKeyStore, CreatedClientKey};
+        use crate::pipeline::testing::FnPlugin;
+        use crate::pipeline::ExtensionKind;
+        use crate::proxy::testing::{
+            chat, echo_upstream, services, spawn_upstream, start_proxy_with,
+        };
+
+        /// Proxy with a `cursor` key limited to gpt-4o models and no plugins
+        async fn proxy() -> (crate::proxy::ProxyServer, String, CreatedClientKey) {
+            let clients = ClientKeyStore::in_memory();
+            let created = clients
+                .create(ClientKeyRequest {
+                    name: "cursor".to_string(),
+                    models: vec!["gpt-4o*".to_string()],
+                    plugins: Some(Vec::new()),
+                    ..ClientKeyRequest::default()
+                })
+                .unwrap();
+            clients.set_required(true).unwrap();
+            let services = services().with_clients(clients);
+            let mut tagger = FnPlugin::new("tagger");
+            tagger.on_request = Box::new(|r| {
+                r.body["model"] = json!("tagged");
+                HookOutcome::Continue
+            });
+            services
+                .pipeline
+                .register(Arc::new(tagger), ExtensionKind::Builtin)
+                .unwrap();
+            let upstream = spawn_upstream(echo_upstream("echo")).await;
+            let (server, base) = start_proxy_with(&upstream, services).await;
+            (server, base, created)
+        }
+
+        fn request(model: &str) -> Value {
+            json!({"model": model, "messages": []})
+        }
+
+        #[tokio::test]
+        async fn test_key_names_the_client_and_is_not_forwarded() {
+            let (server, base, created) = proxy().await;
+            let bearer = format!("Bearer {}", created.key);
+            let response = chat(&base, request("gpt-4o"), &[("authorization", &bearer)]).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Value = response.json().await.unwrap();
+            // No plugin ran and the key stayed with the proxy
+            assert_eq!(body["choices"][0]["message"]["content"], "echo gpt-4o");
+            assert_eq!(body["authorization"], Value::Null);
+
+            let call = &server.captures().recent(1)[0];
+            assert_eq!(call.client.as_deref(), Some("cursor"));
+            assert_eq!(call.metadata[clients::METADATA_KEY], created.client.id);
+        }
+
+        #[tokio::test]
+        async fn test_missing_key_is_rejected() {
+            let (server, base, _) = proxy().await;
+            let provider_key = [("authorization", "Bearer sk-provider")];
+            let response = chat(&base, request("gpt-4o"), &provider_key).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["error"]["type"], "authentication_error");
+            assert_eq!(server.status().await.requests_served, 0);
+        }
+
+        #[tokio::test]
+        async fn test_key_limits_the_models() {
+            let (server, base, created) = proxy().await;
+            let bearer = format!("Bearer {}", created.key);
+            let response = chat(
+                &base,
+                request("claude-sonnet-4"),
+                &[("authorization", &bearer)],
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["error"]["type"], "model_not_allowed");
+            let call = &server.captures().recent(1)[0];
+            assert_eq!(call.status, 403);
+            assert_eq!(call.client.as_deref(), Some("cursor"));
+            assert_eq!(server.status().await.requests_served, 0);
+        }
+    }
+}
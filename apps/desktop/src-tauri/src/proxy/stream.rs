@@ -0,0 +1,474 @@
+//! Server-sent events passthrough
+//!
+//! Streamed responses are forwarded chunk by chunk as soon as they arrive.
+//! Each chunk is also fed through an [`SseParser`] and a provider-specific
+//! [`StreamAccumulator`] so the complete response can be rebuilt and
+//! captured once the stream ends, is cut short by the client, or fails
+//! upstream after the first chunk was already sent.
+//!
+//! While no plugin is enabled the upstream bytes are forwarded untouched.
+//! Otherwise every event goes through the `on_stream_chunk` hooks and is
+//! re-encoded before it is sent. Streams replayed from the response cache
+//! take the same path.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use futures_util::{Stream, StreamExt};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use super::{relay_response, ProxyContext};
+use crate::cache::CacheSlot;
+use crate::capture::CapturedCall;
+use crate::pipeline::{ChunkOutcome, PluginContext};
+
+/// Chunks buffered between the upstream reader and a slow client
+const CHANNEL_CAPACITY: usize = 32;
+
+/// Error recorded when the client goes away before the stream ends
+pub const CLIENT_DISCONNECTED: &str = "client disconnected before the stream completed";
+
+/// A single parsed server-sent event
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// Value of the `event:` field, if any
+    pub event: Option<String>,
+    /// All `data:` lines joined with newlines
+    pub data: String,
+}
+
+impl SseEvent {
+    /// Parses the data payload as JSON
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.data).ok()
+    }
+
+    /// Serializes the event back to its wire format
+    pub fn encode(&self) -> Bytes {
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {}\n", event));
+        }
+        for line in self.data.split('\n') {
+            out.push_str(&format!("data: {}\n", line));
+        }
+        out.push('\n');
+        Bytes::from(out)
+    }
+}
+
+/// Incremental parser that tolerates events split across network chunks
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    pending: SseEvent,
+    has_data: bool,
+}
+
+impl SseParser {
+    /// Creates an empty parser
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds raw bytes and returns every event completed by them
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        // Lines are split on raw bytes so multi-byte characters cut across
+        // network chunks are decoded only once they are complete
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if let Some(event) = self.take_pending() {
+                    events.push(event);
+                }
+                continue;
+            }
+            self.apply_line(line);
+        }
+        events
+    }
+
+    /// Flushes an event left unterminated when the stream ends
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let rest = std::mem::take(&mut self.buffer);
+        let rest = String::from_utf8_lossy(&rest);
+        let rest = rest.trim_end_matches(['\n', '\r']);
+        if !rest.is_empty() {
+            self.apply_line(rest);
+        }
+        self.take_pending()
+    }
+
+    fn take_pending(&mut self) -> Option<SseEvent> {
+        let complete = self.has_data || self.pending.event.is_some();
+        self.has_data = false;
+        complete.then(|| std::mem::take(&mut self.pending))
+    }
+
+    fn apply_line(&mut self, line: &str) {
+        if line.starts_with(':') {
+            return;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.pending.event = Some(value.to_string()),
+            "data" => {
+                if self.has_data {
+                    self.pending.data.push('\n');
+                }
+                self.pending.data.push_str(value);
+                self.has_data = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Rebuilds a complete response from the events of one stream
+pub(crate) trait StreamAccumulator: Send {
+    /// Inspects a single event
+    fn on_event(&mut self, event: &SseEvent);
+
+    /// Writes the rebuilt response into the captured call
+    fn finish(self: Box<Self>, call: &mut CapturedCall);
+
+    /// Encodes an error in the provider's stream format
+    fn error_chunk(&self, message: &str) -> Bytes {
+        let payload = json!({"error": {"message": message, "type": "upstream_error"}});
+        Bytes::from(format!("data: {}\n\n", payload))
+    }
+}
+
+/// Returns true when the upstream answered with an event stream
+pub(crate) fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"))
+}
+
+/// Where the events of a stream come from
+pub(crate) struct StreamSource {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Stores the raw stream once it completed without error
+    pub slot: Option<CacheSlot>,
+}
+
+/// Streams an upstream response to the client while capturing it on the side
+pub(crate) fn relay_stream<S>(
+    context: Arc<ProxyContext>,
+    mut call: CapturedCall,
+    mut ctx: PluginContext,
+    started: Instant,
+    source: StreamSource,
+    chunks: S,
+    mut accumulator: Box<dyn StreamAccumulator>,
+) -> Response
+where
+    S: Stream<Item = Result<Bytes, String>> + Send + 'static,
+{
+    let StreamSource {
+        status,
+        headers,
+        slot,
+    } = source;
+    let (tx, mut rx) = mpsc::channel::<Bytes>(CHANNEL_CAPACITY);
+    let response_headers = headers.clone();
+
+    tokio::spawn(async move {
+        let mut parser = SseParser::new();
+        let mut chunks = Box::pin(chunks);
+        let plugins_active = !context.pipeline.is_idle(&ctx);
+        let mut raw = slot.as_ref().map(|_| Vec::new());
+        let mut error = None;
+
+        while let Some(chunk) = chunks.next().await {
+            let bytes = match chunk {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    // Headers are already sent, so report the failure in-band
+                    let message = format!("upstream stream failed: {}", e);
+                    let _ = tx.send(accumulator.error_chunk(&message)).await;
+                    error = Some(message);
+                    break;
+                }
+            };
+            if let Some(raw) = raw.as_mut() {
+                raw.extend_from_slice(&bytes);
+            }
+            let events = parser.feed(&bytes);
+            let outgoing = if plugins_active {
+                match run_hooks(&context, &mut ctx, events, accumulator.as_mut()).await {
+                    Ok(encoded) => encoded,
+                    Err(message) => {
+                        let _ = tx.send(accumulator.error_chunk(&message)).await;
+                        error = Some(message);
+                        break;
+                    }
+                }
+            } else {
+                events.iter().for_each(|event| accumulator.on_event(event));
+                bytes
+            };
+            if outgoing.is_empty() {
+                continue;
+            }
+            if tx.send(outgoing).await.is_err() {
+                error = Some(CLIENT_DISCONNECTED.to_string());
+                break;
+            }
+        }
+        if let Some(event) = parser.finish() {
+            if plugins_active {
+                // The trailing event was never forwarded, only plugins may still rewrite it
+                match run_hooks(&context, &mut ctx, vec![event], accumulator.as_mut()).await {
+                    Ok(encoded) if !encoded.is_empty() => {
+                        let _ = tx.send(encoded).await;
+                    }
+                    Ok(_) => {}
+                    Err(message) => error = error.or(Some(message)),
+                }
+            } else {
+                accumulator.on_event(&event);
+            }
+        }
+
+        if let (Some(slot), Some(raw), None) = (slot, raw, &error) {
+            slot.fill(status, &headers, true, &raw);
+        }
+        accumulator.finish(&mut call);
+        call.status = status.as_u16();
+        if error.is_some() {
+            call.error = error;
+        }
+        call.latency = started.elapsed().as_millis() as u64;
+        call.metadata = ctx.metadata;
+        context.record(call);
+    });
+
+    let body = futures_util::stream::poll_fn(move |cx| {
+        rx.poll_recv(cx).map(|chunk| chunk.map(Ok::<_, Infallible>))
+    });
+    relay_response(status, &response_headers, Body::from_stream(body))
+}
+
+/// Runs stream hooks over parsed events and re-encodes the ones that are kept.
+/// Returns the rejection message when a plugin aborts the stream.
+async fn run_hooks(
+    context: &ProxyContext,
+    ctx: &mut PluginContext,
+    events: Vec<SseEvent>,
+    accumulator: &mut dyn StreamAccumulator,
+) -> Result<Bytes, String> {
+    let mut out = Vec::new();
+    for mut event in events {
+        match context.pipeline.run_stream_chunk(ctx, &mut event).await {
+            ChunkOutcome::Continue => {
+                accumulator.on_event(&event);
+                out.extend_from_slice(&event.encode());
+            }
+            ChunkOutcome::Insert(inserted) => {
+                for event in inserted.iter().chain([&event]) {
+                    accumulator.on_event(event);
+                    out.extend_from_slice(&event.encode());
+                }
+            }
+            ChunkOutcome::Skip => {}
+            ChunkOutcome::Reject(rejection) => return Err(rejection.message),
+        }
+    }
+    Ok(Bytes::from(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod sse_parser_tests {
+        use super::*;
+
+        #[test]
+        fn test_parses_complete_events() {
+            let mut parser = SseParser::new();
+            let events = parser.feed(b"data: one\n\nevent: ping\ndata: two\n\n");
+            assert_eq!(
+                events,
+                vec![
+                    SseEvent {
+                        event: None,
+                        data: "one".to_string()
+                    },
+                    SseEvent {
+                        event: Some("ping".to_string()),
+                        data: "two".to_string()
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn test_handles_events_split_across_chunks() {
+            let mut parser = SseParser::new();
+            assert!(parser.feed(b"data: {\"a\"").is_empty());
+            assert!(parser.feed(b":1}\r\n").is_empty());
+            let events = parser.feed(b"\r\n");
+            assert_eq!(events[0].json(), Some(json!({"a": 1})));
+        }
+
+        #[test]
+        fn test_handles_utf8_split_across_chunks() {
+            let mut parser = SseParser::new();
+            let bytes = "data: héllo\n\n".as_bytes();
+            assert!(parser.feed(&bytes[..8]).is_empty());
+            assert_eq!(parser.feed(&bytes[8..])[0].data, "héllo");
+        }
+
+        #[test]
+        fn test_joins_multiline_data_and_skips_comments() {
+            let mut parser = SseParser::new();
+            let events = parser.feed(b": keep-alive\ndata: a\ndata: b\n\n");
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].data, "a\nb");
+        }
+
+        #[test]
+        fn test_finish_flushes_unterminated_event() {
+            let mut parser = SseParser::new();
+            assert!(parser.feed(b"data: [DONE]").is_empty());
+            assert_eq!(parser.finish().unwrap().data, "[DONE]");
+            assert!(parser.finish().is_none());
+        }
+    }
+
+    mod relay_stream_tests {
+        use super::*;
+        use crate::proxy::testing::{spawn_upstream, start_proxy, wait_for_capture};
+        use axum::{routing::post, Router};
+        use std::time::Duration;
+        use tokio::sync::Notify;
+
+        fn sse_response(
+            chunks: impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static,
+        ) -> Response {
+            Response::builder()
+                .header("content-type", "text/event-stream")
+                .body(Body::from_stream(chunks))
+                .unwrap()
+        }
+
+        fn chunk(content: &str) -> Bytes {
+            let payload = json!({"choices": [{"delta": {"content": content}}]});
+            Bytes::from(format!("data: {}\n\n", payload))
+        }
+
+        async fn request(base: &str) -> reqwest::Response {
+            reqwest::Client::new()
+                .post(format!("{}/v1/chat/completions", base))
+                .json(&json!({"model": "gpt-4o", "stream": true, "messages": []}))
+                .send()
+                .await
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn test_chunks_are_forwarded_before_upstream_finishes() {
+            let release = Arc::new(Notify::new());
+            let gate = release.clone();
+            let app = Router::new().route(
+                "/v1/chat/completions",
+                post(move || {
+                    let gate = gate.clone();
+                    async move {
+                        let first = futures_util::stream::iter([Ok(chunk("Hel"))]);
+                        let rest = futures_util::stream::once(async move {
+                            gate.notified().await;
+                            Ok(chunk("lo"))
+                        })
+                        .chain(futures_util::stream::iter([Ok(
+                            Bytes::from("data: [DONE]\n\n"),
+                        )]));
+                        sse_response(first.chain(rest))
+                    }
+                }),
+            );
+            let (server, base) = start_proxy(&spawn_upstream(app).await).await;
+
+            let mut response = request(&base).await;
+            let first = tokio::time::timeout(Duration::from_secs(5), response.chunk())
+                .await
+                .expect("first chunk should arrive while upstream is still open")
+                .unwrap()
+                .unwrap();
+            assert_eq!(first, chunk("Hel"));
+
+            release.notify_one();
+            while response.chunk().await.unwrap().is_some() {}
+
+            let call = wait_for_capture(&server).await;
+            assert!(call.stream);
+            assert_eq!(call.response.content.as_deref(), Some("Hello"));
+            assert_eq!(call.error, None);
+        }
+
+        #[tokio::test]
+        async fn test_upstream_error_after_first_chunk() {
+            let app = Router::new().route(
+                "/v1/chat/completions",
+                post(|| async {
+                    let failure = futures_util::stream::once(async {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        Err(std::io::Error::other("boom"))
+                    });
+                    sse_response(futures_util::stream::iter([Ok(chunk("partial"))]).chain(failure))
+                }),
+            );
+            let (server, base) = start_proxy(&spawn_upstream(app).await).await;
+
+            let body = request(&base).await.text().await.unwrap();
+            assert!(body.starts_with(std::str::from_utf8(&chunk("partial")).unwrap()));
+            assert!(body.contains("upstream_error"));
+
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.response.content.as_deref(), Some("partial"));
+            assert!(call.error.unwrap().starts_with("upstream stream failed"));
+        }
+
+        #[tokio::test]
+        async fn test_client_disconnect_mid_stream() {
+            let app = Router::new().route(
+                "/v1/chat/completions",
+                post(|| async {
+                    let endless = futures_util::stream::unfold(0u32, |n| async move {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                        Some((Ok(chunk("x")), n + 1))
+                    });
+                    sse_response(endless)
+                }),
+            );
+            let (server, base) = start_proxy(&spawn_upstream(app).await).await;
+
+            let mut response = request(&base).await;
+            response.chunk().await.unwrap();
+            drop(response);
+
+            let call = wait_for_capture(&server).await;
+            assert_eq!(call.error.as_deref(), Some(CLIENT_DISCONNECTED));
+            assert!(call.response.content.unwrap().starts_with('x'));
+        }
+    }
+}
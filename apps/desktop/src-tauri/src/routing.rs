@@ -0,0 +1,784 @@
+//! Model-to-upstream routing
+//!
+//! Without a routing table every request goes to the proxy's configured
+//! upstream, LiteLLM by default. The table in `routes.toml` under the app
+//! data dir sends models elsewhere: routes match the requested model against
+//! glob patterns in order and name the [`Upstream`] to use, and aliases
+//! rename a model before routing, e.g. `gpt-4` → `ollama/llama3.2` to work
+//! offline. The file is re-read whenever it changes on disk.
+//!
+//! Failed requests are retried and then sent to the route's ordered
+//! `fallbacks`, see [`FailoverPolicy`]; unrouted models use the top-level
+//! `fallbacks` list.
+//!
+//! ```toml
+//! [aliases]
+//! "gpt-4" = "ollama/llama3.2"
+//!
+//! [[upstreams]]
+//! name = "ollama"
+//! base_url = "http://localhost:11434"
+//! kind = "ollama"
+//! auth = { type = "none" }
+//!
+//! [[routes]]
+//! pattern = "ollama/*"
+//! upstream = "ollama"
+//! strip_prefix = true
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::Provider;
+use crate::sync::lock;
+
+/// Routing table file under the app data dir
+pub const ROUTES_FILE: &str = "routes.toml";
+/// Name of the upstream configured in the proxy settings
+pub const DEFAULT_UPSTREAM: &str = "default";
+/// Key under which the route a call took is recorded on it
+pub const METADATA_KEY: &str = "route";
+
+/// Longest alias chain followed before giving up
+const MAX_ALIAS_DEPTH: usize = 16;
+
+/// Written on first launch so the format is discoverable
+const DEFAULT_ROUTES: &str = r#"# Blackbox routing table, reloaded automatically when saved.
+#
+# Routes are tried in order; models matching none of them go to the upstream
+# set in the proxy settings. Patterns support `*` and `?`.
+
+[aliases]
+# "gpt-4" = "ollama/llama3.2"
+
+[[upstreams]]
+name = "ollama"
+base_url = "http://localhost:11434"
+kind = "ollama"
+auth = { type = "none" }
+
+# [[upstreams]]
+# name = "anthropic"
+# base_url = "https://api.anthropic.com"
+# kind = "anthropic"
+# auth = { type = "credential", name = "ANTHROPIC_API_KEY" }
+
+[[routes]]
+pattern = "ollama/*"
+upstream = "ollama"
+strip_prefix = true
+# fallbacks = [{ upstream = "default", model = "gpt-4o-mini" }]
+
+# Retries with backoff, then fallbacks; breakers stop calling failing upstreams
+[failover]
+max_retries = 2
+breaker_threshold = 5
+breaker_open_secs = 30
+"#;
+
+/// API an upstream serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamKind {
+    /// OpenAI or any OpenAI-compatible host
+    OpenAi,
+    Anthropic,
+    Gemini,
+    /// Ollama through its OpenAI-compatible API
+    Ollama,
+    /// LiteLLM gateway, which accepts every supported request format
+    LiteLlm,
+}
+
+impl UpstreamKind {
+    /// Whether requests in `provider`'s wire format can be sent to this upstream
+    pub fn speaks(self, provider: Provider) -> bool {
+        match self {
+            UpstreamKind::OpenAi | UpstreamKind::Ollama => provider == Provider::OpenAi,
+            UpstreamKind::Anthropic => provider == Provider::Anthropic,
+            UpstreamKind::Gemini => provider == Provider::Gemini,
+            UpstreamKind::LiteLlm => true,
+        }
+    }
+}
+
+/// Credentials sent to an upstream
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RouteAuth {
+    /// Forward the client's own credentials
+    #[default]
+    Passthrough,
+    /// Strip the client's credentials
+    None,
+    /// Send this key in the request format's auth header
+    Key { key: String },
+    /// Send the key held by an environment variable
+    Env { var: String },
+    /// Send a key from the encrypted credential store
+    Credential { name: String },
+}
+
+impl RouteAuth {
+    /// Name of the mode, safe to show in the UI
+    pub fn mode(&self) -> &'static str {
+        match self {
+            RouteAuth::Passthrough => "passthrough",
+            RouteAuth::None => "none",
+            RouteAuth::Key { .. } => "key",
+            RouteAuth::Env { .. } => "env",
+            RouteAuth::Credential { .. } => "credential",
+        }
+    }
+}
+
+/// Host requests can be forwarded to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upstream {
+    pub name: String,
+    /// Base URL without the `/v1` suffix
+    pub base_url: String,
+    pub kind: UpstreamKind,
+    #[serde(default)]
+    pub auth: RouteAuth,
+}
+
+impl Upstream {
+    /// Joins the base URL with a request path and query
+    pub fn endpoint(&self, path_and_query: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            path_and_query.trim_start_matches('/')
+        )
+    }
+
+    /// Whether the base URL points at this machine
+    pub fn is_loopback(&self) -> bool {
+        let Ok(url) = reqwest::Url::parse(&self.base_url) else {
+            return false;
+        };
+        let host = url.host_str().unwrap_or_default();
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        host.eq_ignore_ascii_case("localhost")
+            || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+    }
+}
+
+/// Sends models matching `pattern` to an upstream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteRule {
+    pub pattern: String,
+    pub upstream: String,
+    /// Forward `ollama/llama3.2` as `llama3.2`
+    #[serde(default)]
+    pub strip_prefix: bool,
+    /// Tried in order once the upstream keeps failing
+    #[serde(default)]
+    pub fallbacks: Vec<Fallback>,
+}
+
+/// Upstream to try when the routed one fails
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fallback {
+    pub upstream: String,
+    /// Model to ask the fallback for, the routed model when unset
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// How hard the proxy tries before giving up on an upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FailoverPolicy {
+    /// Retries per upstream after the first attempt
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for each further one
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Longer `Retry-After` waits move on to the next fallback instead
+    pub max_retry_after_secs: u64,
+    /// Consecutive failures that open an upstream's circuit breaker, 0 disables it
+    pub breaker_threshold: u32,
+    /// How long an open breaker rejects requests before letting one through
+    pub breaker_open_secs: u64,
+}
+
+impl Default for FailoverPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay_ms: 250,
+            max_delay_ms: 8_000,
+            max_retry_after_secs: 30,
+            breaker_threshold: 5,
+            breaker_open_secs: 30,
+        }
+    }
+}
+
+/// Contents of `routes.toml`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingTable {
+    /// Model names replaced before routing
+    pub aliases: HashMap<String, String>,
+    pub upstreams: Vec<Upstream>,
+    pub routes: Vec<RouteRule>,
+    /// Fallbacks of models no route matches
+    pub fallbacks: Vec<Fallback>,
+    pub failover: FailoverPolicy,
+}
+
+/// Errors raised while loading the table or resolving a model
+#[derive(Debug, thiserror::Error)]
+pub enum RouteError {
+    #[error("failed to read {ROUTES_FILE}: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid {ROUTES_FILE}: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("route {pattern} names unknown upstream {upstream}")]
+    UnknownUpstream { pattern: String, upstream: String },
+    #[error("upstream {name} is defined twice")]
+    DuplicateUpstream { name: String },
+    #[error("upstream {name} has an invalid base_url: {message}")]
+    InvalidUrl { name: String, message: String },
+    #[error("alias {0} leads back to itself")]
+    AliasCycle(String),
+    #[error(
+        "model {model} is routed to upstream {upstream}, which does not accept {format} requests"
+    )]
+    WrongFormat {
+        model: String,
+        upstream: String,
+        format: &'static str,
+    },
+}
+
+impl RoutingTable {
+    /// Parses and validates a table
+    pub fn parse(text: &str) -> Result<Self, RouteError> {
+        let table: RoutingTable = toml::from_str(text)?;
+        table.validate()?;
+        Ok(table)
+    }
+
+    /// Checks upstream URLs, route targets and alias chains
+    pub fn validate(&self) -> Result<(), RouteError> {
+        let mut names = HashSet::new();
+        for upstream in &self.upstreams {
+            if upstream.name == DEFAULT_UPSTREAM || !names.insert(upstream.name.as_str()) {
+                return Err(RouteError::DuplicateUpstream {
+                    name: upstream.name.clone(),
+                });
+            }
+            let invalid = |message: String| RouteError::InvalidUrl {
+                name: upstream.name.clone(),
+                message,
+            };
+            let url =
+                reqwest::Url::parse(&upstream.base_url).map_err(|e| invalid(e.to_string()))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(invalid(format!("unsupported scheme {}", url.scheme())));
+            }
+        }
+        let targets = self.routes.iter().flat_map(|rule| {
+            let fallbacks = rule.fallbacks.iter().map(|f| f.upstream.as_str());
+            [rule.upstream.as_str()]
+                .into_iter()
+                .chain(fallbacks)
+                .map(|upstream| (rule.pattern.as_str(), upstream))
+        });
+        let top_level = self
+            .fallbacks
+            .iter()
+            .map(|f| ("fallbacks", f.upstream.as_str()));
+        for (pattern, upstream) in targets.chain(top_level) {
+            if !names.contains(upstream) && upstream != DEFAULT_UPSTREAM {
+                return Err(RouteError::UnknownUpstream {
+                    pattern: pattern.to_string(),
+                    upstream: upstream.to_string(),
+                });
+            }
+        }
+        for model in self.aliases.keys() {
+            self.unalias(model)?;
+        }
+        Ok(())
+    }
+
+    /// Follows aliases from `model`, returning every name passed through
+    fn unalias(&self, model: &str) -> Result<Vec<String>, RouteError> {
+        let mut chain = vec![model.to_string()];
+        let mut current = model;
+        while let Some(next) = self.aliases.get(current) {
+            if chain.contains(next) || chain.len() > MAX_ALIAS_DEPTH {
+                return Err(RouteError::AliasCycle(model.to_string()));
+            }
+            chain.push(next.clone());
+            current = next;
+        }
+        Ok(chain)
+    }
+
+    /// Works out where a request for `model` goes; `default` serves unrouted models
+    pub fn resolve(&self, model: &str, default: &Upstream) -> Result<ResolvedRoute, RouteError> {
+        let chain = self.unalias(model)?;
+        let aliased = chain.last().map_or(model, String::as_str);
+        let rule = self
+            .routes
+            .iter()
+            .find(|rule| glob_match(&rule.pattern, aliased));
+        let find = |name: &str| {
+            self.upstreams
+                .iter()
+                .find(|u| u.name == name)
+                .unwrap_or(default)
+                .clone()
+        };
+        let upstream = rule.map_or_else(|| default.clone(), |rule| find(&rule.upstream));
+        let fallbacks = rule.map_or(&self.fallbacks, |rule| &rule.fallbacks);
+        let forwarded = match rule {
+            Some(rule) if rule.strip_prefix => {
+                aliased.split_once('/').map_or(aliased, |(_, rest)| rest)
+            }
+            _ => aliased,
+        };
+        Ok(ResolvedRoute {
+            requested_model: model.to_string(),
+            model: forwarded.to_string(),
+            aliases: chain[1..].to_vec(),
+            pattern: rule.map(|rule| rule.pattern.clone()),
+            upstream: upstream.name.clone(),
+            base_url: upstream.base_url.clone(),
+            kind: upstream.kind,
+            auth: upstream.auth.mode(),
+            fallbacks: fallbacks.iter().map(|f| f.upstream.clone()).collect(),
+            target: upstream,
+            fallback_targets: fallbacks
+                .iter()
+                .map(|f| FallbackTarget {
+                    upstream: find(&f.upstream),
+                    model: f.model.clone(),
+                })
+                .collect(),
+            failover: self.failover,
+        })
+    }
+}
+
+/// Where a request for a model goes, returned by `resolve_route`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResolvedRoute {
+    pub requested_model: String,
+    /// Model name sent upstream
+    pub model: String,
+    /// Names the alias chain led through, empty without aliases
+    pub aliases: Vec<String>,
+    /// Pattern of the matching route, `None` for the default upstream
+    pub pattern: Option<String>,
+    pub upstream: String,
+    pub base_url: String,
+    pub kind: UpstreamKind,
+    /// Auth mode only, keys never leave the backend
+    pub auth: &'static str,
+    /// Names of the fallback upstreams in order
+    pub fallbacks: Vec<String>,
+    #[serde(skip)]
+    pub target: Upstream,
+    #[serde(skip)]
+    pub fallback_targets: Vec<FallbackTarget>,
+    #[serde(skip)]
+    pub failover: FailoverPolicy,
+}
+
+impl ResolvedRoute {
+    /// Fails unless the upstream takes requests in `provider`'s wire format
+    pub fn accepts(&self, provider: Provider) -> Result<(), RouteError> {
+        if self.kind.speaks(provider) {
+            return Ok(());
+        }
+        Err(RouteError::WrongFormat {
+            model: self.requested_model.clone(),
+            upstream: self.upstream.clone(),
+            format: provider.as_str(),
+        })
+    }
+
+    /// Whether the request and all its fallbacks stay with Ollama on this machine
+    pub fn is_local(&self) -> bool {
+        let local =
+            |upstream: &Upstream| upstream.kind == UpstreamKind::Ollama && upstream.is_loopback();
+        local(&self.target)
+            && self
+                .fallback_targets
+                .iter()
+                .all(|fallback| local(&fallback.upstream))
+    }
+}
+
+/// Resolved fallback of a route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FallbackTarget {
+    pub upstream: Upstream,
+    pub model: Option<String>,
+}
+
+struct Loaded {
+    table: RoutingTable,
+    /// Modification time of the file the table was read from
+    modified: Option<SystemTime>,
+}
+
+/// Live routing table, reloaded when its file changes
+pub struct RouteStore {
+    /// `None` keeps the table in memory only
+    path: Option<PathBuf>,
+    loaded: Mutex<Loaded>,
+}
+
+impl RouteStore {
+    /// Creates a store serving `table` that never reloads
+    pub fn in_memory(table: RoutingTable) -> Self {
+        Self {
+            path: None,
+            loaded: Mutex::new(Loaded {
+                table,
+                modified: None,
+            }),
+        }
+    }
+
+    /// Opens the table at `path`, writing the default table when there is none
+    pub fn open(path: PathBuf) -> Result<Self, RouteError> {
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, DEFAULT_ROUTES)?;
+        }
+        let store = Self {
+            path: Some(path),
+            ..Self::in_memory(RoutingTable::default())
+        };
+        store.reload()?;
+        Ok(store)
+    }
+
+    /// Re-reads the file, keeping the current table when the new one is invalid
+    pub fn reload(&self) -> Result<(), RouteError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let modified = fs::metadata(path)?.modified().ok();
+        let table = RoutingTable::parse(&fs::read_to_string(path)?);
+        let mut loaded = lock(&self.loaded);
+        // Remember the broken version too so it is not re-parsed on every request
+        loaded.modified = modified;
+        loaded.table = table?;
+        Ok(())
+    }
+
+    fn refresh(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let modified = fs::metadata(path).ok().and_then(|m| m.modified().ok());
+        if modified == lock(&self.loaded).modified {
+            return;
+        }
+        if let Err(e) = self.reload() {
+            log::warn!("Keeping the previous routing table: {}", e);
+        }
+    }
+
+    /// Returns the current table
+    pub fn table(&self) -> RoutingTable {
+        self.refresh();
+        lock(&self.loaded).table.clone()
+    }
+
+    /// Works out where a request for `model` goes
+    pub fn resolve(&self, model: &str, default: &Upstream) -> Result<ResolvedRoute, RouteError> {
+        self.refresh();
+        lock(&self.loaded).table.resolve(model, default)
+    }
+}
+
+/// Matches `text` against a pattern where `*` is any run of characters and `?` any one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text it currently swallows up to
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    p = star + 1;
+                    t = swallowed + 1;
+                    backtrack = Some((star, t));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_upstream() -> Upstream {
+        Upstream {
+            name: DEFAULT_UPSTREAM.to_string(),
+            base_url: "http://localhost:4213".to_string(),
+            kind: UpstreamKind::LiteLlm,
+            auth: RouteAuth::Passthrough,
+        }
+    }
+
+    fn table() -> RoutingTable {
+        RoutingTable::parse(
+            r#"
+            [aliases]
+            "gpt-4" = "local"
+            "local" = "ollama/llama3.2"
+
+            [[upstreams]]
+            name = "ollama"
+            base_url = "http://localhost:11434"
+            kind = "ollama"
+            auth = { type = "none" }
+
+            [[upstreams]]
+            name = "anthropic"
+            base_url = "https://api.anthropic.com"
+            kind = "anthropic"
+            auth = { type = "env", var = "ANTHROPIC_API_KEY" }
+
+            [[routes]]
+            pattern = "ollama/*"
+            upstream = "ollama"
+            strip_prefix = true
+
+            [[routes]]
+            pattern = "claude-*"
+            upstream = "anthropic"
+            fallbacks = [{ upstream = "default" }, { upstream = "ollama", model = "llama3.2" }]
+            "#,
+        )
+        .unwrap()
+    }
+
+    mod glob_tests {
+        use super::*;
+
+        #[test]
+        fn test_glob_match() {
+            assert!(glob_match("gpt-4*", "gpt-4o-mini"));
+            assert!(glob_match("*", ""));
+            assert!(glob_match("claude-?-*", "claude-3-opus"));
+            assert!(glob_match("*/llama*", "ollama/llama3.2"));
+            assert!(!glob_match("gpt-4*", "chatgpt-4"));
+            assert!(!glob_match("claude-?", "claude-35"));
+        }
+    }
+
+    mod resolve_tests {
+        use super::*;
+
+        #[test]
+        fn test_alias_chain_reaches_ollama() {
+            let route = table().resolve("gpt-4", &default_upstream()).unwrap();
+            assert_eq!(route.model, "llama3.2");
+            assert_eq!(route.aliases, vec!["local", "ollama/llama3.2"]);
+            assert_eq!(route.upstream, "ollama");
+            assert_eq!(route.pattern.as_deref(), Some("ollama/*"));
+            assert_eq!(route.auth, "none");
+            assert!(route.is_local());
+            assert_eq!(
+                route.target.endpoint("/v1/chat/completions"),
+                "http://localhost:11434/v1/chat/completions"
+            );
+        }
+
+        #[test]
+        fn test_remote_ollama_is_not_local() {
+            let mut route = table().resolve("gpt-4", &default_upstream()).unwrap();
+            for (base_url, local) in [
+                ("http://127.0.0.1:11434", true),
+                ("http://[::1]:11434", true),
+                ("http://gpu-box.lan:11434", false),
+                ("https://ollama.example.com", false),
+            ] {
+                route.target.base_url = base_url.to_string();
+                assert_eq!(route.is_local(), local, "{}", base_url);
+            }
+        }
+
+        #[test]
+        fn test_patterns_and_default() {
+            let table = table();
+            let claude = table
+                .resolve("claude-3-5-sonnet", &default_upstream())
+                .unwrap();
+            assert_eq!(
+                (claude.upstream.as_str(), claude.kind),
+                ("anthropic", UpstreamKind::Anthropic)
+            );
+            assert_eq!(claude.model, "claude-3-5-sonnet");
+            assert!(claude.aliases.is_empty());
+            assert!(!claude.is_local());
+            assert_eq!(claude.fallbacks, vec![DEFAULT_UPSTREAM, "ollama"]);
+            assert_eq!(
+                claude.fallback_targets[1].model.as_deref(),
+                Some("llama3.2")
+            );
+            assert_eq!(
+                claude.fallback_targets[1].upstream.kind,
+                UpstreamKind::Ollama
+            );
+
+            let other = table.resolve("gpt-4o", &default_upstream()).unwrap();
+            assert_eq!(other.upstream, DEFAULT_UPSTREAM);
+            assert_eq!(other.pattern, None);
+        }
+
+        #[test]
+        fn test_serialized_route_hides_keys() {
+            let mut table = table();
+            table.upstreams[1].auth = RouteAuth::Key {
+                key: "sk-ant-secret".to_string(),
+            };
+            let route = table.resolve("claude-3", &default_upstream()).unwrap();
+            let json = serde_json::to_string(&route).unwrap();
+            assert!(!json.contains("sk-ant-secret"));
+            assert!(json.contains("\"auth\":\"key\""));
+        }
+
+        #[test]
+        fn test_invalid_tables_are_rejected() {
+            let cycle = "[aliases]\na = \"b\"\nb = \"a\"\n";
+            assert!(matches!(
+                RoutingTable::parse(cycle),
+                Err(RouteError::AliasCycle(_))
+            ));
+            let unknown = "[[routes]]\npattern = \"*\"\nupstream = \"nowhere\"\n";
+            let unknown_fallback = "fallbacks = [{ upstream = \"nowhere\" }]\n";
+            assert!(matches!(
+                RoutingTable::parse(unknown_fallback),
+                Err(RouteError::UnknownUpstream { .. })
+            ));
+            assert!(matches!(
+                RoutingTable::parse(unknown),
+                Err(RouteError::UnknownUpstream { .. })
+            ));
+            let url = "[[upstreams]]\nname = \"x\"\nbase_url = \"ftp://host\"\nkind = \"openai\"\n";
+            assert!(matches!(
+                RoutingTable::parse(url),
+                Err(RouteError::InvalidUrl { .. })
+            ));
+        }
+
+        #[test]
+        fn test_route_must_speak_the_request_format() {
+            let route = table().resolve("gpt-4", &default_upstream()).unwrap();
+            assert!(route.accepts(Provider::OpenAi).is_ok());
+            assert!(matches!(
+                route.accepts(Provider::Anthropic),
+                Err(RouteError::WrongFormat {
+                    format: "anthropic",
+                    ..
+                })
+            ));
+        }
+
+        #[test]
+        fn test_kinds_speak_their_formats() {
+            assert!(UpstreamKind::Ollama.speaks(Provider::OpenAi));
+            assert!(!UpstreamKind::Ollama.speaks(Provider::Anthropic));
+            assert!(UpstreamKind::LiteLlm.speaks(Provider::Anthropic));
+            assert!(UpstreamKind::LiteLlm.speaks(Provider::Gemini));
+            assert!(!UpstreamKind::Gemini.speaks(Provider::OpenAi));
+        }
+    }
+
+    mod store_tests {
+        use super::*;
+
+        #[test]
+        fn test_default_table_is_written_and_valid() {
+            let dir = tempfile::tempdir().unwrap();
+            let store = RouteStore::open(dir.path().join(ROUTES_FILE)).unwrap();
+            let route = store
+                .resolve("ollama/qwen2.5", &default_upstream())
+                .unwrap();
+            assert_eq!(
+                (route.upstream.as_str(), route.model.as_str()),
+                ("ollama", "qwen2.5")
+            );
+        }
+
+        #[test]
+        fn test_changes_are_picked_up() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(ROUTES_FILE);
+            let store = RouteStore::open(path.clone()).unwrap();
+            assert_eq!(
+                store.resolve("gpt-4", &default_upstream()).unwrap().model,
+                "gpt-4"
+            );
+
+            let edited = format!(
+                "{}\n[aliases]\n\"gpt-4\" = \"ollama/llama3.2\"\n",
+                DEFAULT_ROUTES.replace("[aliases]", "")
+            );
+            fs::write(&path, edited).unwrap();
+            // Filesystems with coarse timestamps would otherwise miss the edit
+            let later = SystemTime::now() + std::time::Duration::from_secs(5);
+            fs::File::options()
+                .append(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(later)
+                .unwrap();
+            assert_eq!(
+                store.resolve("gpt-4", &default_upstream()).unwrap().model,
+                "llama3.2"
+            );
+
+            fs::write(&path, "not = [valid").unwrap();
+            let even_later = later + std::time::Duration::from_secs(5);
+            fs::File::options()
+                .append(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(even_later)
+                .unwrap();
+            assert_eq!(
+                store.resolve("gpt-4", &default_upstream()).unwrap().model,
+                "llama3.2"
+            );
+            assert!(store.reload().is_err());
+        }
+    }
+}
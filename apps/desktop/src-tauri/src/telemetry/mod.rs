@@ -0,0 +1,612 @@
+//! OpenTelemetry export of proxied calls
+//!
+//! When enabled, every captured call becomes a span following the GenAI
+//! semantic conventions, see [`spans`], sent to an OTLP/HTTP collector such
+//! as Langfuse, Phoenix or the OpenTelemetry Collector. Spans are batched in
+//! memory and flushed when a batch fills up or on a timer. Each batch is
+//! written to a queue dir under the app data dir before it is sent and only
+//! removed once the collector accepts it, so batches survive both collector
+//! outages and restarts; they are retried oldest first on every flush.
+
+pub mod spans;
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Notify;
+
+use crate::capture::CapturedCall;
+use crate::sync::lock;
+
+/// Directory under the app data dir holding batches waiting to be sent
+pub const TELEMETRY_QUEUE_DIR: &str = "telemetry-queue";
+/// Traces endpoint of a collector running next to the app
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4318/v1/traces";
+
+/// Batches kept for retrying before the oldest are dropped
+const MAX_QUEUED_BATCHES: usize = 1000;
+/// Spans kept in memory while the exporter is not flushing
+const MAX_PENDING_SPANS: usize = 10_000;
+/// How long the collector may take to accept a batch
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// OTLP export settings, persisted in the settings store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    /// OTLP/HTTP traces endpoint, including the `/v1/traces` path
+    pub endpoint: String,
+    /// Sent with every export, e.g. the collector's `Authorization`
+    pub headers: HashMap<String, String>,
+    /// `service.name` of the exported resource
+    pub service_name: String,
+    /// Whether prompts and answers are attached to spans
+    pub capture_content: bool,
+    /// Spans sent per export request
+    pub batch_size: usize,
+    /// Longest time a span waits before it is sent
+    pub flush_interval_secs: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: DEFAULT_OTLP_ENDPOINT.to_string(),
+            headers: HashMap::new(),
+            service_name: "blackbox".to_string(),
+            capture_content: false,
+            batch_size: 64,
+            flush_interval_secs: 5,
+        }
+    }
+}
+
+impl TelemetryConfig {
+    /// Checks the endpoint URL, headers and batching limits
+    pub fn validate(&self) -> Result<(), TelemetryError> {
+        let invalid = |message: String| Err(TelemetryError::InvalidConfig(message));
+        match reqwest::Url::parse(&self.endpoint) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(url) => {
+                return invalid(format!(
+                    "endpoint must use http or https, got {}",
+                    url.scheme()
+                ))
+            }
+            Err(e) => return invalid(format!("endpoint: {}", e)),
+        }
+        for (name, value) in &self.headers {
+            if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err()
+                || reqwest::header::HeaderValue::from_str(value).is_err()
+            {
+                return invalid(format!("header {:?} is not valid", name));
+            }
+        }
+        if self.batch_size == 0 || self.flush_interval_secs == 0 {
+            return invalid(
+                "batch_size and flush_interval_secs must be greater than zero".to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot of the exporter reported to the frontend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TelemetryStatus {
+    pub enabled: bool,
+    pub endpoint: String,
+    /// Spans waiting for the next flush
+    pub pending: usize,
+    /// Batches written to the queue and not yet accepted
+    pub queued: usize,
+    /// Spans the collector accepted since launch
+    pub exported: u64,
+    /// Spans given up on since launch
+    pub dropped: u64,
+    pub last_error: Option<String>,
+}
+
+/// Errors raised while configuring the exporter or sending spans
+#[derive(Debug, thiserror::Error)]
+pub enum TelemetryError {
+    #[error("invalid telemetry configuration: {0}")]
+    InvalidConfig(String),
+    #[error("failed to access the telemetry queue: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to reach the collector: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("collector answered {0}")]
+    Status(u16),
+}
+
+impl TelemetryError {
+    /// Whether sending the same batch again may succeed
+    fn is_retryable(&self) -> bool {
+        match self {
+            TelemetryError::Status(status) => matches!(status, 408 | 429 | 500..),
+            _ => true,
+        }
+    }
+}
+
+/// Encoded export requests waiting to be accepted, oldest first
+struct BatchQueue {
+    /// `None` keeps batches in memory only
+    dir: Option<PathBuf>,
+    memory: Mutex<VecDeque<Vec<u8>>>,
+}
+
+impl BatchQueue {
+    fn new(dir: Option<PathBuf>) -> io::Result<Self> {
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            dir,
+            memory: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// Queued batch files, oldest first
+    fn files(&self) -> io::Result<Vec<PathBuf>> {
+        let Some(dir) = &self.dir else {
+            return Ok(Vec::new());
+        };
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|e| e == "json"))
+            .collect();
+        // Names start with a zero-padded timestamp
+        files.sort();
+        Ok(files)
+    }
+
+    fn len(&self) -> usize {
+        match &self.dir {
+            Some(_) => self.files().map_or(0, |files| files.len()),
+            None => lock(&self.memory).len(),
+        }
+    }
+
+    /// Appends a batch, returning how many old batches were dropped to make room
+    fn push(&self, batch: Vec<u8>) -> io::Result<usize> {
+        let Some(dir) = &self.dir else {
+            let mut memory = lock(&self.memory);
+            memory.push_back(batch);
+            let excess = memory.len().saturating_sub(MAX_QUEUED_BATCHES);
+            memory.drain(..excess);
+            return Ok(excess);
+        };
+        let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let name = format!("{:020}-{}.json", nanos, uuid::Uuid::new_v4().simple());
+        // Written under another name first so a crash never leaves half a batch
+        let partial = dir.join(format!("{}.partial", name));
+        fs::write(&partial, batch)?;
+        fs::rename(partial, dir.join(name))?;
+
+        let files = self.files()?;
+        let excess = files.len().saturating_sub(MAX_QUEUED_BATCHES);
+        for file in &files[..excess] {
+            fs::remove_file(file)?;
+        }
+        Ok(excess)
+    }
+
+    fn front(&self) -> io::Result<Option<Vec<u8>>> {
+        match &self.dir {
+            Some(_) => match self.files()?.first() {
+                Some(file) => Ok(Some(fs::read(file)?)),
+                None => Ok(None),
+            },
+            None => Ok(lock(&self.memory).front().cloned()),
+        }
+    }
+
+    fn pop_front(&self) -> io::Result<()> {
+        match &self.dir {
+            Some(_) => match self.files()?.first() {
+                Some(file) => fs::remove_file(file),
+                None => Ok(()),
+            },
+            None => {
+                lock(&self.memory).pop_front();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Number of spans in an encoded export request
+fn span_count(batch: &[u8]) -> u64 {
+    let Ok(request) = serde_json::from_slice::<Value>(batch) else {
+        return 0;
+    };
+    request["resourceSpans"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|resource| resource["scopeSpans"].as_array().into_iter().flatten())
+        .map(|scope| {
+            scope["spans"]
+                .as_array()
+                .map_or(0, |spans| spans.len() as u64)
+        })
+        .sum()
+}
+
+/// Turns captured calls into spans and ships them to the collector
+pub struct TelemetryExporter {
+    config: Mutex<TelemetryConfig>,
+    client: reqwest::Client,
+    pending: Mutex<Vec<Value>>,
+    queue: BatchQueue,
+    /// Held while flushing so batches are sent one at a time and in order
+    flushing: tokio::sync::Mutex<()>,
+    /// Wakes [`TelemetryExporter::run`] when a batch is full
+    batch_full: Notify,
+    exported: AtomicU64,
+    dropped: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl TelemetryExporter {
+    fn new(config: TelemetryConfig, queue: BatchQueue) -> Self {
+        Self {
+            config: Mutex::new(config),
+            client: reqwest::Client::builder()
+                .timeout(EXPORT_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            pending: Mutex::new(Vec::new()),
+            queue,
+            flushing: tokio::sync::Mutex::new(()),
+            batch_full: Notify::new(),
+            exported: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Creates an exporter queueing failed batches in memory only
+    pub fn in_memory(config: TelemetryConfig) -> Self {
+        Self::new(
+            config,
+            BatchQueue {
+                dir: None,
+                memory: Mutex::new(VecDeque::new()),
+            },
+        )
+    }
+
+    /// Creates an exporter queueing batches in `dir`, picking up what an earlier run left there
+    pub fn open(dir: PathBuf, config: TelemetryConfig) -> Result<Self, TelemetryError> {
+        Ok(Self::new(config, BatchQueue::new(Some(dir))?))
+    }
+
+    /// Returns the current settings
+    pub fn config(&self) -> TelemetryConfig {
+        lock(&self.config).clone()
+    }
+
+    /// Validates and applies new settings
+    pub fn set_config(&self, config: TelemetryConfig) -> Result<(), TelemetryError> {
+        config.validate()?;
+        *lock(&self.config) = config;
+        self.batch_full.notify_one();
+        Ok(())
+    }
+
+    /// Reports what is waiting and what has been sent
+    pub fn status(&self) -> TelemetryStatus {
+        let config = self.config();
+        TelemetryStatus {
+            enabled: config.enabled,
+            endpoint: config.endpoint,
+            pending: lock(&self.pending).len(),
+            queued: self.queue.len(),
+            exported: self.exported.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            last_error: lock(&self.last_error).clone(),
+        }
+    }
+
+    /// Adds a span for a finished call to the next batch
+    pub fn record(&self, call: &CapturedCall) {
+        let config = self.config();
+        if !config.enabled {
+            return;
+        }
+        let span = spans::span(call, config.capture_content);
+        let mut pending = lock(&self.pending);
+        if pending.len() >= MAX_PENDING_SPANS {
+            pending.remove(0);
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        pending.push(span);
+        if pending.len() >= config.batch_size {
+            self.batch_full.notify_one();
+        }
+    }
+
+    /// Queues pending spans and sends every queued batch the collector accepts,
+    /// returning the number of spans exported
+    pub async fn flush(&self) -> Result<u64, TelemetryError> {
+        let _flushing = self.flushing.lock().await;
+        let config = self.config();
+        if !config.enabled {
+            return Ok(0);
+        }
+        let spans = std::mem::take(&mut *lock(&self.pending));
+        for chunk in spans.chunks(config.batch_size) {
+            let request = spans::export_request(&config.service_name, chunk.to_vec());
+            let batch = serde_json::to_vec(&request).map_err(io::Error::from)?;
+            let dropped = self.queue.push(batch)?;
+            if dropped > 0 {
+                log::warn!("Telemetry queue is full, dropped {} old batches", dropped);
+            }
+        }
+
+        let mut exported = 0;
+        while let Some(batch) = self.queue.front()? {
+            let result = self.send(&config, batch.clone()).await;
+            let error = match result {
+                Ok(()) => {
+                    exported += span_count(&batch);
+                    self.queue.pop_front()?;
+                    continue;
+                }
+                Err(error) => error,
+            };
+            *lock(&self.last_error) = Some(error.to_string());
+            if !error.is_retryable() {
+                log::warn!("Collector rejected a telemetry batch: {}", error);
+                self.dropped
+                    .fetch_add(span_count(&batch), Ordering::Relaxed);
+                self.queue.pop_front()?;
+                continue;
+            }
+            // Keep the batch and try again on the next flush
+            self.exported.fetch_add(exported, Ordering::Relaxed);
+            return Err(error);
+        }
+        self.exported.fetch_add(exported, Ordering::Relaxed);
+        *lock(&self.last_error) = None;
+        Ok(exported)
+    }
+
+    async fn send(&self, config: &TelemetryConfig, batch: Vec<u8>) -> Result<(), TelemetryError> {
+        let mut request = self
+            .client
+            .post(&config.endpoint)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(batch);
+        for (name, value) in &config.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(TelemetryError::Status(response.status().as_u16()));
+        }
+        Ok(())
+    }
+
+    /// Flushes whenever a batch fills up or the flush interval passes; runs until the app exits
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            let interval = Duration::from_secs(self.config().flush_interval_secs.max(1));
+            let _ = tokio::time::timeout(interval, self.batch_full.notified()).await;
+            if let Err(e) = self.flush().await {
+                log::debug!("Telemetry export failed, will retry: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+
+    use crate::proxy::testing::spawn_upstream;
+
+    /// Collector stand-in recording export requests, answering 503 while `down` is set
+    #[derive(Clone, Default)]
+    struct Collector {
+        requests: Arc<Mutex<Vec<(HeaderMap, Value)>>>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl Collector {
+        async fn spawn(&self) -> String {
+            let app =
+                Router::new()
+                    .route(
+                        "/v1/traces",
+                        post(
+                            |State(collector): State<Collector>,
+                             headers: HeaderMap,
+                             body: Bytes| async move {
+                                if collector.down.load(Ordering::Relaxed) {
+                                    return StatusCode::SERVICE_UNAVAILABLE;
+                                }
+                                let body = serde_json::from_slice(&body).unwrap();
+                                collector.requests.lock().unwrap().push((headers, body));
+                                StatusCode::OK
+                            },
+                        ),
+                    )
+                    .with_state(self.clone());
+            format!("{}/v1/traces", spawn_upstream(app).await)
+        }
+
+        fn span_counts(&self) -> Vec<u64> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, body)| span_count(body.to_string().as_bytes()))
+                .collect()
+        }
+    }
+
+    fn config(endpoint: &str) -> TelemetryConfig {
+        TelemetryConfig {
+            enabled: true,
+            endpoint: endpoint.to_string(),
+            batch_size: 2,
+            ..TelemetryConfig::default()
+        }
+    }
+
+    fn call(model: &str) -> CapturedCall {
+        let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+        call.model = model.to_string();
+        call.status = 200;
+        call
+    }
+
+    mod config_tests {
+        use super::*;
+
+        #[test]
+        fn test_validate() {
+            assert!(TelemetryConfig::default().validate().is_ok());
+            let invalid = [
+                TelemetryConfig {
+                    endpoint: "localhost:4318".to_string(),
+                    ..TelemetryConfig::default()
+                },
+                TelemetryConfig {
+                    batch_size: 0,
+                    ..TelemetryConfig::default()
+                },
+                TelemetryConfig {
+                    headers: HashMap::from([("bad header".to_string(), "x".to_string())]),
+                    ..TelemetryConfig::default()
+                },
+            ];
+            for config in invalid {
+                assert!(config.validate().is_err(), "{:?}", config);
+            }
+        }
+    }
+
+    mod export_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_batches_are_exported_with_headers() {
+            let collector = Collector::default();
+            let mut config = config(&collector.spawn().await);
+            config.headers =
+                HashMap::from([("authorization".to_string(), "Basic abc".to_string())]);
+            let exporter = TelemetryExporter::in_memory(config);
+            for model in ["a", "b", "c"] {
+                exporter.record(&call(model));
+            }
+            assert_eq!(exporter.status().pending, 3);
+
+            assert_eq!(exporter.flush().await.unwrap(), 3);
+            assert_eq!(collector.span_counts(), vec![2, 1]);
+            let requests = collector.requests.lock().unwrap();
+            assert_eq!(requests[0].0["authorization"], "Basic abc");
+            let span = &requests[0].1["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+            assert_eq!(span["name"], "chat a");
+            let status = exporter.status();
+            assert_eq!((status.pending, status.queued, status.exported), (0, 0, 3));
+        }
+
+        #[tokio::test]
+        async fn test_disabled_exporter_records_nothing() {
+            let collector = Collector::default();
+            let mut config = config(&collector.spawn().await);
+            config.enabled = false;
+            let exporter = TelemetryExporter::in_memory(config);
+            exporter.record(&call("a"));
+            assert_eq!(exporter.flush().await.unwrap(), 0);
+            assert_eq!(exporter.status().pending, 0);
+            assert!(collector.span_counts().is_empty());
+        }
+
+        #[tokio::test]
+        async fn test_outage_is_survived_on_disk() {
+            let dir = tempfile::tempdir().unwrap();
+            let collector = Collector::default();
+            let endpoint = collector.spawn().await;
+            collector.down.store(true, Ordering::Relaxed);
+
+            let exporter =
+                TelemetryExporter::open(dir.path().to_path_buf(), config(&endpoint)).unwrap();
+            exporter.record(&call("a"));
+            exporter.record(&call("b"));
+            exporter.record(&call("c"));
+            assert!(matches!(
+                exporter.flush().await,
+                Err(TelemetryError::Status(503))
+            ));
+            let status = exporter.status();
+            assert_eq!((status.queued, status.exported), (2, 0));
+            assert_eq!(status.last_error.as_deref(), Some("collector answered 503"));
+            drop(exporter);
+
+            // A restarted app delivers what the previous run could not, in order
+            collector.down.store(false, Ordering::Relaxed);
+            let exporter =
+                TelemetryExporter::open(dir.path().to_path_buf(), config(&endpoint)).unwrap();
+            exporter.record(&call("d"));
+            assert_eq!(exporter.flush().await.unwrap(), 4);
+            assert_eq!(collector.span_counts(), vec![2, 1, 1]);
+            let requests = collector.requests.lock().unwrap();
+            let first = &requests[0].1["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+            assert_eq!(first["name"], "chat a");
+            assert_eq!(exporter.status().queued, 0);
+        }
+
+        #[tokio::test]
+        async fn test_rejected_batches_are_dropped() {
+            let app = Router::new().route("/v1/traces", post(|| async { StatusCode::BAD_REQUEST }));
+            let endpoint = format!("{}/v1/traces", spawn_upstream(app).await);
+            let exporter = TelemetryExporter::in_memory(config(&endpoint));
+            exporter.record(&call("a"));
+            assert_eq!(exporter.flush().await.unwrap(), 0);
+            let status = exporter.status();
+            assert_eq!((status.queued, status.dropped), (0, 1));
+        }
+
+        #[tokio::test]
+        async fn test_full_batches_are_sent_without_waiting() {
+            let collector = Collector::default();
+            let mut config = config(&collector.spawn().await);
+            config.flush_interval_secs = 3600;
+            let exporter = Arc::new(TelemetryExporter::in_memory(config));
+            tokio::spawn(exporter.clone().run());
+            exporter.record(&call("a"));
+            exporter.record(&call("b"));
+            for _ in 0..200 {
+                if !collector.span_counts().is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(collector.span_counts(), vec![2]);
+        }
+    }
+}
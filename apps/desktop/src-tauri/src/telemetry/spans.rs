@@ -0,0 +1,320 @@
+//! OTLP spans for captured calls
+//!
+//! Each call becomes one client span named `{operation} {model}` carrying
+//! the OpenTelemetry GenAI semantic convention attributes. Spans are encoded
+//! in the OTLP/JSON mapping of the protobuf messages, where 64-bit integers
+//! are strings and ids are hex.
+
+use serde_json::{json, Value};
+
+use crate::capture::{CapturedCall, Message, ToolCall};
+
+/// Instrumentation scope reported with every span
+const SCOPE_NAME: &str = "blackbox.proxy";
+/// `SPAN_KIND_CLIENT`
+const KIND_CLIENT: u8 = 3;
+/// `STATUS_CODE_OK` and `STATUS_CODE_ERROR`
+const STATUS_OK: u8 = 1;
+const STATUS_ERROR: u8 = 2;
+
+/// Value of `gen_ai.operation.name` for a proxy route
+fn operation(endpoint: &str) -> &'static str {
+    if endpoint.ends_with("/completions") && !endpoint.ends_with("/chat/completions") {
+        "text_completion"
+    } else {
+        "chat"
+    }
+}
+
+/// Value of `gen_ai.system` for a captured provider name
+fn system(provider: &str) -> &str {
+    match provider {
+        "gemini" => "gcp.gemini",
+        other => other,
+    }
+}
+
+fn string(key: &str, value: impl Into<String>) -> Value {
+    json!({"key": key, "value": {"stringValue": value.into()}})
+}
+
+fn int(key: &str, value: u64) -> Value {
+    json!({"key": key, "value": {"intValue": value.to_string()}})
+}
+
+fn double(key: &str, value: f64) -> Value {
+    json!({"key": key, "value": {"doubleValue": value}})
+}
+
+fn strings(key: &str, values: &[&str]) -> Value {
+    let values: Vec<Value> = values.iter().map(|v| json!({"stringValue": v})).collect();
+    json!({"key": key, "value": {"arrayValue": {"values": values}}})
+}
+
+fn tool_call_parts(tool_calls: &[ToolCall]) -> impl Iterator<Item = Value> + '_ {
+    tool_calls.iter().map(|call| {
+        json!({
+            "type": "tool_call",
+            "id": call.id,
+            "name": call.function.name,
+            "arguments": call.function.arguments,
+        })
+    })
+}
+
+/// `gen_ai.input.messages` in the convention's role-and-parts shape
+fn input_messages(messages: &[Message]) -> Value {
+    messages
+        .iter()
+        .map(|message| {
+            let mut parts: Vec<Value> = Vec::new();
+            if let Some(content) = &message.content {
+                let kind = match &message.tool_call_id {
+                    Some(_) => "tool_call_response",
+                    None => "text",
+                };
+                let mut part = json!({"type": kind, "content": content});
+                if let Some(id) = &message.tool_call_id {
+                    part["id"] = json!(id);
+                }
+                parts.push(part);
+            }
+            parts.extend(tool_call_parts(&message.tool_calls));
+            json!({"role": message.role, "parts": parts})
+        })
+        .collect()
+}
+
+/// `gen_ai.output.messages` for the answer of `call`
+fn output_messages(call: &CapturedCall) -> Value {
+    let response = &call.response;
+    let mut parts: Vec<Value> = Vec::new();
+    if let Some(content) = &response.content {
+        parts.push(json!({"type": "text", "content": content}));
+    }
+    parts.extend(tool_call_parts(&response.tool_calls));
+    json!([{
+        "role": "assistant",
+        "parts": parts,
+        "finish_reason": response.finish_reason,
+    }])
+}
+
+/// Start of `call` in nanoseconds since the Unix epoch
+fn start_nanos(call: &CapturedCall) -> u64 {
+    chrono::DateTime::parse_from_rfc3339(&call.timestamp)
+        .ok()
+        .and_then(|time| time.timestamp_nanos_opt())
+        .and_then(|nanos| u64::try_from(nanos).ok())
+        .unwrap_or(0)
+}
+
+/// Encodes `call` as an OTLP span, with prompt and answer when `content` is set
+pub fn span(call: &CapturedCall, content: bool) -> Value {
+    let operation = operation(&call.endpoint);
+    let mut attributes = vec![
+        string("gen_ai.operation.name", operation),
+        string("gen_ai.system", system(&call.provider)),
+        string(
+            "gen_ai.request.model",
+            call.requested_model.as_deref().unwrap_or(&call.model),
+        ),
+        string("gen_ai.response.model", call.model.as_str()),
+        int("http.response.status_code", call.status.into()),
+        string("blackbox.call_id", call.id.as_str()),
+        string("blackbox.endpoint", call.endpoint.as_str()),
+        int("blackbox.latency_ms", call.latency),
+    ];
+    if let Some(parameters) = &call.parameters {
+        if let Some(temperature) = parameters.temperature {
+            attributes.push(double("gen_ai.request.temperature", temperature));
+        }
+        if let Some(top_p) = parameters.top_p {
+            attributes.push(double("gen_ai.request.top_p", top_p));
+        }
+        if let Some(max_tokens) = parameters.max_tokens {
+            attributes.push(int("gen_ai.request.max_tokens", max_tokens));
+        }
+    }
+    if let Some(usage) = &call.usage {
+        attributes.push(int("gen_ai.usage.input_tokens", usage.prompt_tokens));
+        attributes.push(int("gen_ai.usage.output_tokens", usage.completion_tokens));
+    }
+    if let Some(reason) = &call.response.finish_reason {
+        attributes.push(strings("gen_ai.response.finish_reasons", &[reason]));
+    }
+    if let Some(cost) = call.cost {
+        attributes.push(double("blackbox.cost_usd", cost));
+    }
+    if let Some(client) = &call.client {
+        attributes.push(string("blackbox.client", client.as_str()));
+    }
+    if call.stream {
+        attributes.push(json!({"key": "blackbox.stream", "value": {"boolValue": true}}));
+    }
+    if content {
+        attributes.push(string(
+            "gen_ai.input.messages",
+            input_messages(&call.messages).to_string(),
+        ));
+        attributes.push(string(
+            "gen_ai.output.messages",
+            output_messages(call).to_string(),
+        ));
+    }
+
+    let status = match &call.error {
+        Some(error) => {
+            attributes.push(string("error.type", call.status.to_string()));
+            json!({"code": STATUS_ERROR, "message": error})
+        }
+        None => json!({"code": STATUS_OK}),
+    };
+    let start = start_nanos(call);
+    let end = start + call.latency * 1_000_000;
+    let span_id = uuid::Uuid::new_v4().simple().to_string();
+    json!({
+        "traceId": uuid::Uuid::new_v4().simple().to_string(),
+        "spanId": &span_id[..16],
+        "name": format!("{} {}", operation, call.model),
+        "kind": KIND_CLIENT,
+        "startTimeUnixNano": start.to_string(),
+        "endTimeUnixNano": end.to_string(),
+        "attributes": attributes,
+        "status": status,
+    })
+}
+
+/// Wraps spans in an `ExportTraceServiceRequest`
+pub fn export_request(service_name: &str, spans: Vec<Value>) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    string("service.name", service_name),
+                    string("service.version", env!("CARGO_PKG_VERSION")),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": {"name": SCOPE_NAME, "version": env!("CARGO_PKG_VERSION")},
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{MessageRole, ModelParameters, Usage};
+
+    /// Finds the OTLP value of `key` among the span's attributes
+    fn attribute<'a>(span: &'a Value, key: &str) -> Option<&'a Value> {
+        span["attributes"]
+            .as_array()?
+            .iter()
+            .find(|a| a["key"] == key)
+            .map(|a| &a["value"])
+    }
+
+    fn call() -> CapturedCall {
+        let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+        call.timestamp = "2025-01-01T00:00:00Z".to_string();
+        call.model = "gpt-4o-2024-08-06".to_string();
+        call.requested_model = Some("gpt-4o".to_string());
+        call.parameters = Some(ModelParameters {
+            temperature: Some(0.2),
+            max_tokens: Some(256),
+            ..ModelParameters::default()
+        });
+        call.messages = vec![Message::text(MessageRole::User, "Hello")];
+        call.response.content = Some("Hi there".to_string());
+        call.response.finish_reason = Some("stop".to_string());
+        call.usage = Some(Usage::new(12, 3));
+        call.latency = 1500;
+        call.status = 200;
+        call
+    }
+
+    mod span_tests {
+        use super::*;
+
+        #[test]
+        fn test_genai_attributes() {
+            let span = span(&call(), false);
+            assert_eq!(span["name"], "chat gpt-4o-2024-08-06");
+            assert_eq!(span["kind"], 3);
+            assert_eq!(span["traceId"].as_str().unwrap().len(), 32);
+            assert_eq!(span["spanId"].as_str().unwrap().len(), 16);
+            assert_eq!(span["startTimeUnixNano"], "1735689600000000000");
+            assert_eq!(span["endTimeUnixNano"], "1735689601500000000");
+            assert_eq!(span["status"]["code"], 1);
+
+            let value = |key| attribute(&span, key).cloned().unwrap();
+            assert_eq!(value("gen_ai.system"), json!({"stringValue": "openai"}));
+            assert_eq!(
+                value("gen_ai.request.model"),
+                json!({"stringValue": "gpt-4o"})
+            );
+            assert_eq!(
+                value("gen_ai.response.model"),
+                json!({"stringValue": "gpt-4o-2024-08-06"})
+            );
+            assert_eq!(
+                value("gen_ai.usage.input_tokens"),
+                json!({"intValue": "12"})
+            );
+            assert_eq!(
+                value("gen_ai.usage.output_tokens"),
+                json!({"intValue": "3"})
+            );
+            assert_eq!(
+                value("gen_ai.request.temperature"),
+                json!({"doubleValue": 0.2})
+            );
+            assert_eq!(
+                value("gen_ai.response.finish_reasons"),
+                json!({"arrayValue": {"values": [{"stringValue": "stop"}]}})
+            );
+            assert!(attribute(&span, "gen_ai.input.messages").is_none());
+        }
+
+        #[test]
+        fn test_bodies_only_when_asked_for() {
+            let span = span(&call(), true);
+            let input = attribute(&span, "gen_ai.input.messages").unwrap();
+            let input: Value =
+                serde_json::from_str(input["stringValue"].as_str().unwrap()).unwrap();
+            assert_eq!(
+                input,
+                json!([{"role": "user", "parts": [{"type": "text", "content": "Hello"}]}])
+            );
+            let output = attribute(&span, "gen_ai.output.messages").unwrap();
+            assert!(output["stringValue"].as_str().unwrap().contains("Hi there"));
+        }
+
+        #[test]
+        fn test_errors_and_operations() {
+            let mut failed = CapturedCall::new("gemini", "/v1/completions");
+            failed.status = 429;
+            failed.error = Some("rate limited".to_string());
+            let span = span(&failed, false);
+            assert_eq!(
+                span["status"],
+                json!({"code": 2, "message": "rate limited"})
+            );
+            assert_eq!(
+                attribute(&span, "error.type").unwrap(),
+                &json!({"stringValue": "429"})
+            );
+            assert_eq!(
+                attribute(&span, "gen_ai.operation.name").unwrap(),
+                &json!({"stringValue": "text_completion"})
+            );
+            assert_eq!(
+                attribute(&span, "gen_ai.system").unwrap(),
+                &json!({"stringValue": "gcp.gemini"})
+            );
+        }
+    }
+}
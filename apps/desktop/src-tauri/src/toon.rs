@@ -0,0 +1,730 @@
+//! TOON (Token-Oriented Object Notation) codec
+//!
+//! TOON carries the JSON data model in fewer tokens: objects use indented
+//! `key: value` lines, arrays declare their length in the header, and
+//! arrays of uniform flat objects become a table that names each field
+//! once. For example
+//!
+//! ```text
+//! users[2]{id,name}:
+//!   1,Ada
+//!   2,Grace
+//! ```
+//!
+//! is `{"users": [{"id": 1, "name": "Ada"}, {"id": 2, "name": "Grace"}]}`.
+//! [`encode`] and [`decode`] round-trip every JSON value.
+
+use serde_json::{Map, Number, Value};
+
+/// Spaces per nesting level
+const INDENT: usize = 2;
+/// Separator between inline array values and table cells
+const DELIMITER: char = ',';
+
+/// Error raised when a document is not valid TOON
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("line {line}: {message}")]
+pub struct ToonError {
+    /// 1-based line the error was found on
+    pub line: usize,
+    pub message: String,
+}
+
+/// Encodes a JSON value as a TOON document
+pub fn encode(value: &Value) -> String {
+    let mut lines = Vec::new();
+    match value {
+        Value::Object(map) => encode_fields(map, 0, &mut lines),
+        Value::Array(items) => encode_array("", items, 0, &mut lines),
+        primitive => lines.push(encode_primitive(primitive)),
+    }
+    lines.join("\n")
+}
+
+/// Decodes a TOON document into a JSON value
+pub fn decode(text: &str) -> Result<Value, ToonError> {
+    let lines = split_lines(text)?;
+    let mut parser = Parser { lines, next: 0 };
+    let value = parser.document()?;
+    match parser.peek() {
+        Some(line) => Err(line.error("unexpected content after the document")),
+        None => Ok(value),
+    }
+}
+
+fn indent(depth: usize) -> String {
+    " ".repeat(depth * INDENT)
+}
+
+fn is_primitive(value: &Value) -> bool {
+    !matches!(value, Value::Array(_) | Value::Object(_))
+}
+
+fn encode_fields(map: &Map<String, Value>, depth: usize, lines: &mut Vec<String>) {
+    for (key, value) in map {
+        let key = encode_key(key);
+        match value {
+            Value::Object(child) => {
+                lines.push(format!("{}{}:", indent(depth), key));
+                encode_fields(child, depth + 1, lines);
+            }
+            Value::Array(items) => encode_array(&key, items, depth, lines),
+            primitive => lines.push(format!(
+                "{}{}: {}",
+                indent(depth),
+                key,
+                encode_primitive(primitive)
+            )),
+        }
+    }
+}
+
+/// Encodes an array under an already encoded key, empty for root and list items
+fn encode_array(key: &str, items: &[Value], depth: usize, lines: &mut Vec<String>) {
+    let header = format!("{}{}[{}]", indent(depth), key, items.len());
+    if items.is_empty() {
+        lines.push(format!("{}:", header));
+    } else if items.iter().all(is_primitive) {
+        lines.push(format!("{}: {}", header, join_row(items.iter())));
+    } else if let Some(fields) = table_fields(items) {
+        let names: Vec<String> = fields.iter().map(|f| encode_key(f)).collect();
+        lines.push(format!(
+            "{}{{{}}}:",
+            header,
+            names.join(&DELIMITER.to_string())
+        ));
+        for item in items {
+            let row = fields.iter().map(|f| &item[f.as_str()]);
+            lines.push(format!("{}{}", indent(depth + 1), join_row(row)));
+        }
+    } else {
+        lines.push(format!("{}:", header));
+        for item in items {
+            encode_list_item(item, depth + 1, lines);
+        }
+    }
+}
+
+/// Encodes one `- ` item of an expanded array
+fn encode_list_item(item: &Value, depth: usize, lines: &mut Vec<String>) {
+    match item {
+        Value::Object(map) if map.is_empty() => lines.push(format!("{}-", indent(depth))),
+        Value::Object(map) => {
+            // Fields line up with the first key, which shares the hyphen line
+            let start = lines.len();
+            encode_fields(map, depth + 1, lines);
+            lines[start] = format!(
+                "{}- {}",
+                indent(depth),
+                &lines[start][(depth + 1) * INDENT..]
+            );
+        }
+        Value::Array(items) => {
+            let start = lines.len();
+            encode_array("", items, depth, lines);
+            lines[start] = format!("{}- {}", indent(depth), &lines[start][depth * INDENT..]);
+        }
+        primitive => lines.push(format!(
+            "{}- {}",
+            indent(depth),
+            encode_primitive(primitive)
+        )),
+    }
+}
+
+/// Returns the shared field names when every item is a non-empty object with
+/// the same keys and only primitive values
+fn table_fields(items: &[Value]) -> Option<Vec<String>> {
+    let first = items.first()?.as_object()?;
+    if first.is_empty() {
+        return None;
+    }
+    let fields: Vec<String> = first.keys().cloned().collect();
+    let uniform = items.iter().all(|item| {
+        item.as_object().is_some_and(|map| {
+            map.len() == fields.len() && fields.iter().all(|f| map.get(f).is_some_and(is_primitive))
+        })
+    });
+    uniform.then_some(fields)
+}
+
+fn join_row<'a>(values: impl Iterator<Item = &'a Value>) -> String {
+    values
+        .map(encode_primitive)
+        .collect::<Vec<_>>()
+        .join(&DELIMITER.to_string())
+}
+
+fn encode_primitive(value: &Value) -> String {
+    match value {
+        Value::String(s) if needs_quotes(s) => quote(s),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn encode_key(key: &str) -> String {
+    let mut chars = key.chars();
+    let bare = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if bare {
+        key.to_string()
+    } else {
+        quote(key)
+    }
+}
+
+/// Returns true when a bare string would not decode back to itself
+fn needs_quotes(s: &str) -> bool {
+    s.is_empty()
+        || s.trim() != s
+        || matches!(s, "true" | "false" | "null")
+        || s.starts_with('-')
+        || s.starts_with(|c: char| c.is_ascii_digit())
+        || s.chars().any(|c| {
+            c.is_control() || matches!(c, ':' | '"' | '\\' | '[' | ']' | '{' | '}' | DELIMITER)
+        })
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A non-blank line with its nesting depth
+struct Line<'a> {
+    number: usize,
+    depth: usize,
+    content: &'a str,
+}
+
+impl Line<'_> {
+    fn error(&self, message: impl Into<String>) -> ToonError {
+        ToonError {
+            line: self.number,
+            message: message.into(),
+        }
+    }
+}
+
+fn split_lines(text: &str) -> Result<Vec<Line<'_>>, ToonError> {
+    let mut lines = Vec::new();
+    for (index, raw) in text.lines().enumerate() {
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        if raw.trim().is_empty() {
+            continue;
+        }
+        let content = raw.trim_start_matches(' ');
+        let spaces = raw.len() - content.len();
+        if content.starts_with('\t') || spaces % INDENT != 0 {
+            return Err(ToonError {
+                line: index + 1,
+                message: format!("indentation must be a multiple of {} spaces", INDENT),
+            });
+        }
+        lines.push(Line {
+            number: index + 1,
+            depth: spaces / INDENT,
+            content: content.trim_end(),
+        });
+    }
+    Ok(lines)
+}
+
+/// Parsed `key[N]{fields}: rest` prefix of a line
+struct Header<'a> {
+    key: Option<String>,
+    /// Declared length and table fields when the line opens an array
+    array: Option<(usize, Option<Vec<String>>)>,
+    /// Text after the colon
+    rest: &'a str,
+}
+
+/// Parses a line as a field or array header, or returns `None` for a bare value
+fn parse_header(content: &str) -> Result<Option<Header<'_>>, String> {
+    let (key, mut rest) = if content.starts_with('"') {
+        let (key, len) = read_quoted(content)?;
+        (Some(key), &content[len..])
+    } else {
+        let len = content
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(content.len());
+        let key = (len > 0).then(|| content[..len].to_string());
+        (key, &content[len..])
+    };
+
+    let mut array = None;
+    if let Some(after) = rest.strip_prefix('[') {
+        let Some(end) = after.find(']') else {
+            return Err("unterminated array length".to_string());
+        };
+        let len = after[..end]
+            .parse::<usize>()
+            .map_err(|_| format!("invalid array length {:?}", &after[..end]))?;
+        rest = &after[end + 1..];
+        let mut fields = None;
+        if let Some(after) = rest.strip_prefix('{') {
+            let end = find_unquoted(after, '}').ok_or("unterminated field list")?;
+            let names = split_row(&after[..end])?
+                .into_iter()
+                .map(|name| match name.starts_with('"') {
+                    true => read_quoted(name).map(|(key, _)| key),
+                    false => Ok(name.to_string()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            fields = Some(names);
+            rest = &after[end + 1..];
+        }
+        array = Some((len, fields));
+    } else if key.is_none() {
+        return Ok(None);
+    }
+
+    match rest.strip_prefix(':') {
+        Some(rest) => Ok(Some(Header {
+            key,
+            array,
+            rest: rest.trim_start(),
+        })),
+        // A bare word such as `hello` is a value, not a key
+        None if array.is_none() => Ok(None),
+        None => Err("expected ':' after array header".to_string()),
+    }
+}
+
+/// Reads a quoted string at the start of `s`, returning it and the bytes consumed
+fn read_quoted(s: &str) -> Result<(String, usize), String> {
+    let mut out = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((out, i + 1)),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('t') => out.push('\t'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    let code = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .filter(|_| hex.len() == 4)
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid escape \\u{}", hex))?;
+                    out.push(code);
+                }
+                other => return Err(format!("invalid escape \\{}", other.unwrap_or(' '))),
+            },
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Finds `target` outside of quoted strings
+fn find_unquoted(s: &str, target: char) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == target && !quoted => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits a row on delimiters outside of quoted strings
+fn split_row(s: &str) -> Result<Vec<&str>, String> {
+    let mut cells = Vec::new();
+    let mut rest = s;
+    while let Some(i) = find_unquoted(rest, DELIMITER) {
+        cells.push(rest[..i].trim());
+        rest = &rest[i + 1..];
+    }
+    cells.push(rest.trim());
+    Ok(cells)
+}
+
+fn parse_primitive(token: &str) -> Result<Value, String> {
+    let token = token.trim();
+    if token.starts_with('"') {
+        let (s, len) = read_quoted(token)?;
+        if len != token.len() {
+            return Err(format!("unexpected text after string: {}", &token[len..]));
+        }
+        return Ok(Value::String(s));
+    }
+    Ok(match token {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        "null" => Value::Null,
+        _ => match serde_json::from_str::<Number>(token) {
+            // serde_json may round floats by one ulp, the std parser is exact
+            Ok(n) if n.is_f64() => token
+                .parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .map_or(Value::Number(n), Value::Number),
+            Ok(n) => Value::Number(n),
+            Err(_) => Value::String(token.to_string()),
+        },
+    })
+}
+
+fn parse_row(row: &str, expected: usize) -> Result<Vec<Value>, String> {
+    let cells = split_row(row)?;
+    if cells.len() != expected {
+        return Err(format!(
+            "expected {} values, found {}",
+            expected,
+            cells.len()
+        ));
+    }
+    cells.into_iter().map(parse_primitive).collect()
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Line<'a>> {
+        self.lines.get(self.next)
+    }
+
+    /// Returns the next line when it sits exactly at `depth`
+    fn peek_at(&self, depth: usize) -> Result<Option<&Line<'a>>, ToonError> {
+        match self.peek() {
+            Some(line) if line.depth > depth => Err(line.error("unexpected indentation")),
+            Some(line) if line.depth == depth => Ok(Some(line)),
+            _ => Ok(None),
+        }
+    }
+
+    fn document(&mut self) -> Result<Value, ToonError> {
+        let Some(first) = self.peek() else {
+            return Ok(Value::Object(Map::new()));
+        };
+        let (number, content) = (first.number, first.content);
+        let at = |message: String| ToonError {
+            line: number,
+            message,
+        };
+        if first.depth != 0 {
+            return Err(first.error("unexpected indentation"));
+        }
+        match parse_header(content).map_err(at)? {
+            Some(Header {
+                key: None,
+                array: Some((len, fields)),
+                rest,
+            }) => {
+                self.next += 1;
+                self.array(len, fields, rest, 0, number)
+            }
+            Some(_) => self.fields(0),
+            None if self.lines.len() == 1 => {
+                self.next += 1;
+                parse_primitive(content).map_err(at)
+            }
+            None => Err(first.error("expected a key")),
+        }
+    }
+
+    /// Parses consecutive fields at `depth` into an object
+    fn fields(&mut self, depth: usize) -> Result<Value, ToonError> {
+        let mut map = Map::new();
+        while let Some(line) = self.peek_at(depth)? {
+            let (number, content) = (line.number, line.content);
+            self.next += 1;
+            self.field(content, number, depth, &mut map)?;
+        }
+        Ok(Value::Object(map))
+    }
+
+    /// Parses one field whose nested content sits at `depth + 1`
+    fn field(
+        &mut self,
+        content: &str,
+        number: usize,
+        depth: usize,
+        map: &mut Map<String, Value>,
+    ) -> Result<(), ToonError> {
+        let at = |message: String| ToonError {
+            line: number,
+            message,
+        };
+        let header = parse_header(content)
+            .map_err(at)?
+            .ok_or_else(|| at("expected a key".to_string()))?;
+        let key = header.key.ok_or_else(|| at("expected a key".to_string()))?;
+        let value = match header.array {
+            Some((len, fields)) => self.array(len, fields, header.rest, depth, number)?,
+            None if header.rest.is_empty() => self.fields(depth + 1)?,
+            None => parse_primitive(header.rest).map_err(at)?,
+        };
+        if map.insert(key.clone(), value).is_some() {
+            return Err(at(format!("duplicate key {:?}", key)));
+        }
+        Ok(())
+    }
+
+    /// Parses the body of an array whose header sits at `depth`
+    fn array(
+        &mut self,
+        len: usize,
+        fields: Option<Vec<String>>,
+        rest: &str,
+        depth: usize,
+        number: usize,
+    ) -> Result<Value, ToonError> {
+        let at = |message: String| ToonError {
+            line: number,
+            message,
+        };
+        if !rest.is_empty() {
+            if fields.is_some() {
+                return Err(at("table rows must start on the next line".to_string()));
+            }
+            return parse_row(rest, len).map(Value::Array).map_err(at);
+        }
+
+        let mut items = Vec::with_capacity(len.min(self.lines.len()));
+        while items.len() < len {
+            let Some(line) = self.peek_at(depth + 1)? else {
+                return Err(at(format!("expected {} items, found {}", len, items.len())));
+            };
+            let (number, content) = (line.number, line.content);
+            self.next += 1;
+            let item = match &fields {
+                Some(fields) => {
+                    let cells = parse_row(content, fields.len()).map_err(|message| ToonError {
+                        line: number,
+                        message,
+                    })?;
+                    Value::Object(fields.iter().cloned().zip(cells).collect())
+                }
+                None => self.list_item(content, number, depth + 1)?,
+            };
+            items.push(item);
+        }
+        if let Some(line) = self.peek_at(depth + 1)? {
+            return Err(line.error(format!("array declares {} items but has more", len)));
+        }
+        Ok(Value::Array(items))
+    }
+
+    /// Parses a `- ` item whose hyphen sits at `depth`
+    fn list_item(
+        &mut self,
+        content: &str,
+        number: usize,
+        depth: usize,
+    ) -> Result<Value, ToonError> {
+        let at = |message: String| ToonError {
+            line: number,
+            message,
+        };
+        if content == "-" {
+            return Ok(Value::Object(Map::new()));
+        }
+        let item = content
+            .strip_prefix("- ")
+            .ok_or_else(|| at("expected a list item".to_string()))?;
+        match parse_header(item).map_err(at)? {
+            Some(Header {
+                key: None,
+                array: Some((len, fields)),
+                rest,
+            }) => self.array(len, fields, rest, depth, number),
+            Some(_) => {
+                // The first field shares the hyphen line, the rest line up with it
+                let mut map = Map::new();
+                self.field(item, number, depth + 1, &mut map)?;
+                while let Some(line) = self.peek_at(depth + 1)? {
+                    let (number, content) = (line.number, line.content);
+                    self.next += 1;
+                    self.field(content, number, depth + 1, &mut map)?;
+                }
+                Ok(Value::Object(map))
+            }
+            None => parse_primitive(item).map_err(at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    mod encode_tests {
+        use super::*;
+
+        #[test]
+        fn test_nested_object() {
+            let value = json!({"user": {"id": 1, "name": "Ada", "tags": ["a", "b"]}});
+            assert_eq!(
+                encode(&value),
+                "user:\n  id: 1\n  name: Ada\n  tags[2]: a,b"
+            );
+        }
+
+        #[test]
+        fn test_uniform_objects_become_a_table() {
+            let value = json!({"users": [
+                {"id": 1, "name": "Ada", "admin": true},
+                {"id": 2, "name": "Grace Hopper", "admin": false}
+            ]});
+            assert_eq!(
+                encode(&value),
+                "users[2]{admin,id,name}:\n  true,1,Ada\n  false,2,Grace Hopper"
+            );
+        }
+
+        #[test]
+        fn test_mixed_arrays_use_list_items() {
+            let value = json!([1, {"a": {"b": 2}, "c": 3}, [4, 5], {}]);
+            assert_eq!(
+                encode(&value),
+                "[4]:\n  - 1\n  - a:\n      b: 2\n    c: 3\n  - [2]: 4,5\n  -"
+            );
+        }
+
+        #[test]
+        fn test_ambiguous_strings_are_quoted() {
+            let value = json!({"a": "true", "b": "42", "c": "x, y", "d": "", "e": " pad", "f": "- item", "g key": "line\nbreak"});
+            assert_eq!(
+                encode(&value),
+                "a: \"true\"\nb: \"42\"\nc: \"x, y\"\nd: \"\"\ne: \" pad\"\nf: \"- item\"\n\"g key\": \"line\\nbreak\""
+            );
+        }
+
+        #[test]
+        fn test_empty_values() {
+            assert_eq!(encode(&json!({})), "");
+            assert_eq!(encode(&json!([])), "[0]:");
+            assert_eq!(encode(&json!({"a": {}, "b": []})), "a:\nb[0]:");
+        }
+
+        #[test]
+        fn test_smaller_than_json_for_tables() {
+            let rows: Vec<Value> = (0..20)
+                .map(|i| json!({"id": i, "status": "open", "title": format!("Issue {}", i)}))
+                .collect();
+            let value = json!({"issues": rows});
+            assert!(encode(&value).len() * 2 < value.to_string().len());
+        }
+    }
+
+    mod decode_tests {
+        use super::*;
+
+        #[test]
+        fn test_decodes_table() {
+            let text = "users[2]{id,name}:\n  1,Ada\n  2,\"Hopper, Grace\"";
+            assert_eq!(
+                decode(text).unwrap(),
+                json!({"users": [{"id": 1, "name": "Ada"}, {"id": 2, "name": "Hopper, Grace"}]})
+            );
+        }
+
+        #[test]
+        fn test_decodes_primitives() {
+            assert_eq!(decode("hello world").unwrap(), json!("hello world"));
+            assert_eq!(decode("-1.5").unwrap(), json!(-1.5));
+            assert_eq!(decode("null").unwrap(), Value::Null);
+            assert_eq!(decode("\"a: b\"").unwrap(), json!("a: b"));
+        }
+
+        #[test]
+        fn test_length_mismatch_is_an_error() {
+            let err = decode("tags[3]: a,b").unwrap_err();
+            assert_eq!(err.line, 1);
+            assert!(err.message.contains("expected 3 values"));
+
+            let err = decode("items[1]:\n  - a\n  - b").unwrap_err();
+            assert_eq!(err.line, 3);
+        }
+
+        #[test]
+        fn test_bad_indentation_is_an_error() {
+            let err = decode("a:\n   b: 1").unwrap_err();
+            assert_eq!(err.line, 2);
+            assert!(decode("a: 1\n  b: 2").is_err());
+        }
+
+        #[test]
+        fn test_duplicate_keys_are_an_error() {
+            assert!(decode("a: 1\na: 2").is_err());
+        }
+    }
+
+    mod property_tests {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn json_value() -> impl Strategy<Value = Value> {
+            let leaf = prop_oneof![
+                Just(Value::Null),
+                any::<bool>().prop_map(Value::Bool),
+                any::<i64>().prop_map(Value::from),
+                any::<u64>().prop_map(Value::from),
+                any::<f64>()
+                    .prop_filter("finite", |f| f.is_finite())
+                    .prop_map(Value::from),
+                any::<String>().prop_map(Value::String),
+                "[a-z0-9 ,:\\-\"\\[\\]{}]{0,12}".prop_map(Value::String),
+            ];
+            leaf.prop_recursive(4, 48, 6, |inner| {
+                prop_oneof![
+                    prop::collection::vec(inner.clone(), 0..6).prop_map(Value::Array),
+                    prop::collection::btree_map("[a-z_][a-z0-9_. ]{0,6}|.{0,4}", inner, 0..6)
+                        .prop_map(|map| Value::Object(map.into_iter().collect())),
+                    // Uniform rows exercise the table form
+                    prop::collection::vec((any::<i32>(), "[a-z ,]{0,8}"), 1..5).prop_map(|rows| {
+                        Value::Array(
+                            rows.into_iter()
+                                .map(|(id, name)| json!({"id": id, "name": name}))
+                                .collect(),
+                        )
+                    }),
+                ]
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn test_round_trip(value in json_value()) {
+                let text = encode(&value);
+                prop_assert_eq!(decode(&text).unwrap(), value);
+            }
+
+            #[test]
+            fn test_decode_never_panics(text in "[ a-z0-9:,\\-\\[\\]{}\"\n]{0,64}") {
+                let _ = decode(&text);
+            }
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! Token and cost accounting
+//!
+//! Providers report usage in complete answers but often not in streams, so
+//! every successful call is also counted locally with the tokenizer of its
+//! model family, see [`tokens`]. The local count is recorded on the call
+//! under `tokens` and stands in for the usage the provider did not report.
+//! The call is then priced from `pricing.toml`, see [`pricing`], and added
+//! to a history of totals per day, model and client kept in the app data
+//! dir, which [`UsageLedger::summary`] reports on. Answers served from the
+//! cache are free.
+
+pub mod pricing;
+pub mod tokens;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::budget::UNKNOWN_CLIENT;
+use crate::cache;
+use crate::capture::CapturedCall;
+use crate::sync::lock;
+use pricing::PriceStore;
+use tokens::Tokenizer;
+
+/// File under the app data dir holding the usage history
+pub const USAGE_HISTORY_FILE: &str = "usage-history.json";
+/// Key under which the local token count is recorded on a call
+pub const METADATA_KEY: &str = "tokens";
+
+/// What usage totals are broken down by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageGroup {
+    Model,
+    Client,
+    Day,
+}
+
+/// Inclusive range of local days; open ends are unbounded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl UsageRange {
+    fn contains(&self, day: NaiveDate) -> bool {
+        self.from.is_none_or(|from| day >= from) && self.to.is_none_or(|to| day <= to)
+    }
+}
+
+/// Calls, tokens and cost added up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageTotals {
+    pub calls: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// US dollars, leaving out calls whose model has no price
+    pub cost: f64,
+    /// Calls whose tokens were counted locally
+    pub estimated_calls: u64,
+    /// Calls with usage whose model has no price
+    pub unpriced_calls: u64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, other: &UsageTotals) {
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cost += other.cost;
+        self.estimated_calls += other.estimated_calls;
+        self.unpriced_calls += other.unpriced_calls;
+    }
+}
+
+/// Totals of one model, client or day
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageGroupTotals {
+    pub key: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+/// Usage over a range of days, broken down by model, client or day
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageSummary {
+    pub range: UsageRange,
+    pub group_by: UsageGroup,
+    pub total: UsageTotals,
+    /// Days in order, models and clients most expensive first
+    pub groups: Vec<UsageGroupTotals>,
+}
+
+/// Line of the history file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct HistoryRow {
+    day: NaiveDate,
+    model: String,
+    client: String,
+    #[serde(flatten)]
+    totals: UsageTotals,
+}
+
+/// Totals by day, model and client
+type History = BTreeMap<(NaiveDate, String, String), UsageTotals>;
+
+/// Counts, prices and remembers the usage of every call
+pub struct UsageLedger {
+    /// `None` keeps the history in memory only
+    path: Option<PathBuf>,
+    prices: PriceStore,
+    history: Mutex<History>,
+}
+
+impl UsageLedger {
+    /// Creates a ledger that forgets its history on restart
+    pub fn in_memory(prices: PriceStore) -> Self {
+        Self {
+            path: None,
+            prices,
+            history: Mutex::new(History::new()),
+        }
+    }
+
+    /// Creates a ledger keeping its history in `path`
+    pub fn open(path: PathBuf, prices: PriceStore) -> Self {
+        let rows: Vec<HistoryRow> = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        let history = rows
+            .into_iter()
+            .map(|row| ((row.day, row.model, row.client), row.totals))
+            .collect();
+        Self {
+            path: Some(path),
+            prices,
+            history: Mutex::new(history),
+        }
+    }
+
+    /// Whether calls to `model` cost nothing
+    pub fn is_free(&self, model: &str) -> bool {
+        self.prices.is_free(model)
+    }
+
+    /// Counts a finished call's tokens, fills in missing usage, prices it and adds it to the history
+    pub fn account(&self, call: &mut CapturedCall) {
+        let mut totals = UsageTotals {
+            calls: 1,
+            ..UsageTotals::default()
+        };
+        if call.error.is_none() && (200..300).contains(&call.status) {
+            let tokenizer = Tokenizer::for_model(&call.model);
+            let counted = tokens::count_call(call, tokenizer);
+            let estimated = call.usage.is_none();
+            call.metadata.insert(
+                METADATA_KEY.to_string(),
+                json!({
+                    "tokenizer": tokenizer.as_str(),
+                    "prompt_tokens": counted.prompt_tokens,
+                    "completion_tokens": counted.completion_tokens,
+                    "estimated": estimated,
+                }),
+            );
+            if estimated {
+                call.usage = Some(counted);
+                totals.estimated_calls = 1;
+            }
+        }
+        if let Some(usage) = call.usage {
+            let cached = call
+                .metadata
+                .get(cache::METADATA_KEY)
+                .and_then(Value::as_str);
+            call.cost = if matches!(cached, Some("hit" | "semantic-hit")) {
+                Some(0.0)
+            } else {
+                self.prices.cost(&call.model, &usage)
+            };
+            totals.prompt_tokens = usage.prompt_tokens;
+            totals.completion_tokens = usage.completion_tokens;
+            match call.cost {
+                Some(cost) => totals.cost = cost,
+                None => totals.unpriced_calls = 1,
+            }
+        }
+
+        let day = DateTime::parse_from_rfc3339(&call.timestamp)
+            .map(|t| t.with_timezone(&Local).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
+        let client = call.client.as_deref().unwrap_or(UNKNOWN_CLIENT);
+        let mut history = lock(&self.history);
+        history
+            .entry((day, call.model.clone(), client.to_string()))
+            .or_default()
+            .add(&totals);
+        let rows: Vec<HistoryRow> = history
+            .iter()
+            .map(|((day, model, client), totals)| HistoryRow {
+                day: *day,
+                model: model.clone(),
+                client: client.clone(),
+                totals: *totals,
+            })
+            .collect();
+        drop(history);
+        if let Err(e) = self.save(&rows) {
+            log::warn!("Failed to save usage history: {}", e);
+        }
+    }
+
+    fn save(&self, rows: &[HistoryRow]) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let staging = path.with_extension("tmp");
+        fs::write(&staging, serde_json::to_vec(rows)?)?;
+        fs::rename(&staging, path)
+    }
+
+    /// Adds up the calls in `range`, broken down by `group_by`
+    pub fn summary(&self, range: UsageRange, group_by: UsageGroup) -> UsageSummary {
+        let mut total = UsageTotals::default();
+        let mut groups: BTreeMap<String, UsageTotals> = BTreeMap::new();
+        for ((day, model, client), totals) in lock(&self.history).iter() {
+            if !range.contains(*day) {
+                continue;
+            }
+            let key = match group_by {
+                UsageGroup::Model => model.clone(),
+                UsageGroup::Client => client.clone(),
+                UsageGroup::Day => day.to_string(),
+            };
+            groups.entry(key).or_default().add(totals);
+            total.add(totals);
+        }
+        let mut groups: Vec<UsageGroupTotals> = groups
+            .into_iter()
+            .map(|(key, totals)| UsageGroupTotals { key, totals })
+            .collect();
+        if group_by != UsageGroup::Day {
+            groups.sort_by(|a, b| {
+                b.totals
+                    .cost
+                    .total_cmp(&a.totals.cost)
+                    .then_with(|| {
+                        let tokens = |t: &UsageTotals| t.prompt_tokens + t.completion_tokens;
+                        tokens(&b.totals).cmp(&tokens(&a.totals))
+                    })
+                    .then_with(|| a.key.cmp(&b.key))
+            });
+        }
+        UsageSummary {
+            range,
+            group_by,
+            total,
+            groups,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{Message, MessageRole, Usage};
+    use pricing::PricingTable;
+
+    fn ledger() -> UsageLedger {
+        let table =
+            PricingTable::parse("[[prices]]\npattern = \"gpt-4o*\"\ninput = 2.5\noutput = 10\n")
+                .unwrap();
+        UsageLedger::in_memory(PriceStore::in_memory(table))
+    }
+
+    /// Local noon of `day`, as a call timestamp
+    fn at(day: &str) -> String {
+        let day: NaiveDate = day.parse().unwrap();
+        day.and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+            .to_rfc3339()
+    }
+
+    fn call(model: &str, client: &str, timestamp: &str, usage: Option<Usage>) -> CapturedCall {
+        let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+        call.model = model.to_string();
+        call.client = Some(client.to_string());
+        call.timestamp = timestamp.to_string();
+        call.status = 200;
+        call.messages = vec![Message::text(MessageRole::User, "Hello, world!")];
+        call.response.content = Some("Hi there".to_string());
+        call.usage = usage;
+        call
+    }
+
+    mod account_tests {
+        use super::*;
+
+        #[test]
+        fn test_reported_usage_is_priced() {
+            let ledger = ledger();
+            let mut call = call(
+                "gpt-4o",
+                "cursor",
+                &at("2026-03-02"),
+                Some(Usage::new(1_000, 100)),
+            );
+            ledger.account(&mut call);
+            assert_eq!(call.usage, Some(Usage::new(1_000, 100)));
+            assert!((call.cost.unwrap() - 0.0035).abs() < 1e-12);
+            assert_eq!(call.metadata[METADATA_KEY]["estimated"], false);
+            assert_eq!(call.metadata[METADATA_KEY]["tokenizer"], "o200k_base");
+        }
+
+        #[test]
+        fn test_missing_usage_is_counted_locally() {
+            let ledger = ledger();
+            let mut call = call("gpt-4o-mini", "cursor", &at("2026-03-02"), None);
+            ledger.account(&mut call);
+            let usage = call.usage.unwrap();
+            assert_eq!(usage.prompt_tokens, 10);
+            assert_eq!(usage.completion_tokens, 2);
+            assert_eq!(call.metadata[METADATA_KEY]["estimated"], true);
+            assert!(call.cost.unwrap() > 0.0);
+        }
+
+        #[test]
+        fn test_failed_unpriced_and_cached_calls() {
+            let ledger = ledger();
+            let mut failed = call("gpt-4o", "cursor", &at("2026-03-02"), None);
+            failed.status = 502;
+            failed.error = Some("bad gateway".to_string());
+            ledger.account(&mut failed);
+            assert_eq!(failed.usage, None);
+            assert_eq!(failed.cost, None);
+
+            let mut unpriced = call("mistral:7b", "cursor", &at("2026-03-02"), None);
+            ledger.account(&mut unpriced);
+            assert!(unpriced.usage.is_some());
+            assert_eq!(unpriced.cost, None);
+
+            let mut cached = call(
+                "gpt-4o",
+                "cursor",
+                &at("2026-03-02"),
+                Some(Usage::new(1_000, 100)),
+            );
+            cached
+                .metadata
+                .insert(cache::METADATA_KEY.to_string(), json!("hit"));
+            ledger.account(&mut cached);
+            assert_eq!(cached.cost, Some(0.0));
+
+            let total = ledger
+                .summary(UsageRange::default(), UsageGroup::Model)
+                .total;
+            assert_eq!(total.calls, 3);
+            assert_eq!(total.unpriced_calls, 1);
+            assert_eq!(total.estimated_calls, 1);
+        }
+    }
+
+    mod summary_tests {
+        use super::*;
+
+        fn ledger_with_calls() -> UsageLedger {
+            let ledger = ledger();
+            for (model, client, timestamp, prompt) in [
+                ("gpt-4o", "cursor", &at("2026-03-01"), 1_000),
+                ("gpt-4o", "aider", &at("2026-03-02"), 2_000),
+                ("gpt-4o-mini", "cursor", &at("2026-03-02"), 500),
+                ("llama3.2", "cursor", &at("2026-03-03"), 4_000),
+            ] {
+                let mut call = call(model, client, timestamp, Some(Usage::new(prompt, 0)));
+                ledger.account(&mut call);
+            }
+            ledger
+        }
+
+        fn day(text: &str) -> NaiveDate {
+            text.parse().unwrap()
+        }
+
+        #[test]
+        fn test_group_by_model_and_client() {
+            let ledger = ledger_with_calls();
+            let by_model = ledger.summary(UsageRange::default(), UsageGroup::Model);
+            let keys: Vec<&str> = by_model.groups.iter().map(|g| g.key.as_str()).collect();
+            assert_eq!(keys, vec!["gpt-4o", "gpt-4o-mini", "llama3.2"]);
+            assert_eq!(by_model.groups[0].totals.calls, 2);
+            assert_eq!(by_model.total.prompt_tokens, 7_500);
+
+            let by_client = ledger.summary(UsageRange::default(), UsageGroup::Client);
+            let cursor = by_client.groups.iter().find(|g| g.key == "cursor").unwrap();
+            assert_eq!(cursor.totals.calls, 3);
+        }
+
+        #[test]
+        fn test_group_by_day_within_range() {
+            let ledger = ledger_with_calls();
+            let range = UsageRange {
+                from: Some(day("2026-03-02")),
+                to: Some(day("2026-03-03")),
+            };
+            let summary = ledger.summary(range, UsageGroup::Day);
+            let keys: Vec<&str> = summary.groups.iter().map(|g| g.key.as_str()).collect();
+            assert_eq!(keys, vec!["2026-03-02", "2026-03-03"]);
+            assert_eq!(summary.total.calls, 3);
+        }
+
+        #[test]
+        fn test_history_survives_restart() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(USAGE_HISTORY_FILE);
+            let prices = || PriceStore::in_memory(PricingTable::default());
+            let ledger = UsageLedger::open(path.clone(), prices());
+            let mut call = call(
+                "gpt-4o",
+                "cursor",
+                &at("2026-03-02"),
+                Some(Usage::new(10, 5)),
+            );
+            ledger.account(&mut call);
+
+            let reopened = UsageLedger::open(path, prices());
+            let summary = reopened.summary(UsageRange::default(), UsageGroup::Day);
+            assert_eq!(summary.total.calls, 1);
+            assert_eq!(summary.total.completion_tokens, 5);
+        }
+
+        #[test]
+        fn test_range_uses_iso_dates() {
+            let range: UsageRange = serde_json::from_str(r#"{"from": "2026-03-02"}"#).unwrap();
+            assert_eq!(range.from, Some(day("2026-03-02")));
+            assert_eq!(range.to, None);
+            assert_eq!(
+                serde_json::to_value(range).unwrap(),
+                serde_json::json!({"from": "2026-03-02", "to": null})
+            );
+        }
+    }
+}
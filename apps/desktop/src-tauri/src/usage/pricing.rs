@@ -0,0 +1,375 @@
+//! Model pricing table
+//!
+//! Prices live in `pricing.toml` under the app data dir, written with
+//! list prices on first launch and re-read whenever it changes on disk.
+//! Entries are tried in order and the first pattern matching the model
+//! wins; models matching none are not priced.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::capture::Usage;
+use crate::routing::glob_match;
+use crate::sync::lock;
+
+/// Pricing table file under the app data dir
+pub const PRICING_FILE: &str = "pricing.toml";
+
+/// Written on first launch, editable by the user
+const DEFAULT_PRICING: &str = r#"# Blackbox pricing table, reloaded automatically when saved.
+#
+# Prices are US dollars per million tokens. Entries are tried in order and
+# the first pattern matching the model wins; patterns support `*` and `?`
+# and are also tried against the name after a `provider/` prefix.
+# Models matching no entry are not priced.
+
+[[prices]]
+pattern = "gpt-4o-mini*"
+input = 0.15
+output = 0.60
+
+[[prices]]
+pattern = "gpt-4o*"
+input = 2.50
+output = 10.00
+
+[[prices]]
+pattern = "gpt-4.1-nano*"
+input = 0.10
+output = 0.40
+
+[[prices]]
+pattern = "gpt-4.1-mini*"
+input = 0.40
+output = 1.60
+
+[[prices]]
+pattern = "gpt-4.1*"
+input = 2.00
+output = 8.00
+
+[[prices]]
+pattern = "gpt-4-turbo*"
+input = 10.00
+output = 30.00
+
+[[prices]]
+pattern = "gpt-4*"
+input = 30.00
+output = 60.00
+
+[[prices]]
+pattern = "gpt-3.5-turbo*"
+input = 0.50
+output = 1.50
+
+[[prices]]
+pattern = "o1-mini*"
+input = 1.10
+output = 4.40
+
+[[prices]]
+pattern = "o1*"
+input = 15.00
+output = 60.00
+
+[[prices]]
+pattern = "o3-mini*"
+input = 1.10
+output = 4.40
+
+[[prices]]
+pattern = "o3*"
+input = 2.00
+output = 8.00
+
+[[prices]]
+pattern = "o4-mini*"
+input = 1.10
+output = 4.40
+
+[[prices]]
+pattern = "claude-opus-4*"
+input = 15.00
+output = 75.00
+
+[[prices]]
+pattern = "claude-3-opus*"
+input = 15.00
+output = 75.00
+
+[[prices]]
+pattern = "claude-sonnet-4*"
+input = 3.00
+output = 15.00
+
+[[prices]]
+pattern = "claude-3-*sonnet*"
+input = 3.00
+output = 15.00
+
+[[prices]]
+pattern = "claude-3-5-haiku*"
+input = 0.80
+output = 4.00
+
+[[prices]]
+pattern = "claude-3-haiku*"
+input = 0.25
+output = 1.25
+
+[[prices]]
+pattern = "gemini-2.5-pro*"
+input = 1.25
+output = 10.00
+
+[[prices]]
+pattern = "gemini-2.5-flash*"
+input = 0.30
+output = 2.50
+
+[[prices]]
+pattern = "gemini-2.0-flash*"
+input = 0.10
+output = 0.40
+
+[[prices]]
+pattern = "gemini-1.5-pro*"
+input = 1.25
+output = 5.00
+
+[[prices]]
+pattern = "gemini-1.5-flash*"
+input = 0.075
+output = 0.30
+
+# Local models cost nothing
+[[prices]]
+pattern = "ollama/*"
+input = 0
+output = 0
+
+[[prices]]
+pattern = "llama*"
+input = 0
+output = 0
+"#;
+
+/// Errors raised while loading the pricing table
+#[derive(Debug, thiserror::Error)]
+pub enum PricingError {
+    #[error("failed to read {PRICING_FILE}: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid {PRICING_FILE}: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("price of {0} must not be negative")]
+    Negative(String),
+}
+
+/// Price of one model family
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub pattern: String,
+    /// US dollars per million prompt tokens
+    pub input: f64,
+    /// US dollars per million completion tokens
+    pub output: f64,
+}
+
+impl ModelPrice {
+    /// Returns what `usage` costs in US dollars
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input + usage.completion_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// Ordered model prices as written in `pricing.toml`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PricingTable {
+    pub prices: Vec<ModelPrice>,
+}
+
+impl PricingTable {
+    /// Parses and validates a TOML pricing table
+    pub fn parse(text: &str) -> Result<Self, PricingError> {
+        let table: PricingTable = toml::from_str(text)?;
+        if let Some(price) = table
+            .prices
+            .iter()
+            .find(|p| !(p.input >= 0.0 && p.output >= 0.0))
+        {
+            return Err(PricingError::Negative(price.pattern.clone()));
+        }
+        Ok(table)
+    }
+
+    /// Finds the price of `model`
+    pub fn price(&self, model: &str) -> Option<&ModelPrice> {
+        let bare = model.rsplit('/').next().unwrap_or(model);
+        self.prices
+            .iter()
+            .find(|p| glob_match(&p.pattern, model) || glob_match(&p.pattern, bare))
+    }
+}
+
+struct Loaded {
+    table: PricingTable,
+    /// Modification time of the file the table was read from
+    modified: Option<SystemTime>,
+}
+
+/// Live pricing table, reloaded when its file changes
+pub struct PriceStore {
+    /// `None` keeps the table in memory only
+    path: Option<PathBuf>,
+    loaded: Mutex<Loaded>,
+}
+
+impl PriceStore {
+    /// Creates a store serving `table` that never reloads
+    pub fn in_memory(table: PricingTable) -> Self {
+        Self {
+            path: None,
+            loaded: Mutex::new(Loaded {
+                table,
+                modified: None,
+            }),
+        }
+    }
+
+    /// Opens the table at `path`, writing the default prices when there is none
+    pub fn open(path: PathBuf) -> Result<Self, PricingError> {
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, DEFAULT_PRICING)?;
+        }
+        let store = Self {
+            path: Some(path),
+            ..Self::in_memory(PricingTable::default())
+        };
+        store.reload()?;
+        Ok(store)
+    }
+
+    /// Re-reads the file, keeping the current table when the new one is invalid
+    pub fn reload(&self) -> Result<(), PricingError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let modified = fs::metadata(path)?.modified().ok();
+        let table = PricingTable::parse(&fs::read_to_string(path)?);
+        let mut loaded = lock(&self.loaded);
+        // Remember the broken version too so it is not re-parsed on every call
+        loaded.modified = modified;
+        loaded.table = table?;
+        Ok(())
+    }
+
+    fn refresh(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let modified = fs::metadata(path).ok().and_then(|m| m.modified().ok());
+        if modified == lock(&self.loaded).modified {
+            return;
+        }
+        if let Err(e) = self.reload() {
+            log::warn!("Keeping the previous pricing table: {}", e);
+        }
+    }
+
+    /// Returns what `usage` of `model` costs, `None` when the model has no price
+    pub fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        self.refresh();
+        lock(&self.loaded)
+            .table
+            .price(model)
+            .map(|price| price.cost(usage))
+    }
+
+    /// Whether `model` is priced at nothing, e.g. a local model
+    pub fn is_free(&self, model: &str) -> bool {
+        self.refresh();
+        lock(&self.loaded)
+            .table
+            .price(model)
+            .is_some_and(|price| price.input == 0.0 && price.output == 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod pricing_tests {
+        use super::*;
+
+        #[test]
+        fn test_default_table_prices_common_models() {
+            let table = PricingTable::parse(DEFAULT_PRICING).unwrap();
+            assert_eq!(table.price("gpt-4o-mini-2024-07-18").unwrap().input, 0.15);
+            assert_eq!(table.price("gpt-4o").unwrap().input, 2.50);
+            assert_eq!(table.price("openai/gpt-4o").unwrap().input, 2.50);
+            assert_eq!(
+                table.price("claude-3-5-sonnet-latest").unwrap().output,
+                15.0
+            );
+            assert_eq!(table.price("ollama/qwen2.5").unwrap().input, 0.0);
+            assert!(table.price("mistral-large").is_none());
+        }
+
+        #[test]
+        fn test_cost() {
+            let price = ModelPrice {
+                pattern: "*".to_string(),
+                input: 2.5,
+                output: 10.0,
+            };
+            let cost = price.cost(&Usage::new(1_000, 500));
+            assert!((cost - 0.0075).abs() < 1e-12);
+        }
+
+        #[test]
+        fn test_invalid_prices_are_rejected() {
+            let text = "[[prices]]\npattern = \"x\"\ninput = -1\noutput = 0\n";
+            assert!(PricingTable::parse(text).is_err());
+            assert!(PricingTable::parse("[[prices]]\npattern = \"x\"\n").is_err());
+        }
+
+        #[test]
+        fn test_store_reloads_changes() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(PRICING_FILE);
+            let store = PriceStore::open(path.clone()).unwrap();
+            assert!(store.cost("gpt-4o", &Usage::new(1, 1)).is_some());
+
+            fs::write(
+                &path,
+                "[[prices]]\npattern = \"custom-*\"\ninput = 1\noutput = 1\n",
+            )
+            .unwrap();
+            store.reload().unwrap();
+            assert_eq!(
+                store.cost("custom-model", &Usage::new(1_000_000, 0)),
+                Some(1.0)
+            );
+            assert_eq!(store.cost("gpt-4o", &Usage::new(1, 1)), None);
+
+            fs::write(&path, "not toml [").unwrap();
+            assert!(store.reload().is_err());
+            assert_eq!(
+                store.cost("custom-model", &Usage::new(1_000_000, 0)),
+                Some(1.0)
+            );
+        }
+    }
+}
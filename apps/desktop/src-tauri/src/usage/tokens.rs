@@ -0,0 +1,158 @@
+//! Local token counting
+//!
+//! OpenAI models are counted with their own BPE vocabularies. Anthropic and
+//! Google do not publish theirs, so Claude is counted with `cl100k_base`
+//! and Gemini with `o200k_base`, which land within a few percent for
+//! English text. Llama 3 extends `cl100k_base`; other local models fall
+//! back to four characters per token.
+
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton};
+
+use crate::capture::{CapturedCall, Usage};
+
+/// Tokens added per message for its role and separators, as in OpenAI's cookbook
+const MESSAGE_OVERHEAD: u64 = 3;
+/// Tokens priming the assistant's reply
+const REPLY_OVERHEAD: u64 = 3;
+
+/// Vocabulary a model's tokens are counted with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tokenizer {
+    O200kBase,
+    Cl100kBase,
+    /// Four characters per token
+    Estimate,
+}
+
+impl Tokenizer {
+    /// Picks the tokenizer matching the family of `model`
+    pub fn for_model(model: &str) -> Self {
+        // Gateways like LiteLLM prefix models with their provider
+        let name = model
+            .rsplit('/')
+            .next()
+            .unwrap_or(model)
+            .to_ascii_lowercase();
+        let starts = |prefixes: &[&str]| prefixes.iter().any(|p| name.starts_with(p));
+        if starts(&[
+            "gpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "chatgpt-", "o1", "o3", "o4", "gemini",
+            "gemma",
+        ]) {
+            Tokenizer::O200kBase
+        } else if starts(&[
+            "gpt-4",
+            "gpt-3.5",
+            "text-embedding",
+            "claude",
+            "llama3",
+            "llama-3",
+        ]) {
+            Tokenizer::Cl100kBase
+        } else {
+            Tokenizer::Estimate
+        }
+    }
+
+    /// Name recorded on captured calls
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Tokenizer::O200kBase => "o200k_base",
+            Tokenizer::Cl100kBase => "cl100k_base",
+            Tokenizer::Estimate => "estimate",
+        }
+    }
+
+    /// Counts the tokens of `text`
+    pub fn count(self, text: &str) -> u64 {
+        if text.is_empty() {
+            return 0;
+        }
+        let tokens = match self {
+            Tokenizer::O200kBase => o200k_base_singleton().encode_ordinary(text).len(),
+            Tokenizer::Cl100kBase => cl100k_base_singleton().encode_ordinary(text).len(),
+            Tokenizer::Estimate => text.chars().count().div_ceil(4),
+        };
+        tokens as u64
+    }
+}
+
+/// Counts the prompt and answer of a captured call with its model's tokenizer
+pub fn count_call(call: &CapturedCall, tokenizer: Tokenizer) -> Usage {
+    let mut prompt = REPLY_OVERHEAD;
+    for message in &call.messages {
+        prompt += MESSAGE_OVERHEAD;
+        prompt += message.content.as_deref().map_or(0, |c| tokenizer.count(c));
+        prompt += message.name.as_deref().map_or(0, |n| tokenizer.count(n));
+        for tool_call in &message.tool_calls {
+            prompt += tokenizer.count(&tool_call.function.name);
+            prompt += tokenizer.count(&tool_call.function.arguments);
+        }
+    }
+    for tool in &call.tools {
+        prompt += tokenizer.count(&tool.to_string());
+    }
+    let response = &call.response;
+    let mut completion = response
+        .content
+        .as_deref()
+        .map_or(0, |c| tokenizer.count(c));
+    for tool_call in &response.tool_calls {
+        completion += tokenizer.count(&tool_call.function.name);
+        completion += tokenizer.count(&tool_call.function.arguments);
+    }
+    Usage::new(prompt, completion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{Message, MessageRole};
+
+    mod tokenizer_tests {
+        use super::*;
+
+        #[test]
+        fn test_families() {
+            assert_eq!(Tokenizer::for_model("gpt-4o-mini"), Tokenizer::O200kBase);
+            assert_eq!(Tokenizer::for_model("openai/o3-mini"), Tokenizer::O200kBase);
+            assert_eq!(Tokenizer::for_model("gpt-4-turbo"), Tokenizer::Cl100kBase);
+            assert_eq!(
+                Tokenizer::for_model("claude-sonnet-4-20250514"),
+                Tokenizer::Cl100kBase
+            );
+            assert_eq!(Tokenizer::for_model("gemini-2.5-pro"), Tokenizer::O200kBase);
+            assert_eq!(
+                Tokenizer::for_model("ollama/llama3.2:3b"),
+                Tokenizer::Cl100kBase
+            );
+            assert_eq!(Tokenizer::for_model("mistral:7b"), Tokenizer::Estimate);
+        }
+
+        #[test]
+        fn test_counts() {
+            assert_eq!(Tokenizer::O200kBase.count("Hello, world!"), 4);
+            assert_eq!(Tokenizer::Cl100kBase.count("Hello, world!"), 4);
+            assert_eq!(Tokenizer::Estimate.count("Hello, world!"), 4);
+            assert_eq!(Tokenizer::O200kBase.count(""), 0);
+        }
+
+        #[test]
+        fn test_call_overheads() {
+            let mut call = CapturedCall::new("openai", "/v1/chat/completions");
+            call.messages = vec![
+                Message::text(MessageRole::System, "Be brief."),
+                Message::text(MessageRole::User, "Hello, world!"),
+            ];
+            call.response.content = Some("Hello, world!".to_string());
+            let usage = count_call(&call, Tokenizer::O200kBase);
+            let system = Tokenizer::O200kBase.count("Be brief.");
+            assert_eq!(
+                usage.prompt_tokens,
+                REPLY_OVERHEAD + 2 * MESSAGE_OVERHEAD + system + 4
+            );
+            assert_eq!(usage.completion_tokens, 4);
+        }
+    }
+}
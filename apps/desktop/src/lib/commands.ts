@@ -34,3 +34,737 @@ export async function disableAutostart(): Promise<void> {
 export async function isAutostartEnabled(): Promise<boolean> {
   return await invoke("is_autostart_enabled");
 }
+
+/**
+ * Embedded proxy configuration
+ */
+export interface ProxyConfig {
+  /** Localhost port to listen on, 0 picks a free port */
+  port: number;
+  /** Base URL requests are forwarded to, without the `/v1` suffix */
+  upstream_url: string;
+  /**
+   * Replaces the client's `Authorization` header when set. Write-only: the
+   * backend moves it into the credential store and never returns it
+   */
+  upstream_api_key?: string | null;
+  /** Stored credential sent to the upstream, ahead of upstream_api_key */
+  upstream_credential: string | null;
+  /** Whether the proxy starts together with the app */
+  auto_start: boolean;
+  /** Maximum silence from the upstream before the request fails */
+  request_timeout_secs: number;
+}
+
+/**
+ * Snapshot of the embedded proxy state
+ */
+export interface ProxyStatus {
+  running: boolean;
+  address: string | null;
+  port: number;
+  upstream_url: string;
+  requests_served: number;
+}
+
+/**
+ * Starts (or restarts) the embedded proxy, optionally with a new configuration
+ */
+export async function startProxy(config?: ProxyConfig): Promise<ProxyStatus> {
+  return await invoke("start_proxy", { config: config ?? null });
+}
+
+/**
+ * Stops the embedded proxy
+ */
+export async function stopProxy(): Promise<ProxyStatus> {
+  return await invoke("stop_proxy");
+}
+
+/**
+ * Returns whether the embedded proxy is running and where
+ */
+export async function proxyStatus(): Promise<ProxyStatus> {
+  return await invoke("proxy_status");
+}
+
+/**
+ * HTTPS interception settings
+ */
+export interface InterceptConfig {
+  /** Localhost port to listen on, 0 picks a free port */
+  port: number;
+  /** Whether interception starts together with the app */
+  auto_start: boolean;
+}
+
+/**
+ * Snapshot of the intercepting proxy state
+ */
+export interface InterceptStatus {
+  running: boolean;
+  address: string | null;
+  port: number;
+  /** Tunnels decrypted since the server was started */
+  intercepted: number;
+  /** Tunnels relayed without decryption since the server was started */
+  tunneled: number;
+  /** URL of the generated PAC file while running */
+  pac_url: string | null;
+}
+
+/**
+ * Starts (or restarts) HTTPS interception, optionally with a new configuration
+ */
+export async function startInterception(
+  config?: InterceptConfig,
+): Promise<InterceptStatus> {
+  return await invoke("start_interception", { config: config ?? null });
+}
+
+/**
+ * Stops HTTPS interception
+ */
+export async function stopInterception(): Promise<InterceptStatus> {
+  return await invoke("stop_interception");
+}
+
+/**
+ * Returns whether HTTPS interception is running and what it has handled
+ */
+export async function interceptionStatus(): Promise<InterceptStatus> {
+  return await invoke("interception_status");
+}
+
+/**
+ * A domain routed through Blackbox by the interception policy
+ */
+export interface DomainRule {
+  /** Host pattern supporting `*` and `?` */
+  pattern: string;
+  /** `intercept` decrypts and captures, `tunnel` passes through untouched */
+  action: "intercept" | "tunnel";
+  /** Paths captured when intercepted, all when empty */
+  paths?: string[];
+  /** Paths forwarded untouched even when matching `paths` */
+  exclude_paths?: string[];
+}
+
+/**
+ * Which domains the intercepting proxy touches
+ */
+export interface InterceptPolicy {
+  /** Hosts refused by the proxy, ahead of any domain rule */
+  deny: string[];
+  /** Rules tried in order, the first matching the host wins */
+  domains: DomainRule[];
+}
+
+/**
+ * Returns which domains are intercepted, tunneled or refused
+ */
+export async function getInterceptPolicy(): Promise<InterceptPolicy> {
+  return await invoke("get_intercept_policy");
+}
+
+/**
+ * Saves the interception policy; running tunnels pick it up immediately
+ */
+export async function setInterceptPolicy(
+  policy: InterceptPolicy,
+): Promise<InterceptPolicy> {
+  return await invoke("set_intercept_policy", { policy });
+}
+
+/**
+ * Details of the Blackbox root certificate
+ */
+export interface CaInfo {
+  subject: string;
+  /** SHA-256 of the DER encoding, colon separated */
+  fingerprint: string;
+  created_at: string;
+  expires_at: string;
+  /** File users import into their trust store */
+  cert_path: string | null;
+}
+
+/**
+ * Generates the Blackbox root certificate unless one exists
+ */
+export async function generateCa(): Promise<CaInfo> {
+  return await invoke("generate_ca");
+}
+
+/**
+ * Returns the root certificate to import into a trust store
+ */
+export async function exportCa(format: "pem" | "der"): Promise<Uint8Array> {
+  const bytes: number[] = await invoke("export_ca", { format });
+  return new Uint8Array(bytes);
+}
+
+/**
+ * Replaces the root certificate; the old one stops being used immediately
+ */
+export async function rotateCa(): Promise<CaInfo> {
+  return await invoke("rotate_ca");
+}
+
+/**
+ * Returns fingerprint and validity of the root certificate, if generated
+ */
+export async function caInfo(): Promise<CaInfo | null> {
+  return await invoke("ca_info");
+}
+
+/**
+ * A pipeline extension as reported by the backend
+ */
+export interface ExtensionInfo {
+  id: string;
+  name: string;
+  description: string;
+  /** Set for installed extensions */
+  version?: string;
+  kind: "builtin" | "wasm" | "process";
+  enabled: boolean;
+}
+
+/**
+ * Lists pipeline extensions in the order they run
+ */
+export async function listExtensions(): Promise<ExtensionInfo[]> {
+  return await invoke("list_extensions");
+}
+
+/**
+ * Enables a pipeline extension
+ */
+export async function enableExtension(id: string): Promise<ExtensionInfo[]> {
+  return await invoke("enable_extension", { id });
+}
+
+/**
+ * Disables a pipeline extension
+ */
+export async function disableExtension(id: string): Promise<ExtensionInfo[]> {
+  return await invoke("disable_extension", { id });
+}
+
+/**
+ * Changes the order extensions run in; `ids` must list every extension once
+ */
+export async function reorderExtensions(ids: string[]): Promise<ExtensionInfo[]> {
+  return await invoke("reorder_extensions", { ids });
+}
+
+/**
+ * A problem found in an extension manifest
+ */
+export interface ManifestIssue {
+  /** Dotted path of the offending field, e.g. `entrypoint.path` */
+  field: string;
+  message: string;
+}
+
+/**
+ * Error returned by `installExtension` and `uninstallExtension`
+ */
+export interface InstallError {
+  kind:
+    | "manifest_not_found"
+    | "manifest_syntax"
+    | "invalid_manifest"
+    | "already_installed"
+    | "not_installed"
+    | "unsafe_archive"
+    | "activation_failed"
+    | "io";
+  message: string;
+  issues?: ManifestIssue[];
+}
+
+/**
+ * Installs an extension from a directory or zip with a blackbox-extension manifest.
+ * Rejects with an `InstallError`.
+ */
+export async function installExtension(path: string): Promise<ExtensionInfo[]> {
+  return await invoke("install_extension", { path });
+}
+
+/**
+ * Removes an installed extension and its files. Rejects with an `InstallError`.
+ */
+export async function uninstallExtension(id: string): Promise<ExtensionInfo[]> {
+  return await invoke("uninstall_extension", { id });
+}
+
+/**
+ * Response cache counters; hits and misses count since the app started
+ */
+export interface CacheStats {
+  enabled: boolean;
+  entries: number;
+  size_bytes: number;
+  max_bytes: number;
+  hits: number;
+  misses: number;
+  evictions: number;
+  semantic_hits: number;
+  semantic_entries: number;
+}
+
+/**
+ * Gets response cache counters
+ */
+export async function cacheStats(): Promise<CacheStats> {
+  return await invoke("cache_stats");
+}
+
+/**
+ * Removes every cached response
+ */
+export async function clearCache(): Promise<CacheStats> {
+  return await invoke("clear_cache");
+}
+
+/**
+ * Token limits of one budget; unset limits never trigger
+ */
+export interface BudgetLimit {
+  soft_tokens?: number | null;
+  hard_tokens?: number | null;
+}
+
+/**
+ * Budget limits; client budgets count per day
+ */
+export interface BudgetSettings {
+  daily: BudgetLimit;
+  session: BudgetLimit;
+  per_client: BudgetLimit;
+  clients: Record<string, BudgetLimit>;
+  downgrade: DowngradeSettings;
+}
+
+/**
+ * Cheaper models requests switch to once a budget reaches the `at` level
+ */
+export interface DowngradeSettings {
+  enabled: boolean;
+  at: "warning" | "exceeded";
+  models: Record<string, string>;
+  fallback?: string | null;
+}
+
+/**
+ * Usage and state of one budget, also the payload of "budget-warning" and "budget-exceeded" events
+ */
+export interface BudgetStatus {
+  scope: "daily" | "session" | "client";
+  client?: string;
+  used: { prompt_tokens: number; completion_tokens: number };
+  limit: BudgetLimit;
+  level: "ok" | "warning" | "exceeded";
+}
+
+/**
+ * Budget limits together with the current usage
+ */
+export interface BudgetOverview {
+  limits: BudgetSettings;
+  day: string;
+  daily: BudgetStatus;
+  session: BudgetStatus;
+  clients: BudgetStatus[];
+}
+
+/**
+ * Gets token budget limits and usage
+ */
+export async function getBudgets(): Promise<BudgetOverview> {
+  return await invoke("get_budgets");
+}
+
+/**
+ * Replaces token budget limits
+ */
+export async function setBudgets(limits: BudgetSettings): Promise<BudgetOverview> {
+  return await invoke("set_budgets", { limits });
+}
+
+/**
+ * Inclusive range of local days as `YYYY-MM-DD`; open ends are unbounded
+ */
+export interface UsageRange {
+  from?: string;
+  to?: string;
+}
+
+/**
+ * What usage totals are broken down by
+ */
+export type UsageGroup = "model" | "client" | "day";
+
+/**
+ * Calls, tokens and cost (US dollars) added up
+ */
+export interface UsageTotals {
+  calls: number;
+  prompt_tokens: number;
+  completion_tokens: number;
+  cost: number;
+  estimated_calls: number;
+  unpriced_calls: number;
+}
+
+/**
+ * Usage over a range of days; days in order, models and clients most expensive first
+ */
+export interface UsageSummary {
+  range: UsageRange;
+  group_by: UsageGroup;
+  total: UsageTotals;
+  groups: Array<UsageTotals & { key: string }>;
+}
+
+/**
+ * Gets usage totals over a range of days by model, client or day
+ */
+export async function getUsageSummary(
+  range: UsageRange,
+  groupBy: UsageGroup,
+): Promise<UsageSummary> {
+  return await invoke("get_usage_summary", { range, groupBy });
+}
+
+/**
+ * Where and how spans of proxied calls are exported over OTLP/HTTP
+ */
+export interface TelemetryConfig {
+  enabled: boolean;
+  endpoint: string;
+  headers: Record<string, string>;
+  service_name: string;
+  capture_content: boolean;
+  batch_size: number;
+  flush_interval_secs: number;
+}
+
+/**
+ * Spans waiting, queued for retry and exported since launch
+ */
+export interface TelemetryStatus {
+  enabled: boolean;
+  endpoint: string;
+  pending: number;
+  queued: number;
+  exported: number;
+  dropped: number;
+  last_error: string | null;
+}
+
+/**
+ * Gets the OTLP span export settings
+ */
+export async function getTelemetrySettings(): Promise<TelemetryConfig> {
+  return await invoke("get_telemetry_settings");
+}
+
+/**
+ * Replaces the OTLP span export settings
+ */
+export async function setTelemetrySettings(
+  settings: TelemetryConfig,
+): Promise<TelemetryConfig> {
+  return await invoke("set_telemetry_settings", { settings });
+}
+
+/**
+ * Gets the state of the span exporter and its retry queue
+ */
+export async function telemetryStatus(): Promise<TelemetryStatus> {
+  return await invoke("telemetry_status");
+}
+
+/**
+ * User-defined redaction pattern; matches become `[NAME_n]`
+ */
+export interface CustomPattern {
+  name: string;
+  regex: string;
+}
+
+/**
+ * What the PII redaction plugin masks
+ */
+export interface RedactionSettings {
+  emails: boolean;
+  phones: boolean;
+  cards: boolean;
+  ip_addresses: boolean;
+  patterns: CustomPattern[];
+  exempt_local: boolean;
+  restore: boolean;
+}
+
+/**
+ * Gets what the PII redaction plugin masks
+ */
+export async function getRedactionSettings(): Promise<RedactionSettings> {
+  return await invoke("get_redaction_settings");
+}
+
+/**
+ * Replaces what the PII redaction plugin masks
+ */
+export async function setRedactionSettings(
+  settings: RedactionSettings,
+): Promise<RedactionSettings> {
+  return await invoke("set_redaction_settings", { settings });
+}
+
+/**
+ * What the secret guard does with a request containing a credential
+ */
+export type SecretAction = "block" | "redact" | "warn";
+
+/**
+ * Secret guard configuration
+ */
+export interface SecretGuardSettings {
+  action: SecretAction;
+  disabled_rules: string[];
+  entropy_threshold: number;
+}
+
+/**
+ * Payload of "secret-detected" events; `rule` is the first rule that fired
+ */
+export interface SecretDetection {
+  call_id: string;
+  model: string;
+  action: SecretAction;
+  rule: string;
+  rules: string[];
+}
+
+/**
+ * Gets the secret guard configuration
+ */
+export async function getSecretGuardSettings(): Promise<SecretGuardSettings> {
+  return await invoke("get_secret_guard_settings");
+}
+
+/**
+ * Replaces the secret guard configuration
+ */
+export async function setSecretGuardSettings(
+  settings: SecretGuardSettings,
+): Promise<SecretGuardSettings> {
+  return await invoke("set_secret_guard_settings", { settings });
+}
+
+/**
+ * What happens to tool output lines that look like prompt injections
+ */
+export type InjectionAction = "tag" | "quarantine" | "strip";
+
+/**
+ * Prompt-injection heuristics configuration; `threshold` is a score from 0 to 1
+ */
+export interface InjectionSettings {
+  action: InjectionAction;
+  threshold: number;
+}
+
+/**
+ * Gets the prompt-injection heuristics configuration
+ */
+export async function getInjectionSettings(): Promise<InjectionSettings> {
+  return await invoke("get_injection_settings");
+}
+
+/**
+ * Replaces the prompt-injection heuristics configuration
+ */
+export async function setInjectionSettings(
+  settings: InjectionSettings,
+): Promise<InjectionSettings> {
+  return await invoke("set_injection_settings", { settings });
+}
+
+/**
+ * Where a request for a model goes; `auth` only names the mode
+ */
+export interface ResolvedRoute {
+  requested_model: string;
+  model: string;
+  aliases: string[];
+  pattern: string | null;
+  upstream: string;
+  base_url: string;
+  kind: "openai" | "anthropic" | "gemini" | "ollama" | "litellm";
+  auth: "passthrough" | "none" | "key" | "env" | "credential";
+  /** Upstreams tried in order when this one keeps failing */
+  fallbacks: string[];
+}
+
+/**
+ * Shows which upstream a request for the model would be sent to
+ */
+export async function resolveRoute(model: string): Promise<ResolvedRoute> {
+  return await invoke("resolve_route", { model });
+}
+
+/**
+ * Circuit breaker of an upstream, also the payload of "circuit-breaker" events
+ */
+export interface BreakerStatus {
+  upstream: string;
+  state: "closed" | "open" | "half_open";
+  failures: number;
+}
+
+/**
+ * Lists upstreams that failed recently and the state of their circuit breakers
+ */
+export async function getCircuitBreakers(): Promise<BreakerStatus[]> {
+  return await invoke("get_circuit_breakers");
+}
+
+/**
+ * Virtual key of a client, without the key itself
+ */
+export interface ClientKey {
+  id: string;
+  name: string;
+  /** Start of the key, enough to recognise it */
+  hint: string;
+  created_at: string;
+  budget: BudgetLimit;
+  /** Glob patterns of allowed models, any model when empty */
+  models: string[];
+  /** Plugins that run on the client's calls, every enabled one when null */
+  plugins: string[] | null;
+}
+
+export interface ClientKeyList {
+  /** Whether requests without a valid key are rejected */
+  required: boolean;
+  keys: ClientKey[];
+}
+
+export interface ClientKeyRequest {
+  name: string;
+  budget?: BudgetLimit;
+  models?: string[];
+  plugins?: string[] | null;
+}
+
+/**
+ * A new key; `key` is only ever returned here
+ */
+export interface CreatedClientKey {
+  key: string;
+  client: ClientKey;
+}
+
+/**
+ * Lists the virtual keys of clients
+ */
+export async function listClientKeys(): Promise<ClientKeyList> {
+  return await invoke("list_client_keys");
+}
+
+/**
+ * Issues a virtual key for a client
+ */
+export async function createClientKey(request: ClientKeyRequest): Promise<CreatedClientKey> {
+  return await invoke("create_client_key", { request });
+}
+
+/**
+ * Revokes a client's virtual key
+ */
+export async function revokeClientKey(id: string): Promise<ClientKey> {
+  return await invoke("revoke_client_key", { id });
+}
+
+/**
+ * Decides whether requests without a valid virtual key are rejected
+ */
+export async function setClientKeysRequired(required: boolean): Promise<ClientKeyList> {
+  return await invoke("set_client_keys_required", { required });
+}
+
+export interface CredentialInfo {
+  name: string;
+  updated_at: string;
+}
+
+/**
+ * Stored credentials by name; values are never sent to the webview
+ */
+export interface CredentialStatus {
+  protection: "key_file" | "passphrase";
+  locked: boolean;
+  credentials: CredentialInfo[];
+}
+
+export interface CredentialTest {
+  ok: boolean;
+  url: string;
+  status?: number;
+  message: string;
+}
+
+/**
+ * Lists stored credentials and whether the store is locked
+ */
+export async function getCredentials(): Promise<CredentialStatus> {
+  return await invoke("get_credentials");
+}
+
+/**
+ * Encrypts and stores an upstream API key, e.g. OPENAI_API_KEY
+ */
+export async function setCredential(name: string, value: string): Promise<CredentialInfo> {
+  return await invoke("set_credential", { name, value });
+}
+
+/**
+ * Deletes a stored credential
+ */
+export async function deleteCredential(name: string): Promise<CredentialStatus> {
+  return await invoke("delete_credential", { name });
+}
+
+/**
+ * Checks a stored credential against an upstream, or the service its name suggests
+ */
+export async function testCredential(name: string, upstream?: string): Promise<CredentialTest> {
+  return await invoke("test_credential", { name, upstream: upstream ?? null });
+}
+
+/**
+ * Unlocks a passphrase-protected credential store
+ */
+export async function unlockCredentials(passphrase: string): Promise<CredentialStatus> {
+  return await invoke("unlock_credentials", { passphrase });
+}
+
+/**
+ * Locks a passphrase-protected credential store
+ */
+export async function lockCredentials(): Promise<CredentialStatus> {
+  return await invoke("lock_credentials");
+}
+
+/**
+ * Protects the credentials with a passphrase, or a generated key when null
+ */
+export async function setCredentialsPassphrase(passphrase: string | null): Promise<CredentialStatus> {
+  return await invoke("set_credentials_passphrase", { passphrase });
+}